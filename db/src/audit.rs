@@ -0,0 +1,60 @@
+//! Audit trail of the statements run from the admin SQL console
+
+use utility::errors::AppResult;
+use crate::setup::PgDb;
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditEntry {
+	pub username: String,
+	pub client_ip: String,
+	pub request_id: String,
+	pub statement: String,
+	pub read_only: bool,
+	pub success: bool,
+	/// Rows returned by reads, inserted, updated or deleted by writes
+	pub row_count: i64,
+	pub duration_ms: f64,
+	pub error: Option<String>,
+}
+
+/// Create the `qaswa.sql_audit` table
+pub async fn init(pg: &PgDb) -> AppResult<()> {
+	sqlx::query("CREATE SCHEMA IF NOT EXISTS qaswa;").execute(pg).await?;
+	sqlx::query(r#"
+		CREATE TABLE IF NOT EXISTS qaswa.sql_audit (
+			id BIGSERIAL PRIMARY KEY,
+			executed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+			username TEXT NOT NULL,
+			client_ip TEXT NOT NULL,
+			request_id TEXT NOT NULL,
+			statement TEXT NOT NULL,
+			read_only BOOLEAN NOT NULL,
+			success BOOLEAN NOT NULL,
+			row_count BIGINT NOT NULL,
+			duration_ms DOUBLE PRECISION NOT NULL,
+			error TEXT
+		);
+	"#).execute(pg).await?;
+	Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn record(pg: &PgDb, entry: &AuditEntry) -> AppResult<()> {
+	sqlx::query(r#"
+		INSERT INTO qaswa.sql_audit
+			(username, client_ip, request_id, statement, read_only, success, row_count, duration_ms, error)
+		VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+	"#)
+		.bind(&entry.username)
+		.bind(&entry.client_ip)
+		.bind(&entry.request_id)
+		.bind(&entry.statement)
+		.bind(entry.read_only)
+		.bind(entry.success)
+		.bind(entry.row_count)
+		.bind(entry.duration_ms)
+		.bind(&entry.error)
+		.execute(pg)
+		.await?;
+	Ok(())
+}
@@ -0,0 +1,204 @@
+//! Tables copied into flinch collections, served from memory.
+//!
+//! The declarations are stored in `qaswa.flinch_cache`. A copy is loaded whole, then follows the
+//! changes of its table, which must be published to realtime clients or captured by CDC.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::Row;
+use utoipa::ToSchema;
+use utility::errors::{AppError, AppResult};
+use crate::notify::Change;
+use crate::setup::PgDb;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CachedTable {
+	pub collection: String,
+	pub schema: String,
+	pub table: String,
+	/// Primary key columns, their values joined by commas are the keys of the documents
+	pub key_columns: Vec<String>,
+	/// Indexed fields of the collection
+	pub index_opts: Vec<String>,
+	pub created_at: DateTime<Utc>,
+}
+
+impl CachedTable {
+	/// Key of the document of `row`, `None` when a key column is missing
+	pub fn key(&self, row: &Value) -> Option<String> {
+		let values: Option<Vec<String>> = self.key_columns.iter()
+			.map(|column| match row.get(column)? {
+				Value::Null => None,
+				Value::String(value) => Some(value.clone()),
+				value => Some(value.to_string()),
+			})
+			.collect();
+		values.map(|values| values.join(","))
+	}
+
+	pub fn follows(&self, change: &Change) -> bool {
+		change.schema == self.schema && change.table == self.table
+	}
+
+	fn source(&self) -> String {
+		format!("{}.{}", quote(&self.schema), quote(&self.table))
+	}
+}
+
+/// Create the `qaswa.flinch_cache` table
+pub async fn init(pg: &PgDb) -> AppResult<()> {
+	sqlx::query("CREATE SCHEMA IF NOT EXISTS qaswa;").execute(pg).await?;
+	sqlx::query(r#"
+		CREATE TABLE IF NOT EXISTS qaswa.flinch_cache (
+			collection TEXT PRIMARY KEY,
+			schema_name TEXT NOT NULL,
+			table_name TEXT NOT NULL,
+			key_columns TEXT[] NOT NULL,
+			index_opts TEXT[] NOT NULL DEFAULT '{}',
+			created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+		);
+	"#).execute(pg).await?;
+	Ok(())
+}
+
+pub async fn list(pg: &PgDb) -> AppResult<Vec<CachedTable>> {
+	let rows = sqlx::query("SELECT collection, schema_name, table_name, key_columns, index_opts, created_at FROM qaswa.flinch_cache ORDER BY collection;")
+		.fetch_all(pg)
+		.await?;
+	rows.iter()
+		.map(|row| Ok(CachedTable {
+			collection: row.try_get(0)?,
+			schema: row.try_get(1)?,
+			table: row.try_get(2)?,
+			key_columns: row.try_get(3)?,
+			index_opts: row.try_get(4)?,
+			created_at: row.try_get(5)?,
+		}))
+		.collect()
+}
+
+/// Declare the copy of a table with a primary key, whose changes are published. Every user may
+/// read the copies, so the table must be readable by `user_role` and have no row-level security
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn add(pg: &PgDb, collection: &str, schema: &str, table: &str, index_opts: &[String], user_role: &str) -> AppResult<CachedTable> {
+	let found: Option<(Vec<String>, bool, bool)> = sqlx::query_as(r#"
+		SELECT coalesce((
+			SELECT array_agg(a.attname::text ORDER BY k.position)
+			FROM pg_catalog.pg_index i
+			CROSS JOIN unnest(i.indkey) WITH ORDINALITY AS k(attnum, position)
+			JOIN pg_catalog.pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+			WHERE i.indrelid = c.oid AND i.indisprimary
+		), '{}'), c.relrowsecurity, has_table_privilege($3, c.oid, 'SELECT')
+		FROM pg_catalog.pg_class c
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		WHERE n.nspname = $1 AND c.relname = $2 AND c.relkind IN ('r', 'p');
+	"#)
+		.bind(schema)
+		.bind(table)
+		.bind(user_role)
+		.fetch_optional(pg)
+		.await?;
+	let (key_columns, row_security, readable) = found.ok_or_else(|| AppError::NotFound { message: format!("table {}.{} not found", schema, table) })?;
+	if key_columns.is_empty() {
+		return Err(AppError::BadRequest { message: format!("table {}.{} has no primary key", schema, table) });
+	}
+	// The copies are read with the connection role, whatever the user
+	if row_security {
+		return Err(AppError::BadRequest { message: format!("table {}.{} has row-level security, which its copy would not apply", schema, table) });
+	}
+	if !readable {
+		return Err(AppError::BadRequest { message: format!("role {} can't read table {}.{}", user_role, schema, table) });
+	}
+	if !crate::notify::is_published(pg, schema, table).await? && !crate::cdc::is_published(pg, schema, table).await? {
+		return Err(AppError::BadRequest {
+			message: format!("changes of table {}.{} are not sent, publish it to realtime clients or capture it first", schema, table),
+		});
+	}
+
+	let created_at = sqlx::query_scalar(r#"
+		INSERT INTO qaswa.flinch_cache (collection, schema_name, table_name, key_columns, index_opts) VALUES ($1, $2, $3, $4, $5)
+		ON CONFLICT (collection) DO NOTHING
+		RETURNING created_at;
+	"#)
+		.bind(collection)
+		.bind(schema)
+		.bind(table)
+		.bind(&key_columns)
+		.bind(index_opts)
+		.fetch_optional(pg)
+		.await?
+		.ok_or_else(|| AppError::BadRequest { message: format!("collection {} is already a copy", collection) })?;
+	Ok(CachedTable {
+		collection: collection.to_string(),
+		schema: schema.to_string(),
+		table: table.to_string(),
+		key_columns,
+		index_opts: index_opts.to_vec(),
+		created_at,
+	})
+}
+
+pub async fn remove(pg: &PgDb, collection: &str) -> AppResult<()> {
+	let deleted = sqlx::query("DELETE FROM qaswa.flinch_cache WHERE collection = $1;")
+		.bind(collection)
+		.execute(pg)
+		.await?
+		.rows_affected();
+	match deleted {
+		0 => Err(AppError::NotFound { message: format!("collection {} is not a copy", collection) }),
+		_ => Ok(()),
+	}
+}
+
+/// Rows of the table as JSON objects, with their key
+#[tracing::instrument(skip_all, fields(otel.kind = "client", db.system = "postgresql", collection = %cached.collection))]
+pub async fn rows(pg: &PgDb, cached: &CachedTable) -> AppResult<Vec<(String, Value)>> {
+	let rows: Vec<Value> = sqlx::query_scalar(&format!("SELECT to_jsonb(t) FROM {} t;", cached.source()))
+		.fetch_all(pg)
+		.await?;
+	Ok(rows.into_iter().filter_map(|row| Some((cached.key(&row)?, row))).collect())
+}
+
+/// Current row with the key of `row`, `None` once deleted
+pub async fn row(pg: &PgDb, cached: &CachedTable, row: &Value) -> AppResult<Option<Value>> {
+	let condition: Vec<String> = cached.key_columns.iter()
+		.enumerate()
+		.map(|(i, column)| format!("to_jsonb(t.{}) = ${}", quote(column), i + 1))
+		.collect();
+	let sql = format!("SELECT to_jsonb(t) FROM {} t WHERE {};", cached.source(), condition.join(" AND "));
+	let mut query = sqlx::query_scalar(&sql);
+	for column in &cached.key_columns {
+		query = query.bind(row.get(column).cloned().unwrap_or(Value::Null));
+	}
+	Ok(query.fetch_optional(pg).await?)
+}
+
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	#[test]
+	fn test_key() {
+		let mut cached = CachedTable {
+			collection: "products".to_string(),
+			schema: "public".to_string(),
+			table: "products".to_string(),
+			key_columns: vec!["id".to_string()],
+			index_opts: vec![],
+			created_at: Utc::now(),
+		};
+		assert_eq!(cached.key(&json!({"id": 42, "name": "tea"})).unwrap(), "42");
+		assert_eq!(cached.key(&json!({"id": "sku-1"})).unwrap(), "sku-1");
+		assert!(cached.key(&json!({"id": null})).is_none());
+		assert!(cached.key(&json!({"name": "tea"})).is_none());
+
+		cached.key_columns.push("currency".to_string());
+		assert_eq!(cached.key(&json!({"id": 42, "currency": "EUR"})).unwrap(), "42,EUR");
+	}
+}
@@ -0,0 +1,328 @@
+//! Messages of the `pgoutput` logical decoding plugin, protocol version 1.
+//!
+//! See <https://www.postgresql.org/docs/current/protocol-logicalrep-message-formats.html>.
+//! Column values are sent in their text format.
+
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::{Map, Number, Value};
+
+/// Position in the WAL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lsn(pub u64);
+
+impl fmt::Display for Lsn {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & 0xFFFF_FFFF)
+	}
+}
+
+impl FromStr for Lsn {
+	type Err = String;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		let (high, low) = text.split_once('/').ok_or_else(|| format!("invalid LSN {:?}", text))?;
+		let part = |part: &str| u32::from_str_radix(part, 16).map_err(|_| format!("invalid LSN {:?}", text));
+		Ok(Lsn((part(high)? as u64) << 32 | part(low)? as u64))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+	pub name: String,
+	pub type_oid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+	pub id: u32,
+	pub schema: String,
+	pub name: String,
+	pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+	Null,
+	/// TOASTed value not changed by an update, and not sent
+	Unchanged,
+	Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+	Begin { final_lsn: Lsn, committed_at: DateTime<Utc>, xid: u32 },
+	Commit { end_lsn: Lsn, committed_at: DateTime<Utc> },
+	Relation(Relation),
+	Insert { relation: u32, new: Vec<Datum> },
+	/// `old` is the previous key or row, depending on the replica identity of the table
+	Update { relation: u32, old: Option<Vec<Datum>>, new: Vec<Datum> },
+	Delete { relation: u32, old: Vec<Datum> },
+	Truncate { relations: Vec<u32> },
+	/// Origin and type messages, not needed to decode rows
+	Other(u8),
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+		if self.data.len() < len {
+			return Err("truncated message".to_string());
+		}
+		let (taken, rest) = self.data.split_at(len);
+		self.data = rest;
+		Ok(taken)
+	}
+
+	fn u8(&mut self) -> Result<u8, String> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn i16(&mut self) -> Result<i16, String> {
+		Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	fn u32(&mut self) -> Result<u32, String> {
+		Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+	}
+
+	fn u64(&mut self) -> Result<u64, String> {
+		Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+	}
+
+	fn lsn(&mut self) -> Result<Lsn, String> {
+		Ok(Lsn(self.u64()?))
+	}
+
+	/// Microseconds since 2000-01-01
+	fn timestamp(&mut self) -> Result<DateTime<Utc>, String> {
+		let micros = self.u64()? as i64;
+		Ok(Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap() + Duration::microseconds(micros))
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		let end = self.data.iter().position(|&byte| byte == 0).ok_or("unterminated string")?;
+		let text = String::from_utf8_lossy(&self.data[..end]).into_owned();
+		self.data = &self.data[end + 1..];
+		Ok(text)
+	}
+
+	fn tuple(&mut self) -> Result<Vec<Datum>, String> {
+		let count = self.i16()?;
+		(0..count)
+			.map(|_| match self.u8()? {
+				b'n' => Ok(Datum::Null),
+				b'u' => Ok(Datum::Unchanged),
+				b't' => {
+					let len = self.u32()? as usize;
+					Ok(Datum::Text(String::from_utf8_lossy(self.take(len)?).into_owned()))
+				}
+				kind => Err(format!("unknown tuple data {:?}", kind as char)),
+			})
+			.collect()
+	}
+}
+
+/// Decode a message
+pub fn parse(data: &[u8]) -> Result<Message, String> {
+	let mut reader = Reader { data };
+	let message = match reader.u8()? {
+		b'B' => Message::Begin { final_lsn: reader.lsn()?, committed_at: reader.timestamp()?, xid: reader.u32()? },
+		b'C' => {
+			let _flags = reader.u8()?;
+			let _commit_lsn = reader.lsn()?;
+			Message::Commit { end_lsn: reader.lsn()?, committed_at: reader.timestamp()? }
+		}
+		b'R' => {
+			let id = reader.u32()?;
+			let schema = match reader.string()? {
+				schema if schema.is_empty() => "pg_catalog".to_string(),
+				schema => schema,
+			};
+			let name = reader.string()?;
+			let _replica_identity = reader.u8()?;
+			let count = reader.i16()?;
+			let columns = (0..count)
+				.map(|_| {
+					let _flags = reader.u8()?;
+					let name = reader.string()?;
+					let type_oid = reader.u32()?;
+					let _type_modifier = reader.u32()?;
+					Ok(Column { name, type_oid })
+				})
+				.collect::<Result<_, String>>()?;
+			Message::Relation(Relation { id, schema, name, columns })
+		}
+		b'I' => {
+			let relation = reader.u32()?;
+			expect(&mut reader, b'N')?;
+			Message::Insert { relation, new: reader.tuple()? }
+		}
+		b'U' => {
+			let relation = reader.u32()?;
+			let old = match reader.u8()? {
+				b'K' | b'O' => {
+					let old = reader.tuple()?;
+					expect(&mut reader, b'N')?;
+					Some(old)
+				}
+				b'N' => None,
+				kind => return Err(format!("unexpected {:?} in update", kind as char)),
+			};
+			Message::Update { relation, old, new: reader.tuple()? }
+		}
+		b'D' => {
+			let relation = reader.u32()?;
+			match reader.u8()? {
+				b'K' | b'O' => Message::Delete { relation, old: reader.tuple()? },
+				kind => return Err(format!("unexpected {:?} in delete", kind as char)),
+			}
+		}
+		b'T' => {
+			let count = reader.u32()?;
+			let _options = reader.u8()?;
+			Message::Truncate { relations: (0..count).map(|_| reader.u32()).collect::<Result<_, _>>()? }
+		}
+		kind => Message::Other(kind),
+	};
+	Ok(message)
+}
+
+fn expect(reader: &mut Reader, kind: u8) -> Result<(), String> {
+	match reader.u8()? {
+		byte if byte == kind => Ok(()),
+		byte => Err(format!("expected {:?}, got {:?}", kind as char, byte as char)),
+	}
+}
+
+/// Row as a JSON object, without the unchanged TOASTed columns
+pub fn row(relation: &Relation, tuple: &[Datum]) -> Value {
+	let mut row = Map::new();
+	for (column, datum) in relation.columns.iter().zip(tuple) {
+		match datum {
+			Datum::Null => {
+				row.insert(column.name.clone(), Value::Null);
+			}
+			Datum::Unchanged => {}
+			Datum::Text(text) => {
+				row.insert(column.name.clone(), value(column.type_oid, text));
+			}
+		}
+	}
+	Value::Object(row)
+}
+
+/// JSON value of the text format of a column: booleans, integers, floats and JSON are decoded,
+/// other types such as `numeric` stay strings so no precision is lost
+fn value(type_oid: u32, text: &str) -> Value {
+	const BOOL: u32 = 16;
+	const INT8: u32 = 20;
+	const INT2: u32 = 21;
+	const INT4: u32 = 23;
+	const OID: u32 = 26;
+	const JSON: u32 = 114;
+	const FLOAT4: u32 = 700;
+	const FLOAT8: u32 = 701;
+	const JSONB: u32 = 3802;
+
+	let decoded = match type_oid {
+		BOOL => Some(Value::Bool(text == "t")),
+		INT2 | INT4 | INT8 | OID => text.parse::<i64>().ok().map(Value::from),
+		FLOAT4 | FLOAT8 => text.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number),
+		JSON | JSONB => serde_json::from_str(text).ok(),
+		_ => None,
+	};
+	decoded.unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	fn cstring(text: &str) -> Vec<u8> {
+		let mut bytes = text.as_bytes().to_vec();
+		bytes.push(0);
+		bytes
+	}
+
+	fn text(value: &str) -> Vec<u8> {
+		let mut bytes = vec![b't'];
+		bytes.extend((value.len() as u32).to_be_bytes());
+		bytes.extend(value.as_bytes());
+		bytes
+	}
+
+	#[test]
+	fn test_lsn() {
+		let lsn: Lsn = "16/B374D848".parse().unwrap();
+		assert_eq!(lsn, Lsn(0x16_B374_D848));
+		assert_eq!(lsn.to_string(), "16/B374D848");
+		assert_eq!(Lsn(0).to_string(), "0/0");
+		assert!("0/28E5B70".parse::<Lsn>().unwrap() < "1/0".parse::<Lsn>().unwrap());
+		assert!("28E5B70".parse::<Lsn>().is_err());
+	}
+
+	#[test]
+	fn test_parse() {
+		let mut begin = vec![b'B'];
+		begin.extend(0x28E_5B70u64.to_be_bytes());
+		begin.extend(1_000_000u64.to_be_bytes());
+		begin.extend(797u32.to_be_bytes());
+		assert_eq!(parse(&begin).unwrap(), Message::Begin {
+			final_lsn: Lsn(0x28E_5B70),
+			committed_at: Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 1).unwrap(),
+			xid: 797,
+		});
+
+		let mut relation = vec![b'R'];
+		relation.extend(16586u32.to_be_bytes());
+		relation.extend(cstring("public"));
+		relation.extend(cstring("orders"));
+		relation.push(b'f');
+		relation.extend(3i16.to_be_bytes());
+		for (name, oid) in [("id", 23u32), ("paid", 16), ("total", 1700)] {
+			relation.push(1);
+			relation.extend(cstring(name));
+			relation.extend(oid.to_be_bytes());
+			relation.extend((-1i32).to_be_bytes());
+		}
+		let Message::Relation(relation) = parse(&relation).unwrap() else { panic!("not a relation") };
+		assert_eq!(relation.name, "orders");
+		assert_eq!(relation.columns[2], Column { name: "total".to_string(), type_oid: 1700 });
+
+		let mut update = vec![b'U'];
+		update.extend(16586u32.to_be_bytes());
+		update.push(b'O');
+		update.extend(3i16.to_be_bytes());
+		update.extend(text("7"));
+		update.extend(text("f"));
+		update.push(b'n');
+		update.push(b'N');
+		update.extend(3i16.to_be_bytes());
+		update.extend(text("7"));
+		update.extend(text("t"));
+		update.push(b'u');
+		let Message::Update { relation: id, old: Some(old), new } = parse(&update).unwrap() else { panic!("not an update") };
+		assert_eq!(id, 16586);
+		assert_eq!(row(&relation, &old), json!({"id": 7, "paid": false, "total": null}));
+		assert_eq!(row(&relation, &new), json!({"id": 7, "paid": true}));
+
+		assert!(parse(&update[..update.len() - 3]).is_err());
+		assert_eq!(parse(b"Y").unwrap(), Message::Other(b'Y'));
+	}
+
+	#[test]
+	fn test_value() {
+		assert_eq!(value(20, "-9007199254740993"), json!(-9007199254740993i64));
+		assert_eq!(value(701, "1.5"), json!(1.5));
+		assert_eq!(value(701, "NaN"), json!("NaN"));
+		assert_eq!(value(1700, "12.50"), json!("12.50"));
+		assert_eq!(value(3802, r#"{"a": [1]}"#), json!({"a": [1]}));
+		assert_eq!(value(1007, "{1,2}"), json!("{1,2}"));
+	}
+}
@@ -0,0 +1,452 @@
+//! Rows as Apache Arrow record batches, written as Arrow IPC streams or Parquet files.
+//!
+//! Postgres types map to typed Arrow columns: integers, floats, `NUMERIC` with a known scale as
+//! `Decimal128(38, s)`, `TIMESTAMP[TZ]` as microsecond timestamps (`UTC` for `TIMESTAMPTZ`), `DATE`,
+//! `TIME`, `INTERVAL` and `BYTEA`. Other types, and `NUMERIC` without a known scale, are strings with
+//! their JSON representation, see [`to_json`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use arrow_array::builder::{
+	BinaryBuilder, BooleanBuilder, Date32Builder, Decimal128Builder, Float32Builder, Float64Builder,
+	Int16Builder, Int32Builder, Int64Builder, IntervalMonthDayNanoBuilder, StringBuilder, Time64MicrosecondBuilder,
+	TimestampMicrosecondBuilder, UInt32Builder,
+};
+use arrow_array::types::IntervalMonthDayNano;
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchOptions};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, IntervalUnit, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgColumn, PgRow, PgValueFormat, PgValueRef};
+use sqlx::{Column, Row, TypeInfo, ValueRef};
+use utility::errors::{AppError, AppResult, ErrorCode};
+use crate::pgrow::{fixed, take, to_json};
+
+/// Rows per record batch
+const BATCH_ROWS: usize = 8192;
+/// Rows per Parquet row group, which is held in memory until it is complete
+const ROW_GROUP_ROWS: usize = 64 * 1024;
+/// `Decimal128` holds up to 38 digits
+const DECIMAL_PRECISION: u8 = 38;
+/// Microseconds and days between the Unix and Postgres epochs
+const PG_EPOCH_MICROS: i64 = 946_684_800_000_000;
+const PG_EPOCH_DAYS: i32 = 10_957;
+/// `TIMESTAMPTZ` values are sent in UTC
+const UTC: &str = "UTC";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarFormat {
+	/// Arrow IPC stream
+	Arrow,
+	/// Parquet file, Snappy compressed
+	Parquet,
+}
+
+/// Encoder of rows in a columnar format, the encoded bytes are collected with [`take`](Self::take)
+pub struct ColumnarWriter {
+	format: ColumnarFormat,
+	columns: Vec<ColumnBuilder>,
+	rows: usize,
+	/// Created with the first batch or at the end
+	writer: Option<Writer>,
+}
+
+enum Writer {
+	Arrow(StreamWriter<Vec<u8>>),
+	Parquet(ArrowWriter<Vec<u8>>),
+}
+
+struct ColumnBuilder {
+	name: String,
+	values: Values,
+}
+
+enum Values {
+	Boolean(BooleanBuilder),
+	Int16(Int16Builder),
+	Int32(Int32Builder),
+	Int64(Int64Builder),
+	UInt32(UInt32Builder),
+	Float32(Float32Builder),
+	Float64(Float64Builder),
+	/// Values with the scale of the column, values with more decimals fail rather than being rounded
+	Decimal(Decimal128Builder, i8),
+	Timestamp(TimestampMicrosecondBuilder),
+	TimestampTz(TimestampMicrosecondBuilder),
+	Date(Date32Builder),
+	Time(Time64MicrosecondBuilder),
+	Interval(IntervalMonthDayNanoBuilder),
+	Binary(BinaryBuilder),
+	Utf8(StringBuilder),
+}
+
+impl ColumnarWriter {
+	/// Writer of rows of `columns`, `numeric_scales` are the scales of the `NUMERIC` columns which
+	/// are decimals, by name; the scale of a column can't be read from its values, which have their own
+	pub fn new(format: ColumnarFormat, columns: &[PgColumn], numeric_scales: &HashMap<String, i8>) -> Self {
+		let columns = columns
+			.iter()
+			.map(|column| ColumnBuilder {
+				name: column.name().to_string(),
+				values: Values::new(format, column.type_info().name(), numeric_scales.get(column.name()).copied()),
+			})
+			.collect();
+		Self { format, columns, rows: 0, writer: None }
+	}
+
+	pub fn push(&mut self, row: &PgRow) -> AppResult<()> {
+		for (index, column) in self.columns.iter_mut().enumerate() {
+			let value = row.try_get_raw(index)?;
+			column.values
+				.push(value)
+				.map_err(|err| AppError::BadRequest { message: format!("column {:?}: {}", column.name, err) })?;
+		}
+		self.rows += 1;
+		if self.rows == BATCH_ROWS {
+			self.write_batch()?;
+		}
+		Ok(())
+	}
+
+	/// Number of encoded bytes not yet taken
+	pub fn pending(&mut self) -> usize {
+		match &mut self.writer {
+			Some(Writer::Arrow(writer)) => writer.get_mut().len(),
+			Some(Writer::Parquet(writer)) => writer.inner_mut().len(),
+			None => 0,
+		}
+	}
+
+	pub fn take(&mut self) -> Vec<u8> {
+		match &mut self.writer {
+			Some(Writer::Arrow(writer)) => std::mem::take(writer.get_mut()),
+			Some(Writer::Parquet(writer)) => std::mem::take(writer.inner_mut()),
+			None => Vec::new(),
+		}
+	}
+
+	/// Remaining bytes, with the end of stream marker or the Parquet footer
+	pub fn finish(mut self) -> AppResult<Vec<u8>> {
+		self.write_batch()?;
+		match self.writer {
+			Some(Writer::Arrow(writer)) => writer.into_inner().map_err(writer_error),
+			Some(Writer::Parquet(writer)) => writer.into_inner().map_err(writer_error),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	fn write_batch(&mut self) -> AppResult<()> {
+		if self.writer.is_none() {
+			let schema = self.schema();
+			self.writer = Some(match self.format {
+				ColumnarFormat::Arrow => Writer::Arrow(StreamWriter::try_new(Vec::new(), &schema).map_err(writer_error)?),
+				ColumnarFormat::Parquet => {
+					let properties = WriterProperties::builder()
+						.set_compression(Compression::SNAPPY)
+						.set_max_row_group_size(ROW_GROUP_ROWS)
+						.build();
+					Writer::Parquet(ArrowWriter::try_new(Vec::new(), schema, Some(properties)).map_err(writer_error)?)
+				}
+			});
+		}
+
+		if self.rows == 0 {
+			return Ok(());
+		}
+		let arrays = self.columns
+			.iter_mut()
+			.map(|column| {
+				column.values
+					.finish()
+					.map_err(|err| AppError::BadRequest { message: format!("column {:?}: {}", column.name, err) })
+			})
+			.collect::<AppResult<Vec<ArrayRef>>>()?;
+		let options = RecordBatchOptions::new().with_row_count(Some(self.rows));
+		self.rows = 0;
+		let batch = RecordBatch::try_new_with_options(self.schema(), arrays, &options).map_err(writer_error)?;
+		match self.writer.as_mut() {
+			Some(Writer::Arrow(writer)) => writer.write(&batch).map_err(writer_error),
+			Some(Writer::Parquet(writer)) => writer.write(&batch).map_err(writer_error),
+			None => Ok(()),
+		}
+	}
+
+	fn schema(&self) -> SchemaRef {
+		let fields: Vec<Field> = self.columns
+			.iter()
+			.map(|column| Field::new(column.name.clone(), column.values.data_type(), true))
+			.collect();
+		Arc::new(Schema::new(fields))
+	}
+}
+
+impl Values {
+	fn new(format: ColumnarFormat, type_name: &str, numeric_scale: Option<i8>) -> Self {
+		match type_name {
+			"BOOL" => Values::Boolean(BooleanBuilder::new()),
+			"INT2" => Values::Int16(Int16Builder::new()),
+			"INT4" => Values::Int32(Int32Builder::new()),
+			"INT8" => Values::Int64(Int64Builder::new()),
+			"OID" => Values::UInt32(UInt32Builder::new()),
+			"FLOAT4" => Values::Float32(Float32Builder::new()),
+			"FLOAT8" => Values::Float64(Float64Builder::new()),
+			"NUMERIC" => {
+				let builder = numeric_scale.map(|scale| (Decimal128Builder::new().with_precision_and_scale(DECIMAL_PRECISION, scale), scale));
+				match builder {
+					Some((Ok(builder), scale)) => Values::Decimal(builder, scale),
+					_ => Values::Utf8(StringBuilder::new()),
+				}
+			}
+			"TIMESTAMP" => Values::Timestamp(TimestampMicrosecondBuilder::new()),
+			"TIMESTAMPTZ" => Values::TimestampTz(TimestampMicrosecondBuilder::new().with_timezone(UTC)),
+			"DATE" => Values::Date(Date32Builder::new()),
+			"TIME" => Values::Time(Time64MicrosecondBuilder::new()),
+			// Parquet has no interval type with the precision of Postgres
+			"INTERVAL" if format == ColumnarFormat::Arrow => Values::Interval(IntervalMonthDayNanoBuilder::new()),
+			"BYTEA" => Values::Binary(BinaryBuilder::new()),
+			_ => Values::Utf8(StringBuilder::new()),
+		}
+	}
+
+	fn data_type(&self) -> DataType {
+		match self {
+			Values::Boolean(_) => DataType::Boolean,
+			Values::Int16(_) => DataType::Int16,
+			Values::Int32(_) => DataType::Int32,
+			Values::Int64(_) => DataType::Int64,
+			Values::UInt32(_) => DataType::UInt32,
+			Values::Float32(_) => DataType::Float32,
+			Values::Float64(_) => DataType::Float64,
+			Values::Decimal(_, scale) => DataType::Decimal128(DECIMAL_PRECISION, *scale),
+			Values::Timestamp(_) => DataType::Timestamp(TimeUnit::Microsecond, None),
+			Values::TimestampTz(_) => DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.into())),
+			Values::Date(_) => DataType::Date32,
+			Values::Time(_) => DataType::Time64(TimeUnit::Microsecond),
+			Values::Interval(_) => DataType::Interval(IntervalUnit::MonthDayNano),
+			Values::Binary(_) => DataType::Binary,
+			Values::Utf8(_) => DataType::Utf8,
+		}
+	}
+
+	fn push(&mut self, value: PgValueRef) -> Result<(), BoxDynError> {
+		if value.is_null() {
+			self.push_null();
+			return Ok(());
+		}
+		if let Values::Utf8(builder) = self {
+			match to_json(value)? {
+				Value::Null => builder.append_null(),
+				Value::String(text) => builder.append_value(text),
+				value => builder.append_value(value.to_string()),
+			}
+			return Ok(());
+		}
+		// Values of prepared statements are in the binary format
+		if value.format() == PgValueFormat::Text {
+			return Err("values in the text format are not supported".into());
+		}
+
+		let bytes = value.as_bytes()?;
+		match self {
+			Values::Boolean(builder) => builder.append_value(fixed::<1>(bytes)?[0] != 0),
+			Values::Int16(builder) => builder.append_value(i16::from_be_bytes(fixed(bytes)?)),
+			Values::Int32(builder) => builder.append_value(i32::from_be_bytes(fixed(bytes)?)),
+			Values::Int64(builder) => builder.append_value(i64::from_be_bytes(fixed(bytes)?)),
+			Values::UInt32(builder) => builder.append_value(u32::from_be_bytes(fixed(bytes)?)),
+			Values::Float32(builder) => builder.append_value(f32::from_be_bytes(fixed(bytes)?)),
+			Values::Float64(builder) => builder.append_value(f64::from_be_bytes(fixed(bytes)?)),
+			Values::Decimal(builder, scale) => {
+				let (value, from) = numeric_to_i128(bytes)?;
+				builder.append_value(rescale(value, from, *scale)?);
+			}
+			// Infinite timestamps and dates have no Arrow equivalent
+			Values::Timestamp(builder) | Values::TimestampTz(builder) => match i64::from_be_bytes(fixed(bytes)?) {
+				i64::MAX | i64::MIN => builder.append_null(),
+				micros => builder.append_value(micros.checked_add(PG_EPOCH_MICROS).ok_or("timestamp out of range")?),
+			},
+			Values::Date(builder) => match i32::from_be_bytes(fixed(bytes)?) {
+				i32::MAX | i32::MIN => builder.append_null(),
+				days => builder.append_value(days.checked_add(PG_EPOCH_DAYS).ok_or("date out of range")?),
+			},
+			Values::Time(builder) => builder.append_value(i64::from_be_bytes(fixed(bytes)?)),
+			Values::Interval(builder) => {
+				let mut buf = bytes;
+				let micros = i64::from_be_bytes(take(&mut buf)?);
+				let days = i32::from_be_bytes(take(&mut buf)?);
+				let months = i32::from_be_bytes(take(&mut buf)?);
+				let nanos = micros.checked_mul(1_000).ok_or("interval out of range")?;
+				builder.append_value(IntervalMonthDayNano::new(months, days, nanos));
+			}
+			Values::Binary(builder) => builder.append_value(bytes),
+			Values::Utf8(_) => {}
+		}
+		Ok(())
+	}
+
+	fn push_null(&mut self) {
+		match self {
+			Values::Boolean(builder) => builder.append_null(),
+			Values::Int16(builder) => builder.append_null(),
+			Values::Int32(builder) => builder.append_null(),
+			Values::Int64(builder) => builder.append_null(),
+			Values::UInt32(builder) => builder.append_null(),
+			Values::Float32(builder) => builder.append_null(),
+			Values::Float64(builder) => builder.append_null(),
+			Values::Decimal(builder, _) => builder.append_null(),
+			Values::Timestamp(builder) | Values::TimestampTz(builder) => builder.append_null(),
+			Values::Date(builder) => builder.append_null(),
+			Values::Time(builder) => builder.append_null(),
+			Values::Interval(builder) => builder.append_null(),
+			Values::Binary(builder) => builder.append_null(),
+			Values::Utf8(builder) => builder.append_null(),
+		}
+	}
+
+	/// Array of the pushed values, the builder is reset
+	fn finish(&mut self) -> Result<ArrayRef, BoxDynError> {
+		let array: ArrayRef = match self {
+			Values::Boolean(builder) => Arc::new(builder.finish()),
+			Values::Int16(builder) => Arc::new(builder.finish()),
+			Values::Int32(builder) => Arc::new(builder.finish()),
+			Values::Int64(builder) => Arc::new(builder.finish()),
+			Values::UInt32(builder) => Arc::new(builder.finish()),
+			Values::Float32(builder) => Arc::new(builder.finish()),
+			Values::Float64(builder) => Arc::new(builder.finish()),
+			Values::Decimal(builder, _) => Arc::new(builder.finish()),
+			Values::Timestamp(builder) | Values::TimestampTz(builder) => Arc::new(builder.finish()),
+			Values::Date(builder) => Arc::new(builder.finish()),
+			Values::Time(builder) => Arc::new(builder.finish()),
+			Values::Interval(builder) => Arc::new(builder.finish()),
+			Values::Binary(builder) => Arc::new(builder.finish()),
+			Values::Utf8(builder) => Arc::new(builder.finish()),
+		};
+		Ok(array)
+	}
+}
+
+/// Unscaled value and scale of a binary `NUMERIC`, `NaN` and infinities have no decimal equivalent
+fn numeric_to_i128(mut buf: &[u8]) -> Result<(i128, i8), BoxDynError> {
+	let ndigits = i16::from_be_bytes(take(&mut buf)?);
+	let weight = i16::from_be_bytes(take(&mut buf)?) as i64;
+	let sign = u16::from_be_bytes(take(&mut buf)?);
+	let scale = u16::from_be_bytes(take(&mut buf)?) as i64;
+	let mut digits = Vec::with_capacity(ndigits.max(0) as usize);
+	for _ in 0..ndigits {
+		digits.push(i16::from_be_bytes(take(&mut buf)?) as i128);
+	}
+	let negative = match sign {
+		0x0000 => false,
+		0x4000 => true,
+		0xC000 | 0xD000 | 0xF000 => return Err("NaN and infinite numeric values have no decimal equivalent, cast the column to float8 or text".into()),
+		sign => return Err(format!("invalid NUMERIC sign {:#x}", sign).into()),
+	};
+	let out_of_range = || format!("numeric value out of range of decimal({}, {})", DECIMAL_PRECISION, scale);
+	if scale > DECIMAL_PRECISION as i64 {
+		return Err(out_of_range().into());
+	}
+
+	// Base 10000 digits, from 10000^weight down to the last one of the scale
+	let groups = (scale + 3) / 4;
+	let mut value: i128 = 0;
+	for position in (-groups..=weight.max(0)).rev() {
+		let index = weight - position;
+		let digit = match index >= 0 && index < digits.len() as i64 {
+			true => digits[index as usize],
+			false => 0,
+		};
+		value = value.checked_mul(10_000).and_then(|value| value.checked_add(digit)).ok_or_else(out_of_range)?;
+	}
+	// Digits after the scale are zeros
+	value /= 10_i128.pow((groups * 4 - scale) as u32);
+	if value.unsigned_abs() >= 10_u128.pow(DECIMAL_PRECISION as u32) {
+		return Err(out_of_range().into());
+	}
+	Ok((if negative { -value } else { value }, scale as i8))
+}
+
+/// `value` with `from` decimals as a value with `to` decimals, dropped decimals must be zeros
+fn rescale(value: i128, from: i8, to: i8) -> Result<i128, BoxDynError> {
+	let out_of_range = || format!("numeric value out of range of decimal({}, {})", DECIMAL_PRECISION, to);
+	if from <= to {
+		let value = 10_i128
+			.checked_pow((to - from) as u32)
+			.and_then(|factor| value.checked_mul(factor))
+			.ok_or_else(out_of_range)?;
+		return match value.unsigned_abs() < 10_u128.pow(DECIMAL_PRECISION as u32) {
+			true => Ok(value),
+			false => Err(out_of_range().into()),
+		};
+	}
+	let factor = 10_i128.pow((from - to) as u32);
+	match value % factor {
+		0 => Ok(value / factor),
+		_ => Err(format!(
+			"value with {} decimals exceeds the scale {} of the column",
+			from, to
+		).into()),
+	}
+}
+
+fn writer_error(err: impl std::fmt::Display) -> AppError {
+	tracing::error!("[E100] columnar writer: {}", err);
+	AppError::InternalError { code: ErrorCode::E100, message: err.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Binary `NUMERIC` of base 10000 `digits`
+	fn numeric(weight: i16, negative: bool, scale: u16, digits: &[i16]) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		bytes.extend((digits.len() as i16).to_be_bytes());
+		bytes.extend(weight.to_be_bytes());
+		bytes.extend((if negative { 0x4000u16 } else { 0 }).to_be_bytes());
+		bytes.extend(scale.to_be_bytes());
+		for digit in digits {
+			bytes.extend(digit.to_be_bytes());
+		}
+		bytes
+	}
+
+	#[test]
+	fn test_numeric() {
+		// 12345.678
+		assert_eq!(numeric_to_i128(&numeric(1, false, 3, &[1, 2345, 6780])).unwrap(), (12_345_678, 3));
+		// -0.05
+		assert_eq!(numeric_to_i128(&numeric(-1, true, 2, &[500])).unwrap(), (-5, 2));
+		// 10000000 with no digit after the weight
+		assert_eq!(numeric_to_i128(&numeric(1, false, 0, &[1000])).unwrap(), (10_000_000, 0));
+		// 0
+		assert_eq!(numeric_to_i128(&numeric(0, false, 1, &[])).unwrap(), (0, 1));
+		// NaN
+		assert!(numeric_to_i128(&[0, 0, 0, 0, 0xC0, 0, 0, 0]).is_err());
+		// 10^40
+		assert!(numeric_to_i128(&numeric(10, false, 0, &[1])).is_err());
+	}
+
+	#[test]
+	fn test_numeric_types() {
+		let data_type = |scale| Values::new(ColumnarFormat::Parquet, "NUMERIC", scale).data_type();
+		assert_eq!(data_type(Some(2)), DataType::Decimal128(DECIMAL_PRECISION, 2));
+		assert_eq!(data_type(Some(-3)), DataType::Decimal128(DECIMAL_PRECISION, -3));
+		// Unconstrained numerics have no scale, their values are exact strings
+		assert_eq!(data_type(None), DataType::Utf8);
+		assert_eq!(data_type(Some(40)), DataType::Utf8);
+	}
+
+	#[test]
+	fn test_rescale() {
+		assert_eq!(rescale(12_345, 2, 4).unwrap(), 1_234_500);
+		assert_eq!(rescale(12_300, 3, 1).unwrap(), 123);
+		assert_eq!(rescale(-12_000, 3, 0).unwrap(), -12);
+		// Never rounded
+		assert!(rescale(12_345, 2, 1).is_err());
+		assert!(rescale(-12_355, 3, 1).is_err());
+		assert!(rescale(i128::MAX / 10, 0, 5).is_err());
+	}
+}
@@ -0,0 +1,163 @@
+//! Databases of the server: listing with sizes, creation, clones from a template and removal.
+//!
+//! Databases are created and dropped on a connection of their own to the `postgres` maintenance
+//! database: Postgres only copies a template, and only drops a database, that nobody uses.
+
+use serde::Serialize;
+use sqlx::postgres::PgConnection;
+use sqlx::{Connection, Executor, FromRow, Postgres};
+use utoipa::ToSchema;
+use utility::errors::{AppError, AppResult};
+use crate::ops::statement_error;
+use crate::setup::PgDb;
+
+/// Databases which are never dropped
+const PROTECTED: [&str; 3] = ["postgres", "template0", "template1"];
+/// Database connected to for creating and dropping the others, `template1` when cloning it
+const MAINTENANCE: [&str; 2] = ["postgres", "template1"];
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct DatabaseInfo {
+	pub name: String,
+	pub owner: String,
+	pub encoding: String,
+	/// `None` without the `CONNECT` privilege
+	pub size_bytes: Option<i64>,
+	/// Size for humans, e.g. `8249 kB`
+	pub size: Option<String>,
+	/// Number of open sessions
+	pub connections: i64,
+	pub is_template: bool,
+	pub allow_connections: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+	/// Database copied into the new one
+	pub template: Option<String>,
+	/// Terminate the sessions of the template first, Postgres only copies a database nobody uses.
+	/// Refused for the database of `pg` while its pool is open
+	pub terminate_connections: bool,
+}
+
+#[tracing::instrument(skip(executor), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn list<'e, E: Executor<'e, Database = Postgres>>(executor: E) -> AppResult<Vec<DatabaseInfo>> {
+	Ok(sqlx::query_as::<_, DatabaseInfo>(r#"
+		SELECT
+			d.datname AS name,
+			pg_get_userbyid(d.datdba) AS owner,
+			pg_encoding_to_char(d.encoding) AS encoding,
+			CASE WHEN has_database_privilege(d.oid, 'CONNECT') THEN pg_database_size(d.oid) END AS size_bytes,
+			CASE WHEN has_database_privilege(d.oid, 'CONNECT') THEN pg_size_pretty(pg_database_size(d.oid)) END AS size,
+			(SELECT count(*) FROM pg_stat_activity a WHERE a.datid = d.oid) AS connections,
+			d.datistemplate AS is_template,
+			d.datallowconn AS allow_connections
+		FROM pg_database d
+		ORDER BY d.datname;
+	"#)
+		.fetch_all(executor)
+		.await?)
+}
+
+#[tracing::instrument(skip(executor), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn get<'e, E: Executor<'e, Database = Postgres>>(executor: E, name: &str) -> AppResult<DatabaseInfo> {
+	list(executor)
+		.await?
+		.into_iter()
+		.find(|database| database.name == name)
+		.ok_or_else(|| AppError::NotFound { message: format!("database {:?} not found", name) })
+}
+
+/// Create the database `name`, a copy of `options.template` when given
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn create(pg: &PgDb, name: &str, options: &CreateOptions) -> AppResult<DatabaseInfo> {
+	check_name(name)?;
+	let mut conn = maintenance(pg, options.template.as_deref().unwrap_or_default()).await?;
+	let mut sql = format!("CREATE DATABASE {}", quote(name));
+	if let Some(template) = &options.template {
+		check_name(template)?;
+		get(&mut conn, template).await?;
+		if options.terminate_connections {
+			if template == &current_database(pg) && !pg.is_closed() {
+				return Err(AppError::BadRequest {
+					message: format!("the sessions of {:?} are the server's own, stop the server to clone its database", template),
+				});
+			}
+			terminate_connections(&mut conn, template).await?;
+		}
+		sql.push_str(&format!(" TEMPLATE {}", quote(template)));
+	}
+	tracing::warn!("{}", sql);
+	// CREATE DATABASE cannot run in a transaction, nor be prepared
+	conn.execute(sql.as_str()).await.map_err(statement_error)?;
+	get(&mut conn, name).await
+}
+
+/// Drop the database `name`, `force` terminates its sessions
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn drop(pg: &PgDb, name: &str, force: bool) -> AppResult<()> {
+	if PROTECTED.contains(&name) || name == current_database(pg) {
+		return Err(AppError::BadRequest { message: format!("database {:?} cannot be dropped", name) });
+	}
+	let mut conn = maintenance(pg, name).await?;
+	get(&mut conn, name).await?;
+
+	let sql = match force {
+		true => format!("DROP DATABASE {} WITH (FORCE)", quote(name)),
+		false => format!("DROP DATABASE {}", quote(name)),
+	};
+	tracing::warn!("{}", sql);
+	conn.execute(sql.as_str()).await.map_err(statement_error)?;
+	Ok(())
+}
+
+/// Connection to a maintenance database other than `busy`, with the credentials of `pg`
+async fn maintenance(pg: &PgDb, busy: &str) -> AppResult<PgConnection> {
+	let database = MAINTENANCE.into_iter().find(|database| *database != busy).unwrap_or_default();
+	let options = pg.connect_options().as_ref().clone().database(database);
+	Ok(PgConnection::connect_with(&options).await?)
+}
+
+/// Database of the connections of `pg`, the user name by default
+fn current_database(pg: &PgDb) -> String {
+	let options = pg.connect_options();
+	options.get_database().unwrap_or(options.get_username()).to_string()
+}
+
+async fn terminate_connections(conn: &mut PgConnection, name: &str) -> AppResult<()> {
+	sqlx::query(r#"
+		SELECT pg_terminate_backend(pid)
+		FROM pg_stat_activity
+		WHERE datname = $1 AND pid <> pg_backend_pid();
+	"#)
+		.bind(name)
+		.execute(conn)
+		.await?;
+	Ok(())
+}
+
+/// Postgres truncates identifiers to 63 bytes, a longer name would not be the created one
+fn check_name(name: &str) -> AppResult<()> {
+	match !name.is_empty() && name.len() <= 63 && !name.contains('\0') {
+		true => Ok(()),
+		false => Err(AppError::BadRequest { message: format!("invalid database name {:?}", name) }),
+	}
+}
+
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_name() {
+		assert!(check_name("preview_42").is_ok());
+		assert!(check_name("Preview \"copy\"").is_ok());
+		assert!(check_name("").is_err());
+		assert!(check_name(&"x".repeat(64)).is_err());
+		assert_eq!(quote("a\"b"), "\"a\"\"b\"");
+	}
+}
@@ -1,4 +1,19 @@
 pub mod pgrow;
 pub mod ops;
 pub mod setup;
+pub mod slow;
 pub mod extension;
+pub mod audit;
+pub mod backup;
+pub mod cache;
+pub mod cdc;
+pub mod columnar;
+pub mod database;
+pub mod migrate;
+pub mod notify;
+pub mod params;
+pub mod pitr;
+pub mod rest;
+pub mod schema;
+pub mod stream;
+pub mod tx;
@@ -0,0 +1,382 @@
+//! Versioned SQL migrations.
+//!
+//! A migration is a pair of files `<version>_<name>.up.sql` and `<version>_<name>.down.sql` in the
+//! migrations directory, versions are the creation timestamp (`YYYYMMDDHHMMSS`). Applied migrations
+//! are recorded in `qaswa.schema_migrations` with the SHA-256 checksum of their up script, so that
+//! scripts modified after being applied are detected.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, Executor, FromRow, PgConnection};
+use sqlx::types::chrono::{DateTime, Utc};
+use utility::errors::{AppError, AppResult};
+use crate::setup::PgDb;
+
+/// Key of the advisory lock held while migrating, so that two servers never migrate concurrently
+const LOCK_KEY: i64 = 0x7161_7377_615f_6d67;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+	pub version: i64,
+	pub name: String,
+	pub up: String,
+	pub down: Option<String>,
+	/// SHA-256 of the up script, hex encoded
+	pub checksum: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationState {
+	Applied,
+	Pending,
+	/// Applied, but its up script changed since
+	Modified,
+	/// Applied, but its files are gone
+	Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+	pub version: i64,
+	pub name: String,
+	pub state: MigrationState,
+	pub applied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct AppliedMigration {
+	version: i64,
+	name: String,
+	checksum: String,
+	applied_at: DateTime<Utc>,
+}
+
+pub struct Migrator {
+	dir: PathBuf,
+}
+
+impl Migrator {
+	pub fn new(dir: impl AsRef<Path>) -> Self {
+		Self { dir: dir.as_ref().to_path_buf() }
+	}
+
+	/// Migrations of the directory ordered by version, a missing directory has none
+	pub fn load(&self) -> AppResult<Vec<Migration>> {
+		if !self.dir.exists() {
+			return Ok(vec![]);
+		}
+		let mut migrations: HashMap<i64, Migration> = HashMap::new();
+		for entry in std::fs::read_dir(&self.dir)? {
+			let path = entry?.path();
+			let file = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+			let (version, name, is_up) = match parse_file_name(&file) {
+				Some(parsed) => parsed,
+				None => {
+					tracing::warn!("migrations: ignoring {:?}, expected <version>_<name>.up.sql or .down.sql", file);
+					continue;
+				}
+			};
+			let script = std::fs::read_to_string(&path)?;
+			let migration = migrations.entry(version).or_insert_with(|| Migration {
+				version,
+				name: name.clone(),
+				up: String::new(),
+				down: None,
+				checksum: String::new(),
+			});
+			if migration.name != name {
+				return Err(migration_error(format!("version {} is used by {:?} and {:?}", version, migration.name, name)));
+			}
+			match is_up {
+				true => {
+					migration.checksum = checksum(&script);
+					migration.up = script;
+				}
+				false => migration.down = Some(script),
+			}
+		}
+
+		let mut migrations: Vec<Migration> = migrations.into_values().collect();
+		if let Some(migration) = migrations.iter().find(|migration| migration.checksum.is_empty()) {
+			return Err(migration_error(format!("{}_{} has no up script", migration.version, migration.name)));
+		}
+		migrations.sort_by_key(|migration| migration.version);
+		Ok(migrations)
+	}
+
+	/// Create the up and down files of a new migration
+	pub fn create(&self, name: &str) -> AppResult<(PathBuf, PathBuf)> {
+		let name: String = name
+			.trim()
+			.to_lowercase()
+			.chars()
+			.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+			.collect();
+		if name.is_empty() {
+			return Err(migration_error("the migration name is empty".to_string()));
+		}
+		std::fs::create_dir_all(&self.dir)?;
+		let version = Utc::now().format("%Y%m%d%H%M%S");
+		let up = self.dir.join(format!("{}_{}.up.sql", version, name));
+		let down = self.dir.join(format!("{}_{}.down.sql", version, name));
+		if up.exists() || down.exists() {
+			return Err(migration_error(format!("{} already exists", up.display())));
+		}
+		std::fs::write(&up, format!("-- {}\n", name))?;
+		std::fs::write(&down, format!("-- Revert {}\n", name))?;
+		Ok((up, down))
+	}
+
+	/// State of the migrations of the directory and of the applied ones
+	pub async fn status(&self, pg: &PgDb) -> AppResult<Vec<MigrationStatus>> {
+		let migrations = self.load()?;
+		let mut conn = pg.acquire().await?;
+		let applied = applied(&mut conn).await?;
+		Ok(status(&migrations, &applied))
+	}
+
+	/// Migrations which are not applied yet, an error when an applied one was modified
+	pub async fn pending(&self, pg: &PgDb) -> AppResult<Vec<Migration>> {
+		let migrations = self.load()?;
+		let mut conn = pg.acquire().await?;
+		let applied = applied(&mut conn).await?;
+		pending(migrations, &applied)
+	}
+
+	/// Apply the pending migrations up to `target` included, or all of them
+	pub async fn up(&self, pg: &PgDb, target: Option<i64>) -> AppResult<Vec<Migration>> {
+		let migrations = self.load()?;
+		let mut conn = pg.acquire().await?;
+		lock(&mut conn).await?;
+		let result = async {
+			let applied = applied(&mut conn).await?;
+			let pending: Vec<Migration> = pending(migrations, &applied)?
+				.into_iter()
+				.filter(|migration| target.map(|target| migration.version <= target).unwrap_or(true))
+				.collect();
+			for migration in &pending {
+				tracing::info!("migrations: applying {}_{}", migration.version, migration.name);
+				let start = Instant::now();
+				let mut tx = conn.begin().await?;
+				tx.execute(migration.up.as_str()).await.map_err(|err| failed(migration, err))?;
+				sqlx::query("INSERT INTO qaswa.schema_migrations (version, name, checksum, duration_ms) VALUES ($1, $2, $3, $4);")
+					.bind(migration.version)
+					.bind(&migration.name)
+					.bind(&migration.checksum)
+					.bind(start.elapsed().as_secs_f64() * 1000.0)
+					.execute(&mut *tx)
+					.await?;
+				tx.commit().await.map_err(|err| failed(migration, err))?;
+			}
+			Ok(pending)
+		}.await;
+		unlock(&mut conn).await?;
+		result
+	}
+
+	/// Revert the `steps` last applied migrations, with their down scripts
+	pub async fn down(&self, pg: &PgDb, steps: usize) -> AppResult<Vec<Migration>> {
+		let migrations = self.load()?;
+		let mut conn = pg.acquire().await?;
+		lock(&mut conn).await?;
+		let result = async {
+			let applied = applied(&mut conn).await?;
+			let mut reverted = vec![];
+			for applied in applied.iter().rev().take(steps) {
+				let migration = migrations
+					.iter()
+					.find(|migration| migration.version == applied.version)
+					.ok_or_else(|| migration_error(format!("{}_{} has no files", applied.version, applied.name)))?;
+				let down = migration
+					.down
+					.as_deref()
+					.ok_or_else(|| migration_error(format!("{}_{} has no down script", migration.version, migration.name)))?;
+				tracing::info!("migrations: reverting {}_{}", migration.version, migration.name);
+				let mut tx = conn.begin().await?;
+				tx.execute(down).await.map_err(|err| failed(migration, err))?;
+				sqlx::query("DELETE FROM qaswa.schema_migrations WHERE version = $1;")
+					.bind(migration.version)
+					.execute(&mut *tx)
+					.await?;
+				tx.commit().await.map_err(|err| failed(migration, err))?;
+				reverted.push(migration.clone());
+			}
+			Ok(reverted)
+		}.await;
+		unlock(&mut conn).await?;
+		result
+	}
+}
+
+/// `(version, name, is_up)` of `<version>_<name>.up.sql` and `<version>_<name>.down.sql`
+fn parse_file_name(file: &str) -> Option<(i64, String, bool)> {
+	let (stem, is_up) = match file.strip_suffix(".up.sql") {
+		Some(stem) => (stem, true),
+		None => (file.strip_suffix(".down.sql")?, false),
+	};
+	let (version, name) = stem.split_once('_')?;
+	match name.is_empty() {
+		true => None,
+		false => Some((version.parse().ok()?, name.to_string(), is_up)),
+	}
+}
+
+fn checksum(script: &str) -> String {
+	Sha256::digest(script.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn status(migrations: &[Migration], applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+	let mut status: Vec<MigrationStatus> = migrations
+		.iter()
+		.map(|migration| {
+			let applied = applied.iter().find(|applied| applied.version == migration.version);
+			MigrationStatus {
+				version: migration.version,
+				name: migration.name.clone(),
+				state: match applied {
+					Some(applied) if applied.checksum != migration.checksum => MigrationState::Modified,
+					Some(_) => MigrationState::Applied,
+					None => MigrationState::Pending,
+				},
+				applied_at: applied.map(|applied| applied.applied_at),
+			}
+		})
+		.collect();
+	for applied in applied {
+		if !migrations.iter().any(|migration| migration.version == applied.version) {
+			status.push(MigrationStatus {
+				version: applied.version,
+				name: applied.name.clone(),
+				state: MigrationState::Missing,
+				applied_at: Some(applied.applied_at),
+			});
+		}
+	}
+	status.sort_by_key(|status| status.version);
+	status
+}
+
+fn pending(migrations: Vec<Migration>, applied: &[AppliedMigration]) -> AppResult<Vec<Migration>> {
+	let status = status(&migrations, applied);
+	if let Some(modified) = status.iter().find(|status| status.state == MigrationState::Modified) {
+		return Err(migration_error(format!(
+			"{}_{} was modified after being applied, add a new migration instead",
+			modified.version, modified.name,
+		)));
+	}
+	for missing in status.iter().filter(|status| status.state == MigrationState::Missing) {
+		tracing::warn!("migrations: {}_{} is applied but its files are missing", missing.version, missing.name);
+	}
+	Ok(migrations
+		.into_iter()
+		.filter(|migration| !applied.iter().any(|applied| applied.version == migration.version))
+		.collect())
+}
+
+/// Applied migrations ordered by version, the tracking table is created when missing
+async fn applied(conn: &mut PgConnection) -> AppResult<Vec<AppliedMigration>> {
+	conn.execute(r#"
+		CREATE SCHEMA IF NOT EXISTS qaswa;
+		CREATE TABLE IF NOT EXISTS qaswa.schema_migrations (
+			version BIGINT PRIMARY KEY,
+			name TEXT NOT NULL,
+			checksum TEXT NOT NULL,
+			applied_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+			duration_ms DOUBLE PRECISION NOT NULL
+		);
+	"#).await?;
+	let applied = sqlx::query_as::<_, AppliedMigration>(
+		"SELECT version, name, checksum, applied_at FROM qaswa.schema_migrations ORDER BY version;"
+	)
+		.fetch_all(conn)
+		.await?;
+	Ok(applied)
+}
+
+async fn lock(conn: &mut PgConnection) -> AppResult<()> {
+	sqlx::query("SELECT pg_advisory_lock($1);").bind(LOCK_KEY).execute(conn).await?;
+	Ok(())
+}
+
+async fn unlock(conn: &mut PgConnection) -> AppResult<()> {
+	sqlx::query("SELECT pg_advisory_unlock($1);").bind(LOCK_KEY).execute(conn).await?;
+	Ok(())
+}
+
+fn failed(migration: &Migration, err: sqlx::Error) -> AppError {
+	let message = match &err {
+		sqlx::Error::Database(err) => err.message().to_string(),
+		err => err.to_string(),
+	};
+	migration_error(format!("{}_{}: {}", migration.version, migration.name, message))
+}
+
+fn migration_error(message: String) -> AppError {
+	AppError::MigrationError { message }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn migration(version: i64, up: &str) -> Migration {
+		Migration { version, name: format!("m{}", version), up: up.to_string(), down: None, checksum: checksum(up) }
+	}
+
+	fn applied(version: i64, up: &str) -> AppliedMigration {
+		AppliedMigration { version, name: format!("m{}", version), checksum: checksum(up), applied_at: Utc::now() }
+	}
+
+	#[test]
+	fn test_parse_file_name() {
+		assert_eq!(parse_file_name("20230901120000_create_users.up.sql"), Some((20230901120000, "create_users".to_string(), true)));
+		assert_eq!(parse_file_name("1_a.down.sql"), Some((1, "a".to_string(), false)));
+		assert_eq!(parse_file_name("1_.up.sql"), None);
+		assert_eq!(parse_file_name("v1_a.up.sql"), None);
+		assert_eq!(parse_file_name("1_a.sql"), None);
+	}
+
+	#[test]
+	fn test_load_and_create() {
+		let dir = std::env::temp_dir().join(format!("qaswa-migrations-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		let migrator = Migrator::new(&dir);
+		assert_eq!(migrator.load().unwrap(), vec![]);
+
+		let (up, down) = migrator.create("Create users!").unwrap();
+		assert!(up.to_str().unwrap().ends_with("_create_users_.up.sql"));
+		assert!(down.exists());
+		std::fs::write(dir.join("1_first.up.sql"), "CREATE TABLE a ();").unwrap();
+		std::fs::write(dir.join("README.md"), "").unwrap();
+
+		let migrations = migrator.load().unwrap();
+		assert_eq!(migrations.len(), 2);
+		assert_eq!((migrations[0].version, migrations[0].down.is_none()), (1, true));
+		assert_eq!(migrations[1].name, "create_users_");
+
+		std::fs::write(dir.join("2_second.down.sql"), "").unwrap();
+		assert!(matches!(migrator.load(), Err(AppError::MigrationError { .. })));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_status_and_pending() {
+		let migrations = vec![migration(1, "a"), migration(2, "b"), migration(3, "c")];
+		let status = status(&migrations, &[applied(1, "a"), applied(2, "changed"), applied(0, "gone")]);
+		let states: Vec<(i64, MigrationState)> = status.iter().map(|status| (status.version, status.state)).collect();
+		assert_eq!(states, vec![
+			(0, MigrationState::Missing),
+			(1, MigrationState::Applied),
+			(2, MigrationState::Modified),
+			(3, MigrationState::Pending),
+		]);
+
+		assert!(matches!(pending(migrations.clone(), &[applied(2, "changed")]), Err(AppError::MigrationError { .. })));
+		let pending = pending(migrations, &[applied(2, "b"), applied(0, "gone")]).unwrap();
+		assert_eq!(pending.iter().map(|migration| migration.version).collect::<Vec<i64>>(), vec![1, 3]);
+	}
+}
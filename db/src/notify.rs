@@ -0,0 +1,570 @@
+//! Changes of the tables published to realtime clients.
+//!
+//! A row trigger on each published table sends the changed row as JSON on the `qaswa_changes`
+//! channel, and a dedicated connection listens to it. Notifications are limited to 8000 bytes:
+//! larger rows are replaced by their primary key and flagged as truncated.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgListener;
+use sqlx::Row;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use utility::errors::{AppError, AppResult};
+use crate::ops::{statement_error, ExecOptions};
+use crate::rest::catalog::CatalogCache;
+use crate::rest::query::{parse_filter, Filter, FilterValue, IsValue, Operator, Page};
+use crate::setup::PgDb;
+
+/// Channel of the notifications
+pub const CHANNEL: &str = "qaswa_changes";
+/// Name of the trigger on the published tables
+const TRIGGER: &str = "qaswa_notify";
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+/// Keys of rows remembered for each subscriber, the oldest ones are forgotten
+const SEEN_KEYS_MAX: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RealtimeTable {
+	pub schema: String,
+	pub table: String,
+	pub created_at: DateTime<Utc>,
+}
+
+/// Row inserted, updated or deleted in a published table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Change {
+	pub schema: String,
+	pub table: String,
+	/// `INSERT`, `UPDATE` or `DELETE`
+	pub op: String,
+	/// New row, `null` for deletes
+	#[schema(value_type = Option<Object>)]
+	pub row: Option<Value>,
+	/// Previous row, `null` for inserts
+	#[schema(value_type = Option<Object>)]
+	pub old: Option<Value>,
+	/// `row` and `old` only hold their primary key, because the rows did not fit in a notification;
+	/// or only `old` does, because the previous row is not read again for the user
+	#[serde(default)]
+	pub truncated: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Notification {
+	Change(Arc<Change>),
+	/// The listening connection was lost then opened again, the changes made meanwhile are missed
+	Reconnected,
+}
+
+/// Changes of a subscriber: of one table or of all of them, and matching filters such as
+/// `status=eq.paid`, with the operators of the REST API
+#[derive(Debug, Clone, Default)]
+pub struct Subscription {
+	/// Schema and name of the table, `None` for all the published tables
+	pub table: Option<(String, String)>,
+	pub filters: Vec<Filter>,
+}
+
+impl Subscription {
+	/// Subscription to `table` (`name` in the `public` schema, or `schema.name`) and to the rows
+	/// matching the `column=operator.value` pairs
+	pub fn parse(table: Option<&str>, pairs: &[(String, String)]) -> Result<Self, String> {
+		let table = table.map(|table| match table.split_once('.') {
+			Some((schema, name)) => (schema.to_string(), name.to_string()),
+			None => (crate::rest::SCHEMA.to_string(), table.to_string()),
+		});
+		let filters = pairs.iter().map(|(column, value)| parse_filter(column, value)).collect::<Result<_, _>>()?;
+		Ok(Self { table, filters })
+	}
+
+	/// Whether the new row, or the previous one for deletes, matches every filter. Truncated
+	/// changes match when their primary key does
+	pub fn matches(&self, change: &Change) -> bool {
+		if !self.covers(change) {
+			return false;
+		}
+		let row = match change.row.as_ref().or(change.old.as_ref()) {
+			Some(row) => row,
+			None => return self.filters.is_empty(),
+		};
+		self.filters.iter().all(|filter| match change.truncated && row.get(&filter.column).is_none() {
+			true => true,
+			false => filter_matches(filter, row),
+		})
+	}
+
+	/// Whether the change is of the subscribed table
+	fn covers(&self, change: &Change) -> bool {
+		match &self.table {
+			Some((schema, table)) => &change.schema == schema && &change.table == table,
+			None => true,
+		}
+	}
+}
+
+/// Primary keys of the rows sent to a subscriber, at most `SEEN_KEYS_MAX`. Whether the user could
+/// read a deleted row is unknown, so deletes are only sent for the rows the subscriber was sent
+#[derive(Debug, Default)]
+pub struct Seen {
+	/// Table and key, with the number of their insertion
+	keys: HashMap<(String, String), u64>,
+	/// Insertions from the oldest, those of removed keys are skipped when forgotten
+	order: VecDeque<(u64, (String, String))>,
+	inserted: u64,
+}
+
+impl Seen {
+	fn insert(&mut self, table: &str, key: &Value) {
+		let entry = (table.to_string(), key.to_string());
+		if self.keys.contains_key(&entry) {
+			return;
+		}
+		self.inserted += 1;
+		self.keys.insert(entry.clone(), self.inserted);
+		self.order.push_back((self.inserted, entry));
+		while self.order.len() > SEEN_KEYS_MAX {
+			if let Some((inserted, oldest)) = self.order.pop_front() {
+				if self.keys.get(&oldest) == Some(&inserted) {
+					self.keys.remove(&oldest);
+				}
+			}
+		}
+	}
+
+	fn contains(&self, table: &str, key: &Value) -> bool {
+		self.keys.contains_key(&(table.to_string(), key.to_string()))
+	}
+
+	fn remove(&mut self, table: &str, key: &Value) {
+		self.keys.remove(&(table.to_string(), key.to_string()));
+	}
+}
+
+/// `change` as the user of `options` sees it, when it matches `subscription`.
+///
+/// The changed row is read again through the REST API so that the grants and the row-level
+/// security policies of the user's role apply: the changes of hidden rows, and of the
+/// tables outside the REST API schema or without primary key, are dropped. The previous and the
+/// deleted rows can't be read again, they only keep their primary key; deletes are only sent
+/// for the rows in `seen`, which records the rows sent.
+pub async fn visible(
+	pg: &PgDb,
+	catalog: &CatalogCache,
+	subscription: &Subscription,
+	change: &Change,
+	options: &ExecOptions,
+	seen: &mut Seen,
+) -> AppResult<Option<Change>> {
+	options.user_identity()?;
+	// Filters are not evaluated on the previous rows, which the user may not see
+	let prefiltered = match change.row {
+		Some(_) => subscription.matches(change),
+		None => subscription.covers(change),
+	};
+	if !prefiltered || change.schema != crate::rest::SCHEMA {
+		return Ok(None);
+	}
+	let key = match catalog.get(pg).await?.tables.get(&change.table) {
+		Some(table) if !table.primary_key.is_empty() => table.primary_key.clone(),
+		_ => return Ok(None),
+	};
+	let key_of = |row: &Value| Value::Object(key.iter().map(|column| (column.clone(), row.get(column).cloned().unwrap_or(Value::Null))).collect());
+	let old = change.old.as_ref().map(key_of);
+	let row = match &change.row {
+		Some(row) => match crate::rest::read_keys(pg, catalog, &change.table, &key, &[key_of(row)], Page::default(), options).await?.into_iter().next() {
+			Some(row) => Some(row),
+			None => return Ok(None),
+		},
+		None if old.as_ref().is_some_and(|old| seen.contains(&change.table, old)) => None,
+		None => return Ok(None),
+	};
+	let visible = Change { row, old, truncated: change.old.is_some(), ..change.clone() };
+	if !subscription.matches(&visible) {
+		return Ok(None);
+	}
+	let new = visible.row.as_ref().map(key_of);
+	if let Some(old) = visible.old.as_ref().filter(|old| Some(*old) != new.as_ref()) {
+		seen.remove(&change.table, old);
+	}
+	if let Some(new) = &new {
+		seen.insert(&change.table, new);
+	}
+	Ok(Some(visible))
+}
+
+/// Evaluate `filter` on a row like Postgres would, comparisons with `NULL` never match
+fn filter_matches(filter: &Filter, row: &Value) -> bool {
+	let value = row.get(&filter.column).unwrap_or(&Value::Null);
+	let matched = match (&filter.operator, &filter.value) {
+		(Operator::Is, FilterValue::Is(is)) => Some(match is {
+			IsValue::Null | IsValue::Unknown => value.is_null(),
+			IsValue::True => value == &Value::Bool(true),
+			IsValue::False => value == &Value::Bool(false),
+		}),
+		_ if value.is_null() => None,
+		(Operator::In, FilterValue::List(items)) => Some(items.iter().any(|item| compare(value, item) == Some(Ordering::Equal))),
+		(Operator::Like, FilterValue::Single(pattern)) => Some(like(&text(value), pattern)),
+		(Operator::Ilike, FilterValue::Single(pattern)) => Some(like(&text(value).to_lowercase(), &pattern.to_lowercase())),
+		(operator, FilterValue::Single(operand)) => compare(value, operand).map(|ordering| match operator {
+			Operator::Eq => ordering == Ordering::Equal,
+			Operator::Neq => ordering != Ordering::Equal,
+			Operator::Lt => ordering == Ordering::Less,
+			Operator::Lte => ordering != Ordering::Greater,
+			Operator::Gt => ordering == Ordering::Greater,
+			Operator::Gte => ordering != Ordering::Less,
+			_ => false,
+		}),
+		_ => None,
+	};
+	matched.is_some_and(|matched| matched != filter.negated)
+}
+
+/// Order of a JSON value and of the text of a filter, `None` when they are not comparable
+fn compare(value: &Value, operand: &str) -> Option<Ordering> {
+	match value {
+		Value::Number(number) => number.as_f64()?.partial_cmp(&operand.parse::<f64>().ok()?),
+		Value::Bool(value) => Some(value.cmp(&match operand {
+			"true" | "t" => true,
+			"false" | "f" => false,
+			_ => return None,
+		})),
+		Value::String(value) => Some(value.as_str().cmp(operand)),
+		_ => None,
+	}
+}
+
+fn text(value: &Value) -> String {
+	match value {
+		Value::String(value) => value.clone(),
+		value => value.to_string(),
+	}
+}
+
+/// `LIKE` matching, `%` matches any text and `_` any character
+fn like(text: &str, pattern: &str) -> bool {
+	let text: Vec<char> = text.chars().collect();
+	let pattern: Vec<char> = pattern.chars().collect();
+	// Positions of the pattern reachable after each character of the text
+	let mut reachable = vec![false; pattern.len() + 1];
+	reachable[0] = true;
+	for i in 0..pattern.len() {
+		reachable[i + 1] = reachable[i] && pattern[i] == '%';
+	}
+	for c in text {
+		let mut next = vec![false; pattern.len() + 1];
+		for i in 0..pattern.len() {
+			next[i + 1] = match pattern[i] {
+				// `%` matches nothing more, or this character too
+				'%' => next[i] || reachable[i + 1],
+				'_' => reachable[i],
+				p => reachable[i] && p == c,
+			};
+		}
+		reachable = next;
+	}
+	reachable[pattern.len()]
+}
+
+/// Create the `qaswa.realtime_tables` table and the trigger function
+pub async fn init(pg: &PgDb) -> AppResult<()> {
+	sqlx::query("CREATE SCHEMA IF NOT EXISTS qaswa;").execute(pg).await?;
+	sqlx::query(r#"
+		CREATE TABLE IF NOT EXISTS qaswa.realtime_tables (
+			schema_name TEXT NOT NULL,
+			table_name TEXT NOT NULL,
+			created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+			PRIMARY KEY (schema_name, table_name)
+		);
+	"#).execute(pg).await?;
+	// The arguments of the trigger are the columns of the primary key
+	sqlx::query(r#"
+		CREATE OR REPLACE FUNCTION qaswa.notify_change() RETURNS trigger LANGUAGE plpgsql AS $$
+		DECLARE
+			new_row jsonb := CASE WHEN TG_OP <> 'DELETE' THEN to_jsonb(NEW) END;
+			old_row jsonb := CASE WHEN TG_OP <> 'INSERT' THEN to_jsonb(OLD) END;
+			new_key jsonb := '{}';
+			old_key jsonb := '{}';
+			payload text;
+		BEGIN
+			payload := jsonb_build_object(
+				'schema', TG_TABLE_SCHEMA, 'table', TG_TABLE_NAME, 'op', TG_OP, 'row', new_row, 'old', old_row
+			)::text;
+			IF octet_length(payload) > 7900 THEN
+				FOR i IN 0 .. TG_NARGS - 1 LOOP
+					new_key := new_key || jsonb_build_object(TG_ARGV[i], new_row -> TG_ARGV[i]);
+					old_key := old_key || jsonb_build_object(TG_ARGV[i], old_row -> TG_ARGV[i]);
+				END LOOP;
+				payload := jsonb_build_object(
+					'schema', TG_TABLE_SCHEMA, 'table', TG_TABLE_NAME, 'op', TG_OP, 'truncated', true,
+					'row', CASE WHEN new_row IS NOT NULL THEN new_key END,
+					'old', CASE WHEN old_row IS NOT NULL THEN old_key END
+				)::text;
+			END IF;
+			PERFORM pg_notify('qaswa_changes', payload);
+			RETURN NULL;
+		END
+		$$;
+	"#).execute(pg).await?;
+	Ok(())
+}
+
+/// Published tables
+pub async fn list(pg: &PgDb) -> AppResult<Vec<RealtimeTable>> {
+	let rows = sqlx::query("SELECT schema_name, table_name, created_at FROM qaswa.realtime_tables ORDER BY schema_name, table_name;")
+		.fetch_all(pg)
+		.await?;
+	rows.iter()
+		.map(|row| Ok(RealtimeTable { schema: row.try_get(0)?, table: row.try_get(1)?, created_at: row.try_get(2)? }))
+		.collect()
+}
+
+/// Whether the table has the trigger
+pub async fn is_published(pg: &PgDb, schema: &str, table: &str) -> AppResult<bool> {
+	Ok(sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM qaswa.realtime_tables WHERE schema_name = $1 AND table_name = $2);")
+		.bind(schema)
+		.bind(table)
+		.fetch_one(pg)
+		.await?)
+}
+
+/// Publish the changes of a table, installing its trigger
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn add(pg: &PgDb, schema: &str, table: &str) -> AppResult<RealtimeTable> {
+	let key = primary_key(pg, schema, table)
+		.await?
+		.ok_or_else(|| AppError::NotFound { message: format!("table {}.{} not found", schema, table) })?;
+	// Its changes would reach the subscribers twice
+	if crate::cdc::is_published(pg, schema, table).await? {
+		return Err(AppError::BadRequest {
+			message: format!("changes of table {}.{} are captured by CDC, which already sends them to realtime clients", schema, table),
+		});
+	}
+
+	let target = format!("{}.{}", quote(schema), quote(table));
+	let arguments: Vec<String> = key.iter().map(|column| format!("'{}'", column.replace('\'', "''"))).collect();
+	let mut tx = pg.begin().await?;
+	sqlx::query(&format!("DROP TRIGGER IF EXISTS {} ON {};", TRIGGER, target)).execute(&mut *tx).await.map_err(statement_error)?;
+	sqlx::query(&format!(
+		"CREATE TRIGGER {} AFTER INSERT OR UPDATE OR DELETE ON {} FOR EACH ROW EXECUTE FUNCTION qaswa.notify_change({});",
+		TRIGGER, target, arguments.join(", "),
+	)).execute(&mut *tx).await.map_err(statement_error)?;
+	let created_at = sqlx::query_scalar(r#"
+		INSERT INTO qaswa.realtime_tables (schema_name, table_name) VALUES ($1, $2)
+		ON CONFLICT (schema_name, table_name) DO UPDATE SET created_at = qaswa.realtime_tables.created_at
+		RETURNING created_at;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_one(&mut *tx)
+		.await?;
+	tx.commit().await?;
+	info!("changes of {} published", target);
+	Ok(RealtimeTable { schema: schema.to_string(), table: table.to_string(), created_at })
+}
+
+/// Columns of the primary key of a table, `None` when it does not exist
+async fn primary_key(pg: &PgDb, schema: &str, table: &str) -> AppResult<Option<Vec<String>>> {
+	Ok(sqlx::query_scalar(r#"
+		SELECT coalesce((
+			SELECT array_agg(a.attname::text ORDER BY k.position)
+			FROM pg_catalog.pg_index i
+			CROSS JOIN unnest(i.indkey) WITH ORDINALITY AS k(attnum, position)
+			JOIN pg_catalog.pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+			WHERE i.indrelid = c.oid AND i.indisprimary
+		), '{}')
+		FROM pg_catalog.pg_class c
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		WHERE n.nspname = $1 AND c.relname = $2 AND c.relkind IN ('r', 'p');
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_optional(pg)
+		.await?)
+}
+
+/// Stop publishing the changes of a table, dropping its trigger
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn remove(pg: &PgDb, schema: &str, table: &str) -> AppResult<()> {
+	let mut tx = pg.begin().await?;
+	let deleted = sqlx::query("DELETE FROM qaswa.realtime_tables WHERE schema_name = $1 AND table_name = $2;")
+		.bind(schema)
+		.bind(table)
+		.execute(&mut *tx)
+		.await?
+		.rows_affected();
+	if deleted == 0 {
+		return Err(AppError::NotFound { message: format!("table {}.{} is not published", schema, table) });
+	}
+	let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL;")
+		.bind(format!("{}.{}", quote(schema), quote(table)))
+		.fetch_one(&mut *tx)
+		.await?;
+	// The table may have been dropped with its trigger
+	if exists {
+		let drop = format!("DROP TRIGGER IF EXISTS {} ON {}.{};", TRIGGER, quote(schema), quote(table));
+		sqlx::query(&drop).execute(&mut *tx).await.map_err(statement_error)?;
+	}
+	tx.commit().await?;
+	Ok(())
+}
+
+/// Listen to the notifications of the triggers on a dedicated connection and send them to `sender`.
+///
+/// The connection is opened again when lost, with an increasing delay, then subscribers receive
+/// [`Notification::Reconnected`].
+pub fn spawn_listener(pg: PgDb, sender: broadcast::Sender<Notification>) {
+	tokio::spawn(async move {
+		let mut delay = Duration::from_secs(1);
+		let mut lost = false;
+		loop {
+			let mut listener = match connect(&pg).await {
+				Ok(listener) => listener,
+				Err(err) => {
+					warn!("realtime listener: unable to listen, retrying in {:?} - {:?}", delay, err);
+					tokio::time::sleep(delay).await;
+					delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+					continue;
+				}
+			};
+			delay = Duration::from_secs(1);
+			if lost {
+				info!("realtime listener reconnected");
+				// Sending fails without subscriber
+				let _ = sender.send(Notification::Reconnected);
+			}
+
+			loop {
+				match listener.try_recv().await {
+					Ok(Some(notification)) => match serde_json::from_str::<Change>(notification.payload()) {
+						Ok(change) => {
+							let _ = sender.send(Notification::Change(Arc::new(change)));
+						}
+						Err(err) => warn!("realtime listener: invalid notification {:?} - {}", notification.payload(), err),
+					},
+					Ok(None) => {
+						warn!("realtime listener: connection lost");
+						break;
+					}
+					Err(err) => {
+						warn!("realtime listener: {:?}", err);
+						break;
+					}
+				}
+			}
+			lost = true;
+		}
+	});
+}
+
+async fn connect(pg: &PgDb) -> AppResult<PgListener> {
+	let mut listener = PgListener::connect_with(pg).await?;
+	listener.listen(CHANNEL).await?;
+	Ok(listener)
+}
+
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	fn subscription(table: Option<&str>, query: &str) -> Subscription {
+		let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap();
+		Subscription::parse(table, &pairs).unwrap()
+	}
+
+	fn change(table: &str, op: &str, row: Option<Value>, old: Option<Value>) -> Change {
+		Change { schema: "public".to_string(), table: table.to_string(), op: op.to_string(), row, old, truncated: false }
+	}
+
+	#[test]
+	fn test_tables() {
+		let insert = change("orders", "INSERT", Some(json!({"id": 1})), None);
+		assert!(subscription(None, "").matches(&insert));
+		assert!(subscription(Some("orders"), "").matches(&insert));
+		assert!(subscription(Some("public.orders"), "").matches(&insert));
+		assert!(!subscription(Some("users"), "").matches(&insert));
+		assert!(!subscription(Some("audit.orders"), "").matches(&insert));
+	}
+
+	#[test]
+	fn test_filters() {
+		let row = json!({"id": 7, "status": "paid", "total": 12.5, "note": null, "urgent": true, "customer": "Ada Lovelace"});
+		let update = change("orders", "UPDATE", Some(row.clone()), Some(json!({"id": 7, "status": "new"})));
+		let matches = |query: &str| subscription(Some("orders"), query).matches(&update);
+		assert!(matches("status=eq.paid"));
+		assert!(!matches("status=eq.new"));
+		assert!(matches("status=neq.new&total=gt.10"));
+		assert!(matches("total=lte.12.5"));
+		assert!(!matches("total=lt.12.5"));
+		assert!(matches("id=in.(1,7)"));
+		assert!(!matches("id=not.in.(1,7)"));
+		assert!(matches("customer=like.Ada*"));
+		assert!(!matches("customer=like.ada*"));
+		assert!(matches("customer=ilike.*LOVE*"));
+		assert!(matches("customer=like.Ada_Lovelace"));
+		assert!(matches("note=is.null&urgent=is.true"));
+		assert!(matches("status=not.is.null"));
+		// Comparisons with NULL never match, even negated
+		assert!(!matches("note=eq.x"));
+		assert!(!matches("note=not.eq.x"));
+		assert!(!matches("missing=eq.1"));
+		assert!(!matches("total=eq.abc"));
+
+		// Deletes are filtered on the previous row
+		let delete = change("orders", "DELETE", None, Some(row));
+		assert!(subscription(Some("orders"), "status=eq.paid").matches(&delete));
+
+		// Truncated changes only have the primary key
+		let truncated = Change { truncated: true, ..change("orders", "UPDATE", Some(json!({"id": 7})), Some(json!({"id": 7}))) };
+		assert!(subscription(Some("orders"), "id=eq.7&status=eq.paid").matches(&truncated));
+		assert!(!subscription(Some("orders"), "id=eq.8").matches(&truncated));
+
+		let pairs = vec![("status".to_string(), "paid".to_string())];
+		assert!(Subscription::parse(None, &pairs).is_err());
+	}
+
+	#[test]
+	fn test_seen() {
+		let mut seen = Seen::default();
+		seen.insert("orders", &json!({"id": 1}));
+		seen.insert("orders", &json!({"id": 1}));
+		assert!(seen.contains("orders", &json!({"id": 1})));
+		assert!(!seen.contains("users", &json!({"id": 1})));
+		seen.remove("orders", &json!({"id": 1}));
+		assert!(!seen.contains("orders", &json!({"id": 1})));
+
+		// The oldest keys are forgotten, removed ones don't count
+		seen.insert("orders", &json!({"id": 1}));
+		for id in 2..=SEEN_KEYS_MAX + 1 {
+			seen.insert("orders", &json!({ "id": id }));
+		}
+		assert!(!seen.contains("orders", &json!({"id": 1})));
+		assert!(seen.contains("orders", &json!({"id": 2})));
+		assert!(seen.contains("orders", &json!({ "id": SEEN_KEYS_MAX + 1 })));
+		assert_eq!(seen.keys.len(), SEEN_KEYS_MAX);
+	}
+
+	#[test]
+	fn test_like() {
+		assert!(like("abc", "abc"));
+		assert!(like("abc", "%"));
+		assert!(like("", "%"));
+		assert!(like("abc", "a%c"));
+		assert!(like("abc", "%b%"));
+		assert!(like("abc", "_b_"));
+		assert!(!like("abc", "_b"));
+		assert!(!like("abc", "%d%"));
+		assert!(like("a%c", "a%%c"));
+	}
+}
@@ -1,28 +1,274 @@
-use utility::errors::AppResult;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::TryStreamExt;
+use sqlx::{Column, Either, Executor, PgConnection, Postgres, Row, Transaction};
+use utility::errors::{AppError, AppResult};
 use crate::extension::res::ResultSet;
+use crate::params::{prepare, Param};
+use crate::pgrow::read_header;
 use crate::setup::{PgDb, PgResultSet};
+use crate::slow::SlowLog;
 
-pub async fn show_databases(pg: &PgDb) -> AppResult<PgResultSet> {
-	let rows = sqlx::query("SELECT datname as database FROM pg_database WHERE datistemplate = false;")
-		.fetch_all(pg)
-		.await?
-		.result_array();
-	Ok(rows)
+/// Options of statements run from the admin SQL console
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+	/// Run inside a `READ ONLY` transaction
+	pub read_only: bool,
+	/// `statement_timeout` of the transaction
+	pub statement_timeout: Duration,
+	/// Rows after this limit are dropped and the result is flagged as truncated
+	pub max_rows: usize,
+	/// User the statement runs for, `None` keeps the role of the connection, which only the admin
+	/// console and the server itself use
+	pub identity: Option<Identity>,
+	/// Request id recorded with slow statements
+	pub request_id: String,
+	/// Log of the slow statements, `None` records nothing
+	pub slow_log: Option<Arc<SlowLog>>,
 }
 
-pub async fn show_tables(pg: &PgDb) -> AppResult<PgResultSet> {
-	let rows = sqlx::query("SELECT * FROM pg_catalog.pg_tables WHERE schemaname='public';")
-		.fetch_all(pg)
-		.await?
-		.result_array();
-	Ok(rows)
+/// Role and JWT claims of the user a transaction runs for.
+///
+/// Row-level security policies read the claims with
+/// `current_setting('request.jwt.claims', true)::jsonb->>'sub'`.
+#[derive(Debug, Clone)]
+pub struct Identity {
+	/// Role set with `SET LOCAL ROLE`, the connection role must be a member of it
+	pub role: String,
+	pub claims: serde_json::Value,
 }
 
-pub async fn exec_any_sql(pg: &PgDb, sql: &str) -> AppResult<PgResultSet> {
+impl ExecOptions {
+	/// Identity of a statement issued by a user, which never runs with the role of the connection
+	pub fn user_identity(&self) -> AppResult<&Identity> {
+		self.identity.as_ref().ok_or_else(|| AppError::ConfigError {
+			message: "statements of users need an identity, see DB_USER_ROLE".to_string(),
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct ExecResult {
+	pub columns: Vec<String>,
+	pub rows: PgResultSet,
+	pub truncated: bool,
+	/// Rows returned, inserted, updated or deleted by the statement
+	pub rows_affected: u64,
+	pub elapsed: Duration,
+}
+
+#[tracing::instrument(skip(pg, slow_log), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn show_databases(pg: &PgDb, slow_log: Option<&SlowLog>, request_id: &str) -> AppResult<PgResultSet> {
+	let sql = "SELECT datname as database FROM pg_database WHERE datistemplate = false;";
+	timed(sql, slow_log, request_id, async {
+		Ok(sqlx::query(sql).fetch_all(pg).await?.result_array())
+	}).await
+}
+
+#[tracing::instrument(skip(pg, slow_log), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn show_tables(pg: &PgDb, slow_log: Option<&SlowLog>, request_id: &str) -> AppResult<PgResultSet> {
+	let sql = "SELECT * FROM pg_catalog.pg_tables WHERE schemaname='public';";
+	timed(sql, slow_log, request_id, async {
+		Ok(sqlx::query(sql).fetch_all(pg).await?.result_array())
+	}).await
+}
+
+#[tracing::instrument(skip(pg, sql, slow_log), fields(otel.kind = "client", db.system = "postgresql", db.statement = sql))]
+pub async fn exec_any_sql(pg: &PgDb, sql: &str, slow_log: Option<&SlowLog>, request_id: &str) -> AppResult<PgResultSet> {
 	tracing::warn!("Executing SQL - {}",sql);
-	let rows = sqlx::query(sql)
-		.fetch_all(pg)
-		.await?
-		.result_array();
-	Ok(rows)
-}
\ No newline at end of file
+	timed(sql, slow_log, request_id, async {
+		Ok(sqlx::query(sql).fetch_all(pg).await?.result_array())
+	}).await
+}
+
+/// Execute a statement with `$1..$n` placeholders bound to `params`
+#[tracing::instrument(skip(pg, sql, params, slow_log), fields(otel.kind = "client", db.system = "postgresql", db.statement = sql))]
+pub async fn exec_params(pg: &PgDb, sql: &str, params: &[Param], slow_log: Option<&SlowLog>, request_id: &str) -> AppResult<PgResultSet> {
+	timed(sql, slow_log, request_id, async {
+		let mut conn = pg.acquire().await?;
+		let rows = prepare(&mut conn, sql, params)
+			.await?
+			.fetch_all(&mut *conn)
+			.await
+			.map_err(statement_error)?
+			.result_array();
+		Ok(rows)
+	}).await
+}
+
+/// Rows of `query`, recorded as `sql` in `slow_log` when slow
+async fn timed(
+	sql: &str,
+	slow_log: Option<&SlowLog>,
+	request_id: &str,
+	query: impl Future<Output = AppResult<PgResultSet>>,
+) -> AppResult<PgResultSet> {
+	let start = Instant::now();
+	let result = query.await;
+	if let Some(slow_log) = slow_log {
+		let row_count = result.as_ref().map_or(0, Vec::len);
+		slow_log.record_rows(sql, start.elapsed(), row_count, result.as_ref().err().map(|err| err.to_string()), request_id);
+	}
+	result
+}
+
+/// Switch the current transaction to the role and claims of `identity`, both are reset at its end
+pub async fn set_identity(conn: &mut PgConnection, identity: &Identity) -> AppResult<()> {
+	// SET does not take bind parameters
+	let role = format!("SET LOCAL ROLE \"{}\"", identity.role.replace('"', "\"\""));
+	sqlx::query(&role).execute(&mut *conn).await?;
+	sqlx::query("SELECT set_config('request.jwt.claims', $1, true)")
+		.bind(identity.claims.to_string())
+		.execute(&mut *conn)
+		.await?;
+	Ok(())
+}
+
+/// Check that the connection can run the statements of users as `role`, which does not bypass
+/// row-level security; a missing role is created when `create`, e.g. in the embedded Postgres
+pub async fn check_user_role(pg: &PgDb, role: &str, create: bool) -> AppResult<()> {
+	if role.is_empty() {
+		return Err(AppError::ConfigError {
+			message: "DB_USER_ROLE is required, the statements of users would otherwise bypass row-level security".to_string(),
+		});
+	}
+	let quoted = format!("\"{}\"", role.replace('"', "\"\""));
+	let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_catalog.pg_roles WHERE rolname = $1)")
+		.bind(role)
+		.fetch_one(pg)
+		.await?;
+	if !exists && create {
+		sqlx::query(&format!("CREATE ROLE {} NOLOGIN", quoted)).execute(pg).await?;
+		sqlx::query(&format!("GRANT {} TO CURRENT_USER", quoted)).execute(pg).await?;
+		tracing::info!("role {} created, grant it the privileges of the users", role);
+	}
+	let checked: Option<(bool, bool)> = sqlx::query_as(r#"
+		SELECT pg_catalog.pg_has_role(current_user, oid, 'MEMBER'), rolsuper OR rolbypassrls
+		FROM pg_catalog.pg_roles WHERE rolname = $1
+	"#)
+		.bind(role)
+		.fetch_optional(pg)
+		.await?;
+	let message = match checked {
+		Some((true, false)) => return Ok(()),
+		Some((false, _)) => format!("the connection role must be a member of DB_USER_ROLE, run GRANT {} TO CURRENT_USER", quoted),
+		Some((true, true)) => format!("DB_USER_ROLE {} bypasses row-level security", role),
+		None => format!("DB_USER_ROLE {} does not exist, create it with CREATE ROLE {} NOLOGIN", role, quoted),
+	};
+	Err(AppError::ConfigError { message })
+}
+
+/// Execute a statement with `$1..$n` placeholders bound to `params` in its own transaction, bounded by `options`
+#[tracing::instrument(skip(pg, sql, params), fields(otel.kind = "client", db.system = "postgresql", db.statement = sql))]
+pub async fn exec_sql(pg: &PgDb, sql: &str, params: &[Param], options: &ExecOptions) -> AppResult<ExecResult> {
+	let start = Instant::now();
+	let result = match begin(pg, options).await {
+		Ok(mut tx) => match fetch(&mut tx, sql, params, options.max_rows).await {
+			Ok(result) => tx.commit().await.map_err(statement_error).map(|_| result),
+			Err(err) => Err(err),
+		},
+		Err(err) => Err(err),
+	};
+	let elapsed = start.elapsed();
+	if let Some(slow_log) = &options.slow_log {
+		slow_log.record(sql, elapsed, &result, &options.request_id);
+	}
+	result.map(|result| ExecResult { elapsed, ..result })
+}
+
+/// Plan of a statement, from `EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON)` when `analyze` is set.
+///
+/// The statement runs in a transaction which is always rolled back, analyzed writes change nothing.
+#[tracing::instrument(skip(pg, sql, params), fields(otel.kind = "client", db.system = "postgresql", db.statement = sql))]
+pub async fn explain(pg: &PgDb, sql: &str, params: &[Param], options: &ExecOptions, analyze: bool) -> AppResult<serde_json::Value> {
+	let explain = match analyze {
+		true => format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {}", sql),
+		false => format!("EXPLAIN (FORMAT JSON) {}", sql),
+	};
+	let start = Instant::now();
+	let result: AppResult<serde_json::Value> = async {
+		let mut tx = begin(pg, options).await?;
+		let row = prepare(&mut tx, &explain, params)
+			.await?
+			.fetch_one(&mut *tx)
+			.await
+			.map_err(statement_error)?;
+		tx.rollback().await?;
+		Ok(row.try_get(0)?)
+	}.await;
+	if let Some(slow_log) = &options.slow_log {
+		let error = result.as_ref().err().map(|err| err.to_string());
+		slow_log.record_rows(&explain, start.elapsed(), result.is_ok() as usize, error, &options.request_id);
+	}
+	result
+}
+
+/// Transaction bounded by `options`
+async fn begin(pg: &PgDb, options: &ExecOptions) -> AppResult<Transaction<'static, Postgres>> {
+	let mut tx = pg.begin().await?;
+	if options.read_only {
+		sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *tx).await?;
+	}
+	// SET does not take bind parameters
+	let timeout = format!("SET LOCAL statement_timeout = {}", options.statement_timeout.as_millis());
+	sqlx::query(&timeout).execute(&mut *tx).await?;
+	if let Some(identity) = &options.identity {
+		set_identity(&mut tx, identity).await?;
+	}
+	Ok(tx)
+}
+
+/// Run a statement on `conn` and keep its first `max_rows` rows
+pub(crate) async fn fetch(conn: &mut PgConnection, sql: &str, params: &[Param], max_rows: usize) -> AppResult<ExecResult> {
+	let start = Instant::now();
+	let mut rows = vec![];
+	let mut truncated = false;
+	let mut rows_affected = 0;
+	{
+		let query = prepare(&mut *conn, sql, params).await?;
+		let mut stream = (&mut *conn).fetch_many(query);
+		while let Some(item) = stream.try_next().await.map_err(statement_error)? {
+			match item {
+				Either::Left(done) => rows_affected += done.rows_affected(),
+				Either::Right(_) if rows.len() == max_rows => {
+					truncated = true;
+					break;
+				}
+				Either::Right(row) => rows.push(row),
+			}
+		}
+	}
+
+	// Statements returning no row still have a header
+	let columns = match rows.first() {
+		Some(row) => read_header(row),
+		None => (&mut *conn)
+			.describe(sql)
+			.await
+			.map(|describe| describe.columns().iter().map(|c| c.name().to_string()).collect())
+			.unwrap_or_default(),
+	};
+
+	Ok(ExecResult {
+		columns,
+		// The rows after a truncation are not counted
+		rows_affected: rows_affected.max(rows.len() as u64),
+		rows: rows.result_array(),
+		truncated,
+		elapsed: start.elapsed(),
+	})
+}
+
+/// Errors raised by Postgres for a client statement are returned to the client
+pub fn statement_error(err: sqlx::Error) -> AppError {
+	match err {
+		sqlx::Error::Database(err) => AppError::SqlError {
+			message: match err.code() {
+				Some(code) => format!("{} (SQLSTATE {})", err.message(), code),
+				None => err.message().to_string(),
+			}
+		},
+		err => AppError::from(err),
+	}
+}
@@ -0,0 +1,284 @@
+//! Typed bind parameters of client statements, given as JSON values
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::Value;
+use sqlx::postgres::{PgArguments, PgConnection, PgHasArrayType, PgTypeInfo};
+use sqlx::query::Query;
+use sqlx::types::chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use sqlx::types::{Decimal, Json, Uuid};
+use sqlx::{Either, Encode, Executor, Postgres, Type, TypeInfo};
+use utility::errors::{AppError, AppResult};
+use crate::ops::statement_error;
+
+pub type PgQuery<'q> = Query<'q, Postgres, PgArguments>;
+
+/// Bind parameter, its type is inferred from the statement when there is no hint
+#[derive(Debug, Clone)]
+pub struct Param {
+	pub value: Value,
+	pub type_hint: Option<ParamType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+	Int2,
+	Int4,
+	Int8,
+	Float4,
+	Float8,
+	Bool,
+	Text,
+	Jsonb,
+	Uuid,
+	Timestamptz,
+	Timestamp,
+	Date,
+	Numeric,
+	Bytea,
+}
+
+/// Postgres type of a bind parameter, a scalar or a one dimension array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamType {
+	pub scalar: ScalarType,
+	pub array: bool,
+}
+
+impl FromStr for ParamType {
+	type Err = String;
+
+	/// Parse SQL type names (`int4`, `integer`, `text[]`, ...) as well as sqlx type names (`INT4[]`)
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		let name = name.trim().to_lowercase();
+		let (scalar, array) = match name.strip_suffix("[]") {
+			Some(scalar) => (scalar, true),
+			None => (name.as_str(), false),
+		};
+		let scalar = match scalar {
+			"int2" | "smallint" => ScalarType::Int2,
+			"int" | "int4" | "integer" => ScalarType::Int4,
+			"int8" | "bigint" => ScalarType::Int8,
+			"float4" | "real" => ScalarType::Float4,
+			"float" | "float8" | "double precision" => ScalarType::Float8,
+			"bool" | "boolean" => ScalarType::Bool,
+			"text" | "varchar" | "character varying" | "char" | "bpchar" | "name" => ScalarType::Text,
+			"json" | "jsonb" => ScalarType::Jsonb,
+			"uuid" => ScalarType::Uuid,
+			"timestamptz" | "timestamp with time zone" => ScalarType::Timestamptz,
+			"timestamp" | "timestamp without time zone" => ScalarType::Timestamp,
+			"date" => ScalarType::Date,
+			"numeric" | "decimal" => ScalarType::Numeric,
+			"bytea" => ScalarType::Bytea,
+			// Text converts to any type in SQL, while a text bind parameter is not a value of the type
+			_ => return Err(format!("unsupported parameter type {:?}, bind it as text and cast it in SQL, e.g. `$1::text::{}`", name, name)),
+		};
+		Ok(Self { scalar, array })
+	}
+}
+
+impl Display for ParamType {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let name = format!("{:?}", self.scalar).to_lowercase();
+		match self.array {
+			true => write!(f, "{}[]", name),
+			false => write!(f, "{}", name),
+		}
+	}
+}
+
+impl TryFrom<&PgTypeInfo> for ParamType {
+	type Error = String;
+
+	fn try_from(info: &PgTypeInfo) -> Result<Self, Self::Error> {
+		info.name().parse()
+	}
+}
+
+/// Query of `sql` with `params` bound, types without hint are inferred by preparing the statement
+pub async fn prepare<'q>(conn: &mut PgConnection, sql: &'q str, params: &'q [Param]) -> AppResult<PgQuery<'q>> {
+	let mut query = sqlx::query(sql);
+	if params.is_empty() {
+		return Ok(query);
+	}
+
+	let inferred = match params.iter().all(|param| param.type_hint.is_some()) {
+		true => vec![],
+		false => {
+			let describe = conn.describe(sql).await.map_err(statement_error)?;
+			let types = match describe.parameters() {
+				Some(Either::Left(types)) => types.to_vec(),
+				_ => vec![],
+			};
+			if types.len() != params.len() {
+				return Err(AppError::BadRequest {
+					message: format!("the statement expects {} parameters, {} given", types.len(), params.len()),
+				});
+			}
+			types
+		}
+	};
+
+	for (index, param) in params.iter().enumerate() {
+		let position = index + 1;
+		let ty = match param.type_hint {
+			Some(ty) => ty,
+			None => ParamType::try_from(&inferred[index])
+				.map_err(|err| AppError::BadRequest { message: format!("${}: {}", position, err) })?,
+		};
+		query = bind(query, &param.value, ty)
+			.map_err(|err| AppError::BadRequest { message: format!("${} ({}): {}", position, ty, err) })?;
+	}
+	Ok(query)
+}
+
+/// Bind `value` as `ty`, JSON `null` is bound as SQL `NULL`
+pub fn bind<'q>(query: PgQuery<'q>, value: &Value, ty: ParamType) -> Result<PgQuery<'q>, String> {
+	match ty.scalar {
+		ScalarType::Int2 => bind_as(query, value, ty.array, |v| to_i64(v).and_then(|n| i16::try_from(n).map_err(|err| err.to_string()))),
+		ScalarType::Int4 => bind_as(query, value, ty.array, |v| to_i64(v).and_then(|n| i32::try_from(n).map_err(|err| err.to_string()))),
+		ScalarType::Int8 => bind_as(query, value, ty.array, to_i64),
+		ScalarType::Float4 => bind_as(query, value, ty.array, |v| to_f64(v).map(|n| n as f32)),
+		ScalarType::Float8 => bind_as(query, value, ty.array, to_f64),
+		ScalarType::Bool => bind_as(query, value, ty.array, to_bool),
+		ScalarType::Text => bind_as(query, value, ty.array, to_text),
+		ScalarType::Jsonb => bind_as(query, value, ty.array, |v| Ok(Json(v.clone()))),
+		ScalarType::Uuid => bind_as(query, value, ty.array, |v| Uuid::parse_str(as_str(v)?).map_err(|err| err.to_string())),
+		ScalarType::Timestamptz => bind_as(query, value, ty.array, to_timestamptz),
+		ScalarType::Timestamp => bind_as(query, value, ty.array, to_timestamp),
+		ScalarType::Date => bind_as(query, value, ty.array, |v| NaiveDate::from_str(as_str(v)?).map_err(|err| err.to_string())),
+		ScalarType::Numeric => bind_as(query, value, ty.array, to_numeric),
+		ScalarType::Bytea => bind_as(query, value, ty.array, |v| BASE64.decode(as_str(v)?).map_err(|err| err.to_string())),
+	}
+}
+
+fn bind_as<'q, T>(query: PgQuery<'q>, value: &Value, array: bool, convert: impl Fn(&Value) -> Result<T, String>) -> Result<PgQuery<'q>, String>
+	where
+		T: 'q + Send + Encode<'q, Postgres> + Type<Postgres> + PgHasArrayType,
+{
+	if !array {
+		return match value {
+			Value::Null => Ok(query.bind(None::<T>)),
+			value => Ok(query.bind(convert(value)?)),
+		};
+	}
+	match value {
+		Value::Null => Ok(query.bind(None::<Vec<Option<T>>>)),
+		Value::Array(values) => {
+			let values = values
+				.iter()
+				.map(|value| match value {
+					Value::Null => Ok(None),
+					value => convert(value).map(Some),
+				})
+				.collect::<Result<Vec<Option<T>>, String>>()?;
+			Ok(query.bind(values))
+		}
+		value => Err(format!("expected an array, got {}", value)),
+	}
+}
+
+fn as_str(value: &Value) -> Result<&str, String> {
+	value.as_str().ok_or_else(|| format!("expected a string, got {}", value))
+}
+
+/// Numbers may be given as strings, JSON numbers lose precision after 2^53
+fn to_i64(value: &Value) -> Result<i64, String> {
+	match value {
+		Value::Number(n) => n.as_i64().ok_or_else(|| format!("expected an integer, got {}", n)),
+		Value::String(s) => s.trim().parse::<i64>().map_err(|err| err.to_string()),
+		value => Err(format!("expected an integer, got {}", value)),
+	}
+}
+
+fn to_f64(value: &Value) -> Result<f64, String> {
+	match value {
+		Value::Number(n) => n.as_f64().ok_or_else(|| format!("expected a number, got {}", n)),
+		Value::String(s) => s.trim().parse::<f64>().map_err(|err| err.to_string()),
+		value => Err(format!("expected a number, got {}", value)),
+	}
+}
+
+fn to_bool(value: &Value) -> Result<bool, String> {
+	value.as_bool().ok_or_else(|| format!("expected a boolean, got {}", value))
+}
+
+/// Strings are bound as is, other values as their JSON text
+fn to_text(value: &Value) -> Result<String, String> {
+	match value {
+		Value::String(s) => Ok(s.clone()),
+		value => Ok(value.to_string()),
+	}
+}
+
+fn to_numeric(value: &Value) -> Result<Decimal, String> {
+	let text = match value {
+		Value::Number(n) => n.to_string(),
+		Value::String(s) => s.trim().to_string(),
+		value => return Err(format!("expected a number, got {}", value)),
+	};
+	Decimal::from_str(&text)
+		.or_else(|_| Decimal::from_scientific(&text))
+		.map_err(|err| err.to_string())
+}
+
+/// RFC 3339 timestamps, e.g. `2023-08-01T10:00:00Z`
+fn to_timestamptz(value: &Value) -> Result<DateTime<Utc>, String> {
+	DateTime::parse_from_rfc3339(as_str(value)?)
+		.map(|date| date.with_timezone(&Utc))
+		.map_err(|err| err.to_string())
+}
+
+/// `2023-08-01T10:00:00` or `2023-08-01 10:00:00`, with optional fractional seconds
+fn to_timestamp(value: &Value) -> Result<NaiveDateTime, String> {
+	let text = as_str(value)?;
+	NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+		.or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f"))
+		.map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	#[test]
+	fn test_param_type() {
+		assert_eq!("integer".parse::<ParamType>(), Ok(ParamType { scalar: ScalarType::Int4, array: false }));
+		assert_eq!("TEXT[]".parse::<ParamType>(), Ok(ParamType { scalar: ScalarType::Text, array: true }));
+		assert_eq!("timestamp with time zone".parse::<ParamType>(), Ok(ParamType { scalar: ScalarType::Timestamptz, array: false }));
+		assert_eq!("inet".parse::<ParamType>(), Err("unsupported parameter type \"inet\", bind it as text and cast it in SQL, e.g. `$1::text::inet`".to_string()));
+		assert_eq!(ParamType { scalar: ScalarType::Numeric, array: true }.to_string(), "numeric[]");
+	}
+
+	#[test]
+	fn test_conversions() {
+		assert_eq!(to_i64(&json!(42)), Ok(42));
+		assert_eq!(to_i64(&json!("9007199254740993")), Ok(9007199254740993));
+		assert!(to_i64(&json!(1.5)).is_err());
+		assert_eq!(to_f64(&json!("1.5")), Ok(1.5));
+		assert!(to_bool(&json!("true")).is_err());
+		assert_eq!(to_text(&json!(12)), Ok("12".to_string()));
+		assert_eq!(to_numeric(&json!("12.345")).unwrap().to_string(), "12.345");
+		assert_eq!(to_numeric(&json!(1e3)).unwrap(), Decimal::from(1000));
+		assert_eq!(to_timestamptz(&json!("2023-08-01T12:00:00+02:00")).unwrap().to_rfc3339(), "2023-08-01T10:00:00+00:00");
+		assert!(to_timestamp(&json!("2023-08-01 10:00:00.5")).is_ok());
+	}
+
+	#[test]
+	fn test_bind() {
+		let int4 = ParamType { scalar: ScalarType::Int4, array: false };
+		let int4_array = ParamType { scalar: ScalarType::Int4, array: true };
+		let bytea = ParamType { scalar: ScalarType::Bytea, array: false };
+
+		assert!(bind(sqlx::query("SELECT $1"), &json!(1), int4).is_ok());
+		assert!(bind(sqlx::query("SELECT $1"), &Value::Null, int4).is_ok());
+		assert!(bind(sqlx::query("SELECT $1"), &json!(4294967296i64), int4).is_err());
+		assert!(bind(sqlx::query("SELECT $1"), &json!([1, null, 3]), int4_array).is_ok());
+		assert!(bind(sqlx::query("SELECT $1"), &json!(1), int4_array).is_err());
+		assert!(bind(sqlx::query("SELECT $1"), &json!("aGVsbG8="), bytea).is_ok());
+		assert!(bind(sqlx::query("SELECT $1"), &json!("not base64!"), bytea).is_err());
+	}
+}
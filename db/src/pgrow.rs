@@ -1,9 +1,27 @@
-use sqlx::postgres::{PgRow, PgValueRef, Postgres};
-use sqlx::{Column, Decode, Row, TypeInfo, ValueRef};
-use serde_json::{Value};
+use std::fmt::Display;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{json, Map, Number, Value};
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgRow, PgTypeInfo, PgTypeKind, PgValueFormat, PgValueRef};
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use sqlx::types::Uuid;
+use sqlx::{Column, Row, TypeInfo, ValueRef};
 
 use serde::{Serialize, Serializer};
-use serde::ser::{SerializeMap, SerializeSeq};
+use serde::ser::{Error, SerializeMap, SerializeSeq};
+
+/// Days between 0001-01-01 and the Postgres epoch (2000-01-01)
+const PG_EPOCH_DAYS_FROM_CE: i64 = 730_120;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// Flags of the binary format of ranges
+const RANGE_EMPTY: u8 = 0x01;
+const RANGE_LB_INC: u8 = 0x02;
+const RANGE_UB_INC: u8 = 0x04;
+const RANGE_LB_INF: u8 = 0x08;
+const RANGE_UB_INF: u8 = 0x10;
 
 pub fn read_header(row: &PgRow) -> Vec<String> {
 	let columns = row.columns();
@@ -18,96 +36,532 @@ pub fn read_row(row: &PgRow) -> Vec<Value> {
 	let columns = row.columns();
 	let mut result: Vec<Value> = Vec::with_capacity(columns.len());
 	for c in columns {
-		let value = row.try_get_raw(c.ordinal()).unwrap();
-		let value = SerPgValueRef(value);
-		let value = serde_json::to_value(&value).unwrap();
+		let value = match row.try_get_raw(c.ordinal()) {
+			Ok(value) => to_json_or_null(value),
+			Err(err) => {
+				tracing::warn!("pgrow: column {}: {}", c.name(), err);
+				Value::Null
+			}
+		};
 		result.push(value);
 	}
 	result
 }
 
-/// Can be used with serialize_with
+/// Can be used with serialize_with.
+/// Values which cannot be decoded are serialized as `null` and logged.
 pub fn serialize_pgvalueref<S>(value: &PgValueRef, s: S) -> Result<S::Ok, S::Error>
 	where
 		S: Serializer,
 {
+	to_json_or_null(value.clone()).serialize(s)
+}
+
+/// JSON value of a Postgres value, `null` when it cannot be decoded
+pub fn to_json_or_null(value: PgValueRef) -> Value {
+	let name = value.type_info().name().to_string();
+	match to_json(value) {
+		Ok(value) => value,
+		Err(err) => {
+			tracing::warn!("pgrow: cannot decode {} value: {}", name, err);
+			Value::Null
+		}
+	}
+}
+
+/// JSON value of a Postgres value.
+///
+/// Numbers which do not fit a JSON number without loss (`NUMERIC`, `MONEY`, `NaN`, infinities)
+/// are strings, `BYTEA` is base64, ranges are `{lower, upper, lower_inc, upper_inc}` objects
+/// or `"empty"`, composites are objects and other types use the Postgres text representation.
+pub fn to_json(value: PgValueRef) -> Result<Value, BoxDynError> {
 	if value.is_null() {
-		return s.serialize_none();
+		return Ok(Value::Null);
 	}
-	let value = value.clone();
-	let info = value.type_info();
-	let name = info.name();
-	match name {
-		"BOOL" => {
-			let v: bool = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_bool(v)
-		}
-		"INT2" => {
-			let v: i16 = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_i16(v)
-		}
-		"INT4" => {
-			let v: i32 = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_i32(v)
-		}
-		"INT8" => {
-			let v: i64 = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_i64(v)
-		}
-		"FLOAT4" => {
-			let v: f32 = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_f32(v)
-		}
-		"FLOAT8" => {
-			let v: f64 = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_f64(v)
-		}
-		#[cfg(feature = "decimal")]
-		"NUMERIC" => {
-			let v: sqlx::types::Decimal = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v.to_string())
-		}
-		"CHAR" | "VARCHAR" | "TEXT" | "\"CHAR\"" => {
-			let v: String = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v)
+	let info = value.type_info().into_owned();
+
+	// Simple query protocol, values are already formatted by Postgres
+	if value.format() == PgValueFormat::Text {
+		return text_to_json(&info, value.as_str()?);
+	}
+	binary_to_json(&info, value.as_bytes()?)
+}
+
+/// Values of the text format, only numbers, booleans and JSON are not kept as strings
+fn text_to_json(info: &PgTypeInfo, text: &str) -> Result<Value, BoxDynError> {
+	let value = match info.name() {
+		"BOOL" => Value::Bool(text == "t"),
+		"INT2" | "INT4" | "INT8" | "OID" => Value::from(text.parse::<i64>()?),
+		"FLOAT4" | "FLOAT8" => float8_to_json(text.parse::<f64>()?),
+		"JSON" | "JSONB" => serde_json::from_str(text)?,
+		_ => Value::String(text.to_string()),
+	};
+	Ok(value)
+}
+
+/// Value of the binary format, arrays, composites and ranges decode their elements with the type
+/// of the elements
+fn binary_to_json(info: &PgTypeInfo, bytes: &[u8]) -> Result<Value, BoxDynError> {
+	match info.kind() {
+		PgTypeKind::Array(element) => array_to_json(bytes, &|cell| cell_to_json(element, cell)),
+		PgTypeKind::Composite(fields) => composite_to_json(bytes, fields),
+		PgTypeKind::Domain(base) => binary_to_json(base, bytes),
+		PgTypeKind::Enum(_) => Ok(Value::String(std::str::from_utf8(bytes)?.to_string())),
+		PgTypeKind::Range(element) => range_to_json(bytes, &|cell| cell_to_json(element, Some(cell))),
+		_ => scalar_to_json(info.name(), bytes),
+	}
+}
+
+/// Value of a built-in type in the binary format, by the name of the type
+fn scalar_to_json(name: &str, bytes: &[u8]) -> Result<Value, BoxDynError> {
+	let value = match name {
+		"BOOL" => Value::Bool(fixed::<1>(bytes)?[0] != 0),
+		"INT2" => Value::from(i16::from_be_bytes(fixed(bytes)?)),
+		"INT4" => Value::from(i32::from_be_bytes(fixed(bytes)?)),
+		"INT8" => Value::from(i64::from_be_bytes(fixed(bytes)?)),
+		"OID" => Value::from(u32::from_be_bytes(fixed(bytes)?)),
+		"FLOAT4" => float4_to_json(f32::from_be_bytes(fixed(bytes)?)),
+		"FLOAT8" => float8_to_json(f64::from_be_bytes(fixed(bytes)?)),
+		"NUMERIC" => Value::String(numeric_to_string(bytes)?),
+		"MONEY" => Value::String(money_to_string(i64::from_be_bytes(fixed(bytes)?))),
+		"CHAR" | "VARCHAR" | "TEXT" | "NAME" | "\"CHAR\"" | "CITEXT" | "XML" | "UNKNOWN" => {
+			Value::String(std::str::from_utf8(bytes)?.to_string())
 		}
-		"BYTEA" => {
-			let v: Vec<u8> = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_some(&v)
+		"BYTEA" => Value::String(BASE64.encode(bytes)),
+		"JSON" => serde_json::from_slice(bytes)?,
+		// Prefixed by the version of the format, `1`
+		"JSONB" => match bytes.split_first() {
+			Some((1, json)) => serde_json::from_slice(json)?,
+			_ => return Err("unsupported JSONB version".into()),
+		},
+		"TIMESTAMP" => Value::String(timestamp_to_string(i64::from_be_bytes(fixed(bytes)?))?),
+		"TIMESTAMPTZ" => Value::String(timestamptz_to_string(i64::from_be_bytes(fixed(bytes)?))?),
+		"DATE" => Value::String(date_to_string(i32::from_be_bytes(fixed(bytes)?))?),
+		"TIME" => Value::String(time_of_day(i64::from_be_bytes(fixed(bytes)?))?.to_string()),
+		// Time of day, then the offset in seconds west of UTC
+		"TIMETZ" => {
+			let mut buf = bytes;
+			let time = time_of_day(i64::from_be_bytes(take(&mut buf)?))?;
+			let offset = FixedOffset::west_opt(i32::from_be_bytes(fixed(buf)?)).ok_or("TIMETZ offset out of range")?;
+			Value::String(format!("{}{}", time, offset))
 		}
-		"JSON" | "JSONB" => {
-			let v: Value = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_some(&v)
+		"UUID" => Value::String(Uuid::from_slice(bytes)?.to_string()),
+		"INTERVAL" => {
+			let mut buf = bytes;
+			let microseconds = i64::from_be_bytes(take(&mut buf)?);
+			let days = i32::from_be_bytes(take(&mut buf)?);
+			let months = i32::from_be_bytes(fixed(buf)?);
+			Value::String(interval_to_iso8601(&PgInterval { months, days, microseconds }))
 		}
-		"TIMESTAMP" => {
-			let v: sqlx::types::chrono::NaiveDateTime = Decode::<Postgres>::decode(value).unwrap();
-			let v = v.format("%Y-%m-%dT%H:%M:%S.%f").to_string();
-			s.serialize_str(&v)
+		"INET" | "CIDR" => Value::String(inet_to_string(bytes)?),
+		"MACADDR" | "MACADDR8" => Value::String(macaddr_to_string(bytes)),
+		"BIT" | "VARBIT" => Value::String(bits_to_string(bytes)?),
+		"RECORD" => record_to_json(bytes)?,
+		"VOID" => Value::Null,
+		name => match std::str::from_utf8(bytes) {
+			Ok(text) => Value::String(text.to_string()),
+			Err(_) => return Err(format!("unsupported type {}", name).into()),
+		},
+	};
+	Ok(value)
+}
+
+/// Element of an array, a composite or a range, `null` when it is NULL or cannot be decoded
+fn cell_to_json(info: &PgTypeInfo, bytes: Option<&[u8]>) -> Value {
+	bytes.map_or(Value::Null, |bytes| or_null(binary_to_json(info, bytes), info.name()))
+}
+
+fn or_null(decoded: Result<Value, BoxDynError>, type_name: impl Display) -> Value {
+	decoded.unwrap_or_else(|err| {
+		tracing::warn!("pgrow: cannot decode {} value: {}", type_name, err);
+		Value::Null
+	})
+}
+
+/// Bytes of an element prefixed by their length, `None` for NULL
+fn cell<'a>(buf: &mut &'a [u8]) -> Result<Option<&'a [u8]>, BoxDynError> {
+	let len = i32::from_be_bytes(take(buf)?);
+	if len < 0 {
+		return Ok(None);
+	}
+	if buf.len() < len as usize {
+		return Err(format!("element of {} bytes, {} left", len, buf.len()).into());
+	}
+	let (bytes, rest) = buf.split_at(len as usize);
+	*buf = rest;
+	Ok(Some(bytes))
+}
+
+/// Arrays of several dimensions are nested, the last dimension innermost
+fn array_to_json(mut buf: &[u8], element: &dyn Fn(Option<&[u8]>) -> Value) -> Result<Value, BoxDynError> {
+	let dimensions = i32::from_be_bytes(take(&mut buf)?);
+	// Whether it holds NULLs, and the OID of the elements
+	take::<8>(&mut buf)?;
+	let mut lengths = vec![];
+	for _ in 0..dimensions {
+		lengths.push(i32::from_be_bytes(take(&mut buf)?) as usize);
+		// Lower bound, `1` unless set
+		take::<4>(&mut buf)?;
+	}
+	let mut values = vec![];
+	while !buf.is_empty() {
+		values.push(element(cell(&mut buf)?));
+	}
+	let expected = if lengths.is_empty() { 0 } else { lengths.iter().product() };
+	if values.len() != expected {
+		return Err(format!("array of {} elements for the dimensions {:?}", values.len(), lengths).into());
+	}
+	for len in lengths.iter().skip(1).rev() {
+		values = values.chunks(*len).map(|chunk| Value::Array(chunk.to_vec())).collect();
+	}
+	Ok(Value::Array(values))
+}
+
+/// Field of a record, the OID of its type and its bytes, `None` for NULL
+type Field<'a> = (u32, Option<&'a [u8]>);
+
+fn record_cells(mut buf: &[u8]) -> Result<Vec<Field<'_>>, BoxDynError> {
+	let len = i32::from_be_bytes(take(&mut buf)?);
+	let mut cells = Vec::with_capacity(len.max(0) as usize);
+	for _ in 0..len {
+		let oid = u32::from_be_bytes(take(&mut buf)?);
+		cells.push((oid, cell(&mut buf)?));
+	}
+	Ok(cells)
+}
+
+fn composite_to_json(buf: &[u8], fields: &[(String, PgTypeInfo)]) -> Result<Value, BoxDynError> {
+	let cells = record_cells(buf)?;
+	if cells.len() != fields.len() {
+		return Err(format!("composite of {} fields, {} expected", cells.len(), fields.len()).into());
+	}
+	let map: Map<String, Value> = fields.iter().zip(cells)
+		.map(|((name, info), (_, bytes))| (name.clone(), cell_to_json(info, bytes)))
+		.collect();
+	Ok(Value::Object(map))
+}
+
+/// Anonymous records, e.g. `SELECT ROW(1, 'a')`, have no field names and are arrays. Their fields
+/// only have the OID of their type
+fn record_to_json(buf: &[u8]) -> Result<Value, BoxDynError> {
+	let values = record_cells(buf)?
+		.into_iter()
+		.map(|(oid, bytes)| bytes.map_or(Value::Null, |bytes| or_null(oid_to_json(oid, bytes), oid)))
+		.collect();
+	Ok(Value::Array(values))
+}
+
+/// Value of a built-in type or of an array of them, by OID. Enums and other types are kept as text
+fn oid_to_json(oid: u32, bytes: &[u8]) -> Result<Value, BoxDynError> {
+	match (builtin_type(oid), builtin_array(oid)) {
+		(Some(name), _) => scalar_to_json(name, bytes),
+		(_, Some(element)) => array_to_json(bytes, &|cell| cell.map_or(Value::Null, |bytes| or_null(oid_to_json(element, bytes), element))),
+		_ => scalar_to_json("?", bytes),
+	}
+}
+
+/// Name of the built-in types decoded by `scalar_to_json`, by OID
+fn builtin_type(oid: u32) -> Option<&'static str> {
+	let name = match oid {
+		16 => "BOOL",
+		17 => "BYTEA",
+		18 => "\"CHAR\"",
+		19 => "NAME",
+		20 => "INT8",
+		21 => "INT2",
+		23 => "INT4",
+		25 => "TEXT",
+		26 => "OID",
+		114 => "JSON",
+		142 => "XML",
+		650 => "CIDR",
+		700 => "FLOAT4",
+		701 => "FLOAT8",
+		705 => "UNKNOWN",
+		774 => "MACADDR8",
+		790 => "MONEY",
+		829 => "MACADDR",
+		869 => "INET",
+		1042 => "CHAR",
+		1043 => "VARCHAR",
+		1082 => "DATE",
+		1083 => "TIME",
+		1114 => "TIMESTAMP",
+		1184 => "TIMESTAMPTZ",
+		1186 => "INTERVAL",
+		1266 => "TIMETZ",
+		1560 => "BIT",
+		1562 => "VARBIT",
+		1700 => "NUMERIC",
+		2249 => "RECORD",
+		2950 => "UUID",
+		3802 => "JSONB",
+		_ => return None,
+	};
+	Some(name)
+}
+
+/// OID of the elements of the arrays of the types of `builtin_type`, by OID
+fn builtin_array(oid: u32) -> Option<u32> {
+	let element = match oid {
+		143 => 142,
+		199 => 114,
+		651 => 650,
+		775 => 774,
+		791 => 790,
+		1000 => 16,
+		1001 => 17,
+		1002 => 18,
+		1003 => 19,
+		1005 => 21,
+		1007 => 23,
+		1009 => 25,
+		1014 => 1042,
+		1015 => 1043,
+		1016 => 20,
+		1021 => 700,
+		1022 => 701,
+		1028 => 26,
+		1040 => 829,
+		1041 => 869,
+		1115 => 1114,
+		1182 => 1082,
+		1183 => 1083,
+		1185 => 1184,
+		1187 => 1186,
+		1231 => 1700,
+		1270 => 1266,
+		1561 => 1560,
+		1563 => 1562,
+		2287 => 2249,
+		2951 => 2950,
+		3807 => 3802,
+		_ => return None,
+	};
+	Some(element)
+}
+
+fn range_to_json(mut buf: &[u8], bound: &dyn Fn(&[u8]) -> Value) -> Result<Value, BoxDynError> {
+	let flags = take::<1>(&mut buf)?[0];
+	if flags & RANGE_EMPTY != 0 {
+		return Ok(Value::String("empty".to_string()));
+	}
+	let mut next = |infinite: u8| -> Result<Value, BoxDynError> {
+		match flags & infinite {
+			0 => Ok(cell(&mut buf)?.map_or(Value::Null, bound)),
+			_ => Ok(Value::Null),
 		}
-		"TIMESTAMPTZ" => {
-			use sqlx::types::chrono;
-			let v: chrono::DateTime::<chrono::Utc> = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v.to_rfc3339())
+	};
+	let lower = next(RANGE_LB_INF)?;
+	let upper = next(RANGE_UB_INF)?;
+	Ok(json!({ "lower": lower, "upper": upper, "lower_inc": flags & RANGE_LB_INC != 0, "upper_inc": flags & RANGE_UB_INC != 0 }))
+}
+
+fn time_of_day(micros: i64) -> Result<NaiveTime, BoxDynError> {
+	let seconds = u32::try_from(micros.div_euclid(1_000_000))?;
+	let nanos = (micros.rem_euclid(1_000_000) * 1000) as u32;
+	NaiveTime::from_num_seconds_from_midnight_opt(seconds, nanos).ok_or_else(|| format!("time of day of {} microseconds", micros).into())
+}
+
+/// Big-endian number of exactly `N` bytes
+pub(crate) fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N], BoxDynError> {
+	bytes.try_into().map_err(|_| format!("expected {} bytes, got {}", N, bytes.len()).into())
+}
+
+/// Next big-endian number of `N` bytes of `buf`
+pub(crate) fn take<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N], BoxDynError> {
+	if buf.len() < N {
+		return Err("unexpected end of value".into());
+	}
+	let (head, tail) = buf.split_at(N);
+	*buf = tail;
+	fixed(head)
+}
+
+/// `NaN` and infinities are not JSON numbers, they are returned like Postgres prints them
+fn float8_to_json(value: f64) -> Value {
+	match Number::from_f64(value) {
+		Some(number) => Value::Number(number),
+		None if value.is_nan() => Value::String("NaN".to_string()),
+		None if value > 0.0 => Value::String("Infinity".to_string()),
+		None => Value::String("-Infinity".to_string()),
+	}
+}
+
+/// Widened through its shortest representation, so that `1.1` is not `1.100000023841858`
+fn float4_to_json(value: f32) -> Value {
+	float8_to_json(value.to_string().parse::<f64>().unwrap_or(value as f64))
+}
+
+/// Exact decimal representation of a binary `NUMERIC`
+fn numeric_to_string(mut buf: &[u8]) -> Result<String, BoxDynError> {
+	let ndigits = i16::from_be_bytes(take(&mut buf)?);
+	let weight = i16::from_be_bytes(take(&mut buf)?) as i64;
+	let sign = u16::from_be_bytes(take(&mut buf)?);
+	let scale = u16::from_be_bytes(take(&mut buf)?) as usize;
+	let mut digits = Vec::with_capacity(ndigits.max(0) as usize);
+	for _ in 0..ndigits {
+		digits.push(i16::from_be_bytes(take(&mut buf)?));
+	}
+
+	let negative = match sign {
+		0x0000 => false,
+		0x4000 => true,
+		0xC000 => return Ok("NaN".to_string()),
+		0xD000 => return Ok("Infinity".to_string()),
+		0xF000 => return Ok("-Infinity".to_string()),
+		sign => return Err(format!("invalid NUMERIC sign {:#x}", sign).into()),
+	};
+
+	// Digits are base 10000, the first one is multiplied by 10000^weight
+	let digit = |index: i64| match index >= 0 {
+		true => digits.get(index as usize).copied().unwrap_or(0),
+		false => 0,
+	};
+	let mut text = String::new();
+	if negative {
+		text.push('-');
+	}
+	match weight < 0 {
+		true => text.push('0'),
+		false => {
+			for index in 0..=weight {
+				match index {
+					0 => text.push_str(&digit(index).to_string()),
+					_ => text.push_str(&format!("{:04}", digit(index))),
+				}
+			}
 		}
-		"DATE" => {
-			use sqlx::types::chrono;
-			let v: chrono::NaiveDate = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v.to_string())
+	}
+	if scale > 0 {
+		let mut fraction = String::new();
+		for index in 0..(scale as i64 + 3) / 4 {
+			fraction.push_str(&format!("{:04}", digit(weight + 1 + index)));
 		}
-		"TIME" => {
-			use sqlx::types::chrono;
-			let v: chrono::NaiveTime = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v.to_string())
+		fraction.truncate(scale);
+		text.push('.');
+		text.push_str(&fraction);
+	}
+	Ok(text)
+}
+
+/// Amount in the smallest currency unit, printed with 2 decimals (`lc_monetary` is not known)
+fn money_to_string(cents: i64) -> String {
+	let sign = if cents < 0 { "-" } else { "" };
+	let cents = cents.unsigned_abs();
+	format!("{}{}.{:02}", sign, cents / 100, cents % 100)
+}
+
+/// `TIMESTAMP` as `%Y-%m-%dT%H:%M:%S%.6f`, or `infinity`/`-infinity`
+fn timestamp_to_string(micros: i64) -> Result<String, BoxDynError> {
+	match micros {
+		i64::MAX => Ok("infinity".to_string()),
+		i64::MIN => Ok("-infinity".to_string()),
+		micros => Ok(naive_timestamp(micros)?.format("%Y-%m-%dT%H:%M:%S%.6f").to_string()),
+	}
+}
+
+/// `TIMESTAMPTZ` as RFC 3339 in UTC, or `infinity`/`-infinity`
+fn timestamptz_to_string(micros: i64) -> Result<String, BoxDynError> {
+	match micros {
+		i64::MAX => Ok("infinity".to_string()),
+		i64::MIN => Ok("-infinity".to_string()),
+		micros => Ok(Utc.from_utc_datetime(&naive_timestamp(micros)?).to_rfc3339()),
+	}
+}
+
+fn naive_timestamp(micros: i64) -> Result<NaiveDateTime, BoxDynError> {
+	let date = naive_date(micros.div_euclid(MICROS_PER_DAY))?;
+	let micros = micros.rem_euclid(MICROS_PER_DAY);
+	let time = NaiveTime::from_num_seconds_from_midnight_opt((micros / 1_000_000) as u32, (micros % 1_000_000 * 1_000) as u32)
+		.ok_or("time out of range")?;
+	Ok(date.and_time(time))
+}
+
+/// Date `days` after the Postgres epoch
+fn naive_date(days: i64) -> Result<NaiveDate, BoxDynError> {
+	i32::try_from(days + PG_EPOCH_DAYS_FROM_CE)
+		.ok()
+		.and_then(NaiveDate::from_num_days_from_ce_opt)
+		.ok_or_else(|| "date out of range".into())
+}
+
+/// `DATE` as `%Y-%m-%d`, or `infinity`/`-infinity`
+fn date_to_string(days: i32) -> Result<String, BoxDynError> {
+	match days {
+		i32::MAX => Ok("infinity".to_string()),
+		i32::MIN => Ok("-infinity".to_string()),
+		days => Ok(naive_date(days as i64)?.to_string()),
+	}
+}
+
+/// ISO 8601 duration, like Postgres with `IntervalStyle = iso_8601`, e.g. `P1Y2M3DT4H5M6.5S`
+fn interval_to_iso8601(interval: &PgInterval) -> String {
+	let (years, months) = (interval.months / 12, interval.months % 12);
+	let mut time = interval.microseconds;
+	let hours = time / 3_600_000_000;
+	time -= hours * 3_600_000_000;
+	let minutes = time / 60_000_000;
+	time -= minutes * 60_000_000;
+
+	let mut text = String::from("P");
+	for (value, unit) in [(years as i64, 'Y'), (months as i64, 'M'), (interval.days as i64, 'D')] {
+		if value != 0 {
+			text.push_str(&format!("{}{}", value, unit));
 		}
-		"UUID" => {
-			let v: String = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v)
+	}
+	if hours != 0 || minutes != 0 || time != 0 {
+		text.push('T');
+		for (value, unit) in [(hours, 'H'), (minutes, 'M')] {
+			if value != 0 {
+				text.push_str(&format!("{}{}", value, unit));
+			}
 		}
-		_ => {
-			let v: String = Decode::<Postgres>::decode(value).unwrap();
-			s.serialize_str(&v)
+		if time != 0 {
+			let sign = if time < 0 { "-" } else { "" };
+			let seconds = time.abs() / 1_000_000;
+			let fraction = format!("{:06}", time.abs() % 1_000_000);
+			match fraction.trim_end_matches('0') {
+				"" => text.push_str(&format!("{}{}S", sign, seconds)),
+				fraction => text.push_str(&format!("{}{}.{}S", sign, seconds, fraction)),
+			}
 		}
 	}
+	if text == "P" {
+		text.push_str("T0S");
+	}
+	text
+}
+
+/// Binary `INET`/`CIDR`: family, netmask bits, is CIDR, address length and address
+fn inet_to_string(mut buf: &[u8]) -> Result<String, BoxDynError> {
+	let [family, bits, is_cidr, len] = take::<4>(&mut buf)?;
+	let address: std::net::IpAddr = match (family, len) {
+		(2, 4) => std::net::Ipv4Addr::from(take::<4>(&mut buf)?).into(),
+		(3, 16) => std::net::Ipv6Addr::from(take::<16>(&mut buf)?).into(),
+		_ => return Err(format!("invalid INET family {} and length {}", family, len).into()),
+	};
+	let max_bits = if address.is_ipv4() { 32 } else { 128 };
+	match is_cidr == 0 && bits == max_bits {
+		true => Ok(address.to_string()),
+		false => Ok(format!("{}/{}", address, bits)),
+	}
+}
+
+fn macaddr_to_string(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(":")
+}
+
+/// Binary `BIT`/`VARBIT`: number of bits followed by the bits, most significant first
+fn bits_to_string(mut buf: &[u8]) -> Result<String, BoxDynError> {
+	let len = i32::from_be_bytes(take(&mut buf)?).max(0) as usize;
+	if buf.len() * 8 < len {
+		return Err("unexpected end of BIT value".into());
+	}
+	Ok((0..len).map(|index| match buf[index / 8] & (0x80 >> (index % 8)) {
+		0 => '0',
+		_ => '1',
+	}).collect())
 }
 
 /// Can be used with serialize_with
@@ -118,7 +572,7 @@ pub fn serialize_pgrow_as_vec<S>(x: &PgRow, s: S) -> Result<S::Ok, S::Error>
 	let cols = x.columns();
 	let mut seq = s.serialize_seq(Some(cols.len()))?;
 	for c in cols {
-		let c: PgValueRef = x.try_get_raw(c.ordinal()).unwrap();
+		let c: PgValueRef = x.try_get_raw(c.ordinal()).map_err(S::Error::custom)?;
 		let c = SerPgValueRef(c);
 		seq.serialize_element(&c)?;
 	}
@@ -133,7 +587,7 @@ pub fn serialize_pgrow_as_map<S>(x: &PgRow, s: S) -> Result<S::Ok, S::Error>
 	let cols = x.columns();
 	let mut map = s.serialize_map(Some(cols.len()))?;
 	for col in cols {
-		let c: PgValueRef = x.try_get_raw(col.ordinal()).unwrap();
+		let c: PgValueRef = x.try_get_raw(col.ordinal()).map_err(S::Error::custom)?;
 		let c = SerPgValueRef(c);
 		map.serialize_entry(col.name(), &c)?;
 	}
@@ -212,4 +666,199 @@ impl Into<PgRow> for SPgRowVec {
 	fn into(self) -> PgRow {
 		self.0
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use sqlx::postgres::types::PgRange;
+	use sqlx::{Postgres, Type};
+	use super::*;
+
+	fn numeric(ndigits: i16, weight: i16, sign: u16, scale: u16, digits: &[i16]) -> Vec<u8> {
+		let mut buf = vec![];
+		for value in [ndigits as u16, weight as u16, sign, scale] {
+			buf.extend_from_slice(&value.to_be_bytes());
+		}
+		for digit in digits {
+			buf.extend_from_slice(&digit.to_be_bytes());
+		}
+		buf
+	}
+
+	#[test]
+	fn test_numeric() {
+		assert_eq!(numeric_to_string(&numeric(2, 0, 0, 2, &[12, 3400])).unwrap(), "12.34");
+		assert_eq!(numeric_to_string(&numeric(2, 1, 0x4000, 0, &[1, 0])).unwrap(), "-10000");
+		assert_eq!(numeric_to_string(&numeric(1, -1, 0, 5, &[50])).unwrap(), "0.00500");
+		assert_eq!(numeric_to_string(&numeric(0, 0, 0, 0, &[])).unwrap(), "0");
+		assert_eq!(numeric_to_string(&numeric(3, 2, 0, 0, &[123, 4567, 8901])).unwrap(), "12345678901");
+		assert_eq!(numeric_to_string(&numeric(0, 0, 0xC000, 0, &[])).unwrap(), "NaN");
+		assert_eq!(numeric_to_string(&numeric(0, 0, 0xF000, 0, &[])).unwrap(), "-Infinity");
+		assert!(numeric_to_string(&[0, 1]).is_err());
+		assert!(numeric_to_string(&numeric(0, 0, 0x1234, 0, &[])).is_err());
+	}
+
+	/// Element of an array or a record, prefixed by its length
+	fn element(bytes: Option<&[u8]>) -> Vec<u8> {
+		match bytes {
+			Some(bytes) => [&(bytes.len() as i32).to_be_bytes()[..], bytes].concat(),
+			None => (-1i32).to_be_bytes().to_vec(),
+		}
+	}
+
+	fn array(element_oid: u32, lengths: &[i32], elements: &[Option<&[u8]>]) -> Vec<u8> {
+		let mut buf = vec![];
+		buf.extend_from_slice(&(lengths.len() as i32).to_be_bytes());
+		buf.extend_from_slice(&(elements.contains(&None) as i32).to_be_bytes());
+		buf.extend_from_slice(&element_oid.to_be_bytes());
+		for len in lengths {
+			buf.extend_from_slice(&len.to_be_bytes());
+			buf.extend_from_slice(&1i32.to_be_bytes());
+		}
+		for bytes in elements {
+			buf.extend(element(*bytes));
+		}
+		buf
+	}
+
+	fn record(fields: &[(u32, Option<&[u8]>)]) -> Vec<u8> {
+		let mut buf = (fields.len() as i32).to_be_bytes().to_vec();
+		for (oid, bytes) in fields {
+			buf.extend_from_slice(&oid.to_be_bytes());
+			buf.extend(element(*bytes));
+		}
+		buf
+	}
+
+	const INET: [u8; 8] = [2, 32, 0, 4, 10, 0, 0, 1];
+
+	#[test]
+	fn test_array() {
+		let int4s = <Vec<i32> as Type<Postgres>>::type_info();
+		let one = 1i32.to_be_bytes();
+		let three = 3i32.to_be_bytes();
+		assert_eq!(binary_to_json(&int4s, &array(23, &[3], &[Some(&one), None, Some(&three)])).unwrap(), json!([1, null, 3]));
+		assert_eq!(binary_to_json(&int4s, &array(23, &[], &[])).unwrap(), json!([]));
+		assert!(binary_to_json(&int4s, &array(23, &[2], &[Some(&one)])).is_err());
+
+		let texts = <Vec<String> as Type<Postgres>>::type_info();
+		let cells: Vec<Option<&[u8]>> = ["a", "b", "c", "d", "e", "f"].iter().map(|text| Some(text.as_bytes())).collect();
+		assert_eq!(binary_to_json(&texts, &array(25, &[2, 3], &cells)).unwrap(), json!([["a", "b", "c"], ["d", "e", "f"]]));
+		// An element which can't be decoded is null
+		assert_eq!(binary_to_json(&int4s, &array(23, &[1], &[Some(&[1, 2])])).unwrap(), json!([null]));
+
+		let inets = array(869, &[2], &[Some(&INET), None]);
+		let inet = |cell: Option<&[u8]>| cell.map_or(Value::Null, |bytes| scalar_to_json("INET", bytes).unwrap());
+		assert_eq!(array_to_json(&inets, &inet).unwrap(), json!(["10.0.0.1", null]));
+	}
+
+	#[test]
+	fn test_composite() {
+		let fields = vec![
+			("name".to_string(), <String as Type<Postgres>>::type_info()),
+			("score".to_string(), <i32 as Type<Postgres>>::type_info()),
+			("tags".to_string(), <Vec<String> as Type<Postgres>>::type_info()),
+		];
+		let tags = array(25, &[1], &[Some(b"new")]);
+		let pair = record(&[(25, Some(b"bob")), (23, None), (1009, Some(&tags))]);
+		assert_eq!(composite_to_json(&pair, &fields).unwrap(), json!({"name": "bob", "score": null, "tags": ["new"]}));
+		assert!(composite_to_json(&record(&[(25, Some(b"bob"))]), &fields).is_err());
+		assert!(composite_to_json(&pair[..pair.len() - 1], &fields).is_err());
+	}
+
+	#[test]
+	fn test_record() {
+		let one = 1i32.to_be_bytes();
+		let int4s = array(23, &[1], &[Some(&one)]);
+		// `mood` is an enum, of an OID which is not built-in
+		let fields = record(&[(23, Some(&one)), (25, Some(b"a")), (869, Some(&INET)), (16385, Some(b"happy")), (1007, Some(&int4s)), (1700, None)]);
+		assert_eq!(record_to_json(&fields).unwrap(), json!([1, "a", "10.0.0.1", "happy", [1], null]));
+		assert_eq!(scalar_to_json("RECORD", &record(&[])).unwrap(), json!([]));
+		// A field which can't be decoded is null
+		assert_eq!(record_to_json(&record(&[(869, Some(&INET[..5]))])).unwrap(), json!([null]));
+		assert!(record_to_json(&[0, 0, 0, 1]).is_err());
+	}
+
+	#[test]
+	fn test_range() {
+		let int4range = <PgRange<i32> as Type<Postgres>>::type_info();
+		let range = |flags: u8, bounds: &[&[u8]]| -> Vec<u8> {
+			let mut buf = vec![flags];
+			bounds.iter().for_each(|bound| buf.extend(element(Some(bound))));
+			buf
+		};
+		let (one, ten) = (1i32.to_be_bytes(), 10i32.to_be_bytes());
+		assert_eq!(
+			binary_to_json(&int4range, &range(RANGE_LB_INC, &[&one, &ten])).unwrap(),
+			json!({"lower": 1, "upper": 10, "lower_inc": true, "upper_inc": false}),
+		);
+		assert_eq!(
+			binary_to_json(&int4range, &range(RANGE_LB_INF | RANGE_UB_INC, &[&ten])).unwrap(),
+			json!({"lower": null, "upper": 10, "lower_inc": false, "upper_inc": true}),
+		);
+		assert_eq!(binary_to_json(&int4range, &range(RANGE_EMPTY, &[])).unwrap(), json!("empty"));
+		assert!(binary_to_json(&int4range, &range(0, &[&one])).is_err());
+	}
+
+	#[test]
+	fn test_scalars() {
+		// Enums, and the types without a decoder, are their text
+		assert_eq!(scalar_to_json("mood", b"happy").unwrap(), json!("happy"));
+		assert!(scalar_to_json("mood", &[0xff]).is_err());
+		assert_eq!(scalar_to_json("INET", &INET).unwrap(), json!("10.0.0.1"));
+		assert_eq!(scalar_to_json("BOOL", &[1]).unwrap(), json!(true));
+		assert_eq!(scalar_to_json("JSONB", b"\x01{\"a\": [1]}").unwrap(), json!({"a": [1]}));
+		assert!(scalar_to_json("JSONB", b"\x02{}").is_err());
+		assert_eq!(scalar_to_json("TIME", &14_706_500_000i64.to_be_bytes()).unwrap(), json!("04:05:06.500"));
+		let timetz = [&36_000_000_000i64.to_be_bytes()[..], &(-7200i32).to_be_bytes()].concat();
+		assert_eq!(scalar_to_json("TIMETZ", &timetz).unwrap(), json!("10:00:00+02:00"));
+		let interval = [&14_706_500_000i64.to_be_bytes()[..], &3i32.to_be_bytes(), &14i32.to_be_bytes()].concat();
+		assert_eq!(scalar_to_json("INTERVAL", &interval).unwrap(), json!("P1Y2M3DT4H5M6.5S"));
+		assert!(scalar_to_json("INT4", &[0, 1]).is_err());
+	}
+
+	#[test]
+	fn test_inet() {
+		assert_eq!(inet_to_string(&[2, 32, 0, 4, 10, 0, 0, 1]).unwrap(), "10.0.0.1");
+		assert_eq!(inet_to_string(&[2, 24, 0, 4, 10, 0, 0, 1]).unwrap(), "10.0.0.1/24");
+		assert_eq!(inet_to_string(&[2, 32, 1, 4, 10, 0, 0, 1]).unwrap(), "10.0.0.1/32");
+		let mut ipv6 = vec![3, 64, 1, 16, 0x20, 0x01, 0x0d, 0xb8];
+		ipv6.extend_from_slice(&[0; 12]);
+		assert_eq!(inet_to_string(&ipv6).unwrap(), "2001:db8::/64");
+		assert!(inet_to_string(&[2, 32, 0, 4, 10]).is_err());
+	}
+
+	#[test]
+	fn test_mac_and_bits() {
+		assert_eq!(macaddr_to_string(&[0x08, 0x00, 0x2b, 0x01, 0x02, 0x03]), "08:00:2b:01:02:03");
+		assert_eq!(bits_to_string(&[0, 0, 0, 10, 0b1010_1010, 0b1100_0000]).unwrap(), "1010101011");
+		assert_eq!(bits_to_string(&[0, 0, 0, 0]).unwrap(), "");
+		assert!(bits_to_string(&[0, 0, 0, 9, 0xff]).is_err());
+	}
+
+	#[test]
+	fn test_money_and_floats() {
+		assert_eq!(money_to_string(123456), "1234.56");
+		assert_eq!(money_to_string(-5), "-0.05");
+		assert_eq!(float4_to_json(1.1), json!(1.1));
+		assert_eq!(float8_to_json(f64::NAN), json!("NaN"));
+		assert_eq!(float8_to_json(f64::NEG_INFINITY), json!("-Infinity"));
+	}
+
+	#[test]
+	fn test_date_time() {
+		assert_eq!(timestamp_to_string(0).unwrap(), "2000-01-01T00:00:00.000000");
+		assert_eq!(timestamp_to_string(-1).unwrap(), "1999-12-31T23:59:59.999999");
+		assert_eq!(timestamp_to_string(i64::MAX).unwrap(), "infinity");
+		assert_eq!(timestamptz_to_string(1_500_000).unwrap(), "2000-01-01T00:00:01.500+00:00");
+		assert_eq!(date_to_string(-1).unwrap(), "1999-12-31");
+		assert_eq!(date_to_string(i32::MIN).unwrap(), "-infinity");
+	}
+
+	#[test]
+	fn test_interval() {
+		let interval = |months, days, microseconds| PgInterval { months, days, microseconds };
+		assert_eq!(interval_to_iso8601(&interval(14, 3, 14_706_500_000)), "P1Y2M3DT4H5M6.5S");
+		assert_eq!(interval_to_iso8601(&interval(0, 0, 0)), "PT0S");
+		assert_eq!(interval_to_iso8601(&interval(0, -1, -1_000_000)), "P-1DT-1S");
+	}
+}
@@ -0,0 +1,220 @@
+//! Point-in-time recovery of the embedded Postgres.
+//!
+//! With `WAL_ARCHIVE_DIR`, the embedded Postgres copies each completed WAL segment into the archive
+//! and the backups include a base backup of its data directory (see [`crate::backup`]). Recovering
+//! to a time unpacks the latest base backup taken before it into a new data directory, and lets
+//! Postgres replay the archived WAL until that time.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::info;
+use utility::env::Variables;
+use utility::errors::AppResult;
+use crate::backup::{backup_error, BackupSettings, Manifest, BASE_DIR};
+use crate::setup::{get_connection, install_postgres_in, PoolSettings};
+
+/// Settings of the embedded Postgres, included by `postgresql.conf` and rewritten at each start
+const CONF: &str = "qaswa.conf";
+
+#[derive(Debug, Clone)]
+pub struct ArchiveSettings {
+	pub dir: PathBuf,
+	/// Seconds after which a partially filled segment is archived anyway, bounding the data lost
+	/// with the server
+	pub timeout: u64,
+}
+
+impl ArchiveSettings {
+	/// `None` when `WAL_ARCHIVE_DIR` is empty
+	pub fn from_env(config: &Variables) -> Option<Self> {
+		match config.wal_archive_dir.is_empty() {
+			true => None,
+			false => Some(Self {
+				dir: PathBuf::from(&config.wal_archive_dir),
+				timeout: config.wal_archive_timeout,
+			}),
+		}
+	}
+}
+
+/// Enable or disable WAL archiving in the data directory, before Postgres starts. The WAL is
+/// always logical, for change data capture (see [`crate::cdc`])
+pub fn configure(data_dir: &Path, archive: Option<&ArchiveSettings>) -> AppResult<()> {
+	let conf = data_dir.join("postgresql.conf");
+	let include = format!("include_if_exists = '{}'", CONF);
+	let content = std::fs::read_to_string(&conf)?;
+	if !content.lines().any(|line| line.trim() == include) {
+		std::fs::write(&conf, format!("{}\n{}\n", content.trim_end(), include))?;
+	}
+
+	let archiving = match archive {
+		Some(archive) => {
+			std::fs::create_dir_all(&archive.dir)?;
+			let dir = std::path::absolute(&archive.dir)?;
+			format!(
+				"archive_mode = on\narchive_command = {}\narchive_timeout = {}\n",
+				quote(&archive_command(&dir)),
+				archive.timeout,
+			)
+		}
+		None => "archive_mode = off\n".to_string(),
+	};
+	std::fs::write(data_dir.join(CONF), format!("wal_level = logical\n{}", archiving))?;
+	Ok(())
+}
+
+/// Copy `%p` into the archive, failing rather than overwriting a segment already archived
+#[cfg(not(windows))]
+fn archive_command(dir: &Path) -> String {
+	format!("test ! -f \"{0}/%f\" && cp \"%p\" \"{0}/%f\"", dir.display())
+}
+
+#[cfg(windows)]
+fn archive_command(dir: &Path) -> String {
+	format!("if not exist \"{0}\\%f\" copy \"%p\" \"{0}\\%f\"", dir.display())
+}
+
+#[cfg(not(windows))]
+fn restore_command(dir: &Path) -> String {
+	format!("cp \"{}/%f\" \"%p\"", dir.display())
+}
+
+#[cfg(windows)]
+fn restore_command(dir: &Path) -> String {
+	format!("copy \"{}\\%f\" \"%p\"", dir.display())
+}
+
+/// Quote a value of `postgresql.conf`
+fn quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Latest backup whose base backup finished before `target`, `manifests` being most recent first
+fn base_for(manifests: &[Manifest], target: DateTime<Utc>) -> Option<&Manifest> {
+	manifests.iter().find(|manifest| matches!(&manifest.base_backup, Some(base) if base.finished_at <= target))
+}
+
+/// Unpack the base backup preceding `target` into `data_dir` and configure Postgres to replay the
+/// archived WAL until `target` then promote
+pub fn prepare(settings: &BackupSettings, target: DateTime<Utc>, data_dir: &Path) -> AppResult<Manifest> {
+	let archive = settings.wal_archive.as_ref()
+		.ok_or_else(|| backup_error("WAL_ARCHIVE_DIR is not set, there is no WAL to recover from".to_string()))?;
+	if data_dir.exists() && std::fs::read_dir(data_dir)?.next().is_some() {
+		return Err(backup_error(format!("{:?} is not empty, recover into a new data directory", data_dir)));
+	}
+	let manifests = crate::backup::list(&settings.dir)?;
+	let manifest = base_for(&manifests, target)
+		.ok_or_else(|| backup_error(format!("no base backup before {}", target.to_rfc3339())))?
+		.clone();
+	crate::backup::verify(&settings.dir, &manifest)?;
+	info!("recovering from base backup {} to {}", manifest.id, target.to_rfc3339());
+
+	crate::backup::unpack(&settings.dir.join(&manifest.id).join(BASE_DIR), data_dir)?;
+	// Postgres refuses to start on a data directory readable by others
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(data_dir, std::fs::Permissions::from_mode(0o700))?;
+	}
+
+	let archive = std::path::absolute(archive)?;
+	let recovery = format!(
+		"restore_command = {}\nrecovery_target_time = {}\nrecovery_target_action = 'promote'\n",
+		quote(&restore_command(&archive)),
+		// Postgres refuses the `T` and `Z` of RFC 3339
+		quote(&target.format("%Y-%m-%d %H:%M:%S%.6f+00").to_string()),
+	);
+	let auto_conf = data_dir.join("postgresql.auto.conf");
+	let content = std::fs::read_to_string(&auto_conf).unwrap_or_default();
+	std::fs::write(&auto_conf, format!("{}{}", content, recovery))?;
+	std::fs::write(data_dir.join("recovery.signal"), "")?;
+	Ok(manifest)
+}
+
+/// Recover the embedded Postgres as it was at `target` into the new data directory `data_dir`.
+///
+/// Postgres is started on `port` without archiving, and stopped once the recovery is finished.
+#[tracing::instrument(skip(config, settings))]
+pub async fn recover(config: &Variables, settings: &BackupSettings, target: DateTime<Utc>, data_dir: &Path, port: u16) -> AppResult<Manifest> {
+	let manifest = prepare(settings, target, data_dir)?;
+	let mut pg_server = install_postgres_in(config, data_dir, port, None).await?;
+	let pool_settings = PoolSettings { min_connections: 0, max_connections: 1, ..Default::default() };
+	let pool = get_connection(&pg_server.full_db_uri("postgres"), &pool_settings).await?;
+	loop {
+		match sqlx::query_scalar::<_, bool>("SELECT pg_is_in_recovery()").fetch_one(&pool).await {
+			Ok(false) => break,
+			Ok(true) => tokio::time::sleep(Duration::from_secs(1)).await,
+			// Postgres shuts down when the archive ends before the target
+			Err(err) => return Err(backup_error(format!(
+				"recovery stopped before {}, the WAL archive may end earlier: {}", target.to_rfc3339(), err
+			))),
+		}
+	}
+	pool.close().await;
+	pg_server.stop_db().await?;
+	info!("recovered to {} in {:?}", target.to_rfc3339(), data_dir);
+	Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::backup::BaseBackup;
+	use super::*;
+
+	fn at(value: &str) -> DateTime<Utc> {
+		DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+	}
+
+	fn manifest(id: &str, finished_at: Option<&str>) -> Manifest {
+		Manifest {
+			id: id.to_string(),
+			created_at: at("2023-09-01T00:00:00Z"),
+			database: "qaswa".to_string(),
+			pg_dump_version: String::new(),
+			duration_ms: 0,
+			files: vec![],
+			base_backup: finished_at.map(|finished_at| BaseBackup {
+				finished_at: at(finished_at),
+				start_wal: "000000010000000000000002".to_string(),
+			}),
+		}
+	}
+
+	#[test]
+	fn test_base_for() {
+		let manifests = vec![
+			manifest("c", Some("2023-09-03T00:00:00Z")),
+			manifest("b", None),
+			manifest("a", Some("2023-09-01T00:00:00Z")),
+		];
+		assert_eq!(base_for(&manifests, at("2023-09-04T00:00:00Z")).unwrap().id, "c");
+		assert_eq!(base_for(&manifests, at("2023-09-02T12:00:00Z")).unwrap().id, "a");
+		assert_eq!(base_for(&manifests, at("2023-09-01T00:00:00Z")).unwrap().id, "a");
+		assert!(base_for(&manifests, at("2023-08-31T00:00:00Z")).is_none());
+	}
+
+	#[test]
+	fn test_configure() {
+		let dir = std::env::temp_dir().join(format!("qaswa-pitr-{}", std::process::id()));
+		let data_dir = dir.join("data");
+		std::fs::create_dir_all(&data_dir).unwrap();
+		std::fs::write(data_dir.join("postgresql.conf"), "port = 5432\n").unwrap();
+
+		let archive = ArchiveSettings { dir: dir.join("it's wal"), timeout: 60 };
+		configure(&data_dir, Some(&archive)).unwrap();
+		configure(&data_dir, Some(&archive)).unwrap();
+		let conf = std::fs::read_to_string(data_dir.join("postgresql.conf")).unwrap();
+		assert_eq!(conf, "port = 5432\ninclude_if_exists = 'qaswa.conf'\n");
+		let qaswa = std::fs::read_to_string(data_dir.join(CONF)).unwrap();
+		assert!(qaswa.starts_with("wal_level = logical\n"));
+		assert!(qaswa.contains("archive_mode = on\n"));
+		assert!(qaswa.contains("it''s wal/%f"));
+		assert!(qaswa.contains("archive_timeout = 60\n"));
+		assert!(archive.dir.is_dir());
+
+		configure(&data_dir, None).unwrap();
+		assert_eq!(std::fs::read_to_string(data_dir.join(CONF)).unwrap(), "wal_level = logical\narchive_mode = off\n");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,173 @@
+//! Tables, columns and relationships exposed by the REST API, read from `pg_catalog`
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sqlx::Row;
+use tokio::sync::Mutex;
+use utility::errors::AppResult;
+use crate::setup::PgDb;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+	pub name: String,
+	/// Type as printed by `format_type`, e.g. `character varying(20)` or `integer[]`
+	pub data_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+	pub name: String,
+	pub columns: Vec<String>,
+	pub foreign_table: String,
+	pub foreign_columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+	pub name: String,
+	pub columns: Vec<Column>,
+	pub foreign_keys: Vec<ForeignKey>,
+	/// Columns of the primary key, empty for views and tables without one
+	pub primary_key: Vec<String>,
+}
+
+impl Table {
+	pub fn column(&self, name: &str) -> Option<&Column> {
+		self.columns.iter().find(|column| column.name == name)
+	}
+}
+
+/// Tables and views of one schema
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+	pub schema: String,
+	pub tables: HashMap<String, Table>,
+}
+
+impl Catalog {
+	/// Read the tables, views, primary keys and foreign keys of `schema`
+	#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+	pub async fn load(pg: &PgDb, schema: &str) -> AppResult<Self> {
+		let mut tables: HashMap<String, Table> = HashMap::new();
+
+		let columns = sqlx::query(r#"
+			SELECT c.relname::text AS table_name, a.attname::text AS column_name,
+				format_type(a.atttypid, a.atttypmod) AS data_type
+			FROM pg_catalog.pg_class c
+			JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+			JOIN pg_catalog.pg_attribute a ON a.attrelid = c.oid
+			WHERE n.nspname = $1 AND c.relkind IN ('r', 'p', 'v', 'm', 'f')
+				AND a.attnum > 0 AND NOT a.attisdropped
+			ORDER BY c.relname, a.attnum;
+		"#)
+			.bind(schema)
+			.fetch_all(pg)
+			.await?;
+		for row in columns {
+			let name: String = row.try_get("table_name")?;
+			tables
+				.entry(name.clone())
+				.or_insert_with(|| Table { name, ..Default::default() })
+				.columns
+				.push(Column { name: row.try_get("column_name")?, data_type: row.try_get("data_type")? });
+		}
+
+		let foreign_keys = sqlx::query(r#"
+			SELECT con.conname::text AS name, c.relname::text AS table_name, f.relname::text AS foreign_table,
+				ARRAY(
+					SELECT a.attname::text FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, i)
+					JOIN pg_catalog.pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+					ORDER BY k.i
+				) AS columns,
+				ARRAY(
+					SELECT a.attname::text FROM unnest(con.confkey) WITH ORDINALITY AS k(attnum, i)
+					JOIN pg_catalog.pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+					ORDER BY k.i
+				) AS foreign_columns
+			FROM pg_catalog.pg_constraint con
+			JOIN pg_catalog.pg_class c ON c.oid = con.conrelid
+			JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+			JOIN pg_catalog.pg_class f ON f.oid = con.confrelid
+			JOIN pg_catalog.pg_namespace fn ON fn.oid = f.relnamespace
+			WHERE con.contype = 'f' AND n.nspname = $1 AND fn.nspname = $1
+			ORDER BY con.conname;
+		"#)
+			.bind(schema)
+			.fetch_all(pg)
+			.await?;
+		for row in foreign_keys {
+			let table: String = row.try_get("table_name")?;
+			if let Some(table) = tables.get_mut(&table) {
+				table.foreign_keys.push(ForeignKey {
+					name: row.try_get("name")?,
+					columns: row.try_get("columns")?,
+					foreign_table: row.try_get("foreign_table")?,
+					foreign_columns: row.try_get("foreign_columns")?,
+				});
+			}
+		}
+
+		let primary_keys = sqlx::query(r#"
+			SELECT c.relname::text AS table_name,
+				ARRAY(
+					SELECT a.attname::text FROM unnest(i.indkey) WITH ORDINALITY AS k(attnum, i)
+					JOIN pg_catalog.pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+					ORDER BY k.i
+				) AS columns
+			FROM pg_catalog.pg_index i
+			JOIN pg_catalog.pg_class c ON c.oid = i.indrelid
+			JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+			WHERE i.indisprimary AND n.nspname = $1;
+		"#)
+			.bind(schema)
+			.fetch_all(pg)
+			.await?;
+		for row in primary_keys {
+			let table: String = row.try_get("table_name")?;
+			if let Some(table) = tables.get_mut(&table) {
+				table.primary_key = row.try_get("columns")?;
+			}
+		}
+
+		Ok(Self { schema: schema.to_string(), tables })
+	}
+}
+
+/// Catalog of one schema read at most once per `ttl`, so that requests do not query `pg_catalog`
+pub struct CatalogCache {
+	schema: String,
+	ttl: Duration,
+	current: Mutex<Option<(Instant, Arc<Catalog>)>>,
+}
+
+impl CatalogCache {
+	pub fn new(schema: &str, ttl: Duration) -> Self {
+		Self { schema: schema.to_string(), ttl, current: Mutex::new(None) }
+	}
+
+	/// Catalog read less than `ttl` ago, or read now
+	pub async fn get(&self, pg: &PgDb) -> AppResult<Arc<Catalog>> {
+		let mut current = self.current.lock().await;
+		match current.as_ref() {
+			Some((read, catalog)) if read.elapsed() < self.ttl => Ok(catalog.clone()),
+			_ => self.load(pg, &mut current).await,
+		}
+	}
+
+	/// Catalog read again when it still is `stale`, e.g. it lacks a table created since; concurrent
+	/// callers with the same `stale` catalog read it once
+	pub async fn reload(&self, pg: &PgDb, stale: &Arc<Catalog>) -> AppResult<Arc<Catalog>> {
+		let mut current = self.current.lock().await;
+		match current.as_ref() {
+			Some((_, catalog)) if !Arc::ptr_eq(catalog, stale) => Ok(catalog.clone()),
+			_ => self.load(pg, &mut current).await,
+		}
+	}
+
+	async fn load(&self, pg: &PgDb, current: &mut Option<(Instant, Arc<Catalog>)>) -> AppResult<Arc<Catalog>> {
+		let catalog = Arc::new(Catalog::load(pg, &self.schema).await?);
+		*current = Some((Instant::now(), catalog.clone()));
+		Ok(catalog)
+	}
+}
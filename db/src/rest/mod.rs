@@ -0,0 +1,88 @@
+//! REST API over the tables of the `public` schema, in the style of PostgREST. Statements run
+//! for users, with their identity (see [`crate::ops::Identity`]).
+//!
+//! Statements are built from a cached [`Catalog`]. A request naming a table or a column the
+//! cached catalog lacks is built again from the catalog read anew, so new tables are served at
+//! once; other changes show once the cache expires.
+
+pub mod catalog;
+pub mod query;
+pub mod sql;
+
+use serde_json::Value;
+use utility::errors::{AppError, AppResult};
+use crate::ops::{exec_sql, ExecOptions};
+use crate::rest::catalog::{Catalog, CatalogCache};
+use crate::rest::query::{Page, RestQuery};
+use crate::rest::sql::Statement;
+use crate::setup::{PgDb, PgResultSet};
+
+/// Schema whose tables and views are exposed
+pub const SCHEMA: &str = "public";
+
+/// Write requests, `Insert` takes a row or an array of rows, `Update` the changed columns
+#[derive(Debug, Clone, Copy)]
+pub enum Mutation<'b> {
+	Insert(&'b Value),
+	Update(&'b Value),
+	Delete,
+}
+
+/// Rows of `table` matching `query`, at most `options.max_rows`
+pub async fn read(pg: &PgDb, catalog: &CatalogCache, table: &str, query: &RestQuery, options: &ExecOptions) -> AppResult<PgResultSet> {
+	options.user_identity()?;
+	let statement = build(pg, catalog, |catalog| sql::select(catalog, table, query, options.max_rows as i64)).await?;
+	Ok(exec_sql(pg, &statement.sql, &statement.params, options).await?.rows)
+}
+
+/// Rows of `table` whose `columns` equal one of `keys`, objects of the key values: the `page` of
+/// the rows of each key, whose `limit` defaults to, and is capped by, `options.max_rows`
+pub async fn read_keys(
+	pg: &PgDb,
+	catalog: &CatalogCache,
+	table: &str,
+	columns: &[String],
+	keys: &[Value],
+	page: Page,
+	options: &ExecOptions,
+) -> AppResult<PgResultSet> {
+	options.user_identity()?;
+	let max_rows = options.max_rows as i64;
+	let page = Page { limit: Some(page.limit.unwrap_or(max_rows).min(max_rows)), ..page };
+	let statement = build(pg, catalog, |catalog| sql::select_keys(catalog, table, columns, keys, page)).await?;
+	// The statement returns at most the page of each key
+	let options = ExecOptions { max_rows: options.max_rows.saturating_mul(keys.len().max(1)), ..options.clone() };
+	Ok(exec_sql(pg, &statement.sql, &statement.params, &options).await?.rows)
+}
+
+/// Apply `mutation` to `table`, the changed rows are returned when `returning`
+pub async fn write(
+	pg: &PgDb,
+	catalog: &CatalogCache,
+	table: &str,
+	query: &RestQuery,
+	mutation: Mutation<'_>,
+	returning: bool,
+	options: &ExecOptions,
+) -> AppResult<PgResultSet> {
+	options.user_identity()?;
+	let statement = build(pg, catalog, |catalog| match mutation {
+		Mutation::Insert(body) => sql::insert(catalog, table, query, body, returning),
+		Mutation::Update(body) => sql::update(catalog, table, query, body, returning),
+		Mutation::Delete => sql::delete(catalog, table, query, returning),
+	})
+	.await?;
+	Ok(exec_sql(pg, &statement.sql, &statement.params, options).await?.rows)
+}
+
+/// Statement built from the cached catalog, or from the catalog read again when it does not build
+async fn build<F>(pg: &PgDb, catalog: &CatalogCache, statement: F) -> AppResult<Statement>
+where
+	F: Fn(&Catalog) -> AppResult<Statement>,
+{
+	let cached = catalog.get(pg).await?;
+	match statement(&cached) {
+		Err(AppError::NotFound { .. } | AppError::BadRequest { .. }) => statement(&*catalog.reload(pg, &cached).await?),
+		result => result,
+	}
+}
@@ -0,0 +1,326 @@
+//! Query string of REST requests, e.g.
+//! `?select=id,title,author(name)&published=is.true&id=in.(1,2)&order=id.desc&limit=10`
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+	/// `*`, every column
+	Star,
+	Column(String),
+	/// `relation(items)`, rows of a table related by a foreign key
+	Embed { relation: String, select: Vec<SelectItem> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+	Eq,
+	Neq,
+	Lt,
+	Lte,
+	Gt,
+	Gte,
+	Like,
+	Ilike,
+	In,
+	Is,
+}
+
+impl FromStr for Operator {
+	type Err = String;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		Ok(match name {
+			"eq" => Operator::Eq,
+			"neq" => Operator::Neq,
+			"lt" => Operator::Lt,
+			"lte" => Operator::Lte,
+			"gt" => Operator::Gt,
+			"gte" => Operator::Gte,
+			"like" => Operator::Like,
+			"ilike" => Operator::Ilike,
+			"in" => Operator::In,
+			"is" => Operator::Is,
+			name => return Err(format!("unknown operator {:?}", name)),
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+	Single(String),
+	/// Values of `in.(a,b,"c,d")`
+	List(Vec<String>),
+	/// Value of `is`, always a keyword
+	Is(IsValue),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsValue {
+	Null,
+	True,
+	False,
+	Unknown,
+}
+
+impl IsValue {
+	pub fn keyword(&self) -> &'static str {
+		match self {
+			IsValue::Null => "NULL",
+			IsValue::True => "TRUE",
+			IsValue::False => "FALSE",
+			IsValue::Unknown => "UNKNOWN",
+		}
+	}
+}
+
+/// `column=[not.]operator.value`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+	pub column: String,
+	pub negated: bool,
+	pub operator: Operator,
+	pub value: FilterValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderTerm {
+	pub column: String,
+	pub descending: bool,
+	/// `Some(true)` for `NULLS FIRST`, `Some(false)` for `NULLS LAST`
+	pub nulls_first: Option<bool>,
+}
+
+/// Rows of each key read at once by [`crate::rest::read_keys`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Page {
+	pub limit: Option<i64>,
+	pub offset: Option<i64>,
+}
+
+/// Parsed query string, `select` defaults to `*`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestQuery {
+	pub select: Vec<SelectItem>,
+	pub filters: Vec<Filter>,
+	pub order: Vec<OrderTerm>,
+	pub limit: Option<i64>,
+	pub offset: Option<i64>,
+}
+
+impl Default for RestQuery {
+	fn default() -> Self {
+		Self { select: vec![SelectItem::Star], filters: vec![], order: vec![], limit: None, offset: None }
+	}
+}
+
+impl RestQuery {
+	/// Parse decoded query string pairs, every key which is not `select`, `order`, `limit`
+	/// or `offset` is a filter on the column of the same name
+	pub fn parse(pairs: &[(String, String)]) -> Result<Self, String> {
+		let mut query = Self::default();
+		for (key, value) in pairs {
+			match key.as_str() {
+				"select" => query.select = parse_select(value)?,
+				"order" => query.order = parse_order(value)?,
+				"limit" => query.limit = Some(parse_count("limit", value)?),
+				"offset" => query.offset = Some(parse_count("offset", value)?),
+				column => query.filters.push(parse_filter(column, value)?),
+			}
+		}
+		Ok(query)
+	}
+}
+
+fn parse_count(key: &str, value: &str) -> Result<i64, String> {
+	value
+		.parse::<i64>()
+		.ok()
+		.filter(|count| *count >= 0)
+		.ok_or_else(|| format!("{}: expected a positive integer, got {:?}", key, value))
+}
+
+/// Split `text` on `separator`, outside of parentheses and double quotes
+fn split_top_level(text: &str, separator: char) -> Result<Vec<String>, String> {
+	let mut parts = vec![];
+	let mut current = String::new();
+	let mut depth = 0usize;
+	let mut quoted = false;
+	for c in text.chars() {
+		match c {
+			'"' => quoted = !quoted,
+			'(' if !quoted => depth += 1,
+			')' if !quoted => depth = depth.checked_sub(1).ok_or_else(|| format!("unbalanced parentheses in {:?}", text))?,
+			c if c == separator && !quoted && depth == 0 => {
+				parts.push(std::mem::take(&mut current));
+				continue;
+			}
+			_ => {}
+		}
+		current.push(c);
+	}
+	if depth != 0 || quoted {
+		return Err(format!("unbalanced parentheses or quotes in {:?}", text));
+	}
+	parts.push(current);
+	Ok(parts)
+}
+
+fn parse_select(text: &str) -> Result<Vec<SelectItem>, String> {
+	split_top_level(text, ',')?
+		.into_iter()
+		.map(|item| {
+			let item = item.trim();
+			if item == "*" {
+				return Ok(SelectItem::Star);
+			}
+			match item.split_once('(') {
+				Some((relation, rest)) => {
+					let inner = rest.strip_suffix(')').ok_or_else(|| format!("select: invalid embedding {:?}", item))?;
+					let select = match inner.trim() {
+						"" => vec![SelectItem::Star],
+						inner => parse_select(inner)?,
+					};
+					Ok(SelectItem::Embed { relation: identifier(relation)?, select })
+				}
+				None => Ok(SelectItem::Column(identifier(item)?)),
+			}
+		})
+		.collect()
+}
+
+fn parse_order(text: &str) -> Result<Vec<OrderTerm>, String> {
+	text
+		.split(',')
+		.map(|term| {
+			let mut parts = term.trim().split('.');
+			let column = identifier(parts.next().unwrap_or_default())?;
+			let mut order = OrderTerm { column, descending: false, nulls_first: None };
+			for modifier in parts {
+				match modifier {
+					"asc" => order.descending = false,
+					"desc" => order.descending = true,
+					"nullsfirst" => order.nulls_first = Some(true),
+					"nullslast" => order.nulls_first = Some(false),
+					modifier => return Err(format!("order: unknown modifier {:?}", modifier)),
+				}
+			}
+			Ok(order)
+		})
+		.collect()
+}
+
+pub(crate) fn parse_filter(column: &str, text: &str) -> Result<Filter, String> {
+	let column = identifier(column)?;
+	let (negated, text) = match text.strip_prefix("not.") {
+		Some(text) => (true, text),
+		None => (false, text),
+	};
+	let (operator, value) = text
+		.split_once('.')
+		.ok_or_else(|| format!("{}: expected operator.value, got {:?}", column, text))?;
+	let operator = operator.parse::<Operator>().map_err(|err| format!("{}: {}", column, err))?;
+	let value = match operator {
+		Operator::In => {
+			let list = value
+				.strip_prefix('(')
+				.and_then(|value| value.strip_suffix(')'))
+				.ok_or_else(|| format!("{}: expected in.(value,...), got {:?}", column, value))?;
+			match list.is_empty() {
+				true => FilterValue::List(vec![]),
+				false => FilterValue::List(split_top_level(list, ',')?.iter().map(|item| unquote(item)).collect()),
+			}
+		}
+		Operator::Is => FilterValue::Is(match value.to_lowercase().as_str() {
+			"null" => IsValue::Null,
+			"true" => IsValue::True,
+			"false" => IsValue::False,
+			"unknown" => IsValue::Unknown,
+			value => return Err(format!("{}: is expects null, true, false or unknown, got {:?}", column, value)),
+		}),
+		// `*` is an URL friendly `%`
+		Operator::Like | Operator::Ilike => FilterValue::Single(value.replace('*', "%")),
+		_ => FilterValue::Single(value.to_string()),
+	};
+	Ok(Filter { column, negated, operator, value })
+}
+
+fn unquote(item: &str) -> String {
+	let item = item.trim();
+	match item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+		true => item[1..item.len() - 1].to_string(),
+		false => item.to_string(),
+	}
+}
+
+/// Names are checked against the catalog later, only their syntax is checked here
+fn identifier(name: &str) -> Result<String, String> {
+	let name = name.trim();
+	match name.is_empty() || name.contains(|c: char| c.is_control() || "(),.\"".contains(c)) {
+		true => Err(format!("invalid name {:?}", name)),
+		false => Ok(name.to_string()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(query: &str) -> Result<RestQuery, String> {
+		let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap();
+		RestQuery::parse(&pairs)
+	}
+
+	#[test]
+	fn test_select() {
+		let query = parse("select=id,title,author(name,country(*)),tags()").unwrap();
+		assert_eq!(query.select, vec![
+			SelectItem::Column("id".to_string()),
+			SelectItem::Column("title".to_string()),
+			SelectItem::Embed {
+				relation: "author".to_string(),
+				select: vec![
+					SelectItem::Column("name".to_string()),
+					SelectItem::Embed { relation: "country".to_string(), select: vec![SelectItem::Star] },
+				],
+			},
+			SelectItem::Embed { relation: "tags".to_string(), select: vec![SelectItem::Star] },
+		]);
+		assert_eq!(parse("").unwrap().select, vec![SelectItem::Star]);
+		assert!(parse("select=author(name").is_err());
+		assert!(parse("select=a.b").is_err());
+	}
+
+	#[test]
+	fn test_filters() {
+		let query = parse("id=in.(1,2,%22a,b%22)&name=not.like.*ab*&deleted_at=is.null&age=gte.18").unwrap();
+		assert_eq!(query.filters, vec![
+			Filter {
+				column: "id".to_string(),
+				negated: false,
+				operator: Operator::In,
+				value: FilterValue::List(vec!["1".to_string(), "2".to_string(), "a,b".to_string()]),
+			},
+			Filter { column: "name".to_string(), negated: true, operator: Operator::Like, value: FilterValue::Single("%ab%".to_string()) },
+			Filter { column: "deleted_at".to_string(), negated: false, operator: Operator::Is, value: FilterValue::Is(IsValue::Null) },
+			Filter { column: "age".to_string(), negated: false, operator: Operator::Gte, value: FilterValue::Single("18".to_string()) },
+		]);
+		assert_eq!(parse("id=eq.a.b").unwrap().filters[0].value, FilterValue::Single("a.b".to_string()));
+		assert!(parse("id=1").is_err());
+		assert!(parse("id=between.1").is_err());
+		assert!(parse("id=is.maybe").is_err());
+		assert!(parse("id=in.1,2").is_err());
+	}
+
+	#[test]
+	fn test_order_and_pagination() {
+		let query = parse("order=age.desc.nullslast,name&limit=10&offset=20").unwrap();
+		assert_eq!(query.order, vec![
+			OrderTerm { column: "age".to_string(), descending: true, nulls_first: Some(false) },
+			OrderTerm { column: "name".to_string(), descending: false, nulls_first: None },
+		]);
+		assert_eq!((query.limit, query.offset), (Some(10), Some(20)));
+		assert!(parse("limit=-1").is_err());
+		assert!(parse("order=age.sideways").is_err());
+	}
+}
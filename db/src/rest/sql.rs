@@ -0,0 +1,464 @@
+//! Statements of REST requests.
+//!
+//! Table and column names are checked against the catalog and quoted, values are always
+//! bind parameters: filter values are bound as text and cast to the column type, bodies
+//! are bound as `jsonb` and converted by `jsonb_populate_record(set)`.
+
+use serde_json::{Map, Value};
+use utility::errors::{AppError, AppResult};
+use crate::params::{Param, ParamType, ScalarType};
+use crate::rest::catalog::{Catalog, ForeignKey, Table};
+use crate::rest::query::{Filter, FilterValue, Operator, OrderTerm, Page, RestQuery, SelectItem};
+
+#[derive(Debug)]
+pub struct Statement {
+	pub sql: String,
+	pub params: Vec<Param>,
+}
+
+/// `SELECT` of the rows matching the filters, at most `max_rows`
+pub fn select(catalog: &Catalog, table: &str, query: &RestQuery, max_rows: i64) -> AppResult<Statement> {
+	let mut builder = Builder::new(catalog);
+	let table = builder.table(table)?;
+	let alias = builder.alias();
+	let list = builder.select_list(table, &alias, &query.select)?;
+	let filters = builder.where_clause(table, &alias, &query.filters)?;
+	let order = order_clause(table, &alias, &query.order)?;
+	let limit = builder.bind(Value::from(query.limit.unwrap_or(max_rows).min(max_rows)), ScalarType::Int8);
+	let offset = builder.bind(Value::from(query.offset.unwrap_or(0)), ScalarType::Int8);
+
+	let sql = format!(
+		"SELECT {} FROM {} AS {}{}{} LIMIT {} OFFSET {}",
+		list, builder.qualified(table), alias, filters, order, limit, offset,
+	);
+	Ok(builder.finish(sql))
+}
+
+/// `SELECT` of the rows whose `columns` equal one of `keys`, objects of the key values, to load
+/// the rows related to many others at once. `page` applies to the rows of each key, ordered by
+/// the primary key
+pub fn select_keys(catalog: &Catalog, table: &str, columns: &[String], keys: &[Value], page: Page) -> AppResult<Statement> {
+	let mut builder = Builder::new(catalog);
+	let table = builder.table(table)?;
+	let alias = builder.alias();
+	let columns = columns.iter().map(|name| column(table, name).map(quote)).collect::<AppResult<Vec<String>>>()?;
+	let target = builder.qualified(table);
+	let keys = builder.bind(Value::Array(keys.to_vec()), ScalarType::Jsonb);
+
+	let own = columns.iter().map(|column| format!("{}.{}", alias, column)).collect::<Vec<String>>().join(", ");
+	let key = columns.iter().map(|column| format!("_key.{}", column)).collect::<Vec<String>>().join(", ");
+	let mut clauses = String::new();
+	if !table.primary_key.is_empty() {
+		let order = table.primary_key.iter().map(|column| format!("{}.{}", alias, quote(column))).collect::<Vec<String>>();
+		clauses.push_str(&format!(" ORDER BY {}", order.join(", ")));
+	}
+	if let Some(limit) = page.limit {
+		clauses.push_str(&format!(" LIMIT {}", builder.bind(Value::from(limit), ScalarType::Int8)));
+	}
+	if let Some(offset) = page.offset {
+		clauses.push_str(&format!(" OFFSET {}", builder.bind(Value::from(offset), ScalarType::Int8)));
+	}
+	let sql = format!(
+		"SELECT {}.* FROM (SELECT DISTINCT {} FROM jsonb_populate_recordset(NULL::{}, {}) AS _key) AS _key \
+		CROSS JOIN LATERAL (SELECT {}.* FROM {} AS {} WHERE ({}) = ({}){}) AS {}",
+		alias, key, target, keys, alias, target, alias, own, key, clauses, alias,
+	);
+	Ok(builder.finish(sql))
+}
+
+/// `INSERT` of a row, or of an array of rows
+pub fn insert(catalog: &Catalog, table: &str, query: &RestQuery, body: &Value, returning: bool) -> AppResult<Statement> {
+	check_mutation(query, false)?;
+	let mut builder = Builder::new(catalog);
+	let table = builder.table(table)?;
+	let rows = match body {
+		Value::Object(row) => vec![row],
+		Value::Array(rows) => rows
+			.iter()
+			.map(|row| row.as_object().ok_or_else(|| bad_request("the body must be an object or an array of objects")))
+			.collect::<AppResult<Vec<&Map<String, Value>>>>()?,
+		_ => return Err(bad_request("the body must be an object or an array of objects")),
+	};
+	if rows.is_empty() {
+		return Err(bad_request("no row to insert"));
+	}
+
+	// Keys missing from some of the rows are inserted as NULL
+	let mut columns: Vec<&str> = vec![];
+	for key in rows.iter().flat_map(|row| row.keys()) {
+		if !columns.contains(&key.as_str()) {
+			columns.push(column(table, key)?);
+		}
+	}
+
+	let target = builder.qualified(table);
+	let dml = match (columns.is_empty(), rows.len()) {
+		(true, 1) => format!("INSERT INTO {} DEFAULT VALUES", target),
+		(true, _) => return Err(bad_request("rows without columns cannot be inserted in bulk")),
+		(false, _) => {
+			let rows = builder.bind(Value::Array(rows.into_iter().cloned().map(Value::Object).collect()), ScalarType::Jsonb);
+			let columns = columns.iter().map(|column| quote(column)).collect::<Vec<String>>().join(", ");
+			format!(
+				"INSERT INTO {} ({}) SELECT {} FROM jsonb_populate_recordset(NULL::{}, {})",
+				target, columns, columns, target, rows,
+			)
+		}
+	};
+	builder.mutation(table, dml, "*", &query.select, returning)
+}
+
+/// `UPDATE` of the rows matching the filters with the values of `body`
+pub fn update(catalog: &Catalog, table: &str, query: &RestQuery, body: &Value, returning: bool) -> AppResult<Statement> {
+	check_mutation(query, true)?;
+	let mut builder = Builder::new(catalog);
+	let table = builder.table(table)?;
+	let values = match body {
+		Value::Object(values) if !values.is_empty() => values,
+		_ => return Err(bad_request("the body must be an object with the updated columns")),
+	};
+
+	let alias = builder.alias();
+	let assignments = values
+		.keys()
+		.map(|key| column(table, key).map(|column| format!("{} = _body.{}", quote(column), quote(column))))
+		.collect::<AppResult<Vec<String>>>()?
+		.join(", ");
+	let target = builder.qualified(table);
+	let body = builder.bind(body.clone(), ScalarType::Jsonb);
+	let filters = builder.where_clause(table, &alias, &query.filters)?;
+	let dml = format!(
+		"UPDATE {} AS {} SET {} FROM jsonb_populate_record(NULL::{}, {}) AS _body{}",
+		target, alias, assignments, target, body, filters,
+	);
+	builder.mutation(table, dml, &format!("{}.*", alias), &query.select, returning)
+}
+
+/// `DELETE` of the rows matching the filters
+pub fn delete(catalog: &Catalog, table: &str, query: &RestQuery, returning: bool) -> AppResult<Statement> {
+	check_mutation(query, true)?;
+	let mut builder = Builder::new(catalog);
+	let table = builder.table(table)?;
+	let alias = builder.alias();
+	let filters = builder.where_clause(table, &alias, &query.filters)?;
+	let dml = format!("DELETE FROM {} AS {}{}", builder.qualified(table), alias, filters);
+	builder.mutation(table, dml, &format!("{}.*", alias), &query.select, returning)
+}
+
+/// Updates and deletes without filters would change the whole table, which is never what a
+/// client building its filters from user input means
+fn check_mutation(query: &RestQuery, requires_filters: bool) -> AppResult<()> {
+	if !query.order.is_empty() || query.limit.is_some() || query.offset.is_some() {
+		return Err(bad_request("order, limit and offset are not supported on writes"));
+	}
+	if requires_filters && query.filters.is_empty() {
+		return Err(bad_request("at least one filter is required, e.g. `?id=eq.1`"));
+	}
+	Ok(())
+}
+
+struct Builder<'c> {
+	catalog: &'c Catalog,
+	params: Vec<Param>,
+	aliases: usize,
+}
+
+impl<'c> Builder<'c> {
+	fn new(catalog: &'c Catalog) -> Self {
+		Self { catalog, params: vec![], aliases: 0 }
+	}
+
+	fn finish(self, sql: String) -> Statement {
+		Statement { sql, params: self.params }
+	}
+
+	/// Placeholder of `value`
+	fn bind(&mut self, value: Value, scalar: ScalarType) -> String {
+		self.params.push(Param { value, type_hint: Some(ParamType { scalar, array: false }) });
+		format!("${}", self.params.len())
+	}
+
+	/// Text placeholder cast to `data_type`, a type name printed by Postgres itself
+	fn bind_as(&mut self, value: &str, data_type: &str) -> String {
+		let placeholder = self.bind(Value::String(value.to_string()), ScalarType::Text);
+		format!("CAST({} AS {})", placeholder, data_type)
+	}
+
+	/// Unique alias of a table in the statement
+	fn alias(&mut self) -> String {
+		self.aliases += 1;
+		format!("t{}", self.aliases)
+	}
+
+	fn table(&self, name: &str) -> AppResult<&'c Table> {
+		self.catalog
+			.tables
+			.get(name)
+			.ok_or_else(|| AppError::NotFound { message: format!("table {:?} not found", name) })
+	}
+
+	fn qualified(&self, table: &Table) -> String {
+		format!("{}.{}", quote(&self.catalog.schema), quote(&table.name))
+	}
+
+	/// `WITH` returning the changed rows through the select list when `returning`
+	fn mutation(mut self, table: &Table, dml: String, returned: &str, select: &[SelectItem], returning: bool) -> AppResult<Statement> {
+		if !returning {
+			return Ok(self.finish(dml));
+		}
+		let alias = self.alias();
+		let list = self.select_list(table, &alias, select)?;
+		let sql = format!("WITH _mutation AS ({} RETURNING {}) SELECT {} FROM _mutation AS {}", dml, returned, list, alias);
+		Ok(self.finish(sql))
+	}
+
+	fn select_list(&mut self, table: &Table, alias: &str, items: &[SelectItem]) -> AppResult<String> {
+		let items = items
+			.iter()
+			.map(|item| match item {
+				SelectItem::Star => Ok(format!("{}.*", alias)),
+				SelectItem::Column(name) => column(table, name).map(|column| format!("{}.{}", alias, quote(column))),
+				SelectItem::Embed { relation, select } => self.embed(table, alias, relation, select),
+			})
+			.collect::<AppResult<Vec<String>>>()?;
+		Ok(items.join(", "))
+	}
+
+	/// Rows of `relation` related to `table` by a single foreign key: the referenced row as an
+	/// object when `table` has the foreign key, the referencing rows as an array otherwise
+	fn embed(&mut self, table: &Table, alias: &str, relation: &str, select: &[SelectItem]) -> AppResult<String> {
+		let related = self
+			.catalog
+			.tables
+			.get(relation)
+			.ok_or_else(|| bad_request(&format!("no relationship between {:?} and {:?}", table.name, relation)))?;
+		let outgoing: Vec<&ForeignKey> = table.foreign_keys.iter().filter(|fk| fk.foreign_table == related.name).collect();
+		let incoming: Vec<&ForeignKey> = related.foreign_keys.iter().filter(|fk| fk.foreign_table == table.name).collect();
+
+		let child = self.alias();
+		let list = self.select_list(related, &child, select)?;
+		let join = |fk: &ForeignKey, own: &str, foreign: &str| {
+			fk.columns
+				.iter()
+				.zip(&fk.foreign_columns)
+				.map(|(column, foreign_column)| format!("{}.{} = {}.{}", own, quote(column), foreign, quote(foreign_column)))
+				.collect::<Vec<String>>()
+				.join(" AND ")
+		};
+		let rows = |join: String| format!("SELECT {} FROM {} AS {} WHERE {}", list, self.qualified(related), child, join);
+
+		match (outgoing.as_slice(), incoming.as_slice()) {
+			([fk], []) => Ok(format!(
+				"(SELECT row_to_json(_{}) FROM ({}) AS _{}) AS {}",
+				child, rows(join(fk, alias, &child)), child, quote(relation),
+			)),
+			([], [fk]) => Ok(format!(
+				"COALESCE((SELECT json_agg(_{}) FROM ({}) AS _{}), '[]'::json) AS {}",
+				child, rows(join(fk, &child, alias)), child, quote(relation),
+			)),
+			([], []) => Err(bad_request(&format!("no relationship between {:?} and {:?}", table.name, relation))),
+			_ => Err(bad_request(&format!("more than one relationship between {:?} and {:?}", table.name, relation))),
+		}
+	}
+
+	fn where_clause(&mut self, table: &Table, alias: &str, filters: &[Filter]) -> AppResult<String> {
+		if filters.is_empty() {
+			return Ok(String::new());
+		}
+		let conditions = filters
+			.iter()
+			.map(|filter| self.condition(table, alias, filter))
+			.collect::<AppResult<Vec<String>>>()?;
+		Ok(format!(" WHERE {}", conditions.join(" AND ")))
+	}
+
+	fn condition(&mut self, table: &Table, alias: &str, filter: &Filter) -> AppResult<String> {
+		let name = column(table, &filter.column)?;
+		let data_type = table.column(name).map(|column| column.data_type.as_str()).unwrap_or("text");
+		let target = format!("{}.{}", alias, quote(name));
+
+		let condition = match (filter.operator, &filter.value) {
+			(Operator::Is, FilterValue::Is(value)) => format!("{} IS {}", target, value.keyword()),
+			(Operator::In, FilterValue::List(values)) if values.is_empty() => "false".to_string(),
+			(Operator::In, FilterValue::List(values)) => {
+				let values = values.iter().map(|value| self.bind_as(value, data_type)).collect::<Vec<String>>();
+				format!("{} IN ({})", target, values.join(", "))
+			}
+			(Operator::Like, FilterValue::Single(value)) => {
+				format!("{}::text LIKE {}", target, self.bind(Value::String(value.clone()), ScalarType::Text))
+			}
+			(Operator::Ilike, FilterValue::Single(value)) => {
+				format!("{}::text ILIKE {}", target, self.bind(Value::String(value.clone()), ScalarType::Text))
+			}
+			(operator, FilterValue::Single(value)) => {
+				let operator = match operator {
+					Operator::Eq => "=",
+					Operator::Neq => "<>",
+					Operator::Lt => "<",
+					Operator::Lte => "<=",
+					Operator::Gt => ">",
+					Operator::Gte => ">=",
+					operator => return Err(bad_request(&format!("{}: invalid value for {:?}", name, operator))),
+				};
+				format!("{} {} {}", target, operator, self.bind_as(value, data_type))
+			}
+			(operator, _) => return Err(bad_request(&format!("{}: invalid value for {:?}", name, operator))),
+		};
+		match filter.negated {
+			true => Ok(format!("NOT ({})", condition)),
+			false => Ok(condition),
+		}
+	}
+}
+
+fn order_clause(table: &Table, alias: &str, order: &[OrderTerm]) -> AppResult<String> {
+	if order.is_empty() {
+		return Ok(String::new());
+	}
+	let terms = order
+		.iter()
+		.map(|term| {
+			let mut sql = format!("{}.{}", alias, quote(column(table, &term.column)?));
+			sql.push_str(if term.descending { " DESC" } else { " ASC" });
+			match term.nulls_first {
+				Some(true) => sql.push_str(" NULLS FIRST"),
+				Some(false) => sql.push_str(" NULLS LAST"),
+				None => {}
+			}
+			Ok(sql)
+		})
+		.collect::<AppResult<Vec<String>>>()?;
+	Ok(format!(" ORDER BY {}", terms.join(", ")))
+}
+
+/// Name of the column `name` of `table`
+fn column<'t>(table: &'t Table, name: &str) -> AppResult<&'t str> {
+	table
+		.column(name)
+		.map(|column| column.name.as_str())
+		.ok_or_else(|| bad_request(&format!("column {:?} of {:?} not found", name, table.name)))
+}
+
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn bad_request(message: &str) -> AppError {
+	AppError::BadRequest { message: message.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use crate::rest::catalog::Column;
+	use super::*;
+
+	fn catalog() -> Catalog {
+		let table = |name: &str, columns: &[(&str, &str)], foreign_keys: Vec<ForeignKey>| Table {
+			name: name.to_string(),
+			columns: columns.iter().map(|(name, data_type)| Column { name: name.to_string(), data_type: data_type.to_string() }).collect(),
+			foreign_keys,
+			..Default::default()
+		};
+		let tables = [
+			table("authors", &[("id", "integer"), ("name", "text")], vec![]),
+			table("books", &[("id", "integer"), ("title", "text"), ("author_id", "integer")], vec![ForeignKey {
+				name: "books_author_id_fkey".to_string(),
+				columns: vec!["author_id".to_string()],
+				foreign_table: "authors".to_string(),
+				foreign_columns: vec!["id".to_string()],
+			}]),
+		];
+		Catalog {
+			schema: "public".to_string(),
+			tables: HashMap::from_iter(tables.into_iter().map(|table| (table.name.clone(), table))),
+		}
+	}
+
+	fn query(query: &str) -> RestQuery {
+		let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap();
+		RestQuery::parse(&pairs).unwrap()
+	}
+
+	fn values(statement: &Statement) -> Vec<Value> {
+		statement.params.iter().map(|param| param.value.clone()).collect()
+	}
+
+	#[test]
+	fn test_select() {
+		let statement = select(&catalog(), "books", &query("select=id,title&id=in.(1,2)&title=like.*rust*&order=id.desc&limit=5000"), 100).unwrap();
+		assert_eq!(
+			statement.sql,
+			r#"SELECT t1."id", t1."title" FROM "public"."books" AS t1 WHERE t1."id" IN (CAST($1 AS integer), CAST($2 AS integer)) AND t1."title"::text LIKE $3 ORDER BY t1."id" DESC LIMIT $4 OFFSET $5"#
+		);
+		assert_eq!(values(&statement), vec![Value::from("1"), Value::from("2"), Value::from("%rust%"), Value::from(100), Value::from(0)]);
+
+		let statement = select(&catalog(), "books", &query("id=not.is.null&author_id=eq.1;DROP TABLE books"), 100).unwrap();
+		assert!(statement.sql.contains(r#"NOT (t1."id" IS NULL) AND t1."author_id" = CAST($1 AS integer)"#));
+		assert_eq!(statement.params[0].value, Value::from("1;DROP TABLE books"));
+
+		assert!(matches!(select(&catalog(), "missing", &query(""), 100), Err(AppError::NotFound { .. })));
+		assert!(matches!(select(&catalog(), "books", &query("select=missing"), 100), Err(AppError::BadRequest { .. })));
+	}
+
+	#[test]
+	fn test_embed() {
+		let statement = select(&catalog(), "books", &query("select=title,authors(name)"), 10).unwrap();
+		assert!(statement.sql.starts_with(
+			r#"SELECT t1."title", (SELECT row_to_json(_t2) FROM (SELECT t2."name" FROM "public"."authors" AS t2 WHERE t1."author_id" = t2."id") AS _t2) AS "authors" FROM"#
+		), "{}", statement.sql);
+
+		let statement = select(&catalog(), "authors", &query("select=*,books(*)"), 10).unwrap();
+		assert!(statement.sql.starts_with(
+			r#"SELECT t1.*, COALESCE((SELECT json_agg(_t2) FROM (SELECT t2.* FROM "public"."books" AS t2 WHERE t2."author_id" = t1."id") AS _t2), '[]'::json) AS "books" FROM"#
+		), "{}", statement.sql);
+
+		assert!(select(&catalog(), "authors", &query("select=authors(*)"), 10).is_err());
+	}
+
+	#[test]
+	fn test_select_keys() {
+		let keys = vec![serde_json::json!({"author_id": 1}), serde_json::json!({"author_id": 2})];
+		let statement = select_keys(&catalog(), "books", &["author_id".to_string()], &keys, Page::default()).unwrap();
+		assert_eq!(
+			statement.sql,
+			r#"SELECT t1.* FROM (SELECT DISTINCT _key."author_id" FROM jsonb_populate_recordset(NULL::"public"."books", $1) AS _key) AS _key CROSS JOIN LATERAL (SELECT t1.* FROM "public"."books" AS t1 WHERE (t1."author_id") = (_key."author_id")) AS t1"#
+		);
+		assert_eq!(values(&statement), vec![Value::Array(keys.clone())]);
+
+		// Pages of the rows of each key, in the order of the primary key
+		let mut catalog = catalog();
+		catalog.tables.get_mut("books").unwrap().primary_key = vec!["id".to_string()];
+		let page = Page { limit: Some(2), offset: Some(4) };
+		let statement = select_keys(&catalog, "books", &["author_id".to_string()], &keys, page).unwrap();
+		assert!(statement.sql.ends_with(r#"WHERE (t1."author_id") = (_key."author_id") ORDER BY t1."id" LIMIT $2 OFFSET $3) AS t1"#), "{}", statement.sql);
+		assert_eq!(values(&statement), vec![Value::Array(keys), Value::from(2), Value::from(4)]);
+		assert!(select_keys(&catalog, "books", &["missing".to_string()], &[], Page::default()).is_err());
+	}
+
+	#[test]
+	fn test_mutations() {
+		let body = serde_json::json!([{"title": "a"}, {"title": "b", "author_id": 1}]);
+		let statement = insert(&catalog(), "books", &query("select=id"), &body, true).unwrap();
+		assert_eq!(
+			statement.sql,
+			r#"WITH _mutation AS (INSERT INTO "public"."books" ("title", "author_id") SELECT "title", "author_id" FROM jsonb_populate_recordset(NULL::"public"."books", $1) RETURNING *) SELECT t1."id" FROM _mutation AS t1"#
+		);
+		assert_eq!(values(&statement), vec![body]);
+		assert!(insert(&catalog(), "books", &query(""), &serde_json::json!({"pages": 1}), false).is_err());
+
+		let statement = update(&catalog(), "books", &query("id=eq.1"), &serde_json::json!({"title": "c"}), false).unwrap();
+		assert_eq!(
+			statement.sql,
+			r#"UPDATE "public"."books" AS t1 SET "title" = _body."title" FROM jsonb_populate_record(NULL::"public"."books", $1) AS _body WHERE t1."id" = CAST($2 AS integer)"#
+		);
+		assert!(update(&catalog(), "books", &query(""), &serde_json::json!({"title": "c"}), false).is_err());
+
+		let statement = delete(&catalog(), "books", &query("id=eq.1"), true).unwrap();
+		assert_eq!(
+			statement.sql,
+			r#"WITH _mutation AS (DELETE FROM "public"."books" AS t1 WHERE t1."id" = CAST($1 AS integer) RETURNING t1.*) SELECT t2.* FROM _mutation AS t2"#
+		);
+		assert!(delete(&catalog(), "books", &query(""), true).is_err());
+		assert!(delete(&catalog(), "books", &query("id=eq.1&limit=1"), true).is_err());
+	}
+}
@@ -0,0 +1,511 @@
+//! Description of Postgres schemas, read from `pg_catalog`
+
+use std::collections::HashMap;
+use serde::Serialize;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use utility::errors::{AppError, AppResult};
+use crate::setup::PgDb;
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct SchemaInfo {
+	pub name: String,
+	pub owner: String,
+	pub comment: Option<String>,
+}
+
+/// Everything defined in a schema
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SchemaDetail {
+	pub name: String,
+	pub tables: Vec<TableInfo>,
+	pub views: Vec<ViewInfo>,
+	pub functions: Vec<FunctionInfo>,
+	pub enums: Vec<EnumInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ColumnInfo {
+	pub name: String,
+	/// Type as printed by `format_type`, e.g. `character varying(20)`
+	pub data_type: String,
+	/// Name of the type in `pg_type`, e.g. `varchar` or `_int4` for arrays
+	pub udt_name: String,
+	pub nullable: bool,
+	/// Default expression
+	pub default: Option<String>,
+	pub is_identity: bool,
+	pub is_generated: bool,
+	pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PrimaryKey {
+	pub name: String,
+	pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ForeignKeyInfo {
+	pub name: String,
+	pub columns: Vec<String>,
+	pub foreign_schema: String,
+	pub foreign_table: String,
+	pub foreign_columns: Vec<String>,
+	/// `NO ACTION`, `RESTRICT`, `CASCADE`, `SET NULL` or `SET DEFAULT`
+	pub on_update: String,
+	pub on_delete: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IndexInfo {
+	pub name: String,
+	/// Indexed columns, or expressions
+	pub columns: Vec<String>,
+	pub unique: bool,
+	pub primary: bool,
+	/// Access method, e.g. `btree` or `gin`
+	pub method: String,
+	/// `CREATE INDEX` statement
+	pub definition: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TableInfo {
+	pub name: String,
+	/// `table`, `partitioned table` or `foreign table`
+	pub kind: String,
+	pub comment: Option<String>,
+	/// Row count estimated by the planner, -1 when the table was never analyzed
+	pub estimated_rows: i64,
+	pub columns: Vec<ColumnInfo>,
+	pub primary_key: Option<PrimaryKey>,
+	pub foreign_keys: Vec<ForeignKeyInfo>,
+	pub indexes: Vec<IndexInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ViewInfo {
+	pub name: String,
+	pub materialized: bool,
+	pub comment: Option<String>,
+	/// `SELECT` statement of the view
+	pub definition: String,
+	pub columns: Vec<ColumnInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct FunctionInfo {
+	pub name: String,
+	/// `function`, `procedure`, `aggregate` or `window`
+	pub kind: String,
+	/// Arguments as in `CREATE FUNCTION`, e.g. `a integer, b text DEFAULT 'x'::text`
+	pub arguments: String,
+	pub return_type: Option<String>,
+	pub language: String,
+	/// `immutable`, `stable` or `volatile`
+	pub volatility: String,
+	pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct EnumInfo {
+	pub name: String,
+	pub values: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct RelationRow {
+	oid: i64,
+	name: String,
+	kind: String,
+	comment: Option<String>,
+	estimated_rows: i64,
+	definition: Option<String>,
+}
+
+#[derive(FromRow)]
+struct ColumnRow {
+	relation: i64,
+	#[sqlx(flatten)]
+	column: ColumnInfo,
+}
+
+#[derive(FromRow)]
+struct ConstraintRow {
+	relation: i64,
+	kind: String,
+	name: String,
+	columns: Vec<String>,
+	foreign_schema: Option<String>,
+	foreign_table: Option<String>,
+	foreign_columns: Vec<String>,
+	on_update: String,
+	on_delete: String,
+}
+
+#[derive(FromRow)]
+struct IndexRow {
+	relation: i64,
+	name: String,
+	columns: Vec<String>,
+	unique: bool,
+	primary: bool,
+	method: String,
+	definition: String,
+}
+
+/// User schemas, without `pg_catalog`, `information_schema` and the other internal schemas
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn list_schemas(pg: &PgDb) -> AppResult<Vec<SchemaInfo>> {
+	let schemas = sqlx::query_as::<_, SchemaInfo>(r#"
+		SELECT n.nspname::text AS name, pg_get_userbyid(n.nspowner)::text AS owner,
+			obj_description(n.oid, 'pg_namespace') AS comment
+		FROM pg_catalog.pg_namespace n
+		WHERE n.nspname !~ '^pg_' AND n.nspname <> 'information_schema'
+		ORDER BY n.nspname;
+	"#)
+		.fetch_all(pg)
+		.await?;
+	Ok(schemas)
+}
+
+/// Tables, views, functions and enums of `schema`
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn describe_schema(pg: &PgDb, schema: &str) -> AppResult<SchemaDetail> {
+	check_schema(pg, schema).await?;
+	let (tables, views) = relations(pg, schema, None).await?;
+
+	let functions = sqlx::query_as::<_, FunctionInfo>(r#"
+		SELECT p.proname::text AS name,
+			CASE p.prokind WHEN 'p' THEN 'procedure' WHEN 'a' THEN 'aggregate' WHEN 'w' THEN 'window' ELSE 'function' END AS kind,
+			pg_get_function_arguments(p.oid) AS arguments,
+			pg_get_function_result(p.oid) AS return_type,
+			l.lanname::text AS language,
+			CASE p.provolatile WHEN 'i' THEN 'immutable' WHEN 's' THEN 'stable' ELSE 'volatile' END AS volatility,
+			obj_description(p.oid, 'pg_proc') AS comment
+		FROM pg_catalog.pg_proc p
+		JOIN pg_catalog.pg_namespace n ON n.oid = p.pronamespace
+		JOIN pg_catalog.pg_language l ON l.oid = p.prolang
+		WHERE n.nspname = $1
+			-- functions of extensions belong to the extension, not to the schema
+			AND NOT EXISTS (SELECT 1 FROM pg_catalog.pg_depend d WHERE d.objid = p.oid AND d.deptype = 'e')
+		ORDER BY p.proname, arguments;
+	"#)
+		.bind(schema)
+		.fetch_all(pg)
+		.await?;
+
+	let enums = sqlx::query_as::<_, EnumInfo>(r#"
+		SELECT t.typname::text AS name,
+			ARRAY(SELECT e.enumlabel::text FROM pg_catalog.pg_enum e WHERE e.enumtypid = t.oid ORDER BY e.enumsortorder) AS "values"
+		FROM pg_catalog.pg_type t
+		JOIN pg_catalog.pg_namespace n ON n.oid = t.typnamespace
+		WHERE n.nspname = $1 AND t.typtype = 'e'
+		ORDER BY t.typname;
+	"#)
+		.bind(schema)
+		.fetch_all(pg)
+		.await?;
+
+	Ok(SchemaDetail { name: schema.to_string(), tables, views, functions, enums })
+}
+
+/// Columns, keys and indexes of `schema`.`table`
+#[tracing::instrument(skip(pg), fields(otel.kind = "client", db.system = "postgresql"))]
+pub async fn describe_table(pg: &PgDb, schema: &str, table: &str) -> AppResult<TableInfo> {
+	check_schema(pg, schema).await?;
+	relations(pg, schema, Some(table))
+		.await?
+		.0
+		.pop()
+		.ok_or_else(|| AppError::NotFound { message: format!("table {:?}.{:?} not found", schema, table) })
+}
+
+async fn check_schema(pg: &PgDb, schema: &str) -> AppResult<()> {
+	let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_catalog.pg_namespace WHERE nspname = $1);")
+		.bind(schema)
+		.fetch_one(pg)
+		.await?;
+	match exists {
+		true => Ok(()),
+		false => Err(AppError::NotFound { message: format!("schema {:?} not found", schema) }),
+	}
+}
+
+/// Tables and views of `schema`, or only `table` when given
+async fn relations(pg: &PgDb, schema: &str, table: Option<&str>) -> AppResult<(Vec<TableInfo>, Vec<ViewInfo>)> {
+	let relations = sqlx::query_as::<_, RelationRow>(r#"
+		SELECT c.oid::int8 AS oid, c.relname::text AS name, c.relkind::text AS kind,
+			obj_description(c.oid, 'pg_class') AS comment,
+			c.reltuples::int8 AS estimated_rows,
+			CASE WHEN c.relkind IN ('v', 'm') THEN pg_get_viewdef(c.oid, true) END AS definition
+		FROM pg_catalog.pg_class c
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		WHERE n.nspname = $1 AND c.relkind IN ('r', 'p', 'f', 'v', 'm') AND ($2::text IS NULL OR c.relname = $2)
+		ORDER BY c.relname;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_all(pg)
+		.await?;
+
+	let mut columns: HashMap<i64, Vec<ColumnInfo>> = HashMap::new();
+	let rows = sqlx::query_as::<_, ColumnRow>(r#"
+		SELECT a.attrelid::int8 AS relation, a.attname::text AS name,
+			format_type(a.atttypid, a.atttypmod) AS data_type, t.typname::text AS udt_name,
+			NOT a.attnotnull AS nullable, pg_get_expr(d.adbin, d.adrelid) AS "default",
+			a.attidentity <> '' AS is_identity, a.attgenerated <> '' AS is_generated,
+			col_description(a.attrelid, a.attnum) AS comment
+		FROM pg_catalog.pg_attribute a
+		JOIN pg_catalog.pg_class c ON c.oid = a.attrelid
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		JOIN pg_catalog.pg_type t ON t.oid = a.atttypid
+		LEFT JOIN pg_catalog.pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+		WHERE n.nspname = $1 AND c.relkind IN ('r', 'p', 'f', 'v', 'm') AND ($2::text IS NULL OR c.relname = $2)
+			AND a.attnum > 0 AND NOT a.attisdropped
+		ORDER BY a.attrelid, a.attnum;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_all(pg)
+		.await?;
+	for row in rows {
+		columns.entry(row.relation).or_default().push(row.column);
+	}
+
+	let constraints = sqlx::query_as::<_, ConstraintRow>(r#"
+		SELECT con.conrelid::int8 AS relation, con.contype::text AS kind, con.conname::text AS name,
+			ARRAY(
+				SELECT a.attname::text FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, i)
+				JOIN pg_catalog.pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+				ORDER BY k.i
+			) AS columns,
+			fn.nspname::text AS foreign_schema, f.relname::text AS foreign_table,
+			ARRAY(
+				SELECT a.attname::text FROM unnest(con.confkey) WITH ORDINALITY AS k(attnum, i)
+				JOIN pg_catalog.pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+				ORDER BY k.i
+			) AS foreign_columns,
+			con.confupdtype::text AS on_update, con.confdeltype::text AS on_delete
+		FROM pg_catalog.pg_constraint con
+		JOIN pg_catalog.pg_class c ON c.oid = con.conrelid
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		LEFT JOIN pg_catalog.pg_class f ON f.oid = con.confrelid
+		LEFT JOIN pg_catalog.pg_namespace fn ON fn.oid = f.relnamespace
+		WHERE n.nspname = $1 AND con.contype IN ('p', 'f') AND ($2::text IS NULL OR c.relname = $2)
+		ORDER BY con.conname;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_all(pg)
+		.await?;
+
+	let indexes = sqlx::query_as::<_, IndexRow>(r#"
+		SELECT i.indrelid::int8 AS relation, ic.relname::text AS name,
+			ARRAY(SELECT pg_get_indexdef(i.indexrelid, k, true) FROM generate_series(1, i.indnkeyatts) AS k ORDER BY k) AS columns,
+			i.indisunique AS "unique", i.indisprimary AS "primary", am.amname::text AS method,
+			pg_get_indexdef(i.indexrelid) AS definition
+		FROM pg_catalog.pg_index i
+		JOIN pg_catalog.pg_class ic ON ic.oid = i.indexrelid
+		JOIN pg_catalog.pg_am am ON am.oid = ic.relam
+		JOIN pg_catalog.pg_class c ON c.oid = i.indrelid
+		JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+		WHERE n.nspname = $1 AND ($2::text IS NULL OR c.relname = $2)
+		ORDER BY ic.relname;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_all(pg)
+		.await?;
+	Ok(assemble(relations, columns, &constraints, &indexes))
+}
+
+/// Tables and views out of the rows of `pg_class`, with their columns, constraints and indexes
+fn assemble(
+	relations: Vec<RelationRow>,
+	mut columns: HashMap<i64, Vec<ColumnInfo>>,
+	constraints: &[ConstraintRow],
+	indexes: &[IndexRow],
+) -> (Vec<TableInfo>, Vec<ViewInfo>) {
+	let mut tables = vec![];
+	let mut views = vec![];
+	for relation in relations {
+		let columns = columns.remove(&relation.oid).unwrap_or_default();
+		match relation.kind.as_str() {
+			"v" | "m" => views.push(ViewInfo {
+				name: relation.name,
+				materialized: relation.kind == "m",
+				comment: relation.comment,
+				definition: relation.definition.unwrap_or_default(),
+				columns,
+			}),
+			kind => {
+				let constraints = constraints.iter().filter(|constraint| constraint.relation == relation.oid);
+				tables.push(TableInfo {
+					name: relation.name,
+					kind: match kind {
+						"p" => "partitioned table",
+						"f" => "foreign table",
+						_ => "table",
+					}.to_string(),
+					comment: relation.comment,
+					estimated_rows: relation.estimated_rows,
+					columns,
+					primary_key: constraints
+						.clone()
+						.find(|constraint| constraint.kind == "p")
+						.map(|constraint| PrimaryKey { name: constraint.name.clone(), columns: constraint.columns.clone() }),
+					foreign_keys: constraints
+						.filter(|constraint| constraint.kind == "f")
+						.map(|constraint| ForeignKeyInfo {
+							name: constraint.name.clone(),
+							columns: constraint.columns.clone(),
+							foreign_schema: constraint.foreign_schema.clone().unwrap_or_default(),
+							foreign_table: constraint.foreign_table.clone().unwrap_or_default(),
+							foreign_columns: constraint.foreign_columns.clone(),
+							on_update: referential_action(&constraint.on_update).to_string(),
+							on_delete: referential_action(&constraint.on_delete).to_string(),
+						})
+						.collect(),
+					indexes: indexes
+						.iter()
+						.filter(|index| index.relation == relation.oid)
+						.map(|index| IndexInfo {
+							name: index.name.clone(),
+							columns: index.columns.clone(),
+							unique: index.unique,
+							primary: index.primary,
+							method: index.method.clone(),
+							definition: index.definition.clone(),
+						})
+						.collect(),
+				});
+			}
+		}
+	}
+	(tables, views)
+}
+
+/// Action of `pg_constraint.confupdtype` and `confdeltype`
+fn referential_action(code: &str) -> &'static str {
+	match code {
+		"r" => "RESTRICT",
+		"c" => "CASCADE",
+		"n" => "SET NULL",
+		"d" => "SET DEFAULT",
+		_ => "NO ACTION",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn column(name: &str) -> ColumnInfo {
+		ColumnInfo {
+			name: name.to_string(),
+			data_type: "integer".to_string(),
+			udt_name: "int4".to_string(),
+			nullable: false,
+			default: None,
+			is_identity: false,
+			is_generated: false,
+			comment: None,
+		}
+	}
+
+	fn relation(oid: i64, name: &str, kind: &str, definition: Option<&str>) -> RelationRow {
+		RelationRow {
+			oid,
+			name: name.to_string(),
+			kind: kind.to_string(),
+			comment: None,
+			estimated_rows: 10,
+			definition: definition.map(str::to_string),
+		}
+	}
+
+	fn constraint(relation: i64, kind: &str, name: &str, columns: &[&str], foreign: Option<(&str, &[&str])>, actions: (&str, &str)) -> ConstraintRow {
+		ConstraintRow {
+			relation,
+			kind: kind.to_string(),
+			name: name.to_string(),
+			columns: columns.iter().map(|column| column.to_string()).collect(),
+			foreign_schema: foreign.map(|_| "public".to_string()),
+			foreign_table: foreign.map(|(table, _)| table.to_string()),
+			foreign_columns: foreign.map_or(vec![], |(_, columns)| columns.iter().map(|column| column.to_string()).collect()),
+			on_update: actions.0.to_string(),
+			on_delete: actions.1.to_string(),
+		}
+	}
+
+	#[test]
+	fn test_assemble() {
+		let relations = vec![
+			relation(1, "authors", "r", None),
+			relation(2, "books", "p", None),
+			relation(3, "recent_books", "m", Some(" SELECT books.id FROM books;")),
+			relation(4, "remote", "f", None),
+		];
+		let columns = HashMap::from([
+			(1, vec![column("id")]),
+			(2, vec![column("id"), column("author_id")]),
+			(3, vec![column("id")]),
+		]);
+		let constraints = vec![
+			constraint(2, "f", "books_author_id_fkey", &["author_id"], Some(("authors", &["id"])), ("c", "n")),
+			constraint(1, "p", "authors_pkey", &["id"], None, (" ", " ")),
+			constraint(2, "p", "books_pkey", &["id", "author_id"], None, (" ", " ")),
+		];
+		let indexes = vec![IndexRow {
+			relation: 1,
+			name: "authors_pkey".to_string(),
+			columns: vec!["id".to_string()],
+			unique: true,
+			primary: true,
+			method: "btree".to_string(),
+			definition: "CREATE UNIQUE INDEX authors_pkey ON public.authors USING btree (id)".to_string(),
+		}];
+
+		let (tables, views) = assemble(relations, columns, &constraints, &indexes);
+		let names: Vec<(&str, &str)> = tables.iter().map(|table| (table.name.as_str(), table.kind.as_str())).collect();
+		assert_eq!(names, vec![("authors", "table"), ("books", "partitioned table"), ("remote", "foreign table")]);
+
+		let authors = &tables[0];
+		assert_eq!(authors.primary_key.as_ref().map(|key| key.name.as_str()), Some("authors_pkey"));
+		assert!(authors.foreign_keys.is_empty());
+		assert_eq!(authors.indexes.len(), 1);
+		assert!(authors.indexes[0].primary);
+
+		let books = &tables[1];
+		assert_eq!(books.columns.iter().map(|column| column.name.as_str()).collect::<Vec<_>>(), vec!["id", "author_id"]);
+		assert_eq!(books.primary_key.as_ref().unwrap().columns, vec!["id", "author_id"]);
+		assert_eq!(books.foreign_keys.len(), 1);
+		let foreign_key = &books.foreign_keys[0];
+		assert_eq!((foreign_key.foreign_schema.as_str(), foreign_key.foreign_table.as_str()), ("public", "authors"));
+		assert_eq!(foreign_key.foreign_columns, vec!["id"]);
+		assert_eq!((foreign_key.on_update.as_str(), foreign_key.on_delete.as_str()), ("CASCADE", "SET NULL"));
+		assert!(books.indexes.is_empty());
+
+		// Relations without columns, e.g. a foreign table whose columns were dropped
+		assert!(tables[2].columns.is_empty());
+		assert!(tables[2].primary_key.is_none());
+
+		assert_eq!(views.len(), 1);
+		assert!(views[0].materialized);
+		assert_eq!(views[0].definition, " SELECT books.id FROM books;");
+		assert_eq!(views[0].columns.len(), 1);
+	}
+
+	#[test]
+	fn test_referential_action() {
+		assert_eq!(referential_action("a"), "NO ACTION");
+		assert_eq!(referential_action("r"), "RESTRICT");
+		assert_eq!(referential_action("c"), "CASCADE");
+		assert_eq!(referential_action("n"), "SET NULL");
+		assert_eq!(referential_action("d"), "SET DEFAULT");
+		// Not a foreign key
+		assert_eq!(referential_action(" "), "NO ACTION");
+	}
+}
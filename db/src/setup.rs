@@ -1,37 +1,114 @@
 pub use pg_embed::postgres::{PgEmbed, PgSettings};
 use pg_embed::pg_enums::{PgAuthMethod, PgServerStatus};
 use pg_embed::pg_fetch::{PgFetchSettings, PG_V15};
-use std::time::Duration;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use std::path::{Path};
-use sqlx::{Pool, Postgres};
-use sqlx::postgres::PgPoolOptions;
-use utility::errors::AppResult;
-use utility::killport::KillPortSignalOptions;
+use std::str::FromStr;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use sqlx::{Describe, Either, Error, Execute, Executor, Pool, Postgres, Transaction};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgQueryResult, PgRow, PgSslMode, PgStatement, PgTypeInfo};
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+use crate::pitr::ArchiveSettings;
 
 pub type PgServer = PgEmbed;
-pub type PgDb = Pool<Postgres>;
 pub type PgResultSet = Vec<serde_json::Value>;
 
-pub async fn install_postgres() -> AppResult<PgServer> {
-	let config = utility::env::Variables::from_env()?;
-	let database_dir = Path::new(config.db_path.as_str());
+/// Called with the wait for each connection out of the pool
+static ACQUIRE_OBSERVER: OnceLock<fn(Duration)> = OnceLock::new();
 
-	let pg_settings = PgSettings {
-		database_dir: database_dir.to_path_buf(),
-		port: config.db_port.clone(),
-		user: config.db_user,
-		password: config.db_pw,
-		auth_method: PgAuthMethod::Plain,
-		persistent: true,
-		timeout: Some(Duration::from_secs(config.db_timeout)),
-		migration_dir: None,
-	};
+/// Report the waits for a pool connection to `observer`, once
+pub fn observe_acquire(observer: fn(Duration)) {
+	let _ = ACQUIRE_OBSERVER.set(observer);
+}
 
-	let fetch_settings = PgFetchSettings{
-		version: PG_V15,
-		..Default::default()
-	};
+async fn observed<T>(acquire: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+	let start = Instant::now();
+	let acquired = acquire.await?;
+	if let Some(observer) = ACQUIRE_OBSERVER.get() {
+		observer(start.elapsed());
+	}
+	Ok(acquired)
+}
+
+/// Connection pool whose every connection, taken by a statement run on the pool or by `acquire` and
+/// `begin`, reports its wait to the acquire observer. Dereferences to the sqlx pool for the rest
+#[derive(Debug, Clone)]
+pub struct PgDb(Pool<Postgres>);
+
+impl PgDb {
+	pub async fn acquire(&self) -> Result<PoolConnection<Postgres>, Error> {
+		observed(self.0.acquire()).await
+	}
+
+	/// Transaction on a connection of the pool, the wait for `BEGIN` is reported too
+	pub async fn begin(&self) -> Result<Transaction<'static, Postgres>, Error> {
+		observed(self.0.begin()).await
+	}
+}
+
+impl Deref for PgDb {
+	type Target = Pool<Postgres>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+/// Same as the executor of the sqlx pool, with the connections taken by `PgDb::acquire`
+impl<'p> Executor<'p> for &'_ PgDb {
+	type Database = Postgres;
+
+	fn fetch_many<'e, 'q: 'e, E>(self, query: E) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, Error>>
+	where
+		E: 'q + Execute<'q, Postgres>,
+	{
+		let pg = self.clone();
+		Box::pin(async_stream::try_stream! {
+			let mut conn = pg.acquire().await?;
+			let mut results = conn.fetch_many(query);
+			while let Some(result) = results.try_next().await? {
+				yield result;
+			}
+		})
+	}
+
+	fn fetch_optional<'e, 'q: 'e, E>(self, query: E) -> BoxFuture<'e, Result<Option<PgRow>, Error>>
+	where
+		E: 'q + Execute<'q, Postgres>,
+	{
+		let pg = self.clone();
+		Box::pin(async move { pg.acquire().await?.fetch_optional(query).await })
+	}
+
+	fn prepare_with<'e, 'q: 'e>(self, sql: &'q str, parameters: &'e [PgTypeInfo]) -> BoxFuture<'e, Result<PgStatement<'q>, Error>> {
+		let pg = self.clone();
+		Box::pin(async move { pg.acquire().await?.prepare_with(sql, parameters).await })
+	}
+
+	fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Postgres>, Error>> {
+		let pg = self.clone();
+		Box::pin(async move { pg.acquire().await?.describe(sql).await })
+	}
+}
+
+pub async fn install_postgres() -> AppResult<PgServer> {
+	let config = utility::env::Variables::from_env()?;
+	let archive = ArchiveSettings::from_env(&config);
+	install_postgres_in(&config, Path::new(config.db_path.as_str()), config.db_port, archive.as_ref()).await
+}
 
+/// Install or start the embedded Postgres with its data in `database_dir`, e.g. a fresh directory
+/// to restore a backup into, archiving its WAL into `archive`. The port must be free: whatever
+/// listens on it is left alone.
+pub async fn install_postgres_in(config: &Variables, database_dir: &Path, port: u16, archive: Option<&ArchiveSettings>) -> AppResult<PgServer> {
+	let (pg_settings, fetch_settings) = embedded_settings(config, database_dir, port);
 	let mut pg = PgServer::new(pg_settings, fetch_settings).await?;
 	let status = *pg.server_status.lock().await;
 	tracing::trace!("pgServerStatus - {status:?}");
@@ -45,6 +122,7 @@ pub async fn install_postgres() -> AppResult<PgServer> {
 	if need_setup {
 		pg.setup().await?;
 	}
+	crate::pitr::configure(database_dir, archive)?;
 
 	let need_startup = match status {
 		PgServerStatus::Uninitialized => true,
@@ -58,16 +136,49 @@ pub async fn install_postgres() -> AppResult<PgServer> {
 	};
 	tracing::trace!("pg need startup ? {}",&need_startup);
 	if need_startup {
-		match utility::killport::kill_processes_by_port(config.db_port, KillPortSignalOptions::SIGKILL) {
-			Ok(_) => {}
-			Err(err) => tracing::error!("{:?}",err)
-		};
-		std::thread::sleep(Duration::from_secs(3));
+		if is_listening(port).await {
+			// Dropping it would stop the Postgres of `database_dir`, which may be the one listening
+			pg.shutting_down = true;
+			return Err(AppError::ConfigError { message: format!("port {} is already in use, stop what listens on it or choose another port", port) });
+		}
 		pg.start_db().await?;
 	}
 	Ok(pg)
 }
 
+/// The embedded Postgres of `database_dir` already listening on `port`, e.g. the one of the
+/// running server for the CLI commands: nothing is installed nor started, and dropping it leaves
+/// it running
+pub async fn attach_postgres(config: &Variables, database_dir: &Path, port: u16) -> AppResult<PgServer> {
+	let (pg_settings, fetch_settings) = embedded_settings(config, database_dir, port);
+	let mut pg = PgServer::new(pg_settings, fetch_settings).await?;
+	pg.shutting_down = true;
+	Ok(pg)
+}
+
+/// Whether a server listens on `port` of the local host
+pub async fn is_listening(port: u16) -> bool {
+	matches!(tokio::time::timeout(Duration::from_secs(1), tokio::net::TcpStream::connect(("localhost", port))).await, Ok(Ok(_)))
+}
+
+fn embedded_settings(config: &Variables, database_dir: &Path, port: u16) -> (PgSettings, PgFetchSettings) {
+	let pg_settings = PgSettings {
+		database_dir: database_dir.to_path_buf(),
+		port,
+		user: config.db_user.clone(),
+		password: config.db_pw.clone(),
+		auth_method: PgAuthMethod::Plain,
+		persistent: true,
+		timeout: Some(Duration::from_secs(config.db_timeout)),
+		migration_dir: None,
+	};
+	let fetch_settings = PgFetchSettings{
+		version: PG_V15,
+		..Default::default()
+	};
+	(pg_settings, fetch_settings)
+}
+
 pub async fn create_db(pg: &PgServer, name: &str) -> AppResult<()> {
 	match pg.database_exists(name).await {
 		Ok(created) => {
@@ -80,19 +191,81 @@ pub async fn create_db(pg: &PgServer, name: &str) -> AppResult<()> {
 	Ok(())
 }
 
-pub async fn get_connection(uri: &str) -> AppResult<PgDb> {
+/// Connection pool settings, see the `DB_*` variables
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+	pub max_connections: u32,
+	pub min_connections: u32,
+	pub acquire_timeout: Duration,
+	/// Idle connections above `min_connections` are closed after this delay
+	pub idle_timeout: Option<Duration>,
+	/// Prepared statements kept per connection, `0` disables the cache (PgBouncer in transaction mode)
+	pub statement_cache_capacity: usize,
+	/// Overrides the `sslmode` of the URL
+	pub ssl_mode: Option<PgSslMode>,
+}
+
+impl Default for PoolSettings {
+	fn default() -> Self {
+		Self {
+			max_connections: 50,
+			min_connections: 10,
+			acquire_timeout: Duration::from_secs(30),
+			idle_timeout: Some(Duration::from_secs(600)),
+			statement_cache_capacity: 100,
+			ssl_mode: None,
+		}
+	}
+}
+
+impl PoolSettings {
+	pub fn from_env(config: &Variables) -> AppResult<Self> {
+		let ssl_mode = match config.db_ssl_mode.as_str() {
+			"" => None,
+			mode => Some(PgSslMode::from_str(mode).map_err(|err| AppError::ConfigError { message: err.to_string() })?),
+		};
+		Ok(Self {
+			max_connections: config.db_max_connections,
+			min_connections: config.db_min_connections,
+			acquire_timeout: Duration::from_secs(config.db_acquire_timeout),
+			idle_timeout: match config.db_idle_timeout {
+				0 => None,
+				seconds => Some(Duration::from_secs(seconds)),
+			},
+			statement_cache_capacity: config.db_statement_cache,
+			ssl_mode,
+		})
+	}
+}
+
+pub async fn get_connection(uri: &str, settings: &PoolSettings) -> AppResult<PgDb> {
+	let mut options = PgConnectOptions::from_str(uri)?.statement_cache_capacity(settings.statement_cache_capacity);
+	if let Some(ssl_mode) = settings.ssl_mode {
+		options = options.ssl_mode(ssl_mode);
+	}
 	let db = PgPoolOptions::new()
-		.max_connections(50)
-		.min_connections(10)
-		.connect(uri).await?;
-	Ok(db)
+		.max_connections(settings.max_connections)
+		.min_connections(settings.min_connections)
+		.acquire_timeout(settings.acquire_timeout)
+		.idle_timeout(settings.idle_timeout)
+		.connect_with(options).await?;
+	Ok(PgDb(db))
 }
 
 #[cfg(test)]
 mod tests {
+	use serde_json::{json, Value};
 	use sqlx::Executor;
+	use crate::extension::res::ResultSet;
 	use crate::ops;
 	use super::*;
+
+	/// Value of the single column of `sql`, decoded from the binary format
+	async fn select(pg: &PgDb, sql: &str) -> Value {
+		let rows = ops::exec_params(pg, sql, &[], None, "").await;
+		assert!(rows.is_ok(), "{}: {:?}", sql, rows.err());
+		rows.unwrap()[0]["v"].clone()
+	}
 	#[tokio::test]
 	async fn test() {
 		let pgs = install_postgres().await;
@@ -104,20 +277,79 @@ mod tests {
 		assert!(created.is_ok(),"{:?}",created.err());
 
 		let uri = pgs.full_db_uri("test");
-		let conn = get_connection(uri.as_str()).await;
+		let conn = get_connection(uri.as_str(), &PoolSettings::default()).await;
 		assert!(conn.is_ok(),"{:?}",conn.err());
 
 		let pg = conn.unwrap();
 		let res = pg.execute("SELECT 1").await;
 		assert!(res.is_ok(),"{:?}",res.err());
 
-		let sd = ops::show_databases(&pg).await;
+		let sd = ops::show_databases(&pg, None, "").await;
 		assert!(sd.is_ok(),"{:?}",sd.err());
 
 		let rs = sd.unwrap();
 		println!("{rs:?}");
 
-		let td = ops::show_tables(&pg).await;
+		let td = ops::show_tables(&pg, None, "").await;
 		assert!(td.is_ok(),"{:?}",td.err());
+
+		test_types(&pg).await;
+	}
+
+	async fn test_types(pg: &PgDb) {
+		let setup = pg.execute(r#"
+			CREATE TYPE mood AS ENUM ('sad', 'happy');
+			CREATE TYPE pair AS (name text, score int4);
+		"#).await;
+		assert!(setup.is_ok(), "{:?}", setup.err());
+
+		let cases = [
+			("SELECT 1.50::numeric AS v", json!("1.50")),
+			("SELECT -123456789.000001::numeric AS v", json!("-123456789.000001")),
+			("SELECT 'NaN'::numeric AS v", json!("NaN")),
+			("SELECT 1.1::float4 AS v", json!(1.1)),
+			("SELECT 'Infinity'::float8 AS v", json!("Infinity")),
+			("SELECT 12.34::money AS v", json!("12.34")),
+			("SELECT 42::oid AS v", json!(42)),
+			("SELECT 'x'::\"char\" AS v", json!("x")),
+			("SELECT '\\x0102'::bytea AS v", json!("AQI=")),
+			("SELECT '2023-07-01 10:00:00.5'::timestamp AS v", json!("2023-07-01T10:00:00.500000")),
+			("SELECT 'infinity'::timestamp AS v", json!("infinity")),
+			("SELECT '2023-07-01 10:00:00+00'::timestamptz AS v", json!("2023-07-01T10:00:00+00:00")),
+			("SELECT '1999-12-31'::date AS v", json!("1999-12-31")),
+			("SELECT '10:00:00+02'::timetz AS v", json!("10:00:00+02:00")),
+			("SELECT 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid AS v", json!("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11")),
+			("SELECT '1 year 2 mons 3 days 04:05:06.5'::interval AS v", json!("P1Y2M3DT4H5M6.5S")),
+			("SELECT '192.168.1.5/24'::inet AS v", json!("192.168.1.5/24")),
+			("SELECT '192.168.1.5'::inet AS v", json!("192.168.1.5")),
+			("SELECT '10.0.0.0/8'::cidr AS v", json!("10.0.0.0/8")),
+			("SELECT '::1'::inet AS v", json!("::1")),
+			("SELECT '08:00:2b:01:02:03'::macaddr AS v", json!("08:00:2b:01:02:03")),
+			("SELECT B'10101'::bit(5) AS v", json!("10101")),
+			("SELECT B'1'::varbit AS v", json!("1")),
+			("SELECT ARRAY[1, NULL, 3]::int4[] AS v", json!([1, null, 3])),
+			("SELECT ARRAY['a', 'b']::text[] AS v", json!(["a", "b"])),
+			("SELECT ARRAY[1.5]::numeric[] AS v", json!(["1.5"])),
+			("SELECT ARRAY[]::int8[] AS v", json!([])),
+			("SELECT ARRAY[[1, 2], [3, 4]]::int4[] AS v", json!([[1, 2], [3, 4]])),
+			("SELECT 'happy'::mood AS v", json!("happy")),
+			("SELECT ARRAY['sad'::mood] AS v", json!(["sad"])),
+			("SELECT ('bob', 3)::pair AS v", json!({"name": "bob", "score": 3})),
+			("SELECT ROW(1, 'a') AS v", json!([1, "a"])),
+			("SELECT ROW(ARRAY[1, 2], '10.0.0.1'::inet, 'happy'::mood) AS v", json!([[1, 2], "10.0.0.1", "happy"])),
+			("SELECT '[1,10)'::int4range AS v", json!({"lower": 1, "upper": 10, "lower_inc": true, "upper_inc": false})),
+			("SELECT '(,5.5]'::numrange AS v", json!({"lower": null, "upper": "5.5", "lower_inc": false, "upper_inc": true})),
+			("SELECT 'empty'::int4range AS v", json!("empty")),
+			("SELECT '{\"a\": [1]}'::jsonb AS v", json!({"a": [1]})),
+			("SELECT NULL::numeric AS v", Value::Null),
+		];
+		for (sql, expected) in cases {
+			assert_eq!(select(pg, sql).await, expected, "{}", sql);
+		}
+
+		// Simple query protocol, values in text format
+		let rows = pg.fetch_all("SELECT 1.50::numeric AS n, true AS b, 2::int8 AS i").await;
+		assert!(rows.is_ok(), "{:?}", rows.err());
+		assert_eq!(rows.unwrap().result_array()[0], json!({"n": "1.50", "b": true, "i": 2}));
 	}
 }
\ No newline at end of file
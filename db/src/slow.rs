@@ -0,0 +1,268 @@
+//! Log of the statements slower than `SQL_SLOW_QUERY_MS`, the most recent ones kept in memory
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+use utility::errors::AppResult;
+use crate::ops::ExecResult;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SlowQuery {
+	pub executed_at: DateTime<Utc>,
+	pub duration_ms: f64,
+	/// Rows returned or changed, `0` for failed statements
+	pub row_count: usize,
+	/// Statement without comments, its literals replaced by `?`
+	pub statement: String,
+	/// `x-request-id` of the request which ran the statement
+	pub request_id: String,
+	/// Error of a failed statement, e.g. cancelled by `statement_timeout`
+	pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SlowLog {
+	/// `None` records nothing
+	threshold: Option<Duration>,
+	capacity: usize,
+	entries: Mutex<VecDeque<SlowQuery>>,
+	/// Slow statements since the start, including the ones dropped from `entries`
+	total: AtomicU64,
+}
+
+impl SlowLog {
+	/// Log of the statements lasting at least `threshold`, a zero threshold disables it
+	pub fn new(threshold: Duration, capacity: usize) -> Self {
+		Self {
+			threshold: match threshold.is_zero() {
+				true => None,
+				false => Some(threshold),
+			},
+			capacity,
+			entries: Mutex::new(VecDeque::with_capacity(capacity)),
+			total: AtomicU64::new(0),
+		}
+	}
+
+	/// Record `sql` when `elapsed` reaches the threshold
+	pub fn record(&self, sql: &str, elapsed: Duration, result: &AppResult<ExecResult>, request_id: &str) {
+		let row_count = result.as_ref().map_or(0, |result| result.rows_affected as usize);
+		self.record_rows(sql, elapsed, row_count, result.as_ref().err().map(|err| err.to_string()), request_id);
+	}
+
+	/// Record `sql`, which returned or changed `row_count` rows, when `elapsed` reaches the threshold
+	pub fn record_rows(&self, sql: &str, elapsed: Duration, row_count: usize, error: Option<String>, request_id: &str) {
+		match self.threshold {
+			Some(threshold) if elapsed >= threshold => {}
+			_ => return,
+		}
+		let entry = SlowQuery {
+			executed_at: Utc::now(),
+			duration_ms: elapsed.as_secs_f64() * 1000.0,
+			row_count,
+			statement: normalize(sql),
+			request_id: request_id.to_string(),
+			error,
+		};
+		warn!(duration_ms = entry.duration_ms, request_id = %entry.request_id, "slow query: {}", entry.statement);
+		self.total.fetch_add(1, Ordering::Relaxed);
+		if self.capacity == 0 {
+			return;
+		}
+		let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+		if entries.len() == self.capacity {
+			entries.pop_front();
+		}
+		entries.push_back(entry);
+	}
+
+	/// At most `limit` slow statements lasting at least `min_duration`, most recent first
+	pub fn list(&self, min_duration: Duration, limit: usize) -> Vec<SlowQuery> {
+		let min_duration_ms = min_duration.as_secs_f64() * 1000.0;
+		let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+		entries.iter().rev().filter(|entry| entry.duration_ms >= min_duration_ms).take(limit).cloned().collect()
+	}
+
+	pub fn total(&self) -> u64 {
+		self.total.load(Ordering::Relaxed)
+	}
+}
+
+/// `sql` without comments, its whitespace collapsed and its literals replaced by `?`, so that
+/// statements differing by their values read the same and values do not leak into the log
+pub fn normalize(sql: &str) -> String {
+	let chars: Vec<char> = sql.chars().collect();
+	let mut normalized = String::with_capacity(sql.len());
+	let mut space = false;
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		let next = chars.get(i + 1).copied();
+		if c == '-' && next == Some('-') {
+			while i < chars.len() && chars[i] != '\n' {
+				i += 1;
+			}
+			space = true;
+			continue;
+		}
+		if c == '/' && next == Some('*') {
+			i += 2;
+			while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+				i += 1;
+			}
+			i += 2;
+			space = true;
+			continue;
+		}
+		if c.is_whitespace() {
+			space = true;
+			i += 1;
+			continue;
+		}
+		if space && !normalized.is_empty() {
+			normalized.push(' ');
+		}
+		space = false;
+
+		let after_identifier = normalized.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+		match c {
+			'\'' => {
+				// `E'...'` strings escape quotes with backslashes
+				let escapes = normalized.ends_with(['E', 'e']) && !normalized[..normalized.len() - 1].ends_with(|c: char| c.is_alphanumeric() || c == '_');
+				if escapes {
+					normalized.pop();
+				}
+				i = skip_quoted(&chars, i, escapes);
+				normalized.push('?');
+			}
+			'"' => {
+				let end = skip_quoted(&chars, i, false);
+				normalized.extend(&chars[i..end]);
+				i = end;
+			}
+			'$' if next.is_some_and(|next| next.is_ascii_digit()) => {
+				normalized.push('$');
+				i += 1;
+				while i < chars.len() && chars[i].is_ascii_digit() {
+					normalized.push(chars[i]);
+					i += 1;
+				}
+			}
+			'$' if !after_identifier => match dollar_quoted(&chars, i) {
+				Some(end) => {
+					normalized.push('?');
+					i = end;
+				}
+				None => {
+					normalized.push(c);
+					i += 1;
+				}
+			},
+			c if c.is_ascii_digit() && !after_identifier => {
+				i += 1;
+				while i < chars.len() {
+					let exponent = matches!(chars[i], 'e' | 'E');
+					let signed = matches!(chars[i], '+' | '-') && matches!(chars[i - 1], 'e' | 'E');
+					if !(chars[i].is_ascii_digit() || chars[i] == '.' || exponent || signed) {
+						break;
+					}
+					i += 1;
+				}
+				normalized.push('?');
+			}
+			c => {
+				normalized.push(c);
+				i += 1;
+			}
+		}
+	}
+	normalized
+}
+
+/// Index after the quoted text starting at `start`, doubled quotes being escaped quotes
+fn skip_quoted(chars: &[char], start: usize, backslash_escapes: bool) -> usize {
+	let quote = chars[start];
+	let mut i = start + 1;
+	while i < chars.len() {
+		let escaped = (backslash_escapes && chars[i] == '\\') || (chars[i] == quote && chars.get(i + 1) == Some(&quote));
+		if escaped {
+			i += 2;
+		} else if chars[i] == quote {
+			return i + 1;
+		} else {
+			i += 1;
+		}
+	}
+	chars.len()
+}
+
+/// Index after the `$tag$...$tag$` string starting at `start`, `None` if it is not one
+fn dollar_quoted(chars: &[char], start: usize) -> Option<usize> {
+	let mut i = start + 1;
+	while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+		i += 1;
+	}
+	if chars.get(i) != Some(&'$') {
+		return None;
+	}
+	let tag = &chars[start..=i];
+	let mut j = i + 1;
+	while j + tag.len() <= chars.len() {
+		if &chars[j..j + tag.len()] == tag {
+			return Some(j + tag.len());
+		}
+		j += 1;
+	}
+	Some(chars.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn result(rows: usize) -> AppResult<ExecResult> {
+		Ok(ExecResult {
+			columns: vec![],
+			rows: vec![serde_json::Value::Null; rows],
+			truncated: false,
+			rows_affected: rows as u64,
+			elapsed: Duration::ZERO,
+		})
+	}
+
+	#[test]
+	fn test_normalize() {
+		assert_eq!(
+			normalize("SELECT *\n  FROM t1 -- users\n WHERE id = 42 AND name = 'O''Brien' /* x */ AND n > 1.5e-3"),
+			"SELECT * FROM t1 WHERE id = ? AND name = ? AND n > ?",
+		);
+		assert_eq!(normalize("select \"col 1\", col2 from t where a = $1 and b = E'it\\'s'"), "select \"col 1\", col2 from t where a = $1 and b = ?");
+		assert_eq!(normalize("SELECT $$a 'b'$$, $fn$ x $fn$, -7"), "SELECT ?, ?, -?");
+		assert_eq!(normalize("INSERT INTO t VALUES (1, 'a'), (2, 'b')"), "INSERT INTO t VALUES (?, ?), (?, ?)");
+	}
+
+	#[test]
+	fn test_record() {
+		let log = SlowLog::new(Duration::from_millis(100), 2);
+		log.record("SELECT 1", Duration::from_millis(99), &result(1), "a");
+		assert_eq!(log.total(), 0);
+		log.record("SELECT 2", Duration::from_millis(300), &result(3), "b");
+		log.record("SELECT 3", Duration::from_millis(100), &result(0), "c");
+		log.record("SELECT 4", Duration::from_millis(200), &result(0), "d");
+		assert_eq!(log.total(), 3);
+		let entries = log.list(Duration::ZERO, 10);
+		assert_eq!(entries.iter().map(|entry| entry.request_id.as_str()).collect::<Vec<_>>(), ["d", "c"]);
+		assert_eq!(entries[1].statement, "SELECT ?");
+		assert_eq!(log.list(Duration::from_millis(150), 10).len(), 1);
+		assert_eq!(log.list(Duration::ZERO, 1)[0].request_id, "d");
+
+		let disabled = SlowLog::new(Duration::ZERO, 10);
+		disabled.record("SELECT 1", Duration::from_secs(60), &result(1), "a");
+		assert_eq!(disabled.total(), 0);
+	}
+}
@@ -0,0 +1,325 @@
+//! Results streamed as NDJSON, CSV, Arrow IPC or Parquet, without holding them in memory.
+//!
+//! Rows are fetched one by one and encoded into chunks sent over a bounded channel, so a slow
+//! client slows down the query instead of growing a buffer. When the client goes away the query
+//! is cancelled and its connection closed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgColumn, PgRow};
+use sqlx::{Column, Connection, Executor, Postgres};
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+use utility::errors::{AppError, AppResult};
+use crate::audit::AuditEntry;
+use crate::columnar::{ColumnarFormat, ColumnarWriter};
+use crate::ops::statement_error;
+use crate::params::{prepare, Param};
+use crate::pgrow::{read_row, SPgRowMap};
+use crate::setup::PgDb;
+use crate::slow::SlowLog;
+
+/// Encoded rows are sent in chunks of about this size
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks waiting for the client before the query is paused
+const CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamFormat {
+	/// One JSON object per line
+	#[default]
+	Ndjson,
+	/// RFC 4180 CSV with a header line
+	Csv,
+	/// Arrow IPC stream
+	Arrow,
+	/// Parquet file
+	Parquet,
+}
+
+impl StreamFormat {
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			StreamFormat::Ndjson => "application/x-ndjson",
+			StreamFormat::Csv => "text/csv; charset=utf-8",
+			StreamFormat::Arrow => "application/vnd.apache.arrow.stream",
+			StreamFormat::Parquet => "application/vnd.apache.parquet",
+		}
+	}
+
+	/// Extension of downloaded files
+	pub fn extension(&self) -> &'static str {
+		match self {
+			StreamFormat::Ndjson => "ndjson",
+			StreamFormat::Csv => "csv",
+			StreamFormat::Arrow => "arrows",
+			StreamFormat::Parquet => "parquet",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+	pub format: StreamFormat,
+	/// `statement_timeout` of the query, which includes the time spent sending the rows
+	pub statement_timeout: Duration,
+	/// Request id recorded with slow queries
+	pub request_id: String,
+	/// Log of the slow queries, `None` records nothing
+	pub slow_log: Option<Arc<SlowLog>>,
+	/// Scales of the `NUMERIC` columns sent as Arrow and Parquet decimals, by name (see
+	/// [`numeric_scales`]); the other `NUMERIC` columns are strings
+	pub numeric_scales: HashMap<String, i8>,
+}
+
+/// Stream the rows of `sql` in a `READ ONLY` transaction.
+///
+/// The first message is sent once the statement is checked, with the CSV header, so that invalid
+/// statements can still be answered with an error status. Errors after that end the stream.
+/// `audit` and slow queries are recorded when the stream ends, with the number of streamed rows.
+pub fn stream_sql(
+	pg: PgDb,
+	sql: String,
+	params: Vec<Param>,
+	options: StreamOptions,
+	audit: Option<AuditEntry>,
+) -> mpsc::Receiver<AppResult<Vec<u8>>> {
+	let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+	tokio::spawn(async move {
+		let start = Instant::now();
+		let result = run(&pg, &sql, &params, &options, &sender).await;
+		if let Err(err) = &result {
+			let _ = sender.send(Err(err.clone())).await;
+		}
+
+		let elapsed = start.elapsed();
+		let (rows, error) = match &result {
+			Ok(Some(rows)) => (*rows, None),
+			Ok(None) => (0, Some("client disconnected".to_string())),
+			Err(err) => (0, Some(err.to_string())),
+		};
+		if let Some(slow_log) = &options.slow_log {
+			slow_log.record_rows(&sql, elapsed, rows as usize, error.clone(), &options.request_id);
+		}
+
+		if let Some(mut entry) = audit {
+			entry.duration_ms = elapsed.as_secs_f64() * 1000.0;
+			entry.success = error.is_none();
+			entry.row_count = rows as i64;
+			entry.error = error;
+			if let Err(err) = crate::audit::record(&pg, &entry).await {
+				tracing::error!("SQL audit: {:?}", err);
+			}
+		}
+	});
+	receiver
+}
+
+/// `SELECT *` statement of a table or view of `schema`
+pub async fn table_sql(pg: &PgDb, schema: &str, table: &str) -> AppResult<String> {
+	let exists: bool = sqlx::query_scalar(r#"
+		SELECT EXISTS (
+			SELECT 1
+			FROM pg_catalog.pg_class c
+			JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+			WHERE n.nspname = $1 AND c.relname = $2 AND c.relkind IN ('r', 'p', 'v', 'm', 'f')
+		);
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_one(pg)
+		.await?;
+	match exists {
+		true => Ok(format!("SELECT * FROM {}.{}", quote(schema), quote(table))),
+		false => Err(AppError::NotFound { message: format!("table {:?}.{:?} not found", schema, table) }),
+	}
+}
+
+/// Scales of the `NUMERIC` columns of a table or view declared with a precision of at most 38
+/// digits, which fit `Decimal128`
+pub async fn numeric_scales(pg: &PgDb, schema: &str, table: &str) -> AppResult<HashMap<String, i8>> {
+	let rows: Vec<(String, i32)> = sqlx::query_as(r#"
+		SELECT column_name::text, numeric_scale::int
+		FROM information_schema.columns
+		WHERE table_schema = $1 AND table_name = $2 AND data_type = 'numeric'
+			AND numeric_precision <= 38 AND numeric_scale IS NOT NULL;
+	"#)
+		.bind(schema)
+		.bind(table)
+		.fetch_all(pg)
+		.await?;
+	Ok(rows.into_iter().filter_map(|(column, scale)| Some((column, i8::try_from(scale).ok()?))).collect())
+}
+
+/// Number of streamed rows, `None` when the client disconnected
+async fn run(
+	pg: &PgDb,
+	sql: &str,
+	params: &[Param],
+	options: &StreamOptions,
+	sender: &mpsc::Sender<AppResult<Vec<u8>>>,
+) -> AppResult<Option<u64>> {
+	let mut conn = pg.acquire().await?;
+	let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid();").fetch_one(&mut *conn).await?;
+	conn.execute("BEGIN READ ONLY;").await?;
+
+	match send_rows(&mut conn, sql, params, options, sender).await {
+		Ok(Some(rows)) => {
+			conn.execute("COMMIT;").await?;
+			Ok(Some(rows))
+		}
+		result => {
+			// The connection may be in the middle of the query, it is not given back to the pool
+			if let Err(err) = sqlx::query("SELECT pg_cancel_backend($1);").bind(pid).execute(pg).await {
+				tracing::warn!("stream: cannot cancel query of backend {}: {:?}", pid, err);
+			}
+			let _ = conn.detach().close().await;
+			result
+		}
+	}
+}
+
+async fn send_rows(
+	conn: &mut PoolConnection<Postgres>,
+	sql: &str,
+	params: &[Param],
+	options: &StreamOptions,
+	sender: &mpsc::Sender<AppResult<Vec<u8>>>,
+) -> AppResult<Option<u64>> {
+	// SET does not take bind parameters
+	let timeout = format!("SET LOCAL statement_timeout = {};", options.statement_timeout.as_millis());
+	conn.execute(timeout.as_str()).await?;
+
+	let columns = conn.describe(sql).await.map_err(statement_error)?.columns().to_vec();
+	let query = prepare(&mut *conn, sql, params).await?;
+
+	let mut encoder = Encoder::new(options.format, &columns, &options.numeric_scales);
+	if sender.send(Ok(encoder.take())).await.is_err() {
+		return Ok(None);
+	}
+
+	let mut rows = query.fetch(&mut **conn);
+	let mut count = 0u64;
+	loop {
+		let row = tokio::select! {
+			row = rows.try_next() => row.map_err(statement_error)?,
+			_ = sender.closed() => return Ok(None),
+		};
+		let row = match row {
+			Some(row) => row,
+			None => break,
+		};
+		encoder.push(row)?;
+		count += 1;
+		if encoder.pending() >= CHUNK_SIZE && sender.send(Ok(encoder.take())).await.is_err() {
+			return Ok(None);
+		}
+	}
+	let rest = encoder.finish()?;
+	if !rest.is_empty() && sender.send(Ok(rest)).await.is_err() {
+		return Ok(None);
+	}
+	Ok(Some(count))
+}
+
+enum Encoder {
+	Ndjson(Vec<u8>),
+	Csv(Vec<u8>),
+	Columnar(ColumnarWriter),
+}
+
+impl Encoder {
+	fn new(format: StreamFormat, columns: &[PgColumn], numeric_scales: &HashMap<String, i8>) -> Self {
+		match format {
+			StreamFormat::Ndjson => Encoder::Ndjson(Vec::with_capacity(CHUNK_SIZE)),
+			StreamFormat::Csv => {
+				let header = csv_line(columns.iter().map(|column| Value::String(column.name().to_string())));
+				Encoder::Csv(header.into_bytes())
+			}
+			StreamFormat::Arrow => Encoder::Columnar(ColumnarWriter::new(ColumnarFormat::Arrow, columns, numeric_scales)),
+			StreamFormat::Parquet => Encoder::Columnar(ColumnarWriter::new(ColumnarFormat::Parquet, columns, numeric_scales)),
+		}
+	}
+
+	fn push(&mut self, row: PgRow) -> AppResult<()> {
+		match self {
+			Encoder::Ndjson(buffer) => {
+				serde_json::to_writer(&mut *buffer, &SPgRowMap::from(row))?;
+				buffer.push(b'\n');
+			}
+			Encoder::Csv(buffer) => buffer.extend(csv_line(read_row(&row).into_iter()).into_bytes()),
+			Encoder::Columnar(writer) => writer.push(&row)?,
+		}
+		Ok(())
+	}
+
+	fn pending(&mut self) -> usize {
+		match self {
+			Encoder::Ndjson(buffer) | Encoder::Csv(buffer) => buffer.len(),
+			Encoder::Columnar(writer) => writer.pending(),
+		}
+	}
+
+	fn take(&mut self) -> Vec<u8> {
+		match self {
+			Encoder::Ndjson(buffer) | Encoder::Csv(buffer) => std::mem::replace(buffer, Vec::with_capacity(CHUNK_SIZE)),
+			Encoder::Columnar(writer) => writer.take(),
+		}
+	}
+
+	fn finish(self) -> AppResult<Vec<u8>> {
+		match self {
+			Encoder::Ndjson(buffer) | Encoder::Csv(buffer) => Ok(buffer),
+			Encoder::Columnar(writer) => writer.finish(),
+		}
+	}
+}
+
+/// CSV record of `values`: `NULL` is an empty field, arrays and objects are JSON
+fn csv_line(values: impl Iterator<Item = Value>) -> String {
+	let fields: Vec<String> = values
+		.map(|value| match value {
+			Value::Null => String::new(),
+			Value::String(text) => csv_field(&text),
+			value => csv_field(&value.to_string()),
+		})
+		.collect();
+	format!("{}\r\n", fields.join(","))
+}
+
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn csv_field(text: &str) -> String {
+	match text.contains(|c| matches!(c, ',' | '"' | '\r' | '\n')) {
+		true => format!("\"{}\"", text.replace('"', "\"\"")),
+		false => text.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	#[test]
+	fn test_csv_line() {
+		let values = vec![json!(1), Value::Null, json!("plain"), json!("a,b"), json!("say \"hi\"\n"), json!({"a": [1]}), json!(true)];
+		assert_eq!(csv_line(values.into_iter()), "1,,plain,\"a,b\",\"say \"\"hi\"\"\n\",\"{\"\"a\"\":[1]}\",true\r\n");
+	}
+
+	#[test]
+	fn test_content_type() {
+		assert_eq!(StreamFormat::default(), StreamFormat::Ndjson);
+		assert_eq!(serde_json::from_value::<StreamFormat>(json!("csv")).unwrap(), StreamFormat::Csv);
+		assert_eq!(StreamFormat::Csv.content_type(), "text/csv; charset=utf-8");
+		assert_eq!(serde_json::from_value::<StreamFormat>(json!("parquet")).unwrap().extension(), "parquet");
+	}
+}
@@ -0,0 +1,317 @@
+//! Transactions spanning several requests. Each open transaction holds a connection out of the
+//! pool until it is committed, rolled back, or rolled back for being idle too long.
+//!
+//! Transactions run with the identity of their user. Their statements can't end them: the ones
+//! beginning, committing or rolling back a transaction are rejected, and a transaction whose
+//! statement changed its role, claims or access mode anyway only accepts a rollback.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use sqlx::{PgConnection, Postgres, Transaction};
+use tracing::{error, info};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use utility::errors::{AppError, AppResult};
+use crate::ops::{fetch, set_identity, statement_error, ExecResult, Identity};
+use crate::params::Param;
+use crate::setup::PgDb;
+
+#[derive(Debug, Clone)]
+pub struct TxSettings {
+	/// Transactions without statement for this long are rolled back
+	pub idle_timeout: Duration,
+	/// Open transactions of a user
+	pub max_per_user: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TxInfo {
+	/// Id of the transaction, used by its statements, commit and rollback
+	pub id: Uuid,
+	pub read_only: bool,
+	/// Rolled back after this many milliseconds without statement
+	pub idle_timeout_ms: u64,
+}
+
+struct OpenTx {
+	/// `None` once committed or rolled back
+	tx: Option<Transaction<'static, Postgres>>,
+	/// Postgres rejects the statements of a failed transaction, and turns its commit into a rollback
+	failed: bool,
+	/// Start, role, claims and access mode of the transaction, which its statements must keep
+	state: TxState,
+	last_used: Instant,
+}
+
+type TxState = (String, String, Option<String>, String);
+
+struct Entry {
+	owner: String,
+	read_only: bool,
+	tx: Arc<tokio::sync::Mutex<OpenTx>>,
+}
+
+/// Open transactions, by id
+pub struct Transactions {
+	pg: PgDb,
+	settings: TxSettings,
+	open: Mutex<HashMap<Uuid, Entry>>,
+}
+
+impl Transactions {
+	pub fn new(pg: PgDb, settings: TxSettings) -> Self {
+		Self { pg, settings, open: Mutex::new(HashMap::new()) }
+	}
+
+	/// Begin a transaction for `owner`, run with `identity`
+	#[tracing::instrument(skip(self, identity), fields(otel.kind = "client", db.system = "postgresql"))]
+	pub async fn begin(&self, owner: &str, read_only: bool, identity: &Identity) -> AppResult<TxInfo> {
+		self.check_limit(&self.lock(), owner)?;
+		let mut tx = self.pg.begin().await?;
+		if read_only {
+			sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *tx).await?;
+		}
+		set_identity(&mut tx, identity).await?;
+		let state = tx_state(&mut tx).await?;
+
+		// Checked again while adding it, other transactions of the owner may have begun meanwhile
+		let id = Uuid::new_v4();
+		let refused = {
+			let mut open = self.lock();
+			match self.check_limit(&open, owner) {
+				Ok(()) => {
+					let tx = OpenTx { tx: Some(tx), failed: false, state, last_used: Instant::now() };
+					open.insert(id, Entry { owner: owner.to_string(), read_only, tx: Arc::new(tokio::sync::Mutex::new(tx)) });
+					None
+				}
+				Err(err) => Some((err, tx)),
+			}
+		};
+		if let Some((err, tx)) = refused {
+			tx.rollback().await?;
+			return Err(err);
+		}
+		Ok(self.tx_info(id, read_only))
+	}
+
+	/// Transaction `id` of `owner`
+	pub fn info(&self, id: Uuid, owner: &str) -> AppResult<TxInfo> {
+		let read_only = match self.lock().get(&id) {
+			Some(entry) if entry.owner == owner => entry.read_only,
+			_ => return Err(not_found(id)),
+		};
+		Ok(self.tx_info(id, read_only))
+	}
+
+	/// Run a statement in the transaction `id`, statements of a transaction run one after the other
+	#[tracing::instrument(skip(self, sql, params), fields(otel.kind = "client", db.system = "postgresql", db.statement = sql))]
+	pub async fn exec(
+		&self,
+		id: Uuid,
+		owner: &str,
+		sql: &str,
+		params: &[Param],
+		statement_timeout: Duration,
+		max_rows: usize,
+	) -> AppResult<ExecResult> {
+		if controls_transaction(sql) {
+			return Err(AppError::BadRequest {
+				message: "transactions are ended with their commit and rollback requests, not with statements".to_string(),
+			});
+		}
+		let open = self.get(id, owner)?;
+		let mut open = open.lock().await;
+		let open = &mut *open;
+		if open.failed {
+			return Err(AppError::BadRequest { message: format!("transaction {} failed, roll it back", id) });
+		}
+		let tx = open.tx.as_mut().ok_or_else(|| not_found(id))?;
+
+		// SET does not take bind parameters
+		let timeout = format!("SET LOCAL statement_timeout = {}", statement_timeout.as_millis());
+		let result = match sqlx::query(&timeout).execute(&mut **tx).await {
+			Ok(_) => fetch(tx, sql, params, max_rows).await,
+			Err(err) => Err(err.into()),
+		};
+		let result = match result {
+			Ok(result) => match tx_state(tx).await {
+				Ok(state) if state == open.state => Ok(result),
+				Ok(_) => Err(AppError::BadRequest {
+					message: format!("the statement changed the role, the claims or the access mode of transaction {}, roll it back", id),
+				}),
+				Err(err) => Err(err),
+			},
+			Err(err) => Err(err),
+		};
+		open.failed = result.is_err();
+		open.last_used = Instant::now();
+		result
+	}
+
+	/// Commit the transaction `id`, a failed transaction is rolled back instead
+	#[tracing::instrument(skip(self), fields(otel.kind = "client", db.system = "postgresql"))]
+	pub async fn commit(&self, id: Uuid, owner: &str) -> AppResult<()> {
+		let (tx, failed) = self.take(id, owner).await?;
+		if failed {
+			tx.rollback().await?;
+			return Err(AppError::BadRequest { message: format!("transaction {} failed and was rolled back", id) });
+		}
+		tx.commit().await.map_err(statement_error)
+	}
+
+	/// Roll back the transaction `id`
+	#[tracing::instrument(skip(self), fields(otel.kind = "client", db.system = "postgresql"))]
+	pub async fn rollback(&self, id: Uuid, owner: &str) -> AppResult<()> {
+		let (tx, _) = self.take(id, owner).await?;
+		Ok(tx.rollback().await?)
+	}
+
+	/// Roll back the transactions idle for longer than `idle_timeout`
+	pub async fn expire(&self) {
+		let candidates: Vec<(Uuid, Arc<tokio::sync::Mutex<OpenTx>>)> = self
+			.lock()
+			.iter()
+			.map(|(id, entry)| (*id, entry.tx.clone()))
+			.collect();
+		for (id, open) in candidates {
+			// A transaction running a statement is not idle
+			let Ok(mut open) = open.try_lock() else { continue };
+			if open.last_used.elapsed() < self.settings.idle_timeout {
+				continue;
+			}
+			self.lock().remove(&id);
+			if let Some(tx) = open.tx.take() {
+				info!("transaction {} idle, rolling back", id);
+				if let Err(err) = tx.rollback().await {
+					error!("transaction {} rollback: {:?}", id, err);
+				}
+			}
+		}
+	}
+
+	/// Roll back the idle transactions every second
+	pub fn spawn_reaper(self: &Arc<Self>) {
+		let transactions = self.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(1));
+			loop {
+				interval.tick().await;
+				transactions.expire().await;
+			}
+		});
+	}
+
+	/// Roll back every open transaction, at shutdown
+	pub async fn rollback_all(&self) {
+		let open: Vec<(Uuid, Entry)> = self.lock().drain().collect();
+		if !open.is_empty() {
+			info!("rolling back {} open transactions", open.len());
+		}
+		for (id, entry) in open {
+			if let Some(tx) = entry.tx.lock().await.tx.take() {
+				if let Err(err) = tx.rollback().await {
+					error!("transaction {} rollback: {:?}", id, err);
+				}
+			}
+		}
+	}
+
+	fn tx_info(&self, id: Uuid, read_only: bool) -> TxInfo {
+		TxInfo { id, read_only, idle_timeout_ms: self.settings.idle_timeout.as_millis() as u64 }
+	}
+
+	fn check_limit(&self, open: &HashMap<Uuid, Entry>, owner: &str) -> AppResult<()> {
+		let count = open.values().filter(|entry| entry.owner == owner).count();
+		match count < self.settings.max_per_user {
+			true => Ok(()),
+			false => Err(AppError::BadRequest {
+				message: format!("{} transactions already open, commit or roll one back first", count),
+			}),
+		}
+	}
+
+	/// Transactions of other users are not found
+	fn get(&self, id: Uuid, owner: &str) -> AppResult<Arc<tokio::sync::Mutex<OpenTx>>> {
+		match self.lock().get(&id) {
+			Some(entry) if entry.owner == owner => Ok(entry.tx.clone()),
+			_ => Err(not_found(id)),
+		}
+	}
+
+	/// Remove the transaction `id`, waiting for its running statement
+	async fn take(&self, id: Uuid, owner: &str) -> AppResult<(Transaction<'static, Postgres>, bool)> {
+		let open = self.get(id, owner)?;
+		let mut open = open.lock().await;
+		self.lock().remove(&id);
+		let tx = open.tx.take().ok_or_else(|| not_found(id))?;
+		Ok((tx, open.failed))
+	}
+
+	fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, Entry>> {
+		self.open.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+/// Start, role, claims and access mode of the transaction on `conn`
+async fn tx_state(conn: &mut PgConnection) -> AppResult<TxState> {
+	Ok(sqlx::query_as(r#"
+		SELECT transaction_timestamp()::text, current_user::text, current_setting('request.jwt.claims', true),
+			current_setting('transaction_read_only')
+	"#)
+		.fetch_one(conn)
+		.await?)
+}
+
+/// Whether `sql` begins, ends or prepares a transaction. Savepoints are left to the statements
+fn controls_transaction(sql: &str) -> bool {
+	let words: Vec<String> = keywords(sql).take(3).collect();
+	let word = |index: usize| words.get(index).map(String::as_str).unwrap_or_default();
+	match word(0) {
+		"BEGIN" | "START" | "COMMIT" | "END" | "ABORT" => true,
+		// `ROLLBACK [WORK | TRANSACTION] TO [SAVEPOINT] name`
+		"ROLLBACK" => !matches!((word(1), word(2)), ("TO", _) | ("WORK" | "TRANSACTION", "TO")),
+		"PREPARE" => word(1) == "TRANSACTION",
+		_ => false,
+	}
+}
+
+/// Leading words of `sql` in upper case, after its comments
+fn keywords(sql: &str) -> impl Iterator<Item = String> + '_ {
+	let mut rest = sql;
+	std::iter::from_fn(move || loop {
+		rest = rest.trim_start();
+		if let Some(comment) = rest.strip_prefix("--") {
+			rest = comment.split_once('\n').map(|(_, rest)| rest).unwrap_or_default();
+		} else if let Some(comment) = rest.strip_prefix("/*") {
+			rest = comment.split_once("*/").map(|(_, rest)| rest).unwrap_or_default();
+		} else {
+			let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+			let (word, after) = rest.split_at(end);
+			rest = after;
+			return (!word.is_empty()).then(|| word.to_uppercase());
+		}
+	})
+}
+
+fn not_found(id: Uuid) -> AppError {
+	AppError::NotFound { message: format!("transaction {} not found, it may have expired", id) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_controls_transaction() {
+		for sql in ["COMMIT", "commit;", " END", "begin isolation level serializable", "START TRANSACTION", "ABORT",
+			"ROLLBACK", "rollback work", "-- done\nCOMMIT", "/* done */ commit", "PREPARE TRANSACTION 'x'", "COMMIT PREPARED 'x'"] {
+			assert!(controls_transaction(sql), "{}", sql);
+		}
+		for sql in ["SELECT 1", "ROLLBACK TO SAVEPOINT a", "rollback transaction to a", "SAVEPOINT a", "RELEASE a",
+			"PREPARE q AS SELECT 1", "UPDATE t SET ended = true", "-- COMMIT\nSELECT 1", ""] {
+			assert!(!controls_transaction(sql), "{}", sql);
+		}
+	}
+}
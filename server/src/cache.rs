@@ -0,0 +1,391 @@
+//! Copies of Postgres tables in flinch collections, see [`db::cache`].
+//!
+//! Each copy is loaded whole at startup, then follows the changes of its table. A copy missing
+//! changes, because its subscription lagged or the listening connection was lost, is stale until
+//! it is loaded again.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use flinch::database::{CollectionOptions, Database};
+use flinch::doc::QueryBased;
+use flinch::doc_trait::Document;
+use flinch::extension::FuncResultExtractor;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use db::cache::CachedTable;
+use db::notify::{Change, Notification};
+use db::setup::PgDb;
+use utility::errors::{AppError, AppResult, ErrorCode};
+
+/// Delay before loading again the stale copies
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStatus {
+	#[serde(flatten)]
+	pub table: CachedTable,
+	/// Documents in the collection
+	pub documents: usize,
+	/// Last time the table was loaded whole
+	pub resynced_at: Option<DateTime<Utc>>,
+	/// Since when changes may be missing, `null` when up to date
+	pub stale_since: Option<DateTime<Utc>>,
+}
+
+struct Copy {
+	table: CachedTable,
+	/// Documents of the copy, locked while a change is applied or loaded rows are written
+	documents: Mutex<Documents>,
+	/// Held while the table is loaded, which happens once at a time
+	loading: Mutex<()>,
+	/// Read without waiting for the loads
+	status: StdMutex<Status>,
+}
+
+#[derive(Default)]
+struct Documents {
+	/// Keys of the documents, to delete the ones of the rows deleted meanwhile when loading again
+	keys: HashSet<String>,
+	/// Changes received while the table is read, applied once its rows are written
+	pending: Option<Vec<Arc<Change>>>,
+}
+
+#[derive(Default)]
+struct Status {
+	documents: usize,
+	resynced_at: Option<DateTime<Utc>>,
+	stale_since: Option<DateTime<Utc>>,
+	/// Times changes were missed, a load only makes the copy up to date when none were missed meanwhile
+	missed: u64,
+}
+
+impl Copy {
+	fn new(table: CachedTable) -> Self {
+		let status = Status { stale_since: Some(Utc::now()), ..Default::default() };
+		Self { table, documents: Mutex::new(Documents::default()), loading: Mutex::new(()), status: StdMutex::new(status) }
+	}
+
+	fn status(&self) -> CacheStatus {
+		let status = self.status.lock().unwrap();
+		CacheStatus {
+			table: self.table.clone(),
+			documents: status.documents,
+			resynced_at: status.resynced_at,
+			stale_since: status.stale_since,
+		}
+	}
+
+	fn is_stale(&self) -> bool {
+		self.status.lock().unwrap().stale_since.is_some()
+	}
+
+	fn mark_stale(&self) {
+		let mut status = self.status.lock().unwrap();
+		status.stale_since.get_or_insert_with(Utc::now);
+		status.missed += 1;
+	}
+}
+
+pub struct Cache {
+	pg: PgDb,
+	flinch: Arc<Database<QueryBased>>,
+	/// Role of the users, which must be able to read the copied tables
+	user_role: String,
+	/// Copies by collection, each one locked on its own
+	copies: RwLock<HashMap<String, Arc<Copy>>>,
+	/// Held while copies are added or removed
+	changing: Mutex<()>,
+}
+
+impl Cache {
+	pub fn new(pg: PgDb, flinch: Arc<Database<QueryBased>>, user_role: &str) -> Self {
+		Self { pg, flinch, user_role: user_role.to_string(), copies: RwLock::new(HashMap::new()), changing: Mutex::new(()) }
+	}
+
+	/// Load the declared copies, the ones failing stay stale and are retried
+	pub async fn load(&self) -> AppResult<()> {
+		db::cache::init(&self.pg).await?;
+		let _changing = self.changing.lock().await;
+		for table in db::cache::list(&self.pg).await? {
+			let copy = Arc::new(Copy::new(table));
+			// A persisted collection may hold the rows deleted while the server was stopped
+			if let Err(err) = self.create_collection(&copy.table).await {
+				warn!("cache {}: {}", copy.table.collection, err);
+			} else if let Err(err) = self.resync_copy(&copy).await {
+				warn!("cache {}: unable to load {}.{} - {}", copy.table.collection, copy.table.schema, copy.table.table, err);
+			}
+			self.copies.write().unwrap().insert(copy.table.collection.clone(), copy);
+		}
+		Ok(())
+	}
+
+	pub fn list(&self) -> Vec<CacheStatus> {
+		let mut statuses: Vec<CacheStatus> = self.copies.read().unwrap().values().map(|copy| copy.status()).collect();
+		statuses.sort_by(|a, b| a.table.collection.cmp(&b.table.collection));
+		statuses
+	}
+
+	/// Copy a table into a new collection, stale when it could not be loaded yet
+	pub async fn add(&self, collection: &str, schema: &str, table: &str, index_opts: &[String]) -> AppResult<CacheStatus> {
+		let _changing = self.changing.lock().await;
+		if self.copy(collection).is_some() || self.flinch.ls().iter().any(|name| name == collection) {
+			return Err(AppError::BadRequest { message: format!("collection {} already exists", collection) });
+		}
+		let cached = db::cache::add(&self.pg, collection, schema, table, index_opts, &self.user_role).await?;
+		let copy = Arc::new(Copy::new(cached));
+		self.create_collection(&copy.table).await?;
+		self.copies.write().unwrap().insert(collection.to_string(), copy.clone());
+		match self.resync_copy(&copy).await {
+			Ok(()) => info!("table {}.{} copied into collection {}", schema, table, collection),
+			Err(err) => warn!("cache {}: unable to load {}.{} - {}", collection, schema, table, err),
+		}
+		Ok(copy.status())
+	}
+
+	/// Drop a copy and its collection
+	pub async fn remove(&self, collection: &str) -> AppResult<()> {
+		let _changing = self.changing.lock().await;
+		db::cache::remove(&self.pg, collection).await?;
+		let removed = self.copies.write().unwrap().remove(collection);
+		// Wait for the load or the change in progress
+		if let Some(copy) = removed {
+			let _loading = copy.loading.lock().await;
+			let _documents = copy.documents.lock().await;
+		}
+		let _ = Database::drop(&self.flinch, collection).await;
+		Ok(())
+	}
+
+	/// Load the table of a copy again
+	pub async fn resync(&self, collection: &str) -> AppResult<CacheStatus> {
+		let copy = self.copy(collection)
+			.ok_or_else(|| AppError::NotFound { message: format!("collection {} is not a copy", collection) })?;
+		self.resync_copy(&copy).await?;
+		Ok(copy.status())
+	}
+
+	/// Document of a copy
+	pub fn get(&self, collection: &str, key: &str) -> AppResult<Value> {
+		// Other collections hold server data, such as the rate limiter buckets
+		if self.copy(collection).is_none() {
+			return Err(AppError::NotFound { message: format!("collection {} is not a copy", collection) });
+		}
+		let result = self.flinch.using(collection).map_err(|err| flinch_error(collection, err))?.get(key);
+		match result.data.is_some() {
+			true => Ok(Value::Object(result.get_object())),
+			false => Err(AppError::NotFound { message: format!("no document {} in {}", key, collection) }),
+		}
+	}
+
+	/// Seconds since each copy is stale, `0` for the ones up to date
+	pub fn staleness(&self) -> Vec<(String, f64)> {
+		let now = Utc::now();
+		self.copies.read().unwrap().values()
+			.map(|copy| {
+				let stale = copy.status.lock().unwrap().stale_since.map_or(0.0, |since| (now - since).num_milliseconds() as f64 / 1000.0);
+				(copy.table.collection.clone(), stale)
+			})
+			.collect()
+	}
+
+	/// Apply the changes to the copies, and load again the copies missing some. Loads run on their
+	/// own, so that the changes keep being received meanwhile
+	pub fn spawn(self: Arc<Self>, mut receiver: broadcast::Receiver<Notification>) {
+		tokio::spawn(async move {
+			let mut retry = tokio::time::interval(RETRY_INTERVAL);
+			loop {
+				tokio::select! {
+					received = receiver.recv() => match received {
+						Ok(Notification::Change(change)) => self.apply(&change).await,
+						Ok(Notification::Reconnected) | Err(RecvError::Lagged(_)) => {
+							self.all().iter().for_each(|copy| copy.mark_stale());
+							self.resync_stale();
+						}
+						Err(RecvError::Closed) => return,
+					},
+					_ = retry.tick() => self.resync_stale(),
+				}
+			}
+		});
+	}
+
+	fn copy(&self, collection: &str) -> Option<Arc<Copy>> {
+		self.copies.read().unwrap().get(collection).cloned()
+	}
+
+	fn all(&self) -> Vec<Arc<Copy>> {
+		self.copies.read().unwrap().values().cloned().collect()
+	}
+
+	/// Load the stale copies which are not being loaded already, each one in its own task
+	fn resync_stale(self: &Arc<Self>) {
+		for copy in self.all().into_iter().filter(|copy| copy.is_stale()) {
+			self.resync_later(copy);
+		}
+	}
+
+	fn resync_later(self: &Arc<Self>, copy: Arc<Copy>) {
+		if copy.loading.try_lock().is_err() {
+			return;
+		}
+		let cache = self.clone();
+		tokio::spawn(async move {
+			if let Err(err) = cache.resync_copy(&copy).await {
+				warn!("cache {}: unable to load {}.{} - {}", copy.table.collection, copy.table.schema, copy.table.table, err);
+			}
+		});
+	}
+
+	async fn apply(self: &Arc<Self>, change: &Arc<Change>) {
+		for copy in self.all().into_iter().filter(|copy| copy.table.follows(change)) {
+			let mut documents = copy.documents.lock().await;
+			if let Some(pending) = &mut documents.pending {
+				pending.push(change.clone());
+				continue;
+			}
+			if let Err(err) = self.apply_copy(&copy, &mut documents, change).await {
+				warn!("cache {}: change not applied, loading the table again - {}", copy.table.collection, err);
+				copy.mark_stale();
+				drop(documents);
+				self.resync_later(copy);
+			}
+		}
+	}
+
+	/// Apply a change to the documents of a copy, a truncated table is loaded again
+	async fn apply_copy(&self, copy: &Copy, documents: &mut Documents, change: &Change) -> AppResult<()> {
+		let old_key = change.old.as_ref().and_then(|old| copy.table.key(old));
+		let row = match (change.op.as_str(), &change.row) {
+			("TRUNCATE", _) => return Err(AppError::BadRequest { message: "table truncated".to_string() }),
+			// The notification only holds the key
+			(_, Some(row)) if change.truncated => db::cache::row(&self.pg, &copy.table, row).await?,
+			(_, Some(row)) => Some(merge(row, change.old.as_ref())),
+			(_, None) => None,
+		};
+		let collection = self.flinch.using(&copy.table.collection).map_err(|err| flinch_error(&copy.table.collection, err))?;
+		let new_key = row.as_ref().and_then(|row| copy.table.key(row));
+		// Deleted, or its key changed
+		if let Some(old_key) = old_key.filter(|old_key| Some(old_key) != new_key.as_ref()) {
+			collection.delete(old_key.clone()).await;
+			documents.keys.remove(&old_key);
+		}
+		if let (Some(key), Some(row)) = (new_key, row) {
+			collection.put(key.clone(), document(&row)?).await.map_err(|err| flinch_error(&copy.table.collection, err))?;
+			documents.keys.insert(key);
+		}
+		copy.status.lock().unwrap().documents = documents.keys.len();
+		Ok(())
+	}
+
+	/// Read the table and write its rows without locking the copy, the changes received meanwhile
+	/// are kept and applied once the rows deleted meanwhile are removed
+	async fn resync_copy(&self, copy: &Copy) -> AppResult<()> {
+		let _loading = copy.loading.lock().await;
+		let missed = copy.status.lock().unwrap().missed;
+		copy.documents.lock().await.pending = Some(Vec::new());
+		let started_at = Utc::now();
+		let written = self.write_rows(copy).await;
+
+		let mut documents = copy.documents.lock().await;
+		let pending = documents.pending.take().unwrap_or_default();
+		let loaded = match written {
+			Ok(keys) => self.swap_keys(copy, &mut documents, keys, &pending).await,
+			Err(err) => Err(err),
+		};
+		let mut status = copy.status.lock().unwrap();
+		status.documents = documents.keys.len();
+		match &loaded {
+			Ok(()) => {
+				status.resynced_at = Some(started_at);
+				if status.missed == missed {
+					status.stale_since = None;
+				}
+			}
+			Err(_) => {
+				status.stale_since.get_or_insert_with(Utc::now);
+			}
+		}
+		loaded
+	}
+
+	/// Write the rows of the table, the keys of the documents only change while the copy is loaded
+	async fn write_rows(&self, copy: &Copy) -> AppResult<HashSet<String>> {
+		let rows = db::cache::rows(&self.pg, &copy.table).await?;
+		let collection = self.flinch.using(&copy.table.collection).map_err(|err| flinch_error(&copy.table.collection, err))?;
+		let mut keys = HashSet::with_capacity(rows.len());
+		for (key, row) in rows {
+			collection.put(key.clone(), document(&row)?).await.map_err(|err| flinch_error(&copy.table.collection, err))?;
+			keys.insert(key);
+		}
+		Ok(keys)
+	}
+
+	async fn swap_keys(&self, copy: &Copy, documents: &mut Documents, keys: HashSet<String>, pending: &[Arc<Change>]) -> AppResult<()> {
+		let collection = self.flinch.using(&copy.table.collection).map_err(|err| flinch_error(&copy.table.collection, err))?;
+		for key in documents.keys.difference(&keys) {
+			collection.delete(key.clone()).await;
+		}
+		documents.keys = keys;
+		// Changes made before the table was read are applied again, which leaves the same rows
+		for change in pending {
+			self.apply_copy(copy, documents, change).await?;
+		}
+		Ok(())
+	}
+
+	async fn create_collection(&self, table: &CachedTable) -> AppResult<()> {
+		let _ = Database::drop(&self.flinch, &table.collection).await;
+		self.flinch
+			.add(CollectionOptions {
+				name: table.collection.clone(),
+				index_opts: table.index_opts.clone(),
+				search_opts: vec![],
+				view_opts: vec![],
+				range_opts: vec![],
+				clips_opts: vec![],
+			})
+			.await
+			.map_err(|err| flinch_error(&table.collection, err))
+	}
+}
+
+/// Row of an update, with the columns of the previous row missing from it: logical replication
+/// leaves out the large values which did not change
+fn merge(row: &Value, old: Option<&Value>) -> Value {
+	match (row, old) {
+		(Value::Object(row), Some(Value::Object(old))) => {
+			let mut merged = old.clone();
+			merged.extend(row.clone());
+			Value::Object(merged)
+		}
+		(row, _) => row.clone(),
+	}
+}
+
+fn document(row: &Value) -> AppResult<QueryBased> {
+	QueryBased::from_value(row).map_err(|err| AppError::InternalError { code: ErrorCode::E100, message: format!("invalid document: {:?}", err) })
+}
+
+fn flinch_error(collection: &str, err: impl std::fmt::Debug) -> AppError {
+	AppError::InternalError { code: ErrorCode::E100, message: format!("flinch collection {}: {:?}", collection, err) }
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	#[test]
+	fn test_merge() {
+		let old = json!({"id": 1, "name": "tea", "description": "long text"});
+		assert_eq!(merge(&json!({"id": 1, "name": "green tea"}), Some(&old)), json!({"id": 1, "name": "green tea", "description": "long text"}));
+		assert_eq!(merge(&json!({"id": 1, "description": null}), Some(&old)), json!({"id": 1, "name": "tea", "description": null}));
+		assert_eq!(merge(&json!({"id": 1}), None), json!({"id": 1}));
+	}
+}
@@ -0,0 +1,137 @@
+use std::io::Write;
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use db::backup::BackupSettings;
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+use crate::setup::{get_postgres, pg_tools};
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+	/// Back up the database and the flinch data directory
+	Create,
+	/// List the backups, most recent first
+	List,
+	/// Restore a backup, replacing the database objects and the flinch data
+	Restore {
+		/// Id of the backup, see `list`
+		id: String,
+		/// Restore into an embedded Postgres on this data directory, e.g. a new one, instead of
+		/// the configured database
+		#[clap(long)]
+		data_dir: Option<PathBuf>,
+		/// Port of the Postgres started on `--data-dir`, `DB_PORT` + 1 by default
+		#[clap(long)]
+		port: Option<u16>,
+		/// Flinch data directory to restore, `FLINCH_DATA_DIR` by default
+		#[clap(long)]
+		flinch_dir: Option<PathBuf>,
+		/// Do not ask for confirmation
+		#[clap(long)]
+		yes: bool,
+	},
+	/// Delete the backups beyond the retention rules
+	Prune {
+		/// List the backups which would be deleted
+		#[clap(long)]
+		dry_run: bool,
+	},
+}
+
+pub async fn run(command: &BackupCommand) -> AppResult<()> {
+	let settings = Variables::from_env()?;
+	let backup = BackupSettings::from_env(&settings)?;
+	match command {
+		BackupCommand::Create => {
+			let (pg_server, _pg) = get_postgres(&settings).await?;
+			let manifest = db::backup::create(&backup, &pg_tools(&settings, pg_server.as_ref())).await?;
+			println!("created {} ({})", manifest.id, human_size(manifest.size()));
+		}
+		BackupCommand::List => {
+			for manifest in db::backup::list(&backup.dir)? {
+				println!(
+					"{:<18} {:<26} {:>10} {:<16} {}",
+					manifest.id,
+					manifest.created_at.to_rfc3339(),
+					human_size(manifest.size()),
+					manifest.database,
+					if manifest.has_flinch() { "flinch" } else { "" },
+				);
+			}
+		}
+		BackupCommand::Restore { id, data_dir, port, flinch_dir, yes } => {
+			let manifest = db::backup::get(&backup.dir, id)?;
+			if !yes && !confirm(id)? {
+				return Err(AppError::BadRequest { message: "confirmation does not match, nothing restored".to_string() });
+			}
+			let flinch_dir = flinch_dir.clone().or(backup.flinch_dir.clone());
+
+			// The embedded server stops when dropped, at the end of the restore
+			let (pg_server, tools) = match data_dir {
+				Some(data_dir) => {
+					// The port of the running server would be freed by killing it
+					let port = port.unwrap_or(settings.db_port + 1);
+					let pg_server = db::setup::install_postgres_in(&settings, data_dir, port, None).await?;
+					db::setup::create_db(&pg_server, &manifest.database).await?;
+					let mut tools = pg_tools(&settings, Some(&pg_server));
+					tools.uri = pg_server.full_db_uri(&manifest.database);
+					(Some(pg_server), tools)
+				}
+				None => {
+					let (pg_server, _pg) = get_postgres(&settings).await?;
+					let tools = pg_tools(&settings, pg_server.as_ref());
+					(pg_server, tools)
+				}
+			};
+			db::backup::restore(&backup, id, &tools, flinch_dir.as_deref()).await?;
+			drop(pg_server);
+			println!("restored {}", manifest.id);
+		}
+		BackupCommand::Prune { dry_run } => {
+			let pruned = db::backup::prune(&backup, *dry_run)?;
+			for manifest in &pruned {
+				println!("{} {}", if *dry_run { "would delete" } else { "deleted" }, manifest.id);
+			}
+			println!("{} backups {}", pruned.len(), if *dry_run { "to delete" } else { "deleted" });
+		}
+	}
+	Ok(())
+}
+
+/// Recover the embedded Postgres as it was at `to` into a new data directory, leaving the current
+/// one untouched
+pub async fn recover(to: DateTime<Utc>, data_dir: Option<PathBuf>, port: Option<u16>) -> AppResult<()> {
+	let settings = Variables::from_env()?;
+	let backup = BackupSettings::from_env(&settings)?;
+	let data_dir = data_dir.unwrap_or_else(|| PathBuf::from(format!("{}.recovered", settings.db_path)));
+	// The port of the running server would be freed by killing it
+	let port = port.unwrap_or(settings.db_port + 1);
+	let manifest = db::pitr::recover(&settings, &backup, to, &data_dir, port).await?;
+	println!("recovered to {} from backup {} in {}", to.to_rfc3339(), manifest.id, data_dir.display());
+	println!("stop the server and replace {} with it, or point DB_PATH to it", settings.db_path);
+	Ok(())
+}
+
+/// Whether the id typed by the user is `id`
+fn confirm(id: &str) -> AppResult<bool> {
+	print!("Restoring replaces the database and the flinch data, type {:?} to restore it: ", id);
+	std::io::stdout().flush()?;
+	let mut answer = String::new();
+	std::io::stdin().read_line(&mut answer)?;
+	Ok(answer.trim_end_matches(['\r', '\n']) == id)
+}
+
+fn human_size(bytes: u64) -> String {
+	const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	match unit {
+		0 => format!("{} B", bytes),
+		_ => format!("{:.1} {}", size, UNITS[unit]),
+	}
+}
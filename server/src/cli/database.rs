@@ -0,0 +1,75 @@
+use std::io::Write;
+use clap::Subcommand;
+use db::database::CreateOptions;
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+use crate::setup::get_postgres;
+
+#[derive(Subcommand)]
+pub enum DatabaseCommand {
+	/// List the databases with their size
+	List,
+	/// Create a database, empty or cloned from a template
+	Create {
+		name: String,
+		/// Database to clone
+		#[clap(long)]
+		template: Option<String>,
+		/// Terminate the sessions of the template before cloning it
+		#[clap(long)]
+		terminate_connections: bool,
+	},
+	/// Drop a database
+	Drop {
+		name: String,
+		/// Terminate the sessions of the database
+		#[clap(long)]
+		force: bool,
+		/// Do not ask for confirmation
+		#[clap(long)]
+		yes: bool,
+	},
+}
+
+pub async fn run(command: &DatabaseCommand) -> AppResult<()> {
+	let settings = Variables::from_env()?;
+	let (_pg_server, pg) = get_postgres(&settings).await?;
+	match command {
+		DatabaseCommand::List => {
+			for database in db::database::list(&pg).await? {
+				println!(
+					"{:<32} {:>10} {:>5} {:<16} {}",
+					database.name,
+					database.size.unwrap_or_default(),
+					database.connections,
+					database.owner,
+					if database.is_template { "template" } else { "" },
+				);
+			}
+		}
+		DatabaseCommand::Create { name, template, terminate_connections } => {
+			let options = CreateOptions { template: template.clone(), terminate_connections: *terminate_connections };
+			// The sessions of the pool would prevent cloning the database of the server
+			pg.close().await;
+			let database = db::database::create(&pg, name, &options).await?;
+			println!("created {} ({})", database.name, database.size.unwrap_or_default());
+		}
+		DatabaseCommand::Drop { name, force, yes } => {
+			if !yes && !confirm(name)? {
+				return Err(AppError::BadRequest { message: "confirmation does not match, nothing dropped".to_string() });
+			}
+			db::database::drop(&pg, name, *force).await?;
+			println!("dropped {}", name);
+		}
+	}
+	Ok(())
+}
+
+/// Whether the name typed by the user is `name`
+fn confirm(name: &str) -> AppResult<bool> {
+	print!("Type {:?} to drop the database: ", name);
+	std::io::stdout().flush()?;
+	let mut answer = String::new();
+	std::io::stdin().read_line(&mut answer)?;
+	Ok(answer.trim_end_matches(['\r', '\n']) == name)
+}
@@ -0,0 +1,73 @@
+use clap::Subcommand;
+use db::migrate::{MigrationState, Migrator};
+use utility::env::Variables;
+use utility::errors::AppResult;
+use crate::setup::get_postgres;
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+	/// Apply the pending migrations
+	Up {
+		/// Stop after this version
+		#[clap(long)]
+		to: Option<i64>,
+	},
+	/// Revert the last applied migrations
+	Down {
+		/// Number of reverted migrations
+		#[clap(long, default_value_t = 1)]
+		steps: usize,
+	},
+	/// Show the applied and pending migrations
+	Status,
+	/// Create the up and down files of a new migration
+	New {
+		/// Name of the migration, e.g. create_users
+		name: String,
+	},
+}
+
+pub async fn run(command: &MigrateCommand) -> AppResult<()> {
+	let settings = Variables::from_env()?;
+	let migrator = Migrator::new(&settings.migrations_dir);
+
+	// Creating files does not need the database
+	if let MigrateCommand::New { name } = command {
+		let (up, down) = migrator.create(name)?;
+		println!("created {}", up.display());
+		println!("created {}", down.display());
+		return Ok(());
+	}
+
+	let (_pg_server, pg) = get_postgres(&settings).await?;
+	match command {
+		MigrateCommand::Up { to } => {
+			let applied = migrator.up(&pg, *to).await?;
+			for migration in &applied {
+				println!("applied {}_{}", migration.version, migration.name);
+			}
+			println!("{} migrations applied", applied.len());
+		}
+		MigrateCommand::Down { steps } => {
+			let reverted = migrator.down(&pg, *steps).await?;
+			for migration in &reverted {
+				println!("reverted {}_{}", migration.version, migration.name);
+			}
+			println!("{} migrations reverted", reverted.len());
+		}
+		MigrateCommand::Status => {
+			for status in migrator.status(&pg).await? {
+				let state = match status.state {
+					MigrationState::Applied => "applied",
+					MigrationState::Pending => "pending",
+					MigrationState::Modified => "modified",
+					MigrationState::Missing => "missing",
+				};
+				let applied_at = status.applied_at.map(|at| at.to_rfc3339()).unwrap_or_default();
+				println!("{:<16} {:<9} {:<26} {}", status.version, state, applied_at, status.name);
+			}
+		}
+		MigrateCommand::New { .. } => {}
+	}
+	Ok(())
+}
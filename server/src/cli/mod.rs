@@ -1,5 +1,14 @@
+mod backup;
+mod database;
+mod migrate;
+
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 use utility::errors::AppResult;
+use crate::cli::backup::BackupCommand;
+use crate::cli::database::DatabaseCommand;
+use crate::cli::migrate::MigrateCommand;
 
 #[derive(Parser)]
 #[clap(
@@ -17,11 +26,46 @@ enum Commands {
 	/// Start server
 	#[clap(about = "Start server", long_about = None)]
 	Serve,
+	/// Manage database migrations
+	#[clap(about = "Manage database migrations", long_about = None)]
+	Migrate {
+		#[clap(subcommand)]
+		command: MigrateCommand,
+	},
+	/// Manage databases
+	#[clap(about = "Create, clone, drop and list databases", long_about = None)]
+	Db {
+		#[clap(subcommand)]
+		command: DatabaseCommand,
+	},
+	/// Manage backups
+	#[clap(about = "Create, list, restore and prune backups", long_about = None)]
+	Backup {
+		#[clap(subcommand)]
+		command: BackupCommand,
+	},
+	/// Recover the embedded Postgres to a point in time
+	#[clap(about = "Recover the embedded Postgres to a point in time from the backups and the WAL archive", long_about = None)]
+	Restore {
+		/// Recovery target, e.g. `2023-10-01T12:00:00Z`
+		#[clap(long)]
+		to: DateTime<Utc>,
+		/// New data directory of the recovered Postgres, `<DB_PATH>.recovered` by default
+		#[clap(long)]
+		data_dir: Option<PathBuf>,
+		/// Port of the Postgres started during the recovery, `DB_PORT + 1` by default
+		#[clap(long)]
+		port: Option<u16>,
+	},
 }
 
 pub async fn start() -> AppResult<()> {
 	let args = Cli::parse();
 	match &args.commands {
-		Commands::Serve => crate::server::serve().await
+		Commands::Serve => crate::server::serve().await,
+		Commands::Migrate { command } => migrate::run(command).await,
+		Commands::Db { command } => database::run(command).await,
+		Commands::Backup { command } => backup::run(command).await,
+		Commands::Restore { to, data_dir, port } => backup::recover(*to, data_dir.clone(), *port).await,
 	}
 }
\ No newline at end of file
@@ -0,0 +1,136 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use validator::Validate;
+use utility::errors::AppResult;
+use crate::cache::CacheStatus;
+use crate::state::SharedState;
+use crate::validator::validate_request_data;
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CacheTableRequest {
+	/// New flinch collection
+	#[validate(length(min = 1))]
+	pub collection: String,
+	#[serde(default = "default_schema")]
+	#[schema(default = "public")]
+	#[validate(length(min = 1))]
+	pub schema: String,
+	/// Table with a primary key, published to realtime clients or captured by CDC
+	#[validate(length(min = 1))]
+	pub table: String,
+	/// Fields indexed by flinch
+	#[serde(default)]
+	pub index_opts: Vec<String>,
+}
+
+fn default_schema() -> String {
+	db::rest::SCHEMA.to_string()
+}
+
+/// List the tables copied into flinch collections
+#[utoipa::path(
+	get,
+	path = "/admin/cache",
+	tag = "admin",
+	responses(
+		(status = 200, description = "Copies, with their staleness", body = [CacheStatus]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_tables(State(state): State<SharedState>) -> Json<Vec<CacheStatus>> {
+	Json(state.cache.list())
+}
+
+/// Copy a table into a flinch collection
+///
+/// The table is loaded, then its changes are applied to the collection. Every user can read the
+/// copy at `/data`, so tables with row-level security or not readable by `DB_USER_ROLE` are refused.
+#[utoipa::path(
+	post,
+	path = "/admin/cache",
+	tag = "admin",
+	request_body = CacheTableRequest,
+	responses(
+		(status = 201, description = "Copied, `stale_since` is set when the table could not be loaded yet", body = CacheStatus),
+		(status = 400, description = "Existing collection, table without primary key, with row-level security, not readable by the user role or whose changes are not sent", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn cache_table(
+	State(state): State<SharedState>,
+	Json(body): Json<CacheTableRequest>,
+) -> AppResult<(StatusCode, Json<CacheStatus>)> {
+	validate_request_data(&body)?;
+	let status = state.cache.add(&body.collection, &body.schema, &body.table, &body.index_opts).await?;
+	Ok((StatusCode::CREATED, Json(status)))
+}
+
+/// Drop the copy of a table and its collection
+#[utoipa::path(
+	delete,
+	path = "/admin/cache/{collection}",
+	tag = "admin",
+	params(("collection" = String, Path, description = "Collection of the copy")),
+	responses(
+		(status = 204, description = "Dropped"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Not a copy", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn drop_table(State(state): State<SharedState>, Path(collection): Path<String>) -> AppResult<StatusCode> {
+	state.cache.remove(&collection).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Load the table of a copy again
+#[utoipa::path(
+	post,
+	path = "/admin/cache/{collection}/resync",
+	tag = "admin",
+	params(("collection" = String, Path, description = "Collection of the copy")),
+	responses(
+		(status = 200, description = "Loaded", body = CacheStatus),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Not a copy", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn resync_table(State(state): State<SharedState>, Path(collection): Path<String>) -> AppResult<Json<CacheStatus>> {
+	Ok(Json(state.cache.resync(&collection).await?))
+}
+
+/// Read a row of a copied table from memory
+///
+/// The key is the value of the primary key, the values of a composite key joined by commas. The
+/// copies are read with the connection role for any user, which is why only tables readable by
+/// `DB_USER_ROLE` and without row-level security can be copied.
+#[utoipa::path(
+	get,
+	path = "/data/{collection}/{key}",
+	tag = "data",
+	params(
+		("collection" = String, Path, description = "Collection of the copy"),
+		("key" = String, Path, description = "Primary key of the row"),
+	),
+	responses(
+		(status = 200, description = "Row", body = Object),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Not a copy, or no row with this key", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn read(State(state): State<SharedState>, Path((collection, key)): Path<(String, String)>) -> AppResult<Json<Value>> {
+	Ok(Json(state.cache.get(&collection, &key)?))
+}
@@ -0,0 +1,78 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use db::cdc::CdcTable;
+use utility::errors::{AppError, AppResult};
+use crate::controller::realtime::PublishTableRequest;
+use crate::state::SharedState;
+use crate::validator::validate_request_data;
+
+/// List the tables whose changes are captured
+#[utoipa::path(
+	get,
+	path = "/admin/cdc/tables",
+	tag = "admin",
+	responses(
+		(status = 200, description = "Tables of the `qaswa_cdc` publication", body = [CdcTable]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_tables(State(state): State<SharedState>) -> AppResult<Json<Vec<CdcTable>>> {
+	Ok(Json(db::cdc::list(&state.pg).await?))
+}
+
+/// Capture the changes of a table
+///
+/// The table is added to the `qaswa_cdc` publication and its replica identity set to `FULL`, so
+/// updates and deletes carry the previous row.
+#[utoipa::path(
+	post,
+	path = "/admin/cdc/tables",
+	tag = "admin",
+	request_body = PublishTableRequest,
+	responses(
+		(status = 201, description = "Captured", body = CdcTable),
+		(status = 400, description = "CDC disabled, or table published with a trigger", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn capture_table(
+	State(state): State<SharedState>,
+	Json(body): Json<PublishTableRequest>,
+) -> AppResult<(StatusCode, Json<CdcTable>)> {
+	validate_request_data(&body)?;
+	if !state.env.cdc_enabled {
+		return Err(AppError::BadRequest { message: "change data capture is disabled, see CDC_ENABLED".to_string() });
+	}
+	Ok((StatusCode::CREATED, Json(db::cdc::add(&state.pg, &body.schema, &body.table).await?)))
+}
+
+/// Stop capturing the changes of a table
+#[utoipa::path(
+	delete,
+	path = "/admin/cdc/tables/{schema}/{table}",
+	tag = "admin",
+	params(
+		("schema" = String, Path, description = "Schema of the table"),
+		("table" = String, Path, description = "Table name"),
+	),
+	responses(
+		(status = 204, description = "Removed from the publication"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Table not captured", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn release_table(
+	State(state): State<SharedState>,
+	Path((schema, table)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+	db::cdc::remove(&state.pg, &schema, &table).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
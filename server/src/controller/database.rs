@@ -0,0 +1,86 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::Validate;
+use db::database::{CreateOptions, DatabaseInfo};
+use utility::errors::{AppError, AppResult};
+use crate::state::SharedState;
+use crate::validator::validate_request_data;
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateDatabaseRequest {
+	#[validate(length(min = 1, max = 63))]
+	pub name: String,
+	/// Database to clone, e.g. a production-like copy for a preview
+	pub template: Option<String>,
+	/// Terminate the sessions of the template, which cannot be copied while it is used
+	#[serde(default)]
+	pub terminate_connections: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DropDatabaseQuery {
+	/// Name of the database again, against mistakes
+	pub confirm: Option<String>,
+	#[serde(default)]
+	pub force: bool,
+}
+
+/// Create a database, empty or cloned from a template
+#[utoipa::path(
+	post,
+	path = "/admin/databases",
+	tag = "admin",
+	request_body = CreateDatabaseRequest,
+	responses(
+		(status = 201, description = "Created database", body = DatabaseInfo),
+		(status = 400, description = "Invalid name, existing database, template in use or used by the server", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown template", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn create_database(
+	State(state): State<SharedState>,
+	Json(body): Json<CreateDatabaseRequest>,
+) -> AppResult<(StatusCode, Json<DatabaseInfo>)> {
+	validate_request_data(&body)?;
+	let options = CreateOptions { template: body.template, terminate_connections: body.terminate_connections };
+	Ok((StatusCode::CREATED, Json(db::database::create(&state.pg, &body.name, &options).await?)))
+}
+
+/// Drop a database
+///
+/// `confirm` must repeat the name of the database. With `force`, its sessions are terminated.
+#[utoipa::path(
+	delete,
+	path = "/admin/databases/{name}",
+	tag = "admin",
+	params(
+		("name" = String, Path, description = "Database"),
+		("confirm" = String, Query, description = "Name of the database again"),
+		("force" = Option<bool>, Query, description = "Terminate the sessions of the database"),
+	),
+	responses(
+		(status = 204, description = "Dropped"),
+		(status = 400, description = "Missing confirmation, protected or used database", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown database", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn drop_database(
+	State(state): State<SharedState>,
+	Path(name): Path<String>,
+	Query(query): Query<DropDatabaseQuery>,
+) -> AppResult<StatusCode> {
+	if query.confirm.as_deref() != Some(name.as_str()) {
+		return Err(AppError::BadRequest { message: format!("confirm the removal with ?confirm={}", name) });
+	}
+	db::database::drop(&state.pg, &name, query.force).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,135 @@
+use async_graphql::http::{ClientMessage, WebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS};
+use axum::extract::State;
+use axum::extract::ws::{CloseFrame, Message, WebSocketUpgrade};
+use axum::http::header::SEC_WEBSOCKET_PROTOCOL;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::{Extension, Json};
+use futures::{future, SinkExt, StreamExt};
+use serde_json::{json, Value};
+use utility::errors::{AppError, AppResult};
+use crate::controller::realtime::Connection;
+use crate::graphql::Session;
+use crate::layers::jwt::claims::{Claims, Jwt};
+use crate::state::SharedState;
+use crate::util::header_value_to_str;
+
+/// Run a GraphQL query or mutation
+///
+/// The schema has a query field, `insert_`, `update_` and `delete_` mutations per table of the
+/// `public` schema, see the `GraphQL` section of the README. Statements run with the role and
+/// the claims of the REST API, errors are returned in `errors` with a `200` status.
+#[utoipa::path(
+	post,
+	path = "/graphql",
+	tag = "graphql",
+	request_body(content = Object, description = "`query`, `variables` and `operationName`"),
+	responses(
+		(status = 200, description = "`data` and `errors`", body = Object),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "No table to query", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn execute(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	headers: HeaderMap,
+	Json(mut request): Json<async_graphql::Request>,
+) -> AppResult<Json<async_graphql::Response>> {
+	let schema = state.graphql.get(&state).await?;
+	request.data = Session::new(&state, &claims, &headers)?.data();
+	Ok(Json(schema.execute(request).await))
+}
+
+/// Subscribe to the changes of the tables over a WebSocket
+///
+/// Speaks the `graphql-transport-ws` and `graphql-ws` subprotocols. The token is sent as `token`,
+/// or as `Authorization: Bearer <JWT>`, in the payload of the `connection_init` message.
+/// Subscriptions are limited to the tables published to realtime clients or captured by CDC, and
+/// to the rows the user may read as in `/realtime/events`.
+#[utoipa::path(
+	get,
+	path = "/graphql/ws",
+	tag = "graphql",
+	responses(
+		(status = 101, description = "Switched to the WebSocket protocol"),
+		(status = 400, description = "Missing subprotocol", body = AppErrorMessage, content_type = "application/problem+json"),
+	)
+)]
+pub async fn subscribe(State(state): State<SharedState>, headers: HeaderMap, upgrade: WebSocketUpgrade) -> AppResult<Response> {
+	let protocol = header_value_to_str(headers.get(SEC_WEBSOCKET_PROTOCOL))
+		.split(',')
+		.find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok())
+		.ok_or_else(|| AppError::BadRequest { message: format!("expected the {} subprotocol", ALL_WEBSOCKET_PROTOCOLS.join(" or ")) })?;
+	Ok(upgrade.protocols(ALL_WEBSOCKET_PROTOCOLS).on_upgrade(move |socket| async move {
+		let _connection = Connection::open();
+		let (mut sink, stream) = socket.split();
+		// Until the client closes the connection
+		let mut stream = stream
+			.take_while(|message| future::ready(!matches!(message, Err(_) | Ok(Message::Close(_)))))
+			.filter_map(|message| future::ready(match message {
+				Ok(Message::Text(text)) => Some(text.into_bytes()),
+				Ok(Message::Binary(bytes)) => Some(bytes),
+				_ => None,
+			}))
+			.boxed();
+		// The schema is only loaded for the clients whose `connection_init` holds a valid token
+		let init = stream.next().await;
+		let started = match authenticate(&state, &headers, init.as_deref()) {
+			Ok(session) => state.graphql.get(&state).await.map(|schema| (schema, session)).map_err(|err| (1011, err.detail())),
+			Err(refused) => Err(refused),
+		};
+		match started {
+			Ok((schema, session)) => {
+				let stream = futures::stream::iter(init).chain(stream);
+				let mut messages = WebSocket::new(schema, stream, protocol).on_connection_init(move |_| async move { Ok(session.data()) });
+				while let Some(message) = messages.next().await {
+					let message = match message {
+						WsMessage::Text(text) => Message::Text(text),
+						WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+					};
+					if sink.send(message).await.is_err() {
+						break;
+					}
+				}
+			}
+			Err((code, reason)) => {
+				let message = match protocol {
+					WebSocketProtocols::SubscriptionsTransportWS => {
+						Message::Text(json!({ "type": "connection_error", "payload": { "message": reason } }).to_string())
+					}
+					WebSocketProtocols::GraphQLWS => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+				};
+				let _ = sink.send(message).await;
+			}
+		}
+		let _ = sink.close().await;
+	}))
+}
+
+/// Session of the `connection_init` message, which must be the first one of the client, or the
+/// close code and the reason of its refusal
+fn authenticate(state: &SharedState, headers: &HeaderMap, message: Option<&[u8]>) -> Result<Session, (u16, String)> {
+	let unauthorized = || (4401, "Unauthorized".to_string());
+	let payload = match message.map(ClientMessage::from_bytes) {
+		Some(Ok(ClientMessage::ConnectionInit { payload })) => payload.unwrap_or_default(),
+		_ => return Err(unauthorized()),
+	};
+	let (claims, _) = Jwt::parse(token(&payload), &state.config.jwt_decoding_key).map_err(|_| unauthorized())?;
+	Session::new(state, &claims, headers).map_err(|err| (1011, err.detail()))
+}
+
+/// Token of the `connection_init` payload
+fn token(payload: &Value) -> &str {
+	match payload.get("token").and_then(Value::as_str) {
+		Some(token) => token,
+		None => payload
+			.get("Authorization")
+			.or_else(|| payload.get("authorization"))
+			.and_then(Value::as_str)
+			.and_then(|authorization| authorization.strip_prefix("Bearer "))
+			.unwrap_or_default(),
+	}
+}
+
@@ -0,0 +1,17 @@
+use axum::Extension;
+use metrics_exporter_prometheus::PrometheusHandle;
+
+/// Prometheus metrics in the text exposition format
+#[utoipa::path(
+	get,
+	path = "/metrics",
+	tag = "monitoring",
+	responses(
+		(status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"),
+		(status = 401, description = "Missing or invalid credentials", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("basic_auth" = []))
+)]
+pub async fn metrics(Extension(handle): Extension<PrometheusHandle>) -> String {
+	handle.render()
+}
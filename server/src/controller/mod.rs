@@ -1 +1,11 @@
-pub mod web;
\ No newline at end of file
+pub mod web;
+pub mod metrics;
+pub mod sql;
+pub mod rest;
+pub mod schema;
+pub mod database;
+pub mod tx;
+pub mod realtime;
+pub mod cdc;
+pub mod cache;
+pub mod graphql;
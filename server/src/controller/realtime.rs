@@ -0,0 +1,210 @@
+use std::convert::Infallible;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::{Extension, Json};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use utoipa::ToSchema;
+use validator::Validate;
+use db::notify::{Change, Notification, RealtimeTable, Seen, Subscription};
+use db::ops::ExecOptions;
+use utility::errors::{AppError, AppResult};
+use crate::controller::rest::options;
+use crate::layers::jwt::claims::Claims;
+use crate::layers::prometheus::PrometheusMetric;
+use crate::state::SharedState;
+use crate::validator::validate_request_data;
+
+/// Parameter of `/realtime/events` naming the table, the other ones are filters
+const TABLE: &str = "table";
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct PublishTableRequest {
+	/// Schema of the table
+	#[serde(default = "default_schema")]
+	#[schema(default = "public")]
+	#[validate(length(min = 1))]
+	pub schema: String,
+	#[validate(length(min = 1))]
+	pub table: String,
+}
+
+fn default_schema() -> String {
+	db::rest::SCHEMA.to_string()
+}
+
+/// List the tables whose changes are sent to realtime clients
+#[utoipa::path(
+	get,
+	path = "/admin/realtime/tables",
+	tag = "admin",
+	responses(
+		(status = 200, description = "Published tables", body = [RealtimeTable]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_tables(State(state): State<SharedState>) -> AppResult<Json<Vec<RealtimeTable>>> {
+	Ok(Json(db::notify::list(&state.pg).await?))
+}
+
+/// Publish the changes of a table to realtime clients
+///
+/// A trigger is installed on the table; publishing a table again keeps it.
+#[utoipa::path(
+	post,
+	path = "/admin/realtime/tables",
+	tag = "admin",
+	request_body = PublishTableRequest,
+	responses(
+		(status = 201, description = "Published", body = RealtimeTable),
+		(status = 400, description = "Invalid request", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn publish_table(
+	State(state): State<SharedState>,
+	Json(body): Json<PublishTableRequest>,
+) -> AppResult<(StatusCode, Json<RealtimeTable>)> {
+	validate_request_data(&body)?;
+	Ok((StatusCode::CREATED, Json(db::notify::add(&state.pg, &body.schema, &body.table).await?)))
+}
+
+/// Stop publishing the changes of a table, dropping its trigger
+#[utoipa::path(
+	delete,
+	path = "/admin/realtime/tables/{schema}/{table}",
+	tag = "admin",
+	params(
+		("schema" = String, Path, description = "Schema of the table"),
+		("table" = String, Path, description = "Table name"),
+	),
+	responses(
+		(status = 204, description = "Unpublished"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Table not published", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn unpublish_table(
+	State(state): State<SharedState>,
+	Path((schema, table)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+	db::notify::remove(&state.pg, &schema, &table).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Receive the changes of the published tables as server-sent events
+///
+/// Each `change` event holds a changed row as JSON. A `resync` event means changes were missed,
+/// because the client was too slow or the server lost its database connection, and the client
+/// should read the rows again. Query parameters other than `table` are filters
+/// `column=[not.]operator.value` as in the REST API, evaluated on the new row, or on the deleted one.
+/// The changed rows are read again as the user, and only the visible ones are sent; the previous
+/// and the deleted rows only hold their primary key, and deletes are only sent for rows sent before.
+#[utoipa::path(
+	get,
+	path = "/realtime/events",
+	tag = "realtime",
+	params(
+		("table" = Option<String>, Query, description = "Table, `name` in the `public` schema or `schema.name`, all the published tables by default"),
+	),
+	responses(
+		(status = 200, description = "Stream of `change` and `resync` events", body = Change, content_type = "text/event-stream"),
+		(status = 400, description = "Invalid filter", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Table not published", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn events(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	headers: HeaderMap,
+	Query(pairs): Query<Vec<(String, String)>>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+	let table = pairs.iter().find(|(name, _)| name == TABLE).map(|(_, table)| table.as_str());
+	let filters: Vec<_> = pairs.iter().filter(|(name, _)| name != TABLE).cloned().collect();
+	let subscription = subscribe(&state, table, &filters).await?;
+	let options = options(&state, &claims, &headers, true)?;
+
+	let receiver = state.changes.subscribe();
+	let stream = futures::stream::unfold(
+		(receiver, state, subscription, options, Seen::default(), Connection::open()),
+		|(mut receiver, state, subscription, options, mut seen, connection)| async move {
+			let event = loop {
+				match receiver.recv().await {
+					Ok(Notification::Change(change)) => match visible(&state, &subscription, &change, &options, &mut seen).await {
+						Some(change) => break Event::default().event("change").json_data(&change).unwrap_or_default(),
+						None => continue,
+					},
+					Ok(Notification::Reconnected) | Err(RecvError::Lagged(_)) => break Event::default().event("resync").data("{}"),
+					Err(RecvError::Closed) => return None,
+				}
+			};
+			Some((Ok(event), (receiver, state, subscription, options, seen, connection)))
+		},
+	);
+	Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Subscription to a published table, or to all of them
+pub(crate) async fn subscribe(state: &SharedState, table: Option<&str>, filters: &[(String, String)]) -> AppResult<Subscription> {
+	let subscription = Subscription::parse(table, filters).map_err(|message| AppError::BadRequest { message })?;
+	if let Some((schema, table)) = &subscription.table {
+		check_published(state, schema, table).await?;
+	}
+	Ok(subscription)
+}
+
+/// `change` as sent to a subscriber, `None` when it does not match the subscription or its row
+/// is hidden from the user of `options` (see [`db::notify::visible`])
+pub(crate) async fn visible(
+	state: &SharedState,
+	subscription: &Subscription,
+	change: &Change,
+	options: &ExecOptions,
+	seen: &mut Seen,
+) -> Option<Change> {
+	match db::notify::visible(&state.pg, &state.catalog, subscription, change, options, seen).await {
+		Ok(change) => change,
+		Err(err) => {
+			debug!("change of {}.{} not sent - {}", change.schema, change.table, err);
+			None
+		}
+	}
+}
+
+/// Whether the changes of a table reach the subscribers
+pub(crate) async fn check_published(state: &SharedState, schema: &str, table: &str) -> AppResult<()> {
+	// Captured tables reach the subscribers through CDC instead of a trigger
+	let captured = state.env.cdc_enabled && db::cdc::is_published(&state.pg, schema, table).await?;
+	if !captured && !db::notify::is_published(&state.pg, schema, table).await? {
+		return Err(AppError::NotFound { message: format!("table {}.{} is not published", schema, table) });
+	}
+	Ok(())
+}
+
+/// Realtime client counted by the `realtime_connections_active` gauge while alive
+pub(crate) struct Connection;
+
+impl Connection {
+	pub(crate) fn open() -> Self {
+		PrometheusMetric::realtime_connected();
+		Self
+	}
+}
+
+impl Drop for Connection {
+	fn drop(&mut self) {
+		PrometheusMetric::realtime_disconnected();
+	}
+}
@@ -0,0 +1,190 @@
+use std::time::Duration;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::{Extension, Json};
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+use db::ops::{ExecOptions, Identity};
+use db::rest::Mutation;
+use db::rest::query::RestQuery;
+use utility::errors::{AppError, AppResult, ErrorCode};
+use crate::layers::jwt::claims::Claims;
+use crate::state::SharedState;
+use crate::util::header_value_to_str;
+
+const PREFER: &str = "prefer";
+const RETURN_REPRESENTATION: &str = "return=representation";
+
+/// Read rows of a table
+///
+/// Query parameters other than `select`, `order`, `limit` and `offset` are filters
+/// `column=[not.]operator.value` with the operators `eq`, `neq`, `lt`, `lte`, `gt`, `gte`,
+/// `like`, `ilike` (`*` matches any text), `in` (`in.(1,2)`) and `is` (`null`, `true`, `false`).
+#[utoipa::path(
+	get,
+	path = "/rest/{table}",
+	tag = "rest",
+	params(
+		("table" = String, Path, description = "Table or view of the `public` schema"),
+		("select" = Option<String>, Query, description = "Columns and embedded relations, e.g. `id,title,author(name)`"),
+		("order" = Option<String>, Query, description = "Ordering, e.g. `created_at.desc.nullslast,id`"),
+		("limit" = Option<i64>, Query, description = "Maximum number of rows, capped by `REST_MAX_ROWS`"),
+		("offset" = Option<i64>, Query, description = "Number of skipped rows"),
+	),
+	responses(
+		(status = 200, description = "Rows", body = [Object]),
+		(status = 400, description = "Invalid query or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn read(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(table): Path<String>,
+	Query(pairs): Query<Vec<(String, String)>>,
+	headers: HeaderMap,
+) -> AppResult<Json<Vec<Value>>> {
+	let query = parse(&pairs)?;
+	Ok(Json(db::rest::read(&state.pg, &state.catalog, &table, &query, &options(&state, &claims, &headers, true)?).await?))
+}
+
+/// Insert a row, or an array of rows
+#[utoipa::path(
+	post,
+	path = "/rest/{table}",
+	tag = "rest",
+	params(
+		("table" = String, Path, description = "Table or view of the `public` schema"),
+		("select" = Option<String>, Query, description = "Returned columns and embedded relations"),
+		("Prefer" = Option<String>, Header, description = "`return=representation` to return the inserted rows"),
+	),
+	request_body(content = Object, description = "Row or array of rows"),
+	responses(
+		(status = 201, description = "Inserted, with the rows when `Prefer: return=representation`", body = [Object]),
+		(status = 400, description = "Invalid body or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn create(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(table): Path<String>,
+	Query(pairs): Query<Vec<(String, String)>>,
+	headers: HeaderMap,
+	Json(body): Json<Value>,
+) -> AppResult<Response> {
+	write(&state, &claims, &table, &pairs, &headers, Mutation::Insert(&body), StatusCode::CREATED).await
+}
+
+/// Update the rows matching the filters, at least one filter is required
+#[utoipa::path(
+	patch,
+	path = "/rest/{table}",
+	tag = "rest",
+	params(
+		("table" = String, Path, description = "Table or view of the `public` schema"),
+		("select" = Option<String>, Query, description = "Returned columns and embedded relations"),
+		("Prefer" = Option<String>, Header, description = "`return=representation` to return the updated rows"),
+	),
+	request_body(content = Object, description = "Updated columns"),
+	responses(
+		(status = 200, description = "Updated rows, with `Prefer: return=representation`", body = [Object]),
+		(status = 204, description = "Updated"),
+		(status = 400, description = "Invalid body, missing filters or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn update(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(table): Path<String>,
+	Query(pairs): Query<Vec<(String, String)>>,
+	headers: HeaderMap,
+	Json(body): Json<Value>,
+) -> AppResult<Response> {
+	write(&state, &claims, &table, &pairs, &headers, Mutation::Update(&body), StatusCode::OK).await
+}
+
+/// Delete the rows matching the filters, at least one filter is required
+#[utoipa::path(
+	delete,
+	path = "/rest/{table}",
+	tag = "rest",
+	params(
+		("table" = String, Path, description = "Table or view of the `public` schema"),
+		("select" = Option<String>, Query, description = "Returned columns and embedded relations"),
+		("Prefer" = Option<String>, Header, description = "`return=representation` to return the deleted rows"),
+	),
+	responses(
+		(status = 200, description = "Deleted rows, with `Prefer: return=representation`", body = [Object]),
+		(status = 204, description = "Deleted"),
+		(status = 400, description = "Missing filters or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn delete(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(table): Path<String>,
+	Query(pairs): Query<Vec<(String, String)>>,
+	headers: HeaderMap,
+) -> AppResult<Response> {
+	write(&state, &claims, &table, &pairs, &headers, Mutation::Delete, StatusCode::OK).await
+}
+
+async fn write(
+	state: &SharedState,
+	claims: &Claims,
+	table: &str,
+	pairs: &[(String, String)],
+	headers: &HeaderMap,
+	mutation: Mutation<'_>,
+	status: StatusCode,
+) -> AppResult<Response> {
+	let query = parse(pairs)?;
+	let returning = headers
+		.get_all(PREFER)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|preference| preference.trim() == RETURN_REPRESENTATION);
+
+	let rows = db::rest::write(&state.pg, &state.catalog, table, &query, mutation, returning, &options(state, claims, headers, false)?).await?;
+	match (returning, status) {
+		(true, status) => Ok((status, [("preference-applied", RETURN_REPRESENTATION)], Json(rows)).into_response()),
+		(false, StatusCode::CREATED) => Ok(StatusCode::CREATED.into_response()),
+		(false, _) => Ok(StatusCode::NO_CONTENT.into_response()),
+	}
+}
+
+fn parse(pairs: &[(String, String)]) -> AppResult<RestQuery> {
+	RestQuery::parse(pairs).map_err(|message| AppError::BadRequest { message })
+}
+
+pub(crate) fn options(state: &SharedState, claims: &Claims, headers: &HeaderMap, read_only: bool) -> AppResult<ExecOptions> {
+	Ok(ExecOptions {
+		read_only,
+		statement_timeout: Duration::from_millis(state.env.sql_statement_timeout_ms),
+		max_rows: state.env.rest_max_rows,
+		identity: Some(identity(state, claims)?),
+		request_id: header_value_to_str(headers.get("x-request-id")).to_string(),
+		slow_log: Some(state.slow_log.clone()),
+	})
+}
+
+/// Role and claims of the statements run for the user, `DB_USER_ROLE` is checked at startup
+pub fn identity(state: &SharedState, claims: &Claims) -> AppResult<Identity> {
+	let claims = serde_json::to_value(claims).map_err(|err| AppError::InternalError {
+		code: ErrorCode::E100,
+		message: err.to_string(),
+	})?;
+	Ok(Identity { role: state.env.db_user_role.clone(), claims })
+}
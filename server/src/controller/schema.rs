@@ -0,0 +1,63 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use db::schema::{SchemaDetail, SchemaInfo, TableInfo};
+use utility::errors::AppResult;
+use crate::state::SharedState;
+
+/// List schemas
+#[utoipa::path(
+	get,
+	path = "/admin/schema",
+	tag = "admin",
+	responses(
+		(status = 200, description = "User schemas", body = [SchemaInfo]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_schemas(State(state): State<SharedState>) -> AppResult<Json<Vec<SchemaInfo>>> {
+	Ok(Json(db::schema::list_schemas(&state.pg).await?))
+}
+
+/// Describe the tables, views, functions and enums of a schema
+#[utoipa::path(
+	get,
+	path = "/admin/schema/{schema}",
+	tag = "admin",
+	params(("schema" = String, Path, description = "Schema name")),
+	responses(
+		(status = 200, description = "Schema", body = SchemaDetail),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown schema", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn describe_schema(State(state): State<SharedState>, Path(schema): Path<String>) -> AppResult<Json<SchemaDetail>> {
+	Ok(Json(db::schema::describe_schema(&state.pg, &schema).await?))
+}
+
+/// Describe the columns, keys and indexes of a table
+#[utoipa::path(
+	get,
+	path = "/admin/schema/{schema}/tables/{table}",
+	tag = "admin",
+	params(
+		("schema" = String, Path, description = "Schema name"),
+		("table" = String, Path, description = "Table name"),
+	),
+	responses(
+		(status = 200, description = "Table", body = TableInfo),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown schema or table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn describe_table(
+	State(state): State<SharedState>,
+	Path((schema, table)): Path<(String, String)>,
+) -> AppResult<Json<TableInfo>> {
+	Ok(Json(db::schema::describe_table(&state.pg, &schema, &table).await?))
+}
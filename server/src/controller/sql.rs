@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use axum::{Extension, Json};
+use axum::body::StreamBody;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, instrument};
+use utoipa::ToSchema;
+use validator::Validate;
+use db::audit::AuditEntry;
+use db::ops::{ExecOptions, ExecResult};
+use db::params::{Param, ParamType};
+use db::slow::SlowQuery;
+use db::stream::{StreamFormat, StreamOptions};
+use utility::errors::{AppError, AppResult, ErrorCode};
+use crate::extractor::{ExtractClientIp, ExtractRequestId};
+use crate::layers::jwt::claims::Claims;
+use crate::state::SharedState;
+use crate::util::header_value_to_str;
+use crate::validator::validate_request_data;
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct SqlRequest {
+	/// Statement to execute, with `$1..$n` placeholders
+	#[validate(length(min = 1))]
+	pub sql: String,
+	/// Values of the placeholders, `bytea` values are base64 encoded
+	#[serde(default)]
+	#[schema(value_type = Vec<Object>)]
+	pub params: Vec<Value>,
+	/// Optional type of each parameter (`int4`, `text[]`, `jsonb`, ...), `null` or missing types
+	/// are inferred from the statement
+	#[serde(default)]
+	pub types: Vec<Option<String>>,
+	/// Run the statement in a `READ ONLY` transaction
+	#[serde(default = "default_read_only")]
+	#[schema(default = true)]
+	pub read_only: bool,
+	/// `statement_timeout` in milliseconds, capped by `SQL_STATEMENT_TIMEOUT_MS`
+	pub timeout_ms: Option<u64>,
+	/// Maximum number of returned rows, capped by `SQL_MAX_ROWS`
+	pub max_rows: Option<usize>,
+}
+
+fn default_read_only() -> bool {
+	true
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SqlResponse {
+	/// Column names
+	pub columns: Vec<String>,
+	/// Rows as objects keyed by column name
+	#[schema(value_type = Vec<Object>)]
+	pub rows: Vec<Value>,
+	pub row_count: usize,
+	/// Whether rows were dropped because of the row limit
+	pub truncated: bool,
+	/// Execution time in milliseconds
+	pub elapsed_ms: f64,
+}
+
+/// Execute a SQL statement
+#[utoipa::path(
+	post,
+	path = "/admin/sql",
+	tag = "admin",
+	request_body = SqlRequest,
+	responses(
+		(status = 200, description = "Statement result", body = SqlResponse),
+		(status = 400, description = "Invalid request or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+#[instrument(skip_all, fields(request_id = %header_value_to_str(Some(&request_id))))]
+pub async fn exec_sql(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+	ExtractClientIp(client_ip): ExtractClientIp,
+	Extension(claims): Extension<Claims>,
+	Json(body): Json<SqlRequest>,
+) -> AppResult<Json<SqlResponse>> {
+	validate_request_data(&body)?;
+	let params = params(&body.params, &body.types)?;
+
+	let settings = &state.env;
+	let options = ExecOptions {
+		read_only: body.read_only,
+		statement_timeout: Duration::from_millis(
+			body.timeout_ms.unwrap_or(settings.sql_statement_timeout_ms).min(settings.sql_statement_timeout_ms)
+		),
+		max_rows: body.max_rows.unwrap_or(settings.sql_max_rows).min(settings.sql_max_rows),
+		identity: None,
+		request_id: header_value_to_str(Some(&request_id)).to_string(),
+		slow_log: Some(state.slow_log.clone()),
+	};
+	let result = db::ops::exec_sql(&state.pg, &body.sql, &params, &options).await;
+
+	let entry = AuditEntry {
+		username: claims.sub,
+		client_ip: client_ip.to_string(),
+		request_id: options.request_id,
+		statement: body.sql,
+		read_only: options.read_only,
+		..Default::default()
+	};
+	audit(&state, entry, &result).await;
+	Ok(Json(result?.into()))
+}
+
+/// Record a statement in the SQL audit
+pub(crate) async fn audit(state: &SharedState, mut entry: AuditEntry, result: &AppResult<ExecResult>) {
+	match result {
+		Ok(result) => {
+			entry.success = true;
+			entry.row_count = result.rows_affected as i64;
+			entry.duration_ms = result.elapsed.as_secs_f64() * 1000.0;
+		}
+		Err(err) => entry.error = Some(err.to_string()),
+	}
+	// The statement has run, a failed audit must not hide its result
+	if let Err(err) = db::audit::record(&state.pg, &entry).await {
+		error!("SQL audit: {:?}", err);
+	}
+}
+
+impl From<ExecResult> for SqlResponse {
+	fn from(result: ExecResult) -> Self {
+		Self {
+			row_count: result.rows.len(),
+			columns: result.columns,
+			rows: result.rows,
+			truncated: result.truncated,
+			elapsed_ms: result.elapsed.as_secs_f64() * 1000.0,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ExplainRequest {
+	/// Statement to explain, with `$1..$n` placeholders
+	#[validate(length(min = 1))]
+	pub sql: String,
+	/// Values of the placeholders, `bytea` values are base64 encoded
+	#[serde(default)]
+	#[schema(value_type = Vec<Object>)]
+	pub params: Vec<Value>,
+	/// Optional type of each parameter, `null` or missing types are inferred from the statement
+	#[serde(default)]
+	pub types: Vec<Option<String>>,
+	/// Run the statement to report the actual times, rows and buffers, `false` only plans it
+	#[serde(default = "default_analyze")]
+	#[schema(default = true)]
+	pub analyze: bool,
+	/// `statement_timeout` in milliseconds, capped by `SQL_STATEMENT_TIMEOUT_MS`
+	pub timeout_ms: Option<u64>,
+}
+
+fn default_analyze() -> bool {
+	true
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExplainResponse {
+	/// Output of `EXPLAIN (FORMAT JSON)`
+	#[schema(value_type = Vec<Object>)]
+	pub plan: Value,
+	/// Execution time in milliseconds
+	pub elapsed_ms: f64,
+}
+
+/// Explain a SQL statement
+///
+/// Returns the plan of `EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON)`. Analyzing runs the statement, in
+/// a transaction which is always rolled back so that writes change nothing.
+#[utoipa::path(
+	post,
+	path = "/admin/sql/explain",
+	tag = "admin",
+	request_body = ExplainRequest,
+	responses(
+		(status = 200, description = "Plan", body = ExplainResponse),
+		(status = 400, description = "Invalid request or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+#[instrument(skip_all, fields(request_id = %header_value_to_str(Some(&request_id))))]
+pub async fn explain(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+	ExtractClientIp(client_ip): ExtractClientIp,
+	Extension(claims): Extension<Claims>,
+	Json(body): Json<ExplainRequest>,
+) -> AppResult<Json<ExplainResponse>> {
+	validate_request_data(&body)?;
+	let params = params(&body.params, &body.types)?;
+
+	let settings = &state.env;
+	let options = ExecOptions {
+		read_only: false,
+		statement_timeout: Duration::from_millis(
+			body.timeout_ms.unwrap_or(settings.sql_statement_timeout_ms).min(settings.sql_statement_timeout_ms)
+		),
+		max_rows: 1,
+		identity: None,
+		request_id: header_value_to_str(Some(&request_id)).to_string(),
+		slow_log: Some(state.slow_log.clone()),
+	};
+	let start = Instant::now();
+	let result = db::ops::explain(&state.pg, &body.sql, &params, &options, body.analyze).await;
+	let elapsed = start.elapsed();
+
+	// Analyzed statements run, even if rolled back
+	let mut entry = AuditEntry {
+		username: claims.sub,
+		client_ip: client_ip.to_string(),
+		request_id: options.request_id,
+		statement: format!("{} {}", if body.analyze { "EXPLAIN ANALYZE" } else { "EXPLAIN" }, body.sql),
+		read_only: false,
+		success: result.is_ok(),
+		duration_ms: elapsed.as_secs_f64() * 1000.0,
+		..Default::default()
+	};
+	if let Err(err) = &result {
+		entry.error = Some(err.to_string());
+	}
+	if let Err(err) = db::audit::record(&state.pg, &entry).await {
+		error!("SQL audit: {:?}", err);
+	}
+	Ok(Json(ExplainResponse { plan: result?, elapsed_ms: entry.duration_ms }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlowQueriesQuery {
+	pub limit: Option<usize>,
+	#[serde(default)]
+	pub min_duration_ms: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SlowQueriesResponse {
+	/// Slow statements since the start, including the ones no longer kept
+	pub total: u64,
+	/// Most recent first
+	pub queries: Vec<SlowQuery>,
+}
+
+/// List the slow statements
+///
+/// Statements of the SQL console, of SQL transactions and of the REST API lasting at least
+/// `SQL_SLOW_QUERY_MS` are kept in memory, the `SQL_SLOW_LOG_SIZE` most recent ones.
+#[utoipa::path(
+	get,
+	path = "/admin/sql/slow",
+	tag = "admin",
+	params(
+		("limit" = Option<usize>, Query, description = "Maximum number of statements, 100 by default"),
+		("min_duration_ms" = Option<u64>, Query, description = "Only the statements lasting at least this long"),
+	),
+	responses(
+		(status = 200, description = "Slow statements", body = SlowQueriesResponse),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn slow_queries(
+	State(state): State<SharedState>,
+	Query(query): Query<SlowQueriesQuery>,
+) -> Json<SlowQueriesResponse> {
+	let queries = state.slow_log.list(Duration::from_millis(query.min_duration_ms), query.limit.unwrap_or(100));
+	Json(SlowQueriesResponse { total: state.slow_log.total(), queries })
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct SqlStreamRequest {
+	/// Query to execute, with `$1..$n` placeholders
+	#[validate(length(min = 1))]
+	pub sql: String,
+	/// Values of the placeholders, `bytea` values are base64 encoded
+	#[serde(default)]
+	#[schema(value_type = Vec<Object>)]
+	pub params: Vec<Value>,
+	/// Optional type of each parameter, `null` or missing types are inferred from the statement
+	#[serde(default)]
+	pub types: Vec<Option<String>>,
+	#[serde(default)]
+	pub format: StreamFormat,
+	/// `statement_timeout` in milliseconds, capped by `SQL_STREAM_TIMEOUT_MS`
+	pub timeout_ms: Option<u64>,
+}
+
+/// Stream the rows of a query as NDJSON, CSV, Arrow IPC or Parquet
+///
+/// The query runs in a `READ ONLY` transaction and its rows are sent as they are fetched, without
+/// row limit. Errors raised once rows are sent abort the response, and the query is cancelled
+/// when the client disconnects.
+#[utoipa::path(
+	post,
+	path = "/admin/sql/stream",
+	tag = "admin",
+	request_body = SqlStreamRequest,
+	responses(
+		(status = 200, description = "Rows, one JSON object per line", body = String, content_type = "application/x-ndjson"),
+		(status = 200, description = "Rows, with a header line", body = String, content_type = "text/csv"),
+		(status = 200, description = "Arrow IPC stream", body = String, content_type = "application/vnd.apache.arrow.stream"),
+		(status = 200, description = "Parquet file", body = String, content_type = "application/vnd.apache.parquet"),
+		(status = 400, description = "Invalid request or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+#[instrument(skip_all, fields(request_id = %header_value_to_str(Some(&request_id))))]
+pub async fn stream_sql(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+	ExtractClientIp(client_ip): ExtractClientIp,
+	Extension(claims): Extension<Claims>,
+	Json(body): Json<SqlStreamRequest>,
+) -> AppResult<Response> {
+	validate_request_data(&body)?;
+	let params = params(&body.params, &body.types)?;
+
+	let entry = AuditEntry {
+		username: claims.sub,
+		client_ip: client_ip.to_string(),
+		request_id: header_value_to_str(Some(&request_id)).to_string(),
+		statement: body.sql.clone(),
+		read_only: true,
+		..Default::default()
+	};
+	stream_response(&state, body.sql, params, body.format, body.timeout_ms, HashMap::new(), entry).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+	#[serde(default)]
+	pub format: StreamFormat,
+	pub timeout_ms: Option<u64>,
+}
+
+/// Download a table or view
+///
+/// All the rows are streamed like with `/admin/sql/stream`, as a file named after the table.
+#[utoipa::path(
+	get,
+	path = "/admin/sql/export/{schema}/{table}",
+	tag = "admin",
+	params(
+		("schema" = String, Path, description = "Schema of the table"),
+		("table" = String, Path, description = "Table or view"),
+		("format" = Option<StreamFormat>, Query, description = "`ndjson` (default), `csv`, `arrow` or `parquet`"),
+		("timeout_ms" = Option<u64>, Query, description = "`statement_timeout` in milliseconds, capped by `SQL_STREAM_TIMEOUT_MS`"),
+	),
+	responses(
+		(status = 200, description = "Rows, one JSON object per line", body = String, content_type = "application/x-ndjson"),
+		(status = 200, description = "Rows, with a header line", body = String, content_type = "text/csv"),
+		(status = 200, description = "Arrow IPC stream", body = String, content_type = "application/vnd.apache.arrow.stream"),
+		(status = 200, description = "Parquet file", body = String, content_type = "application/vnd.apache.parquet"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown table", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+#[instrument(skip_all, fields(request_id = %header_value_to_str(Some(&request_id))))]
+pub async fn export_table(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+	ExtractClientIp(client_ip): ExtractClientIp,
+	Extension(claims): Extension<Claims>,
+	Path((schema, table)): Path<(String, String)>,
+	Query(query): Query<ExportQuery>,
+) -> AppResult<Response> {
+	let sql = db::stream::table_sql(&state.pg, &schema, &table).await?;
+	let numeric_scales = db::stream::numeric_scales(&state.pg, &schema, &table).await?;
+
+	let entry = AuditEntry {
+		username: claims.sub,
+		client_ip: client_ip.to_string(),
+		request_id: header_value_to_str(Some(&request_id)).to_string(),
+		statement: sql.clone(),
+		read_only: true,
+		..Default::default()
+	};
+	// Header values are ASCII
+	let filename: String = table
+		.chars()
+		.map(|c| match c.is_ascii_alphanumeric() || c == '_' || c == '-' { true => c, false => '_' })
+		.collect();
+	let mut response = stream_response(&state, sql, vec![], query.format, query.timeout_ms, numeric_scales, entry).await?;
+	if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{}.{}\"", filename, query.format.extension())) {
+		response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+	}
+	Ok(response)
+}
+
+/// Response streaming the rows of `sql`, the audit entry is recorded once they are all sent
+async fn stream_response(
+	state: &SharedState,
+	sql: String,
+	params: Vec<Param>,
+	format: StreamFormat,
+	timeout_ms: Option<u64>,
+	numeric_scales: HashMap<String, i8>,
+	entry: AuditEntry,
+) -> AppResult<Response> {
+	let max_timeout = state.env.sql_stream_timeout_ms;
+	let options = StreamOptions {
+		format,
+		statement_timeout: Duration::from_millis(timeout_ms.unwrap_or(max_timeout).min(max_timeout)),
+		request_id: entry.request_id.clone(),
+		slow_log: Some(state.slow_log.clone()),
+		numeric_scales,
+	};
+	let mut receiver = db::stream::stream_sql((*state.pg).clone(), sql, params, options, Some(entry));
+
+	// The first chunk comes once the statement is checked, its errors still have a status
+	let first = match receiver.recv().await {
+		Some(chunk) => chunk?,
+		None => return Err(AppError::InternalError { code: ErrorCode::E100, message: "SQL stream closed".to_string() }),
+	};
+	let rest = futures::stream::unfold(receiver, |mut receiver| async move {
+		receiver.recv().await.map(|chunk| (chunk, receiver))
+	});
+	let body = StreamBody::new(futures::stream::once(async { Ok(first) }).chain(rest));
+	Ok(([(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()))], body).into_response())
+}
+
+/// Bind parameters of the request, with their type hints
+pub(crate) fn params(params: &[Value], types: &[Option<String>]) -> AppResult<Vec<Param>> {
+	if types.len() > params.len() {
+		return Err(AppError::BadRequest { message: format!("{} types given for {} parameters", types.len(), params.len()) });
+	}
+	params
+		.iter()
+		.enumerate()
+		.map(|(index, value)| {
+			let type_hint = match types.get(index) {
+				Some(Some(name)) => Some(
+					name.parse::<ParamType>()
+						.map_err(|err| AppError::BadRequest { message: format!("${}: {}", index + 1, err) })?
+				),
+				_ => None,
+			};
+			Ok(Param { value: value.clone(), type_hint })
+		})
+		.collect()
+}
+
+/// List databases
+#[utoipa::path(
+	get,
+	path = "/admin/sql/databases",
+	tag = "admin",
+	responses(
+		(status = 200, description = "Databases", body = [Object]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_databases(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+) -> AppResult<Json<Vec<Value>>> {
+	let request_id = header_value_to_str(Some(&request_id));
+	Ok(Json(db::ops::show_databases(&state.pg, Some(&state.slow_log), request_id).await?))
+}
+
+/// List tables of the `public` schema
+#[utoipa::path(
+	get,
+	path = "/admin/sql/tables",
+	tag = "admin",
+	responses(
+		(status = 200, description = "Tables", body = [Object]),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 403, description = "Not an admin", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn list_tables(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+) -> AppResult<Json<Vec<Value>>> {
+	let request_id = header_value_to_str(Some(&request_id));
+	Ok(Json(db::ops::show_tables(&state.pg, Some(&state.slow_log), request_id).await?))
+}
@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+use axum::{Extension, Json};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::instrument;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+use db::audit::AuditEntry;
+use db::tx::TxInfo;
+use utility::errors::AppResult;
+use crate::controller::rest::identity;
+use crate::controller::sql::{audit, params, SqlResponse};
+use crate::extractor::{ExtractClientIp, ExtractRequestId};
+use crate::layers::jwt::claims::Claims;
+use crate::state::SharedState;
+use crate::util::header_value_to_str;
+use crate::validator::validate_request_data;
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct TxBeginRequest {
+	/// Begin a `READ ONLY` transaction
+	#[serde(default)]
+	pub read_only: bool,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct TxStatementRequest {
+	/// Statement to execute, with `$1..$n` placeholders
+	#[validate(length(min = 1))]
+	pub sql: String,
+	/// Values of the placeholders, `bytea` values are base64 encoded
+	#[serde(default)]
+	#[schema(value_type = Vec<Object>)]
+	pub params: Vec<Value>,
+	/// Optional type of each parameter, `null` or missing types are inferred from the statement
+	#[serde(default)]
+	pub types: Vec<Option<String>>,
+	/// `statement_timeout` in milliseconds, capped by `SQL_STATEMENT_TIMEOUT_MS`
+	pub timeout_ms: Option<u64>,
+	/// Maximum number of returned rows, capped by `SQL_MAX_ROWS`
+	pub max_rows: Option<usize>,
+}
+
+/// Begin a transaction
+///
+/// The transaction holds a connection until it is committed or rolled back. It is rolled back
+/// after `SQL_TX_IDLE_TIMEOUT_MS` without statement, and a user has at most `SQL_TX_MAX_PER_USER`
+/// open transactions. Its statements run with the role and the claims of the REST API.
+#[utoipa::path(
+	post,
+	path = "/sql/tx",
+	tag = "sql",
+	request_body = TxBeginRequest,
+	responses(
+		(status = 201, description = "Open transaction", body = TxInfo),
+		(status = 400, description = "Too many open transactions", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn begin(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	body: Option<Json<TxBeginRequest>>,
+) -> AppResult<(StatusCode, Json<TxInfo>)> {
+	let body = body.map(|Json(body)| body).unwrap_or_default();
+	let identity = identity(&state, &claims)?;
+	Ok((StatusCode::CREATED, Json(state.transactions.begin(&claims.sub, body.read_only, &identity).await?)))
+}
+
+/// Execute a statement in a transaction
+///
+/// Statements of a transaction run one after the other. Statements beginning or ending a
+/// transaction are rejected. Once a statement fails, or changes the role, the claims or the access
+/// mode of the transaction, the transaction only accepts a rollback.
+#[utoipa::path(
+	post,
+	path = "/sql/tx/{id}",
+	tag = "sql",
+	params(("id" = Uuid, Path, description = "Transaction")),
+	request_body = TxStatementRequest,
+	responses(
+		(status = 200, description = "Statement result", body = SqlResponse),
+		(status = 400, description = "Invalid request, failed transaction or statement rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown, expired or finished transaction", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+#[instrument(skip_all, fields(request_id = %header_value_to_str(Some(&request_id))))]
+pub async fn exec(
+	State(state): State<SharedState>,
+	ExtractRequestId(request_id): ExtractRequestId,
+	ExtractClientIp(client_ip): ExtractClientIp,
+	Extension(claims): Extension<Claims>,
+	Path(id): Path<Uuid>,
+	Json(body): Json<TxStatementRequest>,
+) -> AppResult<Json<SqlResponse>> {
+	validate_request_data(&body)?;
+	let params = params(&body.params, &body.types)?;
+	let info = state.transactions.info(id, &claims.sub)?;
+
+	let settings = &state.env;
+	let statement_timeout = Duration::from_millis(
+		body.timeout_ms.unwrap_or(settings.sql_statement_timeout_ms).min(settings.sql_statement_timeout_ms)
+	);
+	let max_rows = body.max_rows.unwrap_or(settings.sql_max_rows).min(settings.sql_max_rows);
+	let start = Instant::now();
+	let result = state.transactions.exec(id, &claims.sub, &body.sql, &params, statement_timeout, max_rows).await;
+	let request_id = header_value_to_str(Some(&request_id)).to_string();
+	state.slow_log.record(&body.sql, start.elapsed(), &result, &request_id);
+
+	let entry = AuditEntry {
+		username: claims.sub,
+		client_ip: client_ip.to_string(),
+		request_id,
+		statement: body.sql,
+		read_only: info.read_only,
+		..Default::default()
+	};
+	audit(&state, entry, &result).await;
+	Ok(Json(result?.into()))
+}
+
+/// Commit a transaction
+///
+/// A failed transaction is rolled back instead, with a 400 response.
+#[utoipa::path(
+	post,
+	path = "/sql/tx/{id}/commit",
+	tag = "sql",
+	params(("id" = Uuid, Path, description = "Transaction")),
+	responses(
+		(status = 204, description = "Committed"),
+		(status = 400, description = "Failed transaction, rolled back, or commit rejected by Postgres", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown, expired or finished transaction", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn commit(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+	state.transactions.commit(id, &claims.sub).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Roll back a transaction
+#[utoipa::path(
+	post,
+	path = "/sql/tx/{id}/rollback",
+	tag = "sql",
+	params(("id" = Uuid, Path, description = "Transaction")),
+	responses(
+		(status = 204, description = "Rolled back"),
+		(status = 401, description = "Missing or invalid token", body = AppErrorMessage, content_type = "application/problem+json"),
+		(status = 404, description = "Unknown, expired or finished transaction", body = AppErrorMessage, content_type = "application/problem+json"),
+	),
+	security(("bearer_jwt" = []))
+)]
+pub async fn rollback(
+	State(state): State<SharedState>,
+	Extension(claims): Extension<Claims>,
+	Path(id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+	state.transactions.rollback(id, &claims.sub).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
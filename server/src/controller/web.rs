@@ -4,11 +4,20 @@ use axum::response::Html;
 use serde_json::Value;
 use tera::Context;
 use tracing::instrument;
-use utility::errors::{AppError, AppResult, IntoInternalError};
+use utility::errors::{AppError, AppResult, ErrorCode};
 use crate::extractor::ExtractRequestId;
 use crate::state::SharedState;
 use crate::TEMPLATES;
 
+/// Liveness probe
+#[utoipa::path(
+	get,
+	path = "/ok",
+	tag = "health",
+	responses(
+		(status = 200, description = "Server is up", body = [String], example = json!(["OK"])),
+	)
+)]
 #[instrument(skip(_state), level = "trace")]
 pub async fn say_ok(
 	State(_state): State<SharedState>,
@@ -17,12 +26,22 @@ pub async fn say_ok(
 	Json(Value::Array(vec![Value::String(format!("OK"))]))
 }
 
+/// Health check page
+#[utoipa::path(
+	get,
+	path = "/health-check",
+	tag = "health",
+	responses(
+		(status = 200, description = "Health check page", body = String, content_type = "text/html"),
+		(status = 500, description = "Template error", body = AppErrorMessage, content_type = "application/problem+json"),
+	)
+)]
 pub async fn health_check() -> AppResult<Html<String>> {
 	Ok(Html(
 		TEMPLATES
 			.as_ref()
-			.map_err(|err| AppError::internal(AppError::InternalError { message: err.to_string() }))?
+			.map_err(|err| AppError::InternalError { code: ErrorCode::E100, message: err.to_string() })?
 			.render("html/index.html", &Context::new())
-			.map_err(|err| AppError::internal(AppError::InternalError { message: err.to_string() }))?,
+			.map_err(|err| AppError::InternalError { code: ErrorCode::E100, message: err.to_string() })?,
 	))
 }
\ No newline at end of file
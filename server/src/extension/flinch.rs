@@ -3,6 +3,7 @@ use async_trait::async_trait;
 use flinch::database::Database;
 use flinch::doc::QueryBased;
 use flinch::extension::{FlinchDbHelper, JsonMapExt};
+use tracing::instrument;
 use crate::GENERAL_BUCKET;
 use crate::layers::jwt::claims::Authenticated;
 
@@ -13,6 +14,7 @@ pub trait FlinchHelper {
 
 #[async_trait]
 impl FlinchHelper for Arc<Database<QueryBased>> {
+	#[instrument(skip_all, fields(otel.kind = "client", db.system = "flinch", db.collection = GENERAL_BUCKET))]
 	async fn get_user(&self, token: &str) -> Option<Authenticated> {
 		let creds = self.get_object(GENERAL_BUCKET, token);
 		match creds.keys().len() > 0 {
@@ -1,6 +1,8 @@
+use std::net::{IpAddr, SocketAddr};
+
 use axum::{
 	async_trait,
-	extract::{FromRequestParts, path::ErrorKind, rejection::PathRejection},
+	extract::{ConnectInfo, FromRequestParts, path::ErrorKind, rejection::PathRejection},
 };
 use axum::http::{header::HeaderValue, request::Parts};
 use hyper::StatusCode;
@@ -10,6 +12,7 @@ use utility::errors::AppErrorCode;
 use utility::app_error;
 
 use utility::errors::{AppError};
+use crate::layers::client_ip::ClientIp;
 
 /// Request ID extractor from HTTP headers
 pub struct ExtractRequestId(pub HeaderValue);
@@ -29,6 +32,30 @@ impl<S> FromRequestParts<S> for ExtractRequestId
 	}
 }
 
+/// Client IP extractor, resolved by `ClientIpLayer` from the peer address and trusted proxy headers
+pub struct ExtractClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ExtractClientIp
+	where
+		S: Send + Sync,
+{
+	type Rejection = (StatusCode, AppError);
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		if let Some(ip) = parts.extensions.get::<ClientIp>() {
+			return Ok(ExtractClientIp(ip.0));
+		}
+		match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+			Some(info) => Ok(ExtractClientIp(info.0.ip())),
+			None => Err((
+				StatusCode::INTERNAL_SERVER_ERROR,
+				app_error!(AppErrorCode::InternalError, "client IP address is not available"),
+			)),
+		}
+	}
+}
+
 // We define our own `Path` extractor that customizes the error from `axum::extract::Path`
 pub struct Path<T>(pub T);
 
@@ -0,0 +1,88 @@
+//! Rows related to the rows of a query, loaded with one statement per relation
+
+use std::collections::HashMap;
+use async_graphql::dataloader::Loader;
+use serde_json::{Map, Value};
+use db::rest::query::Page;
+use super::{error, Session};
+
+/// `page` of the rows of `table` whose `columns` equal `values`, a JSON array
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelationKey {
+	pub table: String,
+	pub columns: Vec<String>,
+	pub page: Page,
+	values: String,
+}
+
+impl RelationKey {
+	/// Key of the rows of `table` whose `columns` equal the `row_columns` of `row`, `None` when
+	/// one of them is null: no row is related
+	pub fn new(table: &str, columns: &[String], page: Page, row: &Value, row_columns: &[String]) -> Option<Self> {
+		let values = row_columns
+			.iter()
+			.map(|column| row.get(column).filter(|value| !value.is_null()).cloned())
+			.collect::<Option<Vec<Value>>>()?;
+		Some(Self { table: table.to_string(), columns: columns.to_vec(), page, values: Value::from(values).to_string() })
+	}
+
+	/// Object of the key values
+	fn object(&self) -> Value {
+		let values: Vec<Value> = serde_json::from_str(&self.values).unwrap_or_default();
+		Value::Object(self.columns.iter().cloned().zip(values).collect::<Map<String, Value>>())
+	}
+}
+
+pub struct RowLoader {
+	session: Session,
+}
+
+impl RowLoader {
+	pub fn new(session: Session) -> Self {
+		Self { session }
+	}
+}
+
+impl Loader<RelationKey> for RowLoader {
+	type Value = Vec<Value>;
+	type Error = async_graphql::Error;
+
+	async fn load(&self, keys: &[RelationKey]) -> Result<HashMap<RelationKey, Vec<Value>>, Self::Error> {
+		let mut relations: HashMap<(&str, &[String], Page), Vec<Value>> = HashMap::new();
+		for key in keys {
+			relations.entry((&key.table, &key.columns, key.page)).or_default().push(key.object());
+		}
+
+		let mut loaded: HashMap<RelationKey, Vec<Value>> = HashMap::new();
+		for ((table, columns, page), keys) in relations {
+			let state = &self.session.state;
+			let rows = db::rest::read_keys(&state.pg, &state.catalog, table, columns, &keys, page, &self.session.read).await.map_err(error)?;
+			for row in rows {
+				if let Some(key) = RelationKey::new(table, columns, page, &row, columns) {
+					loaded.entry(key).or_default().push(row);
+				}
+			}
+		}
+		Ok(loaded)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	#[test]
+	fn test_key() {
+		let columns = vec!["author_id".to_string(), "edition".to_string()];
+		let parent = vec!["id".to_string(), "edition".to_string()];
+		let page = Page { limit: Some(2), offset: None };
+		let key = RelationKey::new("books", &columns, page, &json!({"id": 7, "edition": "first", "name": "A"}), &parent).unwrap();
+		assert_eq!(key.object(), json!({"author_id": 7, "edition": "first"}));
+		// The same key from a loaded row
+		assert_eq!(RelationKey::new("books", &columns, page, &json!({"author_id": 7, "edition": "first", "title": "B"}), &columns), Some(key.clone()));
+		assert_ne!(RelationKey::new("books", &columns, Page::default(), &json!({"author_id": 7, "edition": "first"}), &columns), Some(key));
+		assert!(RelationKey::new("books", &columns, page, &json!({"id": 7, "edition": null}), &parent).is_none());
+		assert!(RelationKey::new("books", &columns, page, &json!({"id": 7}), &parent).is_none());
+	}
+}
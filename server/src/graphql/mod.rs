@@ -0,0 +1,77 @@
+//! GraphQL API generated from the tables of the `public` schema, over the REST API statements
+//! (see [`db::rest`]).
+//!
+//! Each table gets a query field, `insert_`, `update_` and `delete_` mutations and a `_changes`
+//! subscription. Relations are fields whose rows are loaded in batches, once per level of the
+//! query. The schema is built again when the tables changed.
+
+mod loader;
+mod schema;
+
+use std::sync::Arc;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::dynamic::Schema;
+use async_graphql::{Data, ErrorExtensions};
+use axum::http::HeaderMap;
+use tokio::sync::Mutex;
+use db::ops::ExecOptions;
+use db::rest::catalog::Catalog;
+use utility::errors::{AppError, AppResult};
+use crate::controller::rest::options;
+use crate::layers::jwt::claims::Claims;
+use crate::state::SharedState;
+use self::loader::RowLoader;
+
+/// Schema of the last tables read
+#[derive(Default)]
+pub struct Schemas {
+	current: Mutex<Option<(Arc<Catalog>, Schema)>>,
+}
+
+impl Schemas {
+	/// Schema of the current tables
+	pub async fn get(&self, state: &SharedState) -> AppResult<Schema> {
+		let catalog = state.catalog.get(&state.pg).await?;
+		let mut current = self.current.lock().await;
+		match current.as_ref() {
+			Some((built, schema)) if Arc::ptr_eq(built, &catalog) || built == &catalog => Ok(schema.clone()),
+			_ => {
+				let schema = schema::build(&catalog, state.env.graphql_max_depth)?;
+				*current = Some((catalog, schema.clone()));
+				Ok(schema)
+			}
+		}
+	}
+}
+
+/// User of a request or of a WebSocket connection
+#[derive(Clone)]
+pub struct Session {
+	pub state: SharedState,
+	/// Options of the queries and of the mutations, run for the user
+	pub read: ExecOptions,
+	pub write: ExecOptions,
+}
+
+impl Session {
+	pub fn new(state: &SharedState, claims: &Claims, headers: &HeaderMap) -> AppResult<Self> {
+		Ok(Self {
+			state: state.clone(),
+			read: options(state, claims, headers, true)?,
+			write: options(state, claims, headers, false)?,
+		})
+	}
+
+	/// Data of the resolvers: the session and a loader of the related rows
+	pub fn data(self) -> Data {
+		let mut data = Data::default();
+		data.insert(DataLoader::new(RowLoader::new(self.clone()), tokio::spawn));
+		data.insert(self);
+		data
+	}
+}
+
+/// GraphQL error with the message and the code of the REST API errors
+fn error(err: AppError) -> async_graphql::Error {
+	async_graphql::Error::new(err.detail()).extend_with(|_, extensions| extensions.set("code", format!("{:?}", err.code())))
+}
@@ -0,0 +1,596 @@
+//! Types, fields and resolvers generated from the catalog.
+//!
+//! For a table `order_items`:
+//! - the `OrderItems` type, with a field per column and per relation,
+//! - the `order_items(filter, orderBy, limit, offset)` query,
+//! - the `insert_order_items`, `update_order_items` and `delete_order_items` mutations,
+//! - the `order_items_changes(filter)` subscription, of `OrderItemsChange` events.
+//!
+//! Tables and columns whose names are not GraphQL names are left out.
+
+use std::collections::HashSet;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::dynamic::{
+	Enum, Field, FieldFuture, FieldValue, InputObject, InputValue, Object, ResolverContext, Scalar, Schema, Subscription,
+	SubscriptionField, SubscriptionFieldFuture, Type, TypeRef,
+};
+use async_graphql::Value as GraphqlValue;
+use futures::{stream, Stream, StreamExt};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use db::notify::{Change, Notification, Seen};
+use db::rest::catalog::{Catalog, ForeignKey, Table};
+use db::rest::query::{Filter, FilterValue, IsValue, Operator, OrderTerm, Page, RestQuery, SelectItem};
+use db::rest::{Mutation, SCHEMA};
+use utility::errors::{AppError, AppResult, ErrorCode};
+use crate::controller::realtime::{check_published, visible};
+use super::loader::{RelationKey, RowLoader};
+use super::{error, Session};
+
+const BIG_INT: &str = "BigInt";
+const JSON: &str = "JSON";
+const ORDER_DIRECTION: &str = "OrderDirection";
+/// Scalars of the columns, each with its `<Scalar>Comparison` input
+const SCALARS: [&str; 6] = [TypeRef::INT, BIG_INT, TypeRef::FLOAT, TypeRef::BOOLEAN, TypeRef::STRING, JSON];
+/// `op` of the change sent when changes were missed, the rows should be read again
+const RESYNC: &str = "RESYNC";
+
+/// Names of the types of a table
+struct Names {
+	table: String,
+	row: String,
+	filter: String,
+	order_by: String,
+	input: String,
+	change: String,
+}
+
+impl Names {
+	fn new(table: &str) -> Self {
+		let row = type_name(table);
+		Self {
+			table: table.to_string(),
+			filter: format!("{}Filter", row),
+			order_by: format!("{}OrderBy", row),
+			input: format!("{}Input", row),
+			change: format!("{}Change", row),
+			row,
+		}
+	}
+
+	fn types(&self) -> [&str; 5] {
+		[&self.row, &self.filter, &self.order_by, &self.input, &self.change]
+	}
+}
+
+/// Schema of the tables of `catalog`, queries nested deeper than `max_depth` are rejected
+pub fn build(catalog: &Catalog, max_depth: usize) -> AppResult<Schema> {
+	let mut used: HashSet<String> = ["Query", "Mutation", "Subscription", ORDER_DIRECTION]
+		.into_iter()
+		.map(String::from)
+		.chain(SCALARS.into_iter().map(String::from))
+		.chain(SCALARS.into_iter().map(comparison_name))
+		.collect();
+	let mut tables: Vec<&Table> = catalog.tables.values().collect();
+	tables.sort_by(|a, b| a.name.cmp(&b.name));
+	let mut exposed: Vec<(&Table, Names)> = vec![];
+	for table in tables {
+		let names = Names::new(&table.name);
+		let valid = is_name(&table.name) && table.columns.iter().any(|column| is_name(&column.name));
+		if !valid || names.types().iter().any(|name| !is_name(name) || used.contains(*name)) {
+			warn!("table {:?} left out of the GraphQL schema, its name or the names of its types are not valid or unique", table.name);
+			continue;
+		}
+		used.extend(names.types().map(String::from));
+		exposed.push((table, names));
+	}
+	if exposed.is_empty() {
+		return Err(AppError::NotFound { message: format!("no table of the {} schema to query", catalog.schema) });
+	}
+
+	let mut query = Object::new("Query");
+	let mut mutation = Object::new("Mutation");
+	let mut subscription = Subscription::new("Subscription");
+	let mut types: Vec<Type> = vec![];
+	for (table, names) in &exposed {
+		query = query.field(query_field(names));
+		mutation = mutation.field(insert_field(names)).field(update_field(names)).field(delete_field(names));
+		subscription = subscription.field(changes_field(names));
+		types.push(row_type(table, names, &exposed).into());
+		types.extend([filter_type(table, names).into(), order_by_type(table, names).into(), input_type(table, names).into()]);
+		types.push(change_type(names).into());
+	}
+
+	let mut builder = Schema::build("Query", Some("Mutation"), Some("Subscription"))
+		.register(query)
+		.register(mutation)
+		.register(subscription)
+		.register(Scalar::new(BIG_INT).description("64-bit integer"))
+		.register(Scalar::new(JSON).description("JSON value, of the `json`, `jsonb` and array columns"))
+		.register(
+			Enum::new(ORDER_DIRECTION)
+				.items(["ASC", "DESC", "ASC_NULLS_FIRST", "ASC_NULLS_LAST", "DESC_NULLS_FIRST", "DESC_NULLS_LAST"]),
+		)
+		.limit_depth(max_depth);
+	for scalar in SCALARS {
+		builder = builder.register(comparison_type(scalar));
+	}
+	for ty in types {
+		builder = builder.register(ty);
+	}
+	builder.finish().map_err(|err| AppError::InternalError { code: ErrorCode::E100, message: format!("invalid GraphQL schema: {}", err.0) })
+}
+
+/// GraphQL scalar of a column type
+fn scalar(data_type: &str) -> &'static str {
+	match data_type {
+		"boolean" => TypeRef::BOOLEAN,
+		"smallint" | "integer" => TypeRef::INT,
+		"bigint" => BIG_INT,
+		"real" | "double precision" => TypeRef::FLOAT,
+		"json" | "jsonb" => JSON,
+		data_type if data_type.ends_with("[]") => JSON,
+		// Numerics, dates, UUIDs... as printed by Postgres
+		_ => TypeRef::STRING,
+	}
+}
+
+fn comparison_name(scalar: &str) -> String {
+	format!("{}Comparison", scalar)
+}
+
+/// Operators of the REST API filters
+fn comparison_type(scalar: &str) -> InputObject {
+	let mut comparison = InputObject::new(comparison_name(scalar));
+	for operator in ["eq", "neq", "lt", "lte", "gt", "gte"] {
+		comparison = comparison.field(InputValue::new(operator, TypeRef::named(scalar)));
+	}
+	comparison = comparison
+		.field(InputValue::new("in", TypeRef::named_nn_list(scalar)))
+		.field(InputValue::new("isNull", TypeRef::named(TypeRef::BOOLEAN)));
+	if scalar == TypeRef::STRING {
+		comparison = comparison
+			.field(InputValue::new("like", TypeRef::named(TypeRef::STRING)).description("`%` matches any text"))
+			.field(InputValue::new("ilike", TypeRef::named(TypeRef::STRING)).description("Case insensitive `like`"));
+	}
+	comparison
+}
+
+fn columns(table: &Table) -> impl Iterator<Item = (&str, &'static str)> {
+	table.columns.iter().filter(|column| is_name(&column.name)).map(|column| (column.name.as_str(), scalar(&column.data_type)))
+}
+
+fn row_type(table: &Table, names: &Names, exposed: &[(&Table, Names)]) -> Object {
+	let mut object = Object::new(&names.row);
+	for (column, scalar) in columns(table) {
+		object = object.field(column_field(column, scalar));
+	}
+	// Relations of a single foreign key between the tables, like the embeddings of the REST API
+	for (related, related_names) in exposed {
+		if table.column(&related.name).is_some() {
+			continue;
+		}
+		let outgoing: Vec<&ForeignKey> = table.foreign_keys.iter().filter(|fk| fk.foreign_table == related.name).collect();
+		let incoming: Vec<&ForeignKey> = related.foreign_keys.iter().filter(|fk| fk.foreign_table == table.name).collect();
+		match (outgoing.as_slice(), incoming.as_slice()) {
+			([fk], []) => object = object.field(parent_field(related_names, fk)),
+			([], [fk]) => object = object.field(children_field(related_names, fk)),
+			_ => {}
+		}
+	}
+	object
+}
+
+fn column_field(column: &str, scalar: &str) -> Field {
+	let name = column.to_string();
+	Field::new(column, TypeRef::named(scalar), move |ctx| {
+		let name = name.clone();
+		FieldFuture::new(async move {
+			match ctx.parent_value.try_downcast_ref::<Value>()?.get(&name) {
+				Some(value) if !value.is_null() => Ok(Some(FieldValue::value(GraphqlValue::from_json(value.clone())?))),
+				_ => Ok(None),
+			}
+		})
+	})
+}
+
+/// Row referenced by the foreign key of the row
+fn parent_field(related: &Names, fk: &ForeignKey) -> Field {
+	let (table, columns, own) = (fk.foreign_table.clone(), fk.foreign_columns.clone(), fk.columns.clone());
+	Field::new(&related.table, TypeRef::named(&related.row), move |ctx| {
+		let (table, columns, own) = (table.clone(), columns.clone(), own.clone());
+		FieldFuture::new(async move {
+			let rows = related_rows(&ctx, &table, &columns, &own, Page::default()).await?;
+			Ok(rows.into_iter().next().map(FieldValue::owned_any))
+		})
+	})
+}
+
+/// Rows referencing the row with their foreign key, at most `limit` (and `REST_MAX_ROWS`) for
+/// each row
+fn children_field(related: &Names, fk: &ForeignKey) -> Field {
+	let (table, columns, own) = (related.table.clone(), fk.columns.clone(), fk.foreign_columns.clone());
+	Field::new(&related.table, TypeRef::named_nn_list_nn(&related.row), move |ctx| {
+		let (table, columns, own) = (table.clone(), columns.clone(), own.clone());
+		FieldFuture::new(async move {
+			let page = Page { limit: count(argument(&ctx, "limit"), "limit")?, offset: count(argument(&ctx, "offset"), "offset")? };
+			let rows = related_rows(&ctx, &table, &columns, &own, page).await?;
+			Ok(Some(FieldValue::list(rows.into_iter().map(FieldValue::owned_any))))
+		})
+	})
+	.argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+	.argument(InputValue::new("offset", TypeRef::named(TypeRef::INT)))
+}
+
+/// `page` of the rows of `table` whose `columns` equal the `own` columns of the parent row, loaded
+/// with the ones of the other rows of the same level
+async fn related_rows(
+	ctx: &ResolverContext<'_>,
+	table: &str,
+	columns: &[String],
+	own: &[String],
+	page: Page,
+) -> async_graphql::Result<Vec<Value>> {
+	let row = ctx.parent_value.try_downcast_ref::<Value>()?;
+	match RelationKey::new(table, columns, page, row, own) {
+		Some(key) => Ok(ctx.data::<DataLoader<RowLoader>>()?.load_one(key).await?.unwrap_or_default()),
+		None => Ok(vec![]),
+	}
+}
+
+fn filter_type(table: &Table, names: &Names) -> InputObject {
+	let mut filter = InputObject::new(&names.filter).description("Rows matching every comparison");
+	for (column, scalar) in columns(table) {
+		filter = filter.field(InputValue::new(column, TypeRef::named(comparison_name(scalar))));
+	}
+	filter
+}
+
+fn order_by_type(table: &Table, names: &Names) -> InputObject {
+	let mut order_by = InputObject::new(&names.order_by);
+	for (column, _) in columns(table) {
+		order_by = order_by.field(InputValue::new(column, TypeRef::named(ORDER_DIRECTION)));
+	}
+	order_by
+}
+
+fn input_type(table: &Table, names: &Names) -> InputObject {
+	let mut input = InputObject::new(&names.input).description("Columns of an inserted row, or updated columns");
+	for (column, scalar) in columns(table) {
+		input = input.field(InputValue::new(column, TypeRef::named(scalar)));
+	}
+	input
+}
+
+fn change_type(names: &Names) -> Object {
+	let field = |name: &str, ty: TypeRef, value: fn(&Change) -> Option<FieldValue<'static>>| {
+		Field::new(name, ty, move |ctx| FieldFuture::Value(ctx.parent_value.downcast_ref::<Change>().and_then(value)))
+	};
+	Object::new(&names.change)
+		.description(format!("Row inserted, updated or deleted, `{}` when changes were missed", RESYNC))
+		.field(field("op", TypeRef::named_nn(TypeRef::STRING), |change| Some(FieldValue::value(change.op.clone()))))
+		.field(field("row", TypeRef::named(&names.row), |change| change.row.clone().map(FieldValue::owned_any)))
+		.field(field("old", TypeRef::named(&names.row), |change| change.old.clone().map(FieldValue::owned_any)))
+		.field(field("truncated", TypeRef::named_nn(TypeRef::BOOLEAN), |change| Some(FieldValue::value(change.truncated))))
+}
+
+fn query_field(names: &Names) -> Field {
+	let table = names.table.clone();
+	Field::new(&names.table, TypeRef::named_nn_list_nn(&names.row), move |ctx| {
+		let table = table.clone();
+		FieldFuture::new(async move {
+			let session = ctx.data::<Session>()?;
+			let query = RestQuery {
+				select: vec![SelectItem::Star],
+				filters: filters(argument(&ctx, "filter"))?,
+				order: order(argument(&ctx, "orderBy"))?,
+				limit: count(argument(&ctx, "limit"), "limit")?,
+				offset: count(argument(&ctx, "offset"), "offset")?,
+			};
+			let rows = db::rest::read(&session.state.pg, &session.state.catalog, &table, &query, &session.read).await.map_err(error)?;
+			Ok(Some(FieldValue::list(rows.into_iter().map(FieldValue::owned_any))))
+		})
+	})
+	.argument(InputValue::new("filter", TypeRef::named(&names.filter)))
+	.argument(InputValue::new("orderBy", TypeRef::named_nn_list(&names.order_by)))
+	.argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+	.argument(InputValue::new("offset", TypeRef::named(TypeRef::INT)))
+}
+
+fn insert_field(names: &Names) -> Field {
+	let table = names.table.clone();
+	Field::new(format!("insert_{}", names.table), TypeRef::named_nn_list_nn(&names.row), move |ctx| {
+		let table = table.clone();
+		FieldFuture::new(async move {
+			let rows = ctx.args.try_get("objects")?.as_value().clone().into_json()?;
+			write(&ctx, &table, vec![], Mutation::Insert(&rows)).await
+		})
+	})
+	.argument(InputValue::new("objects", TypeRef::named_nn_list_nn(&names.input)))
+}
+
+fn update_field(names: &Names) -> Field {
+	let table = names.table.clone();
+	Field::new(format!("update_{}", names.table), TypeRef::named_nn_list_nn(&names.row), move |ctx| {
+		let table = table.clone();
+		FieldFuture::new(async move {
+			let values = ctx.args.try_get("set")?.as_value().clone().into_json()?;
+			write(&ctx, &table, filters(argument(&ctx, "filter"))?, Mutation::Update(&values)).await
+		})
+	})
+	.argument(InputValue::new("filter", TypeRef::named_nn(&names.filter)))
+	.argument(InputValue::new("set", TypeRef::named_nn(&names.input)))
+}
+
+fn delete_field(names: &Names) -> Field {
+	let table = names.table.clone();
+	Field::new(format!("delete_{}", names.table), TypeRef::named_nn_list_nn(&names.row), move |ctx| {
+		let table = table.clone();
+		FieldFuture::new(async move { write(&ctx, &table, filters(argument(&ctx, "filter"))?, Mutation::Delete).await })
+	})
+	.argument(InputValue::new("filter", TypeRef::named_nn(&names.filter)))
+}
+
+/// Apply `mutation` to the rows matching `filters`, the changed rows are returned
+async fn write<'a>(ctx: &ResolverContext<'a>, table: &str, filters: Vec<Filter>, mutation: Mutation<'_>) -> async_graphql::Result<Option<FieldValue<'a>>> {
+	let session = ctx.data::<Session>()?;
+	let query = RestQuery { select: vec![SelectItem::Star], filters, ..Default::default() };
+	let rows = db::rest::write(&session.state.pg, &session.state.catalog, table, &query, mutation, true, &session.write).await.map_err(error)?;
+	Ok(Some(FieldValue::list(rows.into_iter().map(FieldValue::owned_any))))
+}
+
+fn changes_field(names: &Names) -> SubscriptionField {
+	let table = names.table.clone();
+	SubscriptionField::new(format!("{}_changes", names.table), TypeRef::named_nn(&names.change), move |ctx| {
+		let table = table.clone();
+		SubscriptionFieldFuture::new(async move {
+			let session = ctx.data::<Session>()?;
+			let filters = filters(argument(&ctx, "filter"))?;
+			check_published(&session.state, SCHEMA, &table).await.map_err(error)?;
+			let subscription = db::notify::Subscription { table: Some((SCHEMA.to_string(), table.clone())), filters };
+			let receiver = session.state.changes.subscribe();
+			Ok(changes(receiver, session.clone(), subscription, table).map(|change| change.map(FieldValue::owned_any)))
+		})
+	})
+	.argument(InputValue::new("filter", TypeRef::named(&names.filter)))
+}
+
+/// Changes matching `subscription` whose rows the user of `session` may read, and a `RESYNC`
+/// change when some were missed
+fn changes(
+	receiver: broadcast::Receiver<Notification>,
+	session: Session,
+	subscription: db::notify::Subscription,
+	table: String,
+) -> impl Stream<Item = async_graphql::Result<Change>> {
+	let seen = Seen::default();
+	stream::unfold((receiver, session, subscription, table, seen), |(mut receiver, session, subscription, table, mut seen)| async move {
+		loop {
+			let change = match receiver.recv().await {
+				Ok(Notification::Change(change)) => match visible(&session.state, &subscription, &change, &session.read, &mut seen).await {
+					Some(change) => change,
+					None => continue,
+				},
+				Ok(Notification::Reconnected) | Err(RecvError::Lagged(_)) => Change {
+					schema: SCHEMA.to_string(),
+					table: table.clone(),
+					op: RESYNC.to_string(),
+					row: None,
+					old: None,
+					truncated: false,
+				},
+				Err(RecvError::Closed) => return None,
+			};
+			return Some((Ok(change), (receiver, session, subscription, table, seen)));
+		}
+	})
+}
+
+/// Argument of a field, `None` when missing or null
+fn argument<'a>(ctx: &'a ResolverContext, name: &str) -> Option<&'a GraphqlValue> {
+	ctx.args.get(name).map(|value| value.as_value()).filter(|value| !matches!(value, GraphqlValue::Null))
+}
+
+/// Filters of a `<Table>Filter` input, one per comparison
+fn filters(filter: Option<&GraphqlValue>) -> async_graphql::Result<Vec<Filter>> {
+	let mut filters = vec![];
+	for (column, comparison) in filter.map(object).transpose()?.into_iter().flatten() {
+		for (operator, value) in object(comparison)? {
+			let column = column.to_string();
+			filters.push(match (operator.as_str(), value) {
+				("isNull", GraphqlValue::Boolean(is_null)) => {
+					Filter { column, negated: !is_null, operator: Operator::Is, value: FilterValue::Is(IsValue::Null) }
+				}
+				("in", GraphqlValue::List(values)) => {
+					Filter { column, negated: false, operator: Operator::In, value: FilterValue::List(values.iter().map(text).collect()) }
+				}
+				(operator, value) => Filter { column, negated: false, operator: operator.parse()?, value: FilterValue::Single(text(value)) },
+			});
+		}
+	}
+	Ok(filters)
+}
+
+/// Fields of an input object, without the null ones
+fn object(value: &GraphqlValue) -> async_graphql::Result<impl Iterator<Item = (&async_graphql::Name, &GraphqlValue)>> {
+	match value {
+		GraphqlValue::Object(fields) => Ok(fields.iter().filter(|(_, value)| !matches!(value, GraphqlValue::Null))),
+		_ => Err("expected an object".into()),
+	}
+}
+
+/// Text of a value, cast by Postgres to the type of the column
+fn text(value: &GraphqlValue) -> String {
+	match value {
+		GraphqlValue::String(text) => text.clone(),
+		GraphqlValue::Number(number) => number.to_string(),
+		GraphqlValue::Boolean(boolean) => boolean.to_string(),
+		value => value.clone().into_json().map(|json| json.to_string()).unwrap_or_default(),
+	}
+}
+
+/// Terms of a `[<Table>OrderBy!]` list, in the order of the list then of the fields of each item
+fn order(order_by: Option<&GraphqlValue>) -> async_graphql::Result<Vec<OrderTerm>> {
+	let items = match order_by {
+		Some(GraphqlValue::List(items)) => items.as_slice(),
+		// A single item is coerced into a list
+		Some(item) => std::slice::from_ref(item),
+		None => &[],
+	};
+	let mut terms = vec![];
+	for item in items {
+		for (column, direction) in object(item)? {
+			let (descending, nulls_first) = match direction {
+				GraphqlValue::Enum(direction) => match direction.as_str() {
+					"ASC" => (false, None),
+					"DESC" => (true, None),
+					"ASC_NULLS_FIRST" => (false, Some(true)),
+					"ASC_NULLS_LAST" => (false, Some(false)),
+					"DESC_NULLS_FIRST" => (true, Some(true)),
+					"DESC_NULLS_LAST" => (true, Some(false)),
+					direction => return Err(format!("unknown direction {}", direction).into()),
+				},
+				direction => return Err(format!("unknown direction {}", direction).into()),
+			};
+			terms.push(OrderTerm { column: column.to_string(), descending, nulls_first });
+		}
+	}
+	Ok(terms)
+}
+
+fn count(value: Option<&GraphqlValue>, name: &str) -> async_graphql::Result<Option<i64>> {
+	match value {
+		None => Ok(None),
+		Some(GraphqlValue::Number(count)) if count.as_i64().is_some_and(|count| count >= 0) => Ok(count.as_i64()),
+		Some(_) => Err(format!("{} must not be negative", name).into()),
+	}
+}
+
+/// `OrderItems` for `order_items`
+fn type_name(table: &str) -> String {
+	table
+		.split('_')
+		.filter_map(|part| {
+			let mut chars = part.chars();
+			chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>())
+		})
+		.collect()
+}
+
+/// `[_A-Za-z][_0-9A-Za-z]*`, without the `__` prefix of the introspection
+fn is_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	matches!(chars.next(), Some(first) if first == '_' || first.is_ascii_alphabetic())
+		&& chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+		&& !name.starts_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use serde_json::json;
+	use db::rest::catalog::Column;
+	use super::*;
+
+	fn catalog() -> Catalog {
+		let table = |name: &str, columns: &[(&str, &str)], foreign_keys: Vec<ForeignKey>| Table {
+			name: name.to_string(),
+			columns: columns.iter().map(|(name, data_type)| Column { name: name.to_string(), data_type: data_type.to_string() }).collect(),
+			foreign_keys,
+			..Default::default()
+		};
+		let tables = [
+			table("authors", &[("id", "bigint"), ("name", "text"), ("tags", "text[]")], vec![]),
+			table("book_reviews", &[("id", "integer"), ("book_id", "integer"), ("score", "real")], vec![ForeignKey {
+				name: "book_reviews_book_id_fkey".to_string(),
+				columns: vec!["book_id".to_string()],
+				foreign_table: "books".to_string(),
+				foreign_columns: vec!["id".to_string()],
+			}]),
+			table("books", &[("id", "integer"), ("title", "character varying(200)"), ("author_id", "bigint"), ("first name", "text")], vec![
+				ForeignKey {
+					name: "books_author_id_fkey".to_string(),
+					columns: vec!["author_id".to_string()],
+					foreign_table: "authors".to_string(),
+					foreign_columns: vec!["id".to_string()],
+				},
+			]),
+			table("book_reviews_", &[("id", "integer")], vec![]),
+			table("order-lines", &[("id", "integer")], vec![]),
+		];
+		Catalog {
+			schema: "public".to_string(),
+			tables: HashMap::from_iter(tables.into_iter().map(|table| (table.name.clone(), table))),
+		}
+	}
+
+	#[test]
+	fn test_build() {
+		let sdl = build(&catalog(), 10).unwrap().sdl();
+		for expected in [
+			"type Authors {\n\tid: BigInt\n\tname: String\n\ttags: JSON\n\tbooks(limit: Int, offset: Int): [Books!]!\n}",
+			"type Books {\n\tid: Int\n\ttitle: String\n\tauthor_id: BigInt\n\tauthors: Authors\n\tbook_reviews(limit: Int, offset: Int): [BookReviews!]!\n}",
+			"books(filter: BooksFilter, orderBy: [BooksOrderBy!], limit: Int, offset: Int): [Books!]!",
+			"insert_books(objects: [BooksInput!]!): [Books!]!",
+			"update_books(filter: BooksFilter!, set: BooksInput!): [Books!]!",
+			"delete_books(filter: BooksFilter!): [Books!]!",
+			"books_changes(filter: BooksFilter): BooksChange!",
+			"input StringComparison {",
+		] {
+			assert!(sdl.contains(expected), "{} not in\n{}", expected, sdl);
+		}
+		// `BookReviews` is already the type of `book_reviews`, `order-lines` is not a name
+		assert!(!sdl.contains("book_reviews_("));
+		assert!(!sdl.contains("OrderLines"));
+
+		let empty = Catalog { schema: "public".to_string(), tables: HashMap::new() };
+		assert!(matches!(build(&empty, 10), Err(AppError::NotFound { .. })));
+	}
+
+	#[tokio::test]
+	async fn test_validation() {
+		let schema = build(&catalog(), 3).unwrap();
+		let response = schema.execute("{ books(filter: {pages: {eq: 1}}) { id } }").await;
+		assert!(response.errors[0].message.contains("pages"), "{:?}", response.errors);
+		let response = schema.execute("{ books { authors { books { authors { id } } } } }").await;
+		assert_eq!(response.errors[0].message, "Query is nested too deep.");
+	}
+
+	#[test]
+	fn test_filters() {
+		let filter = GraphqlValue::from_json(json!({
+			"title": {"ilike": "%rust%", "isNull": false},
+			"id": {"in": [1, 2], "gte": null},
+			"author_id": null,
+		}))
+		.unwrap();
+		let expected = |column: &str, negated, operator, value| Filter { column: column.to_string(), negated, operator, value };
+		assert_eq!(filters(Some(&filter)).unwrap(), vec![
+			expected("id", false, Operator::In, FilterValue::List(vec!["1".to_string(), "2".to_string()])),
+			expected("title", false, Operator::Ilike, FilterValue::Single("%rust%".to_string())),
+			expected("title", true, Operator::Is, FilterValue::Is(IsValue::Null)),
+		]);
+		assert_eq!(filters(None).unwrap(), vec![]);
+		assert_eq!(text(&GraphqlValue::from_json(json!({"a": [1]})).unwrap()), r#"{"a":[1]}"#);
+
+		let order_by = GraphqlValue::List(vec![
+			GraphqlValue::from_json(json!({"title": null})).unwrap(),
+			GraphqlValue::Object([(async_graphql::Name::new("id"), GraphqlValue::Enum(async_graphql::Name::new("DESC_NULLS_LAST")))].into()),
+		]);
+		assert_eq!(order(Some(&order_by)).unwrap(), vec![OrderTerm { column: "id".to_string(), descending: true, nulls_first: Some(false) }]);
+
+		assert_eq!(count(Some(&GraphqlValue::from(5)), "limit").unwrap(), Some(5));
+		assert!(count(Some(&GraphqlValue::from(-1)), "limit").is_err());
+	}
+
+	#[test]
+	fn test_names() {
+		assert_eq!(type_name("order_items"), "OrderItems");
+		assert_eq!(type_name("_audit__log2"), "AuditLog2");
+		assert_eq!(type_name("Books"), "Books");
+		assert!(is_name("_audit") && is_name("order_items2"));
+		assert!(!is_name("2fa") && !is_name("order-lines") && !is_name("__schema") && !is_name(""));
+	}
+}
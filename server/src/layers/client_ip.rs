@@ -0,0 +1,228 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{Body, boxed, Full};
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, HeaderName, Request, StatusCode};
+use axum::http::header::FORWARDED;
+use axum::response::Response;
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+use tracing::warn;
+use utility::cidr::{canonical, CidrList};
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+use crate::proxy_protocol::ProxiedAddr;
+use crate::util::body_from_parts;
+
+/// Client IP address resolved from the peer address and the headers of trusted proxies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[derive(Clone)]
+pub struct ClientIpLayer {
+	pub trusted_proxies: Arc<CidrList>,
+	pub forwarded_header: HeaderName,
+	pub allow_list: Arc<CidrList>,
+	pub deny_list: Arc<CidrList>,
+}
+
+impl ClientIpLayer {
+	/// Create a new `ClientIpLayer` from `TRUSTED_PROXIES`, `FORWARDED_HEADER`, `IP_ALLOW_LIST` and `IP_DENY_LIST`
+	pub fn new(settings: &Variables) -> AppResult<Self> {
+		let parse = |name: &str, list: &str| {
+			CidrList::parse(list).map_err(|err| AppError::ConfigError { message: format!("{}: {}", name, err) })
+		};
+		let forwarded_header = HeaderName::try_from(settings.forwarded_header.trim())
+			.map_err(|err| AppError::ConfigError { message: format!("FORWARDED_HEADER: {}", err) })?;
+		Ok(Self {
+			trusted_proxies: Arc::new(parse("TRUSTED_PROXIES", &settings.trusted_proxies)?),
+			forwarded_header,
+			allow_list: Arc::new(parse("IP_ALLOW_LIST", &settings.ip_allow_list)?),
+			deny_list: Arc::new(parse("IP_DENY_LIST", &settings.ip_deny_list)?),
+		})
+	}
+}
+
+impl<S> Layer<S> for ClientIpLayer {
+	type Service = ClientIpMiddleware<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		ClientIpMiddleware {
+			inner,
+			trusted_proxies: Arc::clone(&self.trusted_proxies),
+			forwarded_header: self.forwarded_header.clone(),
+			allow_list: Arc::clone(&self.allow_list),
+			deny_list: Arc::clone(&self.deny_list),
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct ClientIpMiddleware<S> {
+	inner: S,
+	trusted_proxies: Arc<CidrList>,
+	forwarded_header: HeaderName,
+	allow_list: Arc<CidrList>,
+	deny_list: Arc<CidrList>,
+}
+
+impl<S> Service<Request<Body>> for ClientIpMiddleware<S>
+	where
+		S: Service<Request<Body>, Response=Response> + Send + 'static,
+		S::Future: Send + 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	// `BoxFuture` is a type alias for `Pin<Box<dyn Future + Send + 'a>>`
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+		let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+		let proxied = request.extensions().get::<ProxiedAddr>().and_then(|addr| addr.0);
+		let client_ip = resolve_client_ip(request.headers(), &self.forwarded_header, peer, proxied, &self.trusted_proxies);
+
+		let is_allowed = match client_ip {
+			Some(ip) => !self.deny_list.contains(&ip) && (self.allow_list.is_empty() || self.allow_list.contains(&ip)),
+			None => self.allow_list.is_empty(),
+		};
+		if let Some(ip) = client_ip {
+			request.extensions_mut().insert(ClientIp(ip));
+		}
+
+		let future = self.inner.call(request);
+		Box::pin(async move {
+			let mut response = Response::default();
+			response = match is_allowed {
+				true => future.await?,
+				false => {
+					warn!("client IP {:?} rejected by allow/deny lists", client_ip);
+					let (mut parts, _body) = response.into_parts();
+					let msg = body_from_parts(&mut parts, StatusCode::FORBIDDEN, "Forbidden", None);
+					Response::from_parts(parts, boxed(Full::from(msg)))
+				}
+			};
+
+			Ok(response)
+		})
+	}
+}
+
+/// Resolve the client IP address.
+///
+/// The peer address is used unless it is a trusted proxy. For trusted proxies the PROXY protocol
+/// source address is used first, then the chain of `forwarded_header` is walked from the nearest
+/// hop, skipping trusted proxies, until the first untrusted address. Other forwarding headers are
+/// ignored, they can be sent by the client and pass through the proxies.
+pub fn resolve_client_ip(
+	headers: &HeaderMap,
+	forwarded_header: &HeaderName,
+	peer: Option<SocketAddr>,
+	proxied: Option<SocketAddr>,
+	trusted_proxies: &CidrList,
+) -> Option<IpAddr> {
+	let peer = canonical(&peer?.ip());
+	if !trusted_proxies.contains(&peer) {
+		return Some(peer);
+	}
+
+	let mut ip = proxied.map(|addr| canonical(&addr.ip())).unwrap_or(peer);
+	if !trusted_proxies.contains(&ip) {
+		return Some(ip);
+	}
+
+	for hop in forwarded_for(headers, forwarded_header).into_iter().rev() {
+		ip = hop;
+		if !trusted_proxies.contains(&hop) {
+			break;
+		}
+	}
+	Some(ip)
+}
+
+/// Forwarding chain of `header`, `for` parameters when it is `Forwarded` (RFC 7239)
+fn forwarded_for(headers: &HeaderMap, header: &HeaderName) -> Vec<IpAddr> {
+	let nodes = headers
+		.get_all(header)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','));
+
+	if header == FORWARDED {
+		return nodes
+			.filter_map(|element| {
+				element
+					.split(';')
+					.filter_map(|pair| pair.split_once('='))
+					.find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+					.and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+			})
+			.collect();
+	}
+
+	nodes.filter_map(|node| parse_node(node.trim())).collect()
+}
+
+/// Parse `ip`, `ip:port`, `[ipv6]` or `[ipv6]:port`
+fn parse_node(node: &str) -> Option<IpAddr> {
+	if let Ok(ip) = node.parse::<IpAddr>() {
+		return Some(canonical(&ip));
+	}
+	if let Ok(addr) = node.parse::<SocketAddr>() {
+		return Some(canonical(&addr.ip()));
+	}
+	node.trim_start_matches('[')
+		.trim_end_matches(']')
+		.parse::<IpAddr>()
+		.ok()
+		.map(|ip| canonical(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::http::HeaderValue;
+	use super::*;
+
+	#[test]
+	fn test_resolve_client_ip() {
+		let trusted = CidrList::parse("10.0.0.0/8,::1").unwrap();
+		let peer = Some("10.0.0.2:40000".parse().unwrap());
+		let x_forwarded_for = HeaderName::from_static("x-forwarded-for");
+		let resolve = |headers: &HeaderMap, peer, proxied| resolve_client_ip(headers, &x_forwarded_for, peer, proxied, &trusted);
+
+		// Untrusted peer, headers are ignored
+		let mut headers = HeaderMap::new();
+		headers.insert("x-forwarded-for", HeaderValue::from_static("1.1.1.1"));
+		let ip = resolve(&headers, Some("8.8.8.8:1234".parse().unwrap()), None);
+		assert_eq!(ip, Some("8.8.8.8".parse().unwrap()));
+
+		// Spoofed first hop is skipped, nearest untrusted hop wins
+		headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, 203.0.113.9, 10.1.1.1"));
+		let ip = resolve(&headers, peer, None);
+		assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+
+		// `Forwarded` sent by the client is ignored
+		headers.insert("forwarded", HeaderValue::from_static("for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\""));
+		let ip = resolve(&headers, peer, None);
+		assert_eq!(ip, Some("203.0.113.9".parse().unwrap()));
+
+		// `Forwarded` set by the trusted proxies
+		let ip = resolve_client_ip(&headers, &FORWARDED, peer, None, &trusted);
+		assert_eq!(ip, Some("2001:db8:cafe::17".parse().unwrap()));
+
+		// PROXY protocol source address
+		let ip = resolve(&HeaderMap::new(), peer, Some("198.51.100.4:5000".parse().unwrap()));
+		assert_eq!(ip, Some("198.51.100.4".parse().unwrap()));
+
+		// Only trusted hops, the furthest one is used
+		let mut headers = HeaderMap::new();
+		headers.insert("x-forwarded-for", HeaderValue::from_static("10.9.9.9"));
+		let ip = resolve(&headers, peer, None);
+		assert_eq!(ip, Some("10.9.9.9".parse().unwrap()));
+	}
+}
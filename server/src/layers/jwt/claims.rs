@@ -15,7 +15,7 @@ pub struct Authenticated {
 pub trait SizedStruct: Sized {}
 impl SizedStruct for Authenticated {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
 	pub sub: String,
 	pub exp: i64,
@@ -25,6 +25,10 @@ pub struct Claims {
 
 	/// Max number of request by second (-1: unlimited)
 	pub rate_limit: i32,
+
+	/// User role, `admin` gives access to the `/admin` routes
+	#[serde(default)]
+	pub role: String,
 }
 
 impl Claims {
@@ -48,6 +52,7 @@ impl Jwt {
 	pub fn generate(
 		id: String,
 		rate_limit: i32,
+		role: String,
 		encoding_key: &EncodingKey,
 		jwt_lifetime: i64,
 	) -> AppResult<(String, i64)> {
@@ -62,6 +67,7 @@ impl Jwt {
 			nbf: now,
 			id,
 			rate_limit,
+			role,
 		};
 
 		let token = encode(&header, &payload, encoding_key).map_err(|err| {
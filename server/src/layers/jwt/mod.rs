@@ -5,13 +5,18 @@ use std::task::{Context, Poll};
 
 use axum::body::{Body, boxed, Full};
 use axum::http::{Request, StatusCode};
-use axum::response::Response;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use futures::future::BoxFuture;
 use tower::{Layer, Service};
 use crate::extension::flinch::FlinchHelper;
 use crate::layers::jwt::claims::Claims;
 use crate::state::SharedState;
 use crate::util::body_from_parts;
+use utility::errors::AppError;
+
+/// Role of the users allowed on the `/admin` routes
+pub const ADMIN_ROLE: &str = "admin";
 
 #[derive(Clone)]
 pub struct JwtLayer {
@@ -55,8 +60,8 @@ impl<S> Service<Request<Body>> for JwtMiddleware<S>
 				Some(data) => {
 					let is_ok = data.is_ok();
 					if is_ok {
-						let parsed_token = data.unwrap();
-						let parsed_token = parsed_token.1;
+						let (claims, parsed_token) = data.unwrap();
+						request.extensions_mut().insert(claims);
 						let flinch = Arc::clone(&self.state.flinch);
 						let authenticated = tokio::task::block_in_place(move || {
 							tokio::runtime::Handle::current().block_on(async move {
@@ -90,4 +95,12 @@ impl<S> Service<Request<Body>> for JwtMiddleware<S>
 			Ok(response)
 		})
 	}
-}
\ No newline at end of file
+}
+
+/// Layer restricting routes to users with the admin role, it has to run after `JwtLayer`
+pub async fn require_admin<B>(req: Request<B>, next: Next<B>) -> Response {
+	match req.extensions().get::<Claims>() {
+		Some(claims) if claims.role == ADMIN_ROLE => next.run(req).await,
+		_ => AppError::Forbidden.into_response(),
+	}
+}
@@ -1,61 +1,188 @@
 use std::{
-	fmt::Display,
+	sync::Arc,
 	task::{Context, Poll},
 	time::{Duration, Instant},
 };
 
-use axum::{body::Body, http::Request, response::Response};
+use axum::{body::{Body, boxed, Full}, http::{HeaderMap, Request}, response::Response};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use bytes::Bytes;
 use futures::future::BoxFuture;
+use hyper::body::{HttpBody, to_bytes};
+use serde_json::Value;
 use tower::{Layer, Service};
 use tracing::info;
+use utility::env::Variables;
+use crate::layers::client_ip::ClientIp;
 use crate::util::header_value_to_str;
 
+const REDACTED: &str = "[REDACTED]";
+
 #[derive(Debug, Default)]
 struct LoggerMessage {
 	method: String,
 	request_id: String,
 	host: String,
+	client_ip: String,
 	uri: String,
 	user_agent: String,
 	status_code: u16,
 	version: String,
 	latency: Duration,
+	request_headers: Option<String>,
+	request_body: Option<String>,
+	response_body: Option<String>,
+}
+
+impl LoggerMessage {
+	/// Emit the message as structured fields, inside the current request span
+	fn log(&self) {
+		info!(
+			status_code = self.status_code,
+			method = %self.method,
+			uri = %self.uri,
+			host = %self.host,
+			client_ip = %self.client_ip,
+			request_id = %self.request_id,
+			user_agent = %self.user_agent,
+			version = %self.version,
+			latency_ms = self.latency.as_secs_f64() * 1000.0,
+			request_headers = self.request_headers.as_deref(),
+			request_body = self.request_body.as_deref(),
+			response_body = self.response_body.as_deref(),
+			"request completed"
+		);
+	}
+}
+
+/// Request/response body capture settings
+#[derive(Debug, Default)]
+pub struct BodyCapture {
+	/// Ratio of captured requests, from 0 (disabled) to 1 (every request)
+	pub sample_ratio: f64,
+	/// Bodies bigger than this are not captured
+	pub max_bytes: usize,
+	/// JSON and form fields replaced by `[REDACTED]` (lowercase)
+	pub redact_fields: Vec<String>,
+	/// Headers replaced by `[REDACTED]` (lowercase)
+	pub redact_headers: Vec<String>,
+}
+
+impl BodyCapture {
+	pub fn new(settings: &Variables) -> Self {
+		let list = |value: &str| {
+			value
+				.split(',')
+				.map(|item| item.trim().to_lowercase())
+				.filter(|item| !item.is_empty())
+				.collect::<Vec<String>>()
+		};
+		Self {
+			sample_ratio: settings.log_body_sample_ratio,
+			max_bytes: settings.log_body_max_bytes,
+			redact_fields: list(&settings.log_redact_fields),
+			redact_headers: list(&settings.log_redact_headers),
+		}
+	}
+
+	fn is_sampled(&self) -> bool {
+		self.sample_ratio > 0.0 && rand::random::<f64>() < self.sample_ratio
+	}
+
+	/// Headers as `name: value` lines, with redacted values
+	fn headers(&self, headers: &HeaderMap) -> String {
+		headers
+			.iter()
+			.map(|(name, value)| match self.redact_headers.iter().any(|h| h == name.as_str()) {
+				true => format!("{}: {}", name, REDACTED),
+				false => format!("{}: {}", name, value.to_str().unwrap_or_default()),
+			})
+			.collect::<Vec<String>>()
+			.join("\n")
+	}
+
+	/// Body as text, with redacted JSON or form fields
+	fn body(&self, content_type: &str, body: &Bytes) -> String {
+		if content_type.starts_with("application/json") || content_type.ends_with("+json") {
+			if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+				redact_json(&mut value, &self.redact_fields);
+				return value.to_string();
+			}
+		}
+		if content_type.starts_with("application/x-www-form-urlencoded") {
+			if let Ok(fields) = serde_urlencoded::from_bytes::<Vec<(String, String)>>(body) {
+				let fields = fields
+					.into_iter()
+					.map(|(key, value)| match self.redact_fields.contains(&key.to_lowercase()) {
+						true => (key, REDACTED.to_string()),
+						false => (key, value),
+					})
+					.collect::<Vec<(String, String)>>();
+				return serde_urlencoded::to_string(fields).unwrap_or_default();
+			}
+		}
+		String::from_utf8_lossy(body).to_string()
+	}
+
+	/// Only text bodies of known and small enough size are captured
+	fn is_capturable(&self, content_type: &str, size: Option<u64>) -> bool {
+		let is_text = content_type.starts_with("application/json")
+			|| content_type.ends_with("+json")
+			|| content_type.starts_with("application/x-www-form-urlencoded")
+			|| content_type.starts_with("text/");
+		is_text && matches!(size, Some(size) if size as usize <= self.max_bytes)
+	}
 }
 
-impl Display for LoggerMessage {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "status_code: {}, method: {}, uri: {}, host: {}, request_id: {}, user_agent: {}, version: {}, latency: {:?}",
-			   self.status_code,
-			   self.method,
-			   self.uri,
-			   self.host,
-			   self.request_id,
-			   self.user_agent,
-			   self.version,
-			   self.latency,
-		)
+/// Replace the values of `fields` keys, at any depth
+fn redact_json(value: &mut Value, fields: &[String]) {
+	match value {
+		Value::Object(map) => {
+			for (key, value) in map.iter_mut() {
+				if fields.contains(&key.to_lowercase()) {
+					*value = Value::String(REDACTED.to_string());
+				} else {
+					redact_json(value, fields);
+				}
+			}
+		}
+		Value::Array(values) => {
+			for value in values {
+				redact_json(value, fields);
+			}
+		}
+		_ => {}
 	}
 }
 
 #[derive(Clone)]
-pub struct LoggerLayer;
+pub struct LoggerLayer {
+	capture: Arc<BodyCapture>,
+}
+
+impl LoggerLayer {
+	pub fn new(settings: &Variables) -> Self {
+		Self { capture: Arc::new(BodyCapture::new(settings)) }
+	}
+}
 
 impl<S> Layer<S> for LoggerLayer {
 	type Service = LoggerMiddleware<S>;
 
 	fn layer(&self, inner: S) -> Self::Service {
-		LoggerMiddleware { inner }
+		LoggerMiddleware { inner, capture: Arc::clone(&self.capture) }
 	}
 }
 
 #[derive(Clone)]
 pub struct LoggerMiddleware<S> {
 	inner: S,
+	capture: Arc<BodyCapture>,
 }
 
 impl<S> Service<Request<Body>> for LoggerMiddleware<S>
 	where
-		S: Service<Request<Body>, Response=Response> + Send + 'static,
+		S: Service<Request<Body>, Response=Response> + Clone + Send + 'static,
 		S::Future: Send + 'static,
 {
 	type Response = S::Response;
@@ -70,26 +197,123 @@ impl<S> Service<Request<Body>> for LoggerMiddleware<S>
 	fn call(&mut self, request: Request<Body>) -> Self::Future {
 		let now = Instant::now();
 		let resquest_headers = request.headers();
+		let is_sampled = self.capture.is_sampled();
 
 		let mut message = LoggerMessage {
 			method: request.method().to_string(),
 			uri: request.uri().to_string(),
 			host: header_value_to_str(resquest_headers.get("host")).to_string(),
+			client_ip: request.extensions().get::<ClientIp>().map(|ip| ip.0.to_string()).unwrap_or_default(),
 			request_id: header_value_to_str(resquest_headers.get("x-request-id")).to_string(),
 			user_agent: header_value_to_str(resquest_headers.get("user-agent")).to_string(),
+			request_headers: is_sampled.then(|| self.capture.headers(resquest_headers)),
 			..Default::default()
 		};
 
-		let future = self.inner.call(request);
+		if !is_sampled {
+			let future = self.inner.call(request);
+			return Box::pin(async move {
+				let response: Response = future.await?;
+
+				message.status_code = response.status().as_u16();
+				message.version = format!("{:?}", response.version());
+				message.latency = now.elapsed();
+
+				message.log();
+				Ok(response)
+			});
+		}
+
+		// The request body is buffered before calling the inner service, which is
+		// ready, so the service clone taking its place has to be made ready again
+		let clone = self.inner.clone();
+		let mut inner = std::mem::replace(&mut self.inner, clone);
+		let capture = Arc::clone(&self.capture);
 		Box::pin(async move {
-			let response: Response = future.await?;
+			let request = {
+				let content_type = header_value_to_str(request.headers().get(CONTENT_TYPE)).to_string();
+				let size = header_value_to_str(request.headers().get(CONTENT_LENGTH)).parse::<u64>().ok();
+				match capture.is_capturable(&content_type, size) {
+					true => {
+						let (parts, body) = request.into_parts();
+						let bytes = to_bytes(body).await.unwrap_or_default();
+						message.request_body = Some(capture.body(&content_type, &bytes));
+						Request::from_parts(parts, Body::from(bytes))
+					}
+					false => request,
+				}
+			};
+
+			let response: Response = inner.call(request).await?;
 
 			message.status_code = response.status().as_u16();
 			message.version = format!("{:?}", response.version());
-			message.latency = now.elapsed();
 
-			info!("{}", message);
+			let content_type = header_value_to_str(response.headers().get(CONTENT_TYPE)).to_string();
+			let size = response.body().size_hint().exact();
+			let response = match capture.is_capturable(&content_type, size) {
+				true => {
+					let (parts, body) = response.into_parts();
+					let bytes = to_bytes(body).await.unwrap_or_default();
+					message.response_body = Some(capture.body(&content_type, &bytes));
+					Response::from_parts(parts, boxed(Full::from(bytes)))
+				}
+				false => response,
+			};
+
+			message.latency = now.elapsed();
+			message.log();
 			Ok(response)
 		})
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::http::HeaderValue;
+	use super::*;
+
+	fn capture() -> BodyCapture {
+		BodyCapture {
+			sample_ratio: 1.0,
+			max_bytes: 1024,
+			redact_fields: vec!["password".to_string(), "token".to_string()],
+			redact_headers: vec!["authorization".to_string()],
+		}
+	}
+
+	#[test]
+	fn test_redact_body() {
+		let capture = capture();
+
+		let body = Bytes::from(r#"{"username":"root","Password":"secret","items":[{"token":"abc","id":1}]}"#);
+		let value: Value = serde_json::from_str(&capture.body("application/json", &body)).unwrap();
+		assert_eq!(value["username"], "root");
+		assert_eq!(value["Password"], REDACTED);
+		assert_eq!(value["items"][0]["token"], REDACTED);
+		assert_eq!(value["items"][0]["id"], 1);
+
+		let body = Bytes::from("username=root&password=secret");
+		assert_eq!(capture.body("application/x-www-form-urlencoded", &body), "username=root&password=%5BREDACTED%5D");
+	}
+
+	#[test]
+	fn test_redact_headers() {
+		let mut headers = HeaderMap::new();
+		headers.insert("authorization", HeaderValue::from_static("Bearer abc"));
+		headers.insert("accept", HeaderValue::from_static("*/*"));
+
+		let text = capture().headers(&headers);
+		assert!(text.contains("authorization: [REDACTED]"));
+		assert!(text.contains("accept: */*"));
+	}
+
+	#[test]
+	fn test_is_capturable() {
+		let capture = capture();
+		assert!(capture.is_capturable("application/json", Some(10)));
+		assert!(!capture.is_capturable("application/json", Some(4096)));
+		assert!(!capture.is_capturable("application/json", None));
+		assert!(!capture.is_capturable("image/png", Some(10)));
+	}
+}
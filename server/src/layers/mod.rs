@@ -3,3 +3,5 @@ pub mod jwt;
 pub mod logger;
 pub mod prometheus;
 pub mod auth;
+pub mod client_ip;
+pub mod span;
@@ -1,13 +1,35 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use axum::{extract::MatchedPath, middleware::Next, response::IntoResponse};
+use db::cdc::CdcSettings;
 use hyper::Request;
-use metrics::{histogram, increment_counter};
+use metrics::{absolute_counter, decrement_gauge, gauge, histogram, increment_counter, increment_gauge};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use tracing::error;
 use utility::app_error;
 use utility::errors::{AppResult,AppErrorCode,AppError};
 use crate::{APP_NAME, SECONDS_DURATION_BUCKETS};
+use crate::state::SharedState;
+
+/// Label used for requests which did not match any route
+const OTHER_PATH: &str = "other";
+const COLLECT_INTERVAL: Duration = Duration::from_secs(15);
+
+pub const HTTP_REQUESTS_TOTAL: &str = "http_requests_total";
+pub const HTTP_REQUESTS_DURATION_SECONDS: &str = "http_requests_duration_seconds";
+pub const RATE_LIMITER_REJECTIONS_TOTAL: &str = "rate_limiter_rejections_total";
+pub const REALTIME_CONNECTIONS_ACTIVE: &str = "realtime_connections_active";
+pub const PG_POOL_CONNECTIONS: &str = "pg_pool_connections";
+pub const PG_POOL_IDLE_CONNECTIONS: &str = "pg_pool_idle_connections";
+pub const PG_POOL_ACQUIRE_SECONDS: &str = "pg_pool_acquire_seconds";
+pub const PG_SLOW_QUERIES_TOTAL: &str = "pg_slow_queries_total";
+pub const CDC_SLOT_LAG_BYTES: &str = "cdc_slot_lag_bytes";
+pub const CDC_WEBHOOK_QUEUED: &str = "cdc_webhook_queued";
+pub const CDC_WEBHOOK_DEAD_LETTERS: &str = "cdc_webhook_dead_letters";
+pub const FLINCH_COLLECTION_DOCUMENTS: &str = "flinch_collection_documents";
+pub const FLINCH_CACHE_STALENESS_SECONDS: &str = "flinch_cache_staleness_seconds";
+pub const PROCESS_RESIDENT_MEMORY_BYTES: &str = "process_resident_memory_bytes";
+pub const PROCESS_OPEN_FDS: &str = "process_open_fds";
 
 pub struct PrometheusMetric {}
 
@@ -16,7 +38,12 @@ impl PrometheusMetric {
 	pub fn get_handle() -> AppResult<PrometheusHandle> {
 		PrometheusBuilder::new()
 			.set_buckets_for_metric(
-				Matcher::Full("http_requests_duration_seconds".to_string()),
+				Matcher::Full(HTTP_REQUESTS_DURATION_SECONDS.to_string()),
+				SECONDS_DURATION_BUCKETS,
+			)
+			.map_err(|err| app_error!(AppErrorCode::InternalError, err.to_string()))?
+			.set_buckets_for_metric(
+				Matcher::Full(PG_POOL_ACQUIRE_SECONDS.to_string()),
 				SECONDS_DURATION_BUCKETS,
 			)
 			.map_err(|err| app_error!(AppErrorCode::InternalError, err.to_string()))?
@@ -24,13 +51,15 @@ impl PrometheusMetric {
 			.map_err(|err| app_error!(AppErrorCode::InternalError, err.to_string()))
 	}
 
-	/// Layer tracking requests
+	/// Layer tracking requests.
+	///
+	/// Metric names are fixed and the `path` label only contains route patterns,
+	/// unmatched paths are counted as `other` to keep the cardinality bounded.
 	pub async fn get_layer<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
 		let start = Instant::now();
-		let path = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
-			matched_path.as_str().to_owned()
-		} else {
-			req.uri().path().to_owned()
+		let path = match req.extensions().get::<MatchedPath>() {
+			Some(matched_path) => matched_path.as_str().to_owned(),
+			None => OTHER_PATH.to_owned(),
 		};
 		let method = req.method().clone();
 
@@ -40,17 +69,84 @@ impl PrometheusMetric {
 		let status = response.status().as_u16().to_string();
 		let labels = [
 			("method", method.to_string()),
-			("path", path.to_owned()),
+			("path", path),
 			("service", APP_NAME.to_owned()),
 			("status", status),
 		];
-		increment_counter!(format!("http_request{}",method.to_string()), &labels);
-		increment_counter!(format!("http_path{}",path.to_owned()), &labels);
-		increment_counter!("http_requests_total", &labels);
-
-		histogram!(format!("http{}_requests_duration_seconds",path.to_owned()), latency.clone(), &labels);
-		histogram!("http_requests_duration_seconds", latency.to_owned(), &labels);
+		increment_counter!(HTTP_REQUESTS_TOTAL, &labels);
+		histogram!(HTTP_REQUESTS_DURATION_SECONDS, latency, &labels);
 
 		response
 	}
-}
\ No newline at end of file
+
+	/// Count a request rejected by the rate limiter
+	pub fn rate_limited() {
+		increment_counter!(RATE_LIMITER_REJECTIONS_TOTAL, "service" => APP_NAME);
+	}
+
+	/// Track a realtime client connection
+	pub fn realtime_connected() {
+		increment_gauge!(REALTIME_CONNECTIONS_ACTIVE, 1.0, "service" => APP_NAME);
+	}
+
+	/// Track a realtime client disconnection
+	pub fn realtime_disconnected() {
+		decrement_gauge!(REALTIME_CONNECTIONS_ACTIVE, 1.0, "service" => APP_NAME);
+	}
+
+	/// Spawn a task sampling Postgres pool, slow query, CDC, flinch and process metrics.
+	///
+	/// Waits for a pool connection are observed by the pool, whoever takes the connection.
+	pub fn spawn_collector(state: SharedState) {
+		db::setup::observe_acquire(|wait| histogram!(PG_POOL_ACQUIRE_SECONDS, wait.as_secs_f64(), "service" => APP_NAME));
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(COLLECT_INTERVAL);
+			loop {
+				interval.tick().await;
+				Self::collect(&state).await;
+			}
+		});
+	}
+
+	async fn collect(state: &SharedState) {
+		// Postgres pool
+		let pg = &state.pg;
+		gauge!(PG_POOL_CONNECTIONS, pg.size() as f64, "service" => APP_NAME);
+		gauge!(PG_POOL_IDLE_CONNECTIONS, pg.num_idle() as f64, "service" => APP_NAME);
+
+		absolute_counter!(PG_SLOW_QUERIES_TOTAL, state.slow_log.total(), "service" => APP_NAME);
+
+		// Change data capture
+		if state.env.cdc_enabled {
+			match db::cdc::lag(pg, &CdcSettings::from_env(&state.env).webhooks).await {
+				Ok(lag) => {
+					gauge!(CDC_SLOT_LAG_BYTES, lag.slot_bytes as f64, "service" => APP_NAME);
+					for webhook in lag.webhooks {
+						gauge!(CDC_WEBHOOK_QUEUED, webhook.queued as f64, "webhook" => webhook.webhook.clone(), "service" => APP_NAME);
+						gauge!(CDC_WEBHOOK_DEAD_LETTERS, webhook.dead_letters as f64, "webhook" => webhook.webhook, "service" => APP_NAME);
+					}
+				}
+				Err(err) => error!("prometheus: unable to read the CDC lag - {:?}", err),
+			}
+		}
+
+		// Flinch collections
+		for name in state.flinch.ls() {
+			if let Ok(collection) = state.flinch.using(name.as_str()) {
+				gauge!(FLINCH_COLLECTION_DOCUMENTS, collection.len() as f64, "collection" => name.clone(), "service" => APP_NAME);
+			}
+		}
+		for (collection, staleness) in state.cache.staleness() {
+			gauge!(FLINCH_CACHE_STALENESS_SECONDS, staleness, "collection" => collection, "service" => APP_NAME);
+		}
+
+		// Process
+		let stats = utility::process::current();
+		if let Some(rss) = stats.rss_bytes {
+			gauge!(PROCESS_RESIDENT_MEMORY_BYTES, rss as f64, "service" => APP_NAME);
+		}
+		if let Some(fds) = stats.open_fds {
+			gauge!(PROCESS_OPEN_FDS, fds as f64, "service" => APP_NAME);
+		}
+	}
+}
@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::task::{Context, Poll};
 
 use axum::body::{Body, Full};
@@ -14,7 +14,10 @@ use flinch::doc_trait::Document;
 use flinch::extension::FuncResultExtractor;
 use futures::future::BoxFuture;
 use tower::{Layer, Service};
-use utility::errors::AppResult;
+use utility::cidr::CidrList;
+use utility::errors::{AppError, AppResult};
+use crate::layers::client_ip::ClientIp;
+use crate::layers::prometheus::PrometheusMetric;
 use crate::layers::jwt::claims::Claims;
 use crate::RATE_LIMITER_BUCKET;
 use crate::state::SharedState;
@@ -31,22 +34,25 @@ pub struct RateLimiterLayer {
 	pub state: SharedState,
 	pub requests_by_second: i32,
 	pub expire_in_seconds: i64,
-	pub white_list: String,
+	pub white_list: CidrList,
 }
 
 impl RateLimiterLayer {
+	/// Create a new `RateLimiterLayer`, `white_list` is a comma delimited list of IP or CIDR
 	pub fn new(
 		state: SharedState,
 		requests_by_second: i32,
 		expire_in_seconds: i64,
 		white_list: String,
-	) -> Self {
-		Self {
+	) -> AppResult<Self> {
+		let white_list = CidrList::parse(&white_list)
+			.map_err(|err| AppError::ConfigError { message: format!("LIMITER_WHITE_LIST: {}", err) })?;
+		Ok(Self {
 			state,
 			requests_by_second,
 			expire_in_seconds,
 			white_list,
-		}
+		})
 	}
 }
 
@@ -54,14 +60,12 @@ impl<S> Layer<S> for RateLimiterLayer {
 	type Service = RateLimiterMiddleware<S>;
 
 	fn layer(&self, inner: S) -> Self::Service {
-		let white_list = self.white_list.split(',').map(|s| s.to_string()).collect();
-
 		RateLimiterMiddleware {
 			inner,
 			state: self.state.clone(),
 			requests_by_second: self.requests_by_second,
 			expire_in_seconds: self.expire_in_seconds,
-			white_list,
+			white_list: self.white_list.clone(),
 		}
 	}
 }
@@ -72,7 +76,7 @@ pub struct RateLimiterMiddleware<S> {
 	state: SharedState,
 	requests_by_second: i32,
 	expire_in_seconds: i64,
-	white_list: Vec<String>,
+	white_list: CidrList,
 }
 
 impl<S> Service<Request<Body>> for RateLimiterMiddleware<S>
@@ -93,13 +97,16 @@ impl<S> Service<Request<Body>> for RateLimiterMiddleware<S>
 		// Check JWT claims
 		let claims = Claims::extract_from_request(request.headers(), &self.state.config.jwt_decoding_key.clone());
 
-		// Get socket address
-		let addr = request.extensions().get::<ConnectInfo<SocketAddr>>();
+		// Get client IP address, resolved by `ClientIpLayer` behind trusted proxies
+		let ip = match request.extensions().get::<ClientIp>() {
+			Some(client_ip) => Some(client_ip.0),
+			None => request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip()),
+		};
 
 		// Initialize RateLimiterCheck
 		let check = RateLimiterCheck::init(
 			claims,
-			addr,
+			ip,
 			&self.white_list,
 			self.requests_by_second,
 		);
@@ -123,6 +130,7 @@ impl<S> Service<Request<Body>> for RateLimiterMiddleware<S>
 				Ok((limit, remaining, reset)) => {
 					// Limit KO
 					// --------
+					PrometheusMetric::rate_limited();
 					let (mut parts, _body) = response.into_parts();
 
 					// Headers
@@ -211,8 +219,8 @@ impl RateLimiterCheck {
 
 	fn init(
 		claims: Option<AppResult<(Claims, String)>>,
-		addr: Option<&ConnectInfo<SocketAddr>>,
-		white_list: &[String],
+		ip: Option<IpAddr>,
+		white_list: &CidrList,
 		requests_by_second: i32,
 	) -> Self {
 		match claims {
@@ -223,12 +231,12 @@ impl RateLimiterCheck {
 					Self::default()
 				} else {
 					// Client Remote IP address
-					match addr {
+					match ip {
 						None => Self::new(Some(RateLimiterError::Ip), None, 0),
-						Some(remote_address) => {
-							let mut key = remote_address.0.ip().to_string();
+						Some(ip) => {
+							let mut key = ip.to_string();
 							// Check if IP address is in white list
-							if white_list.contains(&key) {
+							if white_list.contains(&ip) {
 								// No limit
 								Self::default()
 							} else {
@@ -264,6 +272,12 @@ impl RateLimiterCheck {
 		} else if self.limit == -1 {
 			Ok((self.limit, 0, 0))
 		} else {
+			let _span = tracing::info_span!(
+				"flinch rate limiter",
+				otel.kind = "client",
+				db.system = "flinch",
+				db.collection = RATE_LIMITER_BUCKET,
+			).entered();
 			let bucket = conn.using(RATE_LIMITER_BUCKET).unwrap();
 
 			let now = Utc::now().timestamp();
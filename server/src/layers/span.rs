@@ -0,0 +1,67 @@
+use std::task::{Context, Poll};
+
+use axum::{body::Body, http::Request, response::Response};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+use tracing::{field, info_span, Instrument};
+use crate::telemetry;
+use crate::util::header_value_to_str;
+
+/// Layer opening a span for each request.
+///
+/// The span continues the W3C trace context of the request headers, records the `x-request-id`
+/// and the response status, and its `traceparent` is returned in the response headers.
+#[derive(Clone)]
+pub struct RequestSpanLayer;
+
+impl<S> Layer<S> for RequestSpanLayer {
+	type Service = RequestSpanMiddleware<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		RequestSpanMiddleware { inner }
+	}
+}
+
+#[derive(Clone)]
+pub struct RequestSpanMiddleware<S> {
+	inner: S,
+}
+
+impl<S> Service<Request<Body>> for RequestSpanMiddleware<S>
+	where
+		S: Service<Request<Body>, Response=Response> + Send + 'static,
+		S::Future: Send + 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	// `BoxFuture` is a type alias for `Pin<Box<dyn Future + Send + 'a>>`
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request<Body>) -> Self::Future {
+		let method = request.method().to_string();
+		let span = info_span!(
+			"request",
+			otel.name = %format!("HTTP {}", method),
+			otel.kind = "server",
+			http.method = %method,
+			http.target = %request.uri().path(),
+			http.status_code = field::Empty,
+			request_id = %header_value_to_str(request.headers().get("x-request-id")),
+		);
+		telemetry::extract_parent(&span, request.headers());
+
+		let future = self.inner.call(request);
+		Box::pin(async move {
+			let mut response: Response = future.instrument(span.clone()).await?;
+
+			span.record("http.status_code", response.status().as_u16());
+			telemetry::inject_context(&span, response.headers_mut());
+
+			Ok(response)
+		})
+	}
+}
@@ -14,6 +14,12 @@ mod routes;
 mod controller;
 pub mod cli;
 mod extension;
+mod proxy_protocol;
+mod telemetry;
+mod openapi;
+mod realtime;
+mod cache;
+mod graphql;
 
 pub const APP_NAME: &str = "qaswa";
 pub const RATE_LIMITER_BUCKET: &str = "rate-limiter-rate";
@@ -0,0 +1,133 @@
+mod rolling;
+
+use std::sync::Mutex;
+
+use opentelemetry::sdk::trace::Tracer;
+use tracing::Subscriber;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{EnvFilter, filter::Filtered, fmt::MakeWriter, prelude::*, registry::LookupSpan, Layer, Registry};
+
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+use crate::logger::rolling::SizeRollingWriter;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Register a subscriber as global default to process span data.
+///
+/// Format, target and rotation come from `LOG_FORMAT`, `LOG_TARGET` and `LOG_ROTATION`.
+/// When empty, production logs JSON to daily files and other environments log pretty to stdout.
+///
+/// It should only be called once!
+pub fn init(settings: &Variables) -> AppResult<()> {
+	let is_production = settings.environment == "production";
+	let format = match settings.log_format.as_str() {
+		"" if is_production => "json",
+		"" => "pretty",
+		format => format,
+	};
+	let target = match settings.log_target.as_str() {
+		"" if is_production => "file",
+		"" => "stdout",
+		target => target,
+	};
+
+	let mut layers: Vec<BoxedLayer> = vec![];
+	if target == "stdout" || target == "both" {
+		layers.push(fmt_layer(settings, format, std::io::stdout, true)?);
+	}
+	if target == "file" || target == "both" {
+		layers.push(file_layer(settings, format)?);
+	}
+	if layers.is_empty() {
+		return Err(AppError::ConfigError { message: format!("LOG_TARGET: unsupported target {:?}", target) });
+	}
+	if let Some(telemetry) = telemetry_layer::<Registry>(settings)? {
+		layers.push(telemetry.boxed());
+	}
+
+	let subscriber = Registry::default().with(layers);
+	tracing::subscriber::set_global_default(subscriber).map_err(|err| AppError::ConfigError{ message: err.to_string() })?;
+
+	Ok(())
+}
+
+fn filter(environment: &str) -> EnvFilter {
+	match environment {
+		"production" => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")),
+		"test" => EnvFilter::new("error"),
+		_ => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+	}
+}
+
+/// Formatting layer writing to `writer`
+fn fmt_layer<W>(settings: &Variables, format: &str, writer: W, ansi: bool) -> AppResult<BoxedLayer>
+	where
+		W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+	let layer = tracing_subscriber::fmt::layer()
+		.with_ansi(ansi)
+		.with_level(true)
+		.with_target(true)
+		.with_thread_ids(true) // include the thread ID of the current thread
+		.with_thread_names(true) // include the name of the current thread
+		.with_file(true)
+		.with_line_number(true)
+		.with_writer(writer);
+
+	let filter = filter(&settings.environment);
+	match format {
+		"json" => Ok(layer.json().with_current_span(true).with_span_list(true).with_filter(filter).boxed()),
+		"pretty" => Ok(layer.pretty().with_filter(filter).boxed()),
+		"compact" => Ok(layer.compact().with_filter(filter).boxed()),
+		format => Err(AppError::ConfigError { message: format!("LOG_FORMAT: unsupported format {:?}", format) }),
+	}
+}
+
+/// Formatting layer writing to `LOG_PATH`/`LOG_FILE`, rotated by `LOG_ROTATION`
+/// (`daily`, `hourly`, `size` or `never`) and keeping `LOG_MAX_FILES` files
+fn file_layer(settings: &Variables, format: &str) -> AppResult<BoxedLayer> {
+	let rotation = match settings.log_rotation.as_str() {
+		"daily" => Rotation::DAILY,
+		"hourly" => Rotation::HOURLY,
+		"never" => Rotation::NEVER,
+		"size" => {
+			let writer = SizeRollingWriter::new(
+				&settings.log_path,
+				&settings.log_file,
+				settings.log_max_size_mb * 1024 * 1024,
+				settings.log_max_files,
+			)?;
+			return fmt_layer(settings, format, Mutex::new(writer), false);
+		}
+		rotation => {
+			return Err(AppError::ConfigError { message: format!("LOG_ROTATION: unsupported rotation {:?}", rotation) });
+		}
+	};
+
+	let mut builder = RollingFileAppender::builder()
+		.rotation(rotation)
+		.filename_prefix(settings.log_file.as_str());
+	if settings.log_max_files > 0 {
+		builder = builder.max_log_files(settings.log_max_files);
+	}
+	let appender = builder
+		.build(&settings.log_path)
+		.map_err(|err| AppError::ConfigError { message: format!("log file: {}", err) })?;
+
+	fmt_layer(settings, format, appender, false)
+}
+
+/// OpenTelemetry layer, filtered by `OTEL_FILTER` independently of `RUST_LOG`
+fn telemetry_layer<S>(settings: &Variables) -> AppResult<Option<Filtered<OpenTelemetryLayer<S, Tracer>, EnvFilter, S>>>
+	where
+		S: Subscriber + for<'span> LookupSpan<'span>,
+{
+	let tracer = crate::telemetry::tracer(settings)?;
+	Ok(tracer.map(|tracer| {
+		tracing_opentelemetry::layer()
+			.with_tracer(tracer)
+			.with_filter(EnvFilter::new(&settings.otel_filter))
+	}))
+}
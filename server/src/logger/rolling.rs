@@ -0,0 +1,128 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Log file writer rotating on size.
+///
+/// The current file is `<directory>/<filename>`, rotated files are `<filename>.1` (newest)
+/// to `<filename>.<max_files>` (oldest). Older files are removed. With `max_files` 0 every file is
+/// kept, numbered in rotation order from `<filename>.1` (oldest).
+pub struct SizeRollingWriter {
+	directory: PathBuf,
+	filename: String,
+	max_bytes: u64,
+	max_files: usize,
+	file: File,
+	written: u64,
+}
+
+impl SizeRollingWriter {
+	pub fn new(directory: impl AsRef<Path>, filename: &str, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+		let directory = directory.as_ref().to_path_buf();
+		fs::create_dir_all(&directory)?;
+		let file = open(&directory.join(filename))?;
+		let written = file.metadata()?.len();
+		Ok(Self {
+			directory,
+			filename: filename.to_string(),
+			max_bytes,
+			max_files,
+			file,
+			written,
+		})
+	}
+
+	fn rotated_path(&self, index: usize) -> PathBuf {
+		self.directory.join(format!("{}.{}", self.filename, index))
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		self.file.flush()?;
+		let current = self.directory.join(&self.filename);
+
+		if self.max_files == 0 {
+			let mut index = 1;
+			while self.rotated_path(index).exists() {
+				index += 1;
+			}
+			fs::rename(&current, self.rotated_path(index))?;
+		} else {
+			let oldest = self.rotated_path(self.max_files);
+			if oldest.exists() {
+				fs::remove_file(oldest)?;
+			}
+			for index in (1..self.max_files).rev() {
+				let path = self.rotated_path(index);
+				if path.exists() {
+					fs::rename(path, self.rotated_path(index + 1))?;
+				}
+			}
+			fs::rename(&current, self.rotated_path(1))?;
+		}
+
+		self.file = open(&current)?;
+		self.written = 0;
+		Ok(())
+	}
+}
+
+impl Write for SizeRollingWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+			self.rotate()?;
+		}
+		let written = self.file.write(buf)?;
+		self.written += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()
+	}
+}
+
+fn open(path: &Path) -> io::Result<File> {
+	OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rotation() {
+		let directory = std::env::temp_dir().join(format!("qaswa-log-{}", uuid::Uuid::new_v4()));
+		let mut writer = SizeRollingWriter::new(&directory, "qaswa.log", 10, 2).unwrap();
+
+		for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+			writer.write_all(line.as_bytes()).unwrap();
+		}
+		writer.flush().unwrap();
+
+		let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+		assert_eq!(read("qaswa.log"), "dddddddd\n");
+		assert_eq!(read("qaswa.log.1"), "cccccccc\n");
+		assert_eq!(read("qaswa.log.2"), "bbbbbbbb\n");
+		assert!(!directory.join("qaswa.log.3").exists());
+
+		let _ = fs::remove_dir_all(directory);
+	}
+
+	#[test]
+	fn test_rotation_unlimited() {
+		let directory = std::env::temp_dir().join(format!("qaswa-log-{}", uuid::Uuid::new_v4()));
+		let mut writer = SizeRollingWriter::new(&directory, "qaswa.log", 10, 0).unwrap();
+
+		for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n"] {
+			writer.write_all(line.as_bytes()).unwrap();
+		}
+		writer.flush().unwrap();
+
+		let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+		assert_eq!(read("qaswa.log"), "cccccccc\n");
+		assert_eq!(read("qaswa.log.1"), "aaaaaaaa\n");
+		assert_eq!(read("qaswa.log.2"), "bbbbbbbb\n");
+
+		let _ = fs::remove_dir_all(directory);
+	}
+}
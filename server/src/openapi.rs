@@ -0,0 +1,151 @@
+//! OpenAPI document, served at `/openapi.json` and browsable at `/docs`.
+//!
+//! `docs/openapi.json` is checked in and must match the handlers, regenerate it with
+//! `UPDATE_OPENAPI=1 cargo test -p server openapi`.
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utility::errors::{AppErrorMessage, ErrorCode};
+use crate::controller;
+
+/// Security scheme of the routes protected by `JwtLayer`
+pub const BEARER_JWT: &str = "bearer_jwt";
+/// Security scheme of `/metrics`
+pub const BASIC_AUTH: &str = "basic_auth";
+
+#[derive(OpenApi)]
+#[openapi(
+	info(
+		title = "qaswa",
+		description = "Open source alternative to firebase real time database",
+		license(name = "Apache-2.0", url = "https://www.apache.org/licenses/LICENSE-2.0"),
+	),
+	paths(
+		controller::web::health_check,
+		controller::web::say_ok,
+		controller::metrics::metrics,
+		controller::sql::exec_sql,
+		controller::sql::stream_sql,
+		controller::sql::explain,
+		controller::sql::slow_queries,
+		controller::sql::export_table,
+		controller::tx::begin,
+		controller::tx::exec,
+		controller::tx::commit,
+		controller::tx::rollback,
+		controller::sql::list_databases,
+		controller::sql::list_tables,
+		controller::database::create_database,
+		controller::database::drop_database,
+		controller::schema::list_schemas,
+		controller::schema::describe_schema,
+		controller::schema::describe_table,
+		controller::realtime::list_tables,
+		controller::realtime::publish_table,
+		controller::realtime::unpublish_table,
+		controller::realtime::events,
+		controller::cdc::list_tables,
+		controller::cdc::capture_table,
+		controller::cdc::release_table,
+		controller::cache::list_tables,
+		controller::cache::cache_table,
+		controller::cache::drop_table,
+		controller::cache::resync_table,
+		controller::cache::read,
+		controller::rest::read,
+		controller::rest::create,
+		controller::rest::update,
+		controller::rest::delete,
+		controller::graphql::execute,
+		controller::graphql::subscribe,
+	),
+	components(schemas(
+		AppErrorMessage,
+		ErrorCode,
+		controller::sql::SqlRequest,
+		controller::sql::SqlResponse,
+		controller::sql::SqlStreamRequest,
+		controller::sql::ExplainRequest,
+		controller::sql::ExplainResponse,
+		controller::sql::SlowQueriesResponse,
+		db::slow::SlowQuery,
+		controller::tx::TxBeginRequest,
+		controller::tx::TxStatementRequest,
+		db::tx::TxInfo,
+		db::stream::StreamFormat,
+		controller::database::CreateDatabaseRequest,
+		db::database::DatabaseInfo,
+		db::schema::SchemaInfo,
+		db::schema::SchemaDetail,
+		db::schema::TableInfo,
+		db::schema::ColumnInfo,
+		db::schema::PrimaryKey,
+		db::schema::ForeignKeyInfo,
+		db::schema::IndexInfo,
+		db::schema::ViewInfo,
+		db::schema::FunctionInfo,
+		db::schema::EnumInfo,
+		controller::realtime::PublishTableRequest,
+		db::notify::RealtimeTable,
+		db::notify::Change,
+		db::cdc::CdcTable,
+		controller::cache::CacheTableRequest,
+		crate::cache::CacheStatus,
+		db::cache::CachedTable,
+	)),
+	modifiers(&SecuritySchemes),
+	tags(
+		(name = "health", description = "Server health"),
+		(name = "monitoring", description = "Prometheus metrics"),
+		(name = "admin", description = "Administration, restricted to the admin role"),
+		(name = "rest", description = "Tables of the embedded Postgres"),
+		(name = "realtime", description = "Changes of the published tables"),
+		(name = "data", description = "Tables copied into flinch collections"),
+		(name = "graphql", description = "GraphQL API of the tables of the embedded Postgres"),
+		(name = "sql", description = "Transactions spanning several requests"),
+	)
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		let components = openapi.components.get_or_insert_with(Default::default);
+		components.add_security_scheme(
+			BEARER_JWT,
+			SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+		);
+		components.add_security_scheme(
+			BASIC_AUTH,
+			SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+	use serde_json::Value;
+	use super::*;
+
+	/// Fails when `docs/openapi.json` is not up to date
+	#[test]
+	fn test_openapi_spec_is_up_to_date() {
+		let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../docs/openapi.json");
+		let generated = ApiDoc::openapi().to_pretty_json().unwrap();
+
+		if std::env::var("UPDATE_OPENAPI").is_ok() {
+			std::fs::write(&path, format!("{}\n", generated)).unwrap();
+			return;
+		}
+
+		let checked_in: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap_or_default())
+			.unwrap_or_default();
+		let generated: Value = serde_json::from_str(&generated).unwrap();
+		assert!(
+			checked_in == generated,
+			"docs/openapi.json is out of date, run `UPDATE_OPENAPI=1 cargo test -p server openapi`"
+		);
+	}
+}
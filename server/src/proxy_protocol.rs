@@ -0,0 +1,193 @@
+//! PROXY protocol v1/v2 support (https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt)
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum_server::accept::Accept;
+use futures::future::BoxFuture;
+use hyper::server::conn::AddrStream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tower_http::add_extension::AddExtension;
+use utility::cidr::CidrList;
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+const V1_MAX_LENGTH: usize = 107;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Source address sent by a trusted proxy in the PROXY protocol header
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProxiedAddr(pub Option<SocketAddr>);
+
+/// Acceptor reading the PROXY protocol header of connections coming from trusted proxies
+#[derive(Clone)]
+pub struct ProxyProtocolAcceptor {
+	enabled: bool,
+	trusted_proxies: Arc<CidrList>,
+}
+
+impl ProxyProtocolAcceptor {
+	pub fn new(settings: &Variables) -> AppResult<Self> {
+		let trusted_proxies = CidrList::parse(&settings.trusted_proxies)
+			.map_err(|err| AppError::ConfigError { message: format!("TRUSTED_PROXIES: {}", err) })?;
+		Ok(Self {
+			enabled: settings.proxy_protocol,
+			trusted_proxies: Arc::new(trusted_proxies),
+		})
+	}
+}
+
+impl<S> Accept<AddrStream, S> for ProxyProtocolAcceptor
+	where
+		S: Send + 'static,
+{
+	type Stream = AddrStream;
+	type Service = AddExtension<S, ProxiedAddr>;
+	type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+	fn accept(&self, mut stream: AddrStream, service: S) -> Self::Future {
+		let expect_header = self.enabled && self.trusted_proxies.contains(&stream.remote_addr().ip());
+
+		Box::pin(async move {
+			let source = if expect_header {
+				tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+					.await
+					.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY protocol header timeout"))??
+			} else {
+				None
+			};
+			Ok((stream, AddExtension::new(service, ProxiedAddr(source))))
+		})
+	}
+}
+
+/// Read and consume a PROXY protocol header (v1 or v2) from `reader`.
+///
+/// Returns the source address, or `None` for `LOCAL`/`UNKNOWN` connections.
+pub async fn read_header<R>(reader: &mut R) -> io::Result<Option<SocketAddr>>
+	where
+		R: AsyncRead + Unpin,
+{
+	let mut prefix = [0u8; 12];
+	reader.read_exact(&mut prefix).await?;
+
+	if prefix == V2_SIGNATURE {
+		let mut head = [0u8; 4];
+		reader.read_exact(&mut head).await?;
+		let length = u16::from_be_bytes([head[2], head[3]]) as usize;
+		let mut addresses = vec![0u8; length];
+		reader.read_exact(&mut addresses).await?;
+		parse_v2(head[0], head[1], &addresses)
+	} else if prefix.starts_with(b"PROXY ") {
+		let mut line = prefix.to_vec();
+		while !line.ends_with(b"\r\n") {
+			if line.len() >= V1_MAX_LENGTH {
+				return Err(invalid("PROXY v1 header too long"));
+			}
+			let mut byte = [0u8; 1];
+			reader.read_exact(&mut byte).await?;
+			line.push(byte[0]);
+		}
+		parse_v1(&line)
+	} else {
+		Err(invalid("missing PROXY protocol header"))
+	}
+}
+
+/// Parse a v1 header line, e.g. `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+	let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY v1 header is not valid ASCII"))?;
+	let fields = line.trim_end_matches("\r\n").split(' ').collect::<Vec<&str>>();
+
+	match fields.get(1) {
+		Some(&"UNKNOWN") => Ok(None),
+		Some(&"TCP4") | Some(&"TCP6") if fields.len() == 6 => {
+			let ip = fields[2].parse::<IpAddr>().map_err(|_| invalid("invalid PROXY v1 source address"))?;
+			let port = fields[4].parse::<u16>().map_err(|_| invalid("invalid PROXY v1 source port"))?;
+			Ok(Some(SocketAddr::new(ip, port)))
+		}
+		_ => Err(invalid("invalid PROXY v1 header")),
+	}
+}
+
+/// Parse the address block of a v2 header
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+	if version_command >> 4 != 2 {
+		return Err(invalid("unsupported PROXY protocol version"));
+	}
+	match version_command & 0x0F {
+		// LOCAL: health checks from the proxy itself
+		0x0 => return Ok(None),
+		0x1 => {}
+		_ => return Err(invalid("unsupported PROXY v2 command")),
+	}
+
+	match family >> 4 {
+		// AF_INET
+		0x1 if addresses.len() >= 12 => {
+			let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+			let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+			Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+		}
+		// AF_INET6
+		0x2 if addresses.len() >= 36 => {
+			let mut octets = [0u8; 16];
+			octets.copy_from_slice(&addresses[0..16]);
+			let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+			Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+		}
+		// AF_UNSPEC, AF_UNIX
+		0x0 | 0x3 => Ok(None),
+		_ => Err(invalid("invalid PROXY v2 address block")),
+	}
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_v1() {
+		let mut data: &[u8] = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET / HTTP/1.1\r\n";
+		let addr = read_header(&mut data).await.unwrap();
+		assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+		assert_eq!(data, b"GET / HTTP/1.1\r\n");
+
+		let mut data: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n";
+		let addr = read_header(&mut data).await.unwrap();
+		assert_eq!(addr, Some("[2001:db8::1]:4711".parse().unwrap()));
+
+		let mut data: &[u8] = b"PROXY UNKNOWN\r\n";
+		assert_eq!(read_header(&mut data).await.unwrap(), None);
+
+		let mut data: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n";
+		assert!(read_header(&mut data).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_v2() {
+		let mut header = V2_SIGNATURE.to_vec();
+		header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+		header.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1]);
+		header.extend_from_slice(&8080u16.to_be_bytes());
+		header.extend_from_slice(&443u16.to_be_bytes());
+		header.extend_from_slice(b"GET");
+
+		let mut data: &[u8] = &header;
+		let addr = read_header(&mut data).await.unwrap();
+		assert_eq!(addr, Some("203.0.113.7:8080".parse().unwrap()));
+		assert_eq!(data, b"GET");
+
+		let mut local = V2_SIGNATURE.to_vec();
+		local.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+		let mut data: &[u8] = &local;
+		assert_eq!(read_header(&mut data).await.unwrap(), None);
+	}
+}
@@ -0,0 +1,153 @@
+//! Socket.IO clients of the realtime changes.
+//!
+//! Clients connect to the `/` namespace with `{"token": "<JWT>"}` as auth, then emit
+//! `subscribe` with `{"table": "orders", "filter": "status=eq.paid"}` and receive `change` and
+//! `resync` events like the `/realtime/events` server-sent events, and a `heartbeat` every 30 seconds.
+//! As there, the changed rows are read again as the user.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use socketioxide::adapter::Adapter;
+use socketioxide::{Namespace, Socket, SocketIoLayer};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use db::notify::{Notification, Seen, Subscription};
+use utility::errors::AppError;
+use crate::controller::realtime::{subscribe, visible, Connection};
+use crate::controller::rest::options;
+use crate::layers::jwt::claims::Jwt;
+use crate::state::SharedState;
+
+/// Interval of the `heartbeat` events, which also detect the clients gone without changes to send
+const HEARTBEAT: Duration = Duration::from_secs(30);
+
+/// Subscriptions of a client, with the table requested in `subscribe`, as `unsubscribe` names it
+type Subscriptions = Arc<Mutex<Vec<(Option<String>, Subscription)>>>;
+
+#[derive(Debug, Deserialize)]
+struct SubscribeRequest {
+	/// All the published tables when missing
+	table: Option<String>,
+	/// Filters as a query string, e.g. `status=eq.paid&total=gt.100`
+	#[serde(default)]
+	filter: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnsubscribeRequest {
+	table: Option<String>,
+}
+
+/// Layer serving Socket.IO at `/socket.io`
+pub fn layer(state: SharedState) -> SocketIoLayer {
+	let namespaces = Namespace::builder()
+		.add("/", move |socket| {
+			let state = state.clone();
+			async move { connect(state, socket) }
+		})
+		.build();
+	SocketIoLayer::new(namespaces)
+}
+
+fn connect<A: Adapter>(state: SharedState, socket: Arc<Socket<A>>) {
+	let token = socket.handshake.auth.get("token").and_then(Value::as_str).unwrap_or_default();
+	let claims = match Jwt::parse(token, &state.config.jwt_decoding_key) {
+		Ok((claims, _)) => claims,
+		Err(err) => {
+			debug!("realtime client rejected: {}", err);
+			let _ = socket.emit("error", json!({ "message": "Unauthorized" }));
+			let _ = socket.disconnect();
+			return;
+		}
+	};
+	let options = match options(&state, &claims, &HeaderMap::new(), true) {
+		Ok(options) => options,
+		Err(err) => {
+			let _ = socket.emit("error", json!({ "message": err.to_string() }));
+			let _ = socket.disconnect();
+			return;
+		}
+	};
+	debug!("realtime client {} connected", claims.sub);
+
+	let subscriptions: Subscriptions = Arc::new(Mutex::new(vec![]));
+	{
+		let state = state.clone();
+		let subscriptions = subscriptions.clone();
+		socket.on("subscribe", move |socket, request: SubscribeRequest, _, ack| {
+			let state = state.clone();
+			let subscriptions = subscriptions.clone();
+			async move {
+				let subscription = match serde_urlencoded::from_str::<Vec<(String, String)>>(&request.filter) {
+					Ok(filters) => subscribe(&state, request.table.as_deref(), &filters).await,
+					Err(err) => Err(AppError::BadRequest { message: format!("invalid filter: {}", err) }),
+				};
+				let answer = match subscription {
+					Ok(subscription) => {
+						subscriptions.lock().unwrap().push((request.table, subscription));
+						json!({ "ok": true })
+					}
+					Err(err) => {
+						let _ = socket.emit("error", json!({ "message": err.to_string() }));
+						json!({ "ok": false, "message": err.to_string() })
+					}
+				};
+				let _ = ack.send(answer);
+			}
+		});
+	}
+	{
+		let subscriptions = subscriptions.clone();
+		socket.on("unsubscribe", move |_, request: UnsubscribeRequest, _, ack| {
+			let subscriptions = subscriptions.clone();
+			async move {
+				subscriptions.lock().unwrap().retain(|(table, _)| table != &request.table);
+				let _ = ack.send(json!({ "ok": true }));
+			}
+		});
+	}
+
+	// Forward the changes until the client is gone, which is noticed when emitting fails
+	let mut receiver = state.changes.subscribe();
+	tokio::spawn(async move {
+		let _connection = Connection::open();
+		let mut seen = Seen::default();
+		let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + HEARTBEAT, HEARTBEAT);
+		loop {
+			let received = tokio::select! {
+				received = receiver.recv() => received,
+				_ = heartbeat.tick() => {
+					match socket.emit("heartbeat", json!({})) {
+						Ok(()) => continue,
+						Err(_) => break,
+					}
+				}
+			};
+			let sent = match received {
+				Ok(Notification::Change(change)) => {
+					let subscribed: Vec<Subscription> = subscriptions.lock().unwrap().iter().map(|(_, subscription)| subscription.clone()).collect();
+					let mut sent = None;
+					for subscription in &subscribed {
+						if let Some(change) = visible(&state, subscription, &change, &options, &mut seen).await {
+							sent = Some(socket.emit("change", &change));
+							break;
+						}
+					}
+					match sent {
+						Some(sent) => sent,
+						None => continue,
+					}
+				}
+				Ok(Notification::Reconnected) | Err(RecvError::Lagged(_)) => socket.emit("resync", json!({})),
+				Err(RecvError::Closed) => break,
+			};
+			if let Err(err) = sent {
+				debug!("realtime client {} gone - {:?}", claims.sub, err);
+				break;
+			}
+		}
+	});
+}
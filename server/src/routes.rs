@@ -1,5 +1,5 @@
-use axum::Router;
-use axum::routing::{get};
+use axum::{middleware, Router};
+use axum::routing::{delete, get, post};
 use crate::{controller, layers};
 use crate::state::SharedState;
 
@@ -8,10 +8,52 @@ pub fn api(state: SharedState) -> Router<SharedState> {
 	Router::new()
 		.route("/health-check", get(controller::web::health_check))
 		.route("/ok", get(controller::web::say_ok))
+		// Authenticated by the `connection_init` message
+		.route("/graphql/ws", get(controller::graphql::subscribe))
 		// Protected routes
 		.nest("/", protected().layer(layers::jwt::JwtLayer { state }))
 }
 
 fn protected() -> Router<SharedState> {
 	Router::new()
-}
\ No newline at end of file
+		.route(
+			"/rest/:table",
+			get(controller::rest::read)
+				.post(controller::rest::create)
+				.patch(controller::rest::update)
+				.delete(controller::rest::delete),
+		)
+		.route("/realtime/events", get(controller::realtime::events))
+		.route("/sql/tx", post(controller::tx::begin))
+		.route("/sql/tx/:id", post(controller::tx::exec))
+		.route("/sql/tx/:id/commit", post(controller::tx::commit))
+		.route("/sql/tx/:id/rollback", post(controller::tx::rollback))
+		.route("/data/:collection/:key", get(controller::cache::read))
+		.route("/graphql", post(controller::graphql::execute))
+		.nest("/admin", admin())
+}
+
+/// Routes restricted to the admin role
+fn admin() -> Router<SharedState> {
+	Router::new()
+		.route("/sql", post(controller::sql::exec_sql))
+		.route("/sql/stream", post(controller::sql::stream_sql))
+		.route("/sql/explain", post(controller::sql::explain))
+		.route("/sql/slow", get(controller::sql::slow_queries))
+		.route("/sql/export/:schema/:table", get(controller::sql::export_table))
+		.route("/sql/databases", get(controller::sql::list_databases))
+		.route("/sql/tables", get(controller::sql::list_tables))
+		.route("/databases", post(controller::database::create_database))
+		.route("/databases/:name", delete(controller::database::drop_database))
+		.route("/schema", get(controller::schema::list_schemas))
+		.route("/schema/:schema", get(controller::schema::describe_schema))
+		.route("/schema/:schema/tables/:table", get(controller::schema::describe_table))
+		.route("/realtime/tables", get(controller::realtime::list_tables).post(controller::realtime::publish_table))
+		.route("/realtime/tables/:schema/:table", delete(controller::realtime::unpublish_table))
+		.route("/cdc/tables", get(controller::cdc::list_tables).post(controller::cdc::capture_table))
+		.route("/cdc/tables/:schema/:table", delete(controller::cdc::release_table))
+		.route("/cache", get(controller::cache::list_tables).post(controller::cache::cache_table))
+		.route("/cache/:collection", delete(controller::cache::drop_table))
+		.route("/cache/:collection/resync", post(controller::cache::resync_table))
+		.route_layer(middleware::from_fn(layers::jwt::require_admin))
+}
@@ -1,23 +1,33 @@
-use std::future::ready;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use axum::error_handling::HandleErrorLayer;
-use axum::{middleware, Router};
+use axum::{Extension, middleware, Router};
 use axum::routing::get;
+use tokio::sync::broadcast;
 use tower::ServiceBuilder;
 use tower_http::ServiceBuilderExt;
 use tower_http::services::ServeDir;
-use tracing::{error, info, trace};
+use tracing::{error, info};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use db::backup::BackupSettings;
+use db::cdc::CdcSettings;
+use db::slow::SlowLog;
+use db::tx::{Transactions, TxSettings};
+use utility::cron::Schedule;
 use utility::env::Variables;
-use utility::errors::AppResult;
-use crate::{APP_NAME, handlers, routes};
+use utility::errors::{AppError, AppResult};
+use crate::{controller, handlers, routes};
 use crate::certs::init_ssl_certs;
 use crate::layers::auth::BasicAuthLayer;
+use crate::layers::client_ip::ClientIpLayer;
 use crate::layers::prometheus::PrometheusMetric;
 use crate::layers::rate_limiter::RateLimiterLayer;
-use crate::setup::{get_flinch, graceful_shutdown, shutdown_signal};
+use crate::openapi::ApiDoc;
+use crate::proxy_protocol::ProxyProtocolAcceptor;
+use crate::setup::{get_flinch, get_postgres, graceful_shutdown, migrate, pg_tools};
 use crate::state::{SharedState, State};
 use crate::util::MakeRequestUuid;
 
@@ -25,23 +35,24 @@ pub async fn serve() -> AppResult<()> {
 	color_eyre::install()?;
 
 	let settings = Variables::from_env()?;
+	utility::errors::expose_internal_details(settings.environment != "production");
 	// Init Flinch Db
 	let mem_db = get_flinch().await;
 	// Setup Postgres
-	trace!("installing or starting postgres...");
-	let mut pg_server = db::setup::install_postgres().await?;
-	match pg_server.create_database(APP_NAME).await {
-		Ok(_) => {}
-		Err(err) => {
-			error!("{:?}",err);
-		}
-	}
-	let pg_uri = pg_server.full_db_uri(APP_NAME);
-	let pg = Arc::new(db::setup::get_connection(pg_uri.as_str()).await?);
-	info!("postgres uri {}",pg_uri);
+	let (pg_server, pg) = get_postgres(&settings).await?;
+	let pg = Arc::new(pg);
+	db::audit::init(&pg).await?;
+	db::notify::init(&pg).await?;
+	db::cdc::init(&pg, settings.cdc_enabled).await?;
 	// Tracing
 	// -------
-	crate::logger::init(&settings.environment, &settings.log_path, &settings.log_file)?;
+	crate::logger::init(&settings)?;
+
+	// Migrations
+	// ----------
+	migrate(&settings, &pg).await?;
+	// The statements of users must not run with the role of the connection
+	db::ops::check_user_role(&pg, &settings.db_user_role, pg_server.is_some()).await?;
 
 	// CORS
 	// ----
@@ -51,32 +62,63 @@ pub async fn serve() -> AppResult<()> {
 	// ------
 	let layers = ServiceBuilder::new()
 		.set_x_request_id(MakeRequestUuid)
-		.layer(crate::layers::logger::LoggerLayer)
+		.layer(middleware::from_fn(crate::util::problem_instance))
+		.layer(ClientIpLayer::new(&settings)?)
+		.layer(crate::layers::span::RequestSpanLayer)
+		.layer(crate::layers::logger::LoggerLayer::new(&settings))
 		.layer(HandleErrorLayer::new(handlers::timeout_error))
 		.timeout(Duration::from_secs(settings.request_timeout))
 		.propagate_x_request_id();
 
-	let pg_server_locked = Arc::new(Mutex::new(pg_server));
-	let state = SharedState::new(State::init(settings.clone(), mem_db, Arc::clone(&pg_server_locked), pg));
+	// Scheduled backups
+	// -----------------
+	if !settings.backup_schedule.is_empty() {
+		let schedule = settings.backup_schedule.parse::<Schedule>()
+			.map_err(|message| AppError::ConfigError { message: format!("BACKUP_SCHEDULE: {}", message) })?;
+		db::backup::spawn_scheduler(BackupSettings::from_env(&settings)?, pg_tools(&settings, pg_server.as_ref()), schedule);
+	}
+
+	let pg_server_locked = pg_server.map(|pg_server| Arc::new(Mutex::new(pg_server)));
+	let transactions = Arc::new(Transactions::new((*pg).clone(), TxSettings {
+		idle_timeout: Duration::from_millis(settings.sql_tx_idle_timeout_ms),
+		max_per_user: settings.sql_tx_max_per_user,
+	}));
+	transactions.spawn_reaper();
+	let slow_log = Arc::new(SlowLog::new(Duration::from_millis(settings.sql_slow_query_ms), settings.sql_slow_log_size));
+	let (changes, _) = broadcast::channel(settings.realtime_channel_capacity);
+	db::notify::spawn_listener((*pg).clone(), changes.clone());
+	if settings.cdc_enabled {
+		db::cdc::spawn_consumer((*pg).clone(), CdcSettings::from_env(&settings), changes.clone());
+	}
+	// Subscribed first, the changes made while loading the cache are applied after
+	let cache_changes = changes.subscribe();
+	let state = SharedState::new(State::init(settings.clone(), mem_db, pg_server_locked.clone(), pg, transactions.clone(), slow_log, changes));
+	state.cache.load().await?;
+	state.cache.clone().spawn(cache_changes);
 	// Routing - API
 	// -------------
 	let mut app = Router::new()
-		.nest("/", routes::api(state.clone()).layer(cors));
+		.nest("/", routes::api(state.clone()).layer(cors))
+		// OpenAPI document and docs UI
+		.merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()));
 
 	// Prometheus metrics
 	// ------------------
 	if settings.prometheus_metrics_enabled {
 		let handle = PrometheusMetric::get_handle()?;
+		PrometheusMetric::spawn_collector(state.clone());
 		app = app
 			.nest(
 				"/metrics",
-				Router::new().route(
-					"/",
-					get(move || ready(handle.render())).layer(BasicAuthLayer::new(
-						&settings.basic_user,
-						&settings.basic_pw,
-					)),
-				),
+				Router::new()
+					.route(
+						"/",
+						get(controller::metrics::metrics).layer(BasicAuthLayer::new(
+							&settings.basic_user,
+							&settings.basic_pw,
+						)),
+					)
+					.layer(Extension(handle)),
 			)
 			.route_layer(middleware::from_fn(PrometheusMetric::get_layer));
 	}
@@ -90,10 +132,11 @@ pub async fn serve() -> AppResult<()> {
 				settings.limiter_requests_by_second.to_owned(),
 				settings.limiter_expire_in_seconds.to_owned(),
 				settings.limiter_white_list.clone(),
-			));
+			)?);
 	}
 
 	app = app
+		.layer(crate::realtime::layer(state.clone()))
 		.fallback_service(ServeDir::new("templates/html").append_index_html_on_directories(true)) // FIXME: static_file_error not work this Axum 0.6.9!
 		.layer(middleware::from_fn(crate::util::override_http_errors))
 		.layer(layers);
@@ -111,11 +154,19 @@ pub async fn serve() -> AppResult<()> {
 		}
 	}
 
+	// PROXY protocol header is read from trusted proxies only
+	let proxy_acceptor = ProxyProtocolAcceptor::new(&settings)?;
+
 	if &settings.environment == "development" || settings.tls_policy.eq("none") {
-		let server =
-			axum::Server::bind(&addr.parse()?)
-				.serve(app.into_make_service_with_connect_info::<SocketAddr>());
-		Ok(server.with_graceful_shutdown(shutdown_signal(Arc::clone(&pg_server_locked))).await?)
+		let gch = axum_server::Handle::new();
+
+		tokio::spawn(graceful_shutdown(gch.clone(),pg_server_locked.clone(), transactions.clone()));
+
+		let server = axum_server::bind(addr.parse()?)
+			.acceptor(proxy_acceptor)
+			.handle(gch)
+			.serve(app.into_make_service_with_connect_info::<SocketAddr>());
+		Ok(server.await?)
 
 	} else {
 		let tls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(
@@ -124,9 +175,10 @@ pub async fn serve() -> AppResult<()> {
 		).await?;
 		let gch = axum_server::Handle::new();
 
-		tokio::spawn(graceful_shutdown(gch.clone(),Arc::clone(&pg_server_locked)));
+		tokio::spawn(graceful_shutdown(gch.clone(),pg_server_locked.clone(), transactions.clone()));
 
-		let server = axum_server::bind_rustls(addr.parse()?, tls_config)
+		let server = axum_server::bind(addr.parse()?)
+			.acceptor(axum_server::tls_rustls::RustlsAcceptor::new(tls_config).acceptor(proxy_acceptor))
 			.handle(gch)
 			.serve(app.into_make_service_with_connect_info::<SocketAddr>());
 		Ok(server.await?)
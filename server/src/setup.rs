@@ -1,10 +1,16 @@
 use std::sync::{Arc, LockResult, Mutex};
+use std::path::Path;
 use std::time::Duration;
 use flinch::database::Database;
 use flinch::doc::QueryBased;
 use tokio::signal;
-use tracing::{error, info};
-use db::setup::PgServer;
+use tracing::{error, info, trace};
+use db::backup::PgTools;
+use db::migrate::Migrator;
+use db::setup::{PgDb, PgServer, PoolSettings};
+use db::tx::Transactions;
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
 use crate::{APP_NAME, GENERAL_BUCKET, RATE_LIMITER_BUCKET};
 
 pub async fn get_flinch() -> Arc<Database<QueryBased>> {
@@ -25,18 +31,78 @@ pub async fn get_flinch() -> Arc<Database<QueryBased>> {
 	Arc::new(mem)
 }
 
-#[allow(unused)]
-pub async fn graceful_shutdown(handle: axum_server::Handle, mut pg_server: Arc<Mutex<PgServer>>) {
-	// Stop postgres first
-	match pg_server.lock() {
-		Ok(mut server) => {
-			server.stop_db_sync();
+/// Connect to `DATABASE_URL`, or install or start the embedded Postgres and connect to the
+/// application database. The embedded server is returned to be stopped at shutdown, unless it was
+/// already running on `DB_PORT`, e.g. for the CLI commands run next to the server: it is then left
+/// running.
+pub async fn get_postgres(settings: &Variables) -> AppResult<(Option<PgServer>, PgDb)> {
+	let pool = PoolSettings::from_env(settings)?;
+	if !settings.database_url.is_empty() {
+		let pg = db::setup::get_connection(&settings.database_url, &pool).await?;
+		// The URL holds the password
+		let options = pg.connect_options();
+		info!("postgres {}:{}/{}", options.get_host(), options.get_port(), options.get_database().unwrap_or_default());
+		return Ok((None, pg));
+	}
+
+	let pg_server = match db::setup::is_listening(settings.db_port).await {
+		true => {
+			trace!("connecting to the running postgres...");
+			db::setup::attach_postgres(settings, Path::new(&settings.db_path), settings.db_port).await?
+		}
+		false => {
+			trace!("installing or starting postgres...");
+			db::setup::install_postgres().await?
 		}
+	};
+	match pg_server.create_database(APP_NAME).await {
+		Ok(_) => {}
 		Err(err) => {
 			error!("{:?}",err);
 		}
 	}
+	let pg_uri = pg_server.full_db_uri(APP_NAME);
+	let pg = db::setup::get_connection(pg_uri.as_str(), &pool).await?;
+	info!("postgres uri {}",pg_uri);
+	Ok((Some(pg_server), pg))
+}
+
+/// `pg_dump` and friends for the database of `get_postgres`, the binaries of the embedded server
+/// or the ones of `PATH`
+pub fn pg_tools(settings: &Variables, pg_server: Option<&PgServer>) -> PgTools {
+	match pg_server {
+		Some(pg_server) => PgTools {
+			uri: pg_server.full_db_uri(APP_NAME),
+			bin_dir: pg_server.pg_access.pg_ctl_exe.parent().map(Path::to_path_buf),
+		},
+		None => PgTools { uri: settings.database_url.clone(), bin_dir: None },
+	}
+}
+
+/// Apply the pending migrations in development, other environments refuse to start with
+/// pending migrations, which are applied with `qaswa migrate up`
+pub async fn migrate(settings: &Variables, pg: &PgDb) -> AppResult<()> {
+	let migrator = Migrator::new(&settings.migrations_dir);
+	let pending = migrator.pending(pg).await?;
+	if pending.is_empty() {
+		return Ok(());
+	}
+	if settings.environment != "development" {
+		return Err(AppError::MigrationError {
+			message: format!("{} pending migrations, apply them with `qaswa migrate up`", pending.len()),
+		});
+	}
+	let applied = migrator.up(pg, None).await?;
+	info!("applied {} migrations", applied.len());
+	Ok(())
+}
 
+#[allow(unused)]
+pub async fn graceful_shutdown(
+	handle: axum_server::Handle,
+	pg_server: Option<Arc<Mutex<PgServer>>>,
+	transactions: Arc<Transactions>,
+) {
 	let ctrl_c = async {
 		signal::ctrl_c().await.expect("failed to install Ctrl+C handlers");
 	};
@@ -58,20 +124,15 @@ pub async fn graceful_shutdown(handle: axum_server::Handle, mut pg_server: Arc<M
     }
 	handle.graceful_shutdown(Some(Duration::from_secs(5)));
 	info!("signal received, starting graceful shutdown");
+	// Abandoned transactions would hold their locks until their connection is closed
+	transactions.rollback_all().await;
+	// Last, the transactions are rolled back on it
+	stop_postgres(pg_server);
+	crate::telemetry::shutdown();
 }
 
 #[allow(unused)]
-pub async fn shutdown_signal(pg_server: Arc<Mutex<PgServer>>) {
-	// Stop postgres first
-	match pg_server.lock() {
-		Ok(mut server) => {
-			server.stop_db_sync();
-		}
-		Err(err) => {
-			error!("{:?}",err);
-		}
-	}
-
+pub async fn shutdown_signal(pg_server: Option<Arc<Mutex<PgServer>>>) {
 	let ctrl_c = async {
 		signal::ctrl_c().await.expect("failed to install Ctrl+C handlers");
 	};
@@ -93,4 +154,21 @@ pub async fn shutdown_signal(pg_server: Arc<Mutex<PgServer>>) {
     }
 
 	info!("signal received, starting graceful shutdown");
-}
\ No newline at end of file
+	stop_postgres(pg_server);
+	crate::telemetry::shutdown();
+}
+
+/// Stop the embedded Postgres, an external one or one started by another process is left running
+fn stop_postgres(pg_server: Option<Arc<Mutex<PgServer>>>) {
+	if let Some(pg_server) = pg_server {
+		match pg_server.lock() {
+			Ok(server) if server.shutting_down => {}
+			Ok(mut server) => {
+				server.stop_db_sync();
+			}
+			Err(err) => {
+				error!("{:?}",err);
+			}
+		}
+	}
+}
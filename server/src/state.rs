@@ -1,8 +1,16 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use flinch::database::Database;
 use flinch::doc::QueryBased;
+use tokio::sync::broadcast;
+use db::notify::Notification;
+use db::rest::catalog::CatalogCache;
 use db::setup::{PgDb, PgServer};
+use db::slow::SlowLog;
+use db::tx::Transactions;
 use utility::env::Variables;
+use crate::cache::Cache;
+use crate::graphql::Schemas;
 use crate::util::ConfigState;
 
 pub type SharedState = Arc<State>;
@@ -11,12 +19,47 @@ pub struct State {
 	pub env: Variables,
 	pub flinch: Arc<Database<QueryBased>>,
 	pub config: ConfigState,
-	pub pg_server: Arc<Mutex<PgServer>>,
-	pub pg: Arc<PgDb>
+	/// Embedded Postgres, `None` with `DATABASE_URL`
+	pub pg_server: Option<Arc<Mutex<PgServer>>>,
+	pub pg: Arc<PgDb>,
+	/// Open `/sql/tx` transactions
+	pub transactions: Arc<Transactions>,
+	/// Statements slower than `SQL_SLOW_QUERY_MS`
+	pub slow_log: Arc<SlowLog>,
+	/// Changes of the tables published to realtime clients
+	pub changes: broadcast::Sender<Notification>,
+	/// Tables copied into flinch collections
+	pub cache: Arc<Cache>,
+	/// Tables of the REST API and of GraphQL
+	pub catalog: CatalogCache,
+	/// GraphQL schema of the `public` tables
+	pub graphql: Schemas,
 }
 
 impl State {
-	pub fn init(env: Variables, flinch: Arc<Database<QueryBased>>, pg_server: Arc<Mutex<PgServer>>, pg: Arc<PgDb>) -> Self {
-		Self { env: env.clone(), config: ConfigState::from(env), flinch, pg_server, pg }
+	pub fn init(
+		env: Variables,
+		flinch: Arc<Database<QueryBased>>,
+		pg_server: Option<Arc<Mutex<PgServer>>>,
+		pg: Arc<PgDb>,
+		transactions: Arc<Transactions>,
+		slow_log: Arc<SlowLog>,
+		changes: broadcast::Sender<Notification>,
+	) -> Self {
+		let cache = Arc::new(Cache::new((*pg).clone(), flinch.clone(), &env.db_user_role));
+		let catalog = CatalogCache::new(db::rest::SCHEMA, Duration::from_millis(env.rest_catalog_ttl_ms));
+		Self {
+			env: env.clone(),
+			config: ConfigState::from(env),
+			flinch,
+			pg_server,
+			pg,
+			transactions,
+			slow_log,
+			changes,
+			cache,
+			catalog,
+			graphql: Schemas::default(),
+		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+//! OpenTelemetry trace export
+
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Sampler, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use utility::env::Variables;
+use utility::errors::{AppError, AppResult};
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Build the OTLP tracer when `OTEL_ENABLED` is set.
+///
+/// `OTEL_PROTOCOL` selects gRPC (`grpc`, default port 4317) or HTTP/protobuf (`http`, default port 4318).
+pub fn tracer(settings: &Variables) -> AppResult<Option<Tracer>> {
+	if !settings.otel_enabled {
+		return Ok(None);
+	}
+
+	global::set_text_map_propagator(TraceContextPropagator::new());
+
+	let exporter: opentelemetry_otlp::SpanExporterBuilder = match settings.otel_protocol.as_str() {
+		"grpc" => opentelemetry_otlp::new_exporter()
+			.tonic()
+			.with_protocol(Protocol::Grpc)
+			.with_endpoint(settings.otel_endpoint.as_str())
+			.with_timeout(EXPORT_TIMEOUT)
+			.into(),
+		"http" => {
+			// The HTTP exporter posts to the endpoint as-is
+			let endpoint = match settings.otel_endpoint.ends_with("/v1/traces") {
+				true => settings.otel_endpoint.clone(),
+				false => format!("{}/v1/traces", settings.otel_endpoint.trim_end_matches('/')),
+			};
+			opentelemetry_otlp::new_exporter()
+				.http()
+				.with_protocol(Protocol::HttpBinary)
+				.with_endpoint(endpoint)
+				.with_timeout(EXPORT_TIMEOUT)
+				.into()
+		}
+		protocol => {
+			return Err(AppError::ConfigError { message: format!("OTEL_PROTOCOL: unsupported protocol {:?}", protocol) });
+		}
+	};
+
+	let tracer = opentelemetry_otlp::new_pipeline()
+		.tracing()
+		.with_exporter(exporter)
+		.with_trace_config(
+			trace::config()
+				.with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(settings.otel_sample_ratio))))
+				.with_resource(Resource::new(vec![
+					KeyValue::new("service.name", settings.otel_service_name.clone()),
+					KeyValue::new("deployment.environment", settings.environment.clone()),
+				])),
+		)
+		.install_batch(opentelemetry::runtime::Tokio)
+		.map_err(|err| AppError::ConfigError { message: format!("OpenTelemetry: {}", err) })?;
+
+	Ok(Some(tracer))
+}
+
+/// Flush pending spans and stop the exporter
+pub fn shutdown() {
+	global::shutdown_tracer_provider();
+}
+
+/// Set the parent of `span` from the W3C `traceparent`/`tracestate` request headers
+pub fn extract_parent(span: &Span, headers: &HeaderMap) {
+	let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+	span.set_parent(parent);
+}
+
+/// Write the W3C `traceparent`/`tracestate` headers of `span`
+pub fn inject_context(span: &Span, headers: &mut HeaderMap) {
+	let context = span.context();
+	global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(headers)));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).and_then(|value| value.to_str().ok())
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		self.0.keys().map(|key| key.as_str()).collect()
+	}
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+	fn set(&mut self, key: &str, value: String) {
+		if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+			self.0.insert(name, value);
+		}
+	}
+}
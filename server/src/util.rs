@@ -3,7 +3,7 @@ use std::str::from_utf8;
 use axum::body::Full;
 use axum::headers::HeaderName;
 use axum::http::{
-	header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ORIGIN},
+	header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN},
 	HeaderValue,
 	Method, Request, response::Parts,
 };
@@ -18,10 +18,10 @@ use tower_http::request_id::{MakeRequestId, RequestId};
 use tracing::error;
 use uuid::Uuid;
 use utility::app_error;
-use utility::errors::{AppErrorMessage,AppErrorCode,AppError};
+use utility::errors::{AppErrorMessage,AppErrorCode,AppError,ErrorCode,PROBLEM_JSON};
 use utility::env::Variables;
 
-/// Construct response body from `Parts`, status code, message and headers
+/// Construct problem details response body from `Parts`, status code, message and headers
 pub fn body_from_parts(
 	parts: &mut Parts,
 	status_code: StatusCode,
@@ -34,7 +34,7 @@ pub fn body_from_parts(
 	// Headers
 	parts
 		.headers
-		.insert(CONTENT_TYPE, HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()));
+		.insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
 	if let Some(headers) = headers {
 		for header in headers {
 			parts.headers.insert(header.0, header.1);
@@ -42,12 +42,31 @@ pub fn body_from_parts(
 	}
 
 	// Body
-	let msg = serde_json::json!(AppErrorMessage {
-        code: status_code.as_u16(),
-        message: String::from(message),
-    });
+	let msg = AppErrorMessage::new(status_code, ErrorCode::from_status(status_code), message);
+	let body = serde_json::to_vec(&msg).unwrap_or_default();
+	parts.extensions.insert(msg);
 
-	Bytes::from(msg.to_string())
+	Bytes::from(body)
+}
+
+/// Layer which sets the `instance` of problem details responses to the request ID
+pub async fn problem_instance<B>(req: Request<B>, next: Next<B>) -> Response {
+	let request_id = header_value_to_str(req.headers().get("x-request-id")).to_string();
+	let response = next.run(req).await;
+
+	match response.extensions().get::<AppErrorMessage>() {
+		Some(msg) if msg.instance.is_none() && !request_id.is_empty() => {
+			let mut msg = msg.clone();
+			msg.instance = Some(request_id);
+
+			let (mut parts, _body) = response.into_parts();
+			parts.headers.remove(CONTENT_LENGTH);
+			let body = serde_json::to_vec(&msg).unwrap_or_default();
+			parts.extensions.insert(msg);
+			Response::from_parts(parts, axum::body::boxed(Full::from(body)))
+		}
+		_ => response,
+	}
 }
 
 /// Request ID middleware
@@ -111,16 +130,9 @@ pub fn cors(config: &Variables) -> CorsLayer {
 pub async fn override_http_errors<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
 	let response = next.run(req).await;
 
-	// If it is an image, audio or video, we return response
-	let headers = response.headers();
-	if let Some(content_type) = headers.get("content-type") {
-		let content_type = content_type.to_str().unwrap_or_default();
-		if content_type.starts_with("image/")
-			|| content_type.starts_with("audio/")
-			|| content_type.starts_with("video/")
-		{
-			return response;
-		}
+	// Other responses, files and streamed bodies included, are returned without being buffered
+	if !matches!(response.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::UNPROCESSABLE_ENTITY) {
+		return response;
 	}
 
 	let (parts, body) = response.into_parts();
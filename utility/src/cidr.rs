@@ -0,0 +1,154 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// IPv4 or IPv6 network in CIDR notation (`10.0.0.0/8`, `2001:db8::/32`).
+/// A bare address is treated as a single host network (`/32` or `/128`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+	addr: IpAddr,
+	prefix: u8,
+}
+
+impl Cidr {
+	/// Create a new `Cidr`, the host bits of `addr` are cleared
+	pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, String> {
+		let max = match addr {
+			IpAddr::V4(_) => 32,
+			IpAddr::V6(_) => 128,
+		};
+		if prefix > max {
+			return Err(format!("invalid prefix length /{} for {}", prefix, addr));
+		}
+		Ok(Self { addr: mask(addr, prefix), prefix })
+	}
+
+	/// Check if `ip` belongs to the network.
+	/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are matched against IPv4 networks.
+	pub fn contains(&self, ip: &IpAddr) -> bool {
+		let ip = canonical(ip);
+		match (self.addr, ip) {
+			(IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => mask(ip, self.prefix) == self.addr,
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		let (addr, prefix) = match s.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (s, None),
+		};
+		let addr = IpAddr::from_str(addr).map_err(|err| format!("invalid address {:?} - {}", s, err))?;
+		let addr = canonical(&addr);
+		let prefix = match prefix {
+			Some(prefix) => prefix.parse::<u8>().map_err(|err| format!("invalid prefix {:?} - {}", s, err))?,
+			None => match addr {
+				IpAddr::V4(_) => 32,
+				IpAddr::V6(_) => 128,
+			},
+		};
+		Cidr::new(addr, prefix)
+	}
+}
+
+impl Display for Cidr {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}/{}", self.addr, self.prefix)
+	}
+}
+
+/// List of networks parsed from a comma delimited string
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CidrList(Vec<Cidr>);
+
+impl CidrList {
+	/// Parse a comma delimited list, empty entries are ignored
+	pub fn parse(list: &str) -> Result<Self, String> {
+		let mut networks = vec![];
+		for item in list.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+			networks.push(Cidr::from_str(item)?);
+		}
+		Ok(Self(networks))
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Check if `ip` belongs to one of the networks
+	pub fn contains(&self, ip: &IpAddr) -> bool {
+		self.0.iter().any(|cidr| cidr.contains(ip))
+	}
+}
+
+/// Convert IPv4-mapped IPv6 addresses to IPv4
+pub fn canonical(ip: &IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+			Some(v4) => IpAddr::V4(v4),
+			None => *ip,
+		},
+		_ => *ip,
+	}
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+	match addr {
+		IpAddr::V4(v4) => {
+			let bits = u32::from(v4);
+			let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix as u32) };
+			IpAddr::V4((bits & mask).into())
+		}
+		IpAddr::V6(v6) => {
+			let bits = u128::from(v6);
+			let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix as u32) };
+			IpAddr::V6((bits & mask).into())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_cidr() {
+		let ip = |s: &str| IpAddr::from_str(s).unwrap();
+
+		let net = Cidr::from_str("10.0.0.0/8").unwrap();
+		assert!(net.contains(&ip("10.20.30.40")));
+		assert!(net.contains(&ip("::ffff:10.1.2.3")));
+		assert!(!net.contains(&ip("11.0.0.1")));
+		assert!(!net.contains(&ip("::1")));
+
+		let host = Cidr::from_str("192.168.1.10").unwrap();
+		assert_eq!(host.to_string(), "192.168.1.10/32");
+		assert!(host.contains(&ip("192.168.1.10")));
+		assert!(!host.contains(&ip("192.168.1.11")));
+
+		let v6 = Cidr::from_str("2001:db8::/32").unwrap();
+		assert!(v6.contains(&ip("2001:db8:cafe::17")));
+		assert!(!v6.contains(&ip("2001:db9::1")));
+
+		assert!(Cidr::from_str("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+		assert!(Cidr::from_str("10.0.0.0/33").is_err());
+		assert!(Cidr::from_str("not-an-ip").is_err());
+	}
+
+	#[test]
+	fn test_cidr_list() {
+		let list = CidrList::parse(" 127.0.0.1, ,172.16.0.0/12,fd00::/8").unwrap();
+		assert!(list.contains(&IpAddr::from_str("127.0.0.1").unwrap()));
+		assert!(list.contains(&IpAddr::from_str("172.31.255.1").unwrap()));
+		assert!(list.contains(&IpAddr::from_str("fd12::1").unwrap()));
+		assert!(!list.contains(&IpAddr::from_str("8.8.8.8").unwrap()));
+
+		assert!(CidrList::parse("").unwrap().is_empty());
+		assert!(CidrList::parse("1.2.3.4,bad").is_err());
+	}
+}
@@ -0,0 +1,179 @@
+//! Cron expressions: `minute hour day-of-month month day-of-week`, evaluated in UTC.
+//!
+//! Fields accept `*`, values, ranges `1-5`, steps `*/15` or `0-30/10` and lists `1,15`.
+//! Days of the week are `0-7`, both `0` and `7` being Sunday. As with cron, when both the day of
+//! the month and the day of the week are restricted, a day matching either of them matches.
+//! `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are shortcuts.
+
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+	minutes: u64,
+	hours: u64,
+	days: u64,
+	months: u64,
+	weekdays: u64,
+	/// The day of the month field is `*`
+	any_day: bool,
+	/// The day of the week field is `*`
+	any_weekday: bool,
+}
+
+impl FromStr for Schedule {
+	type Err = String;
+
+	fn from_str(expression: &str) -> Result<Self, Self::Err> {
+		let expression = match expression.trim() {
+			"@hourly" => "0 * * * *",
+			"@daily" | "@midnight" => "0 0 * * *",
+			"@weekly" => "0 0 * * 0",
+			"@monthly" => "0 0 1 * *",
+			"@yearly" | "@annually" => "0 0 1 1 *",
+			expression => expression,
+		};
+		let fields: Vec<&str> = expression.split_whitespace().collect();
+		if fields.len() != 5 {
+			return Err(format!("expected 5 fields in {:?}", expression));
+		}
+		let mut weekdays = parse_field(fields[4], 0, 7)?;
+		// 7 is Sunday too
+		if weekdays & (1 << 7) != 0 {
+			weekdays = (weekdays | 1) & !(1 << 7);
+		}
+		Ok(Self {
+			minutes: parse_field(fields[0], 0, 59)?,
+			hours: parse_field(fields[1], 0, 23)?,
+			days: parse_field(fields[2], 1, 31)?,
+			months: parse_field(fields[3], 1, 12)?,
+			weekdays,
+			any_day: fields[2] == "*",
+			any_weekday: fields[4] == "*",
+		})
+	}
+}
+
+impl Schedule {
+	/// First matching minute strictly after `after`, `None` when nothing matches within 5 years
+	/// (e.g. `0 0 31 2 *`)
+	pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+		let limit = time + Duration::days(5 * 366);
+		while time < limit {
+			if !has(self.months, time.month()) {
+				let (year, month) = match time.month() {
+					12 => (time.year() + 1, 1),
+					month => (time.year(), month + 1),
+				};
+				time = midnight(NaiveDate::from_ymd_opt(year, month, 1)?);
+			} else if !self.day_matches(time.date_naive()) {
+				time = midnight(time.date_naive().succ_opt()?);
+			} else if !has(self.hours, time.hour()) {
+				time = time.with_minute(0)? + Duration::hours(1);
+			} else if !has(self.minutes, time.minute()) {
+				time += Duration::minutes(1);
+			} else {
+				return Some(time);
+			}
+		}
+		None
+	}
+
+	fn day_matches(&self, date: NaiveDate) -> bool {
+		let day = has(self.days, date.day());
+		let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+		match (self.any_day, self.any_weekday) {
+			(true, true) => true,
+			(false, true) => day,
+			(true, false) => weekday,
+			(false, false) => day || weekday,
+		}
+	}
+}
+
+fn has(set: u64, value: u32) -> bool {
+	set & (1 << value) != 0
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+	Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+/// Set of the values of a field, as a bit mask
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+	let mut set = 0u64;
+	for part in field.split(',') {
+		let (range, step) = match part.split_once('/') {
+			Some((range, step)) => (range, parse_value(step, 1, max)?),
+			None => (part, 1),
+		};
+		let (start, end) = match range {
+			"*" => (min, max),
+			range => match range.split_once('-') {
+				Some((start, end)) => (parse_value(start, min, max)?, parse_value(end, min, max)?),
+				// `5/10` starts at 5 until the end
+				None if step > 1 => (parse_value(range, min, max)?, max),
+				None => {
+					let value = parse_value(range, min, max)?;
+					(value, value)
+				}
+			},
+		};
+		if start > end {
+			return Err(format!("invalid range {:?}", range));
+		}
+		for value in (start..=end).step_by(step as usize) {
+			set |= 1 << value;
+		}
+	}
+	Ok(set)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+	match value.parse::<u32>() {
+		Ok(value) if (min..=max).contains(&value) => Ok(value),
+		_ => Err(format!("{:?} is not a value between {} and {}", value, min, max)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(value: &str) -> DateTime<Utc> {
+		DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+	}
+
+	fn next(expression: &str, after: &str) -> String {
+		let schedule: Schedule = expression.parse().unwrap();
+		schedule.next_after(at(after)).unwrap().to_rfc3339()
+	}
+
+	#[test]
+	fn test_next_after() {
+		assert_eq!(next("*/15 * * * *", "2023-09-01T10:07:30Z"), "2023-09-01T10:15:00+00:00");
+		assert_eq!(next("*/15 * * * *", "2023-09-01T10:15:00Z"), "2023-09-01T10:30:00+00:00");
+		assert_eq!(next("30 2 * * *", "2023-09-01T10:07:00Z"), "2023-09-02T02:30:00+00:00");
+		assert_eq!(next("@daily", "2023-12-31T23:59:59Z"), "2024-01-01T00:00:00+00:00");
+		assert_eq!(next("0 0 29 2 *", "2023-03-01T00:00:00Z"), "2024-02-29T00:00:00+00:00");
+		// 2023-09-01 is a Friday
+		assert_eq!(next("0 9 * * 1-5", "2023-09-01T09:00:00Z"), "2023-09-04T09:00:00+00:00");
+		assert_eq!(next("0 0 * * 7", "2023-09-01T00:00:00Z"), "2023-09-03T00:00:00+00:00");
+		// Day of the month or day of the week
+		assert_eq!(next("0 0 15 * 1", "2023-09-01T00:00:00Z"), "2023-09-04T00:00:00+00:00");
+		assert_eq!(next("0 0 1,15 * *", "2023-09-02T00:00:00Z"), "2023-09-15T00:00:00+00:00");
+		assert_eq!(next("5/20 3 * * *", "2023-09-01T03:30:00Z"), "2023-09-01T03:45:00+00:00");
+		assert!("0 0 31 2 *".parse::<Schedule>().unwrap().next_after(at("2023-01-01T00:00:00Z")).is_none());
+	}
+
+	#[test]
+	fn test_invalid() {
+		assert!("* * * *".parse::<Schedule>().is_err());
+		assert!("60 * * * *".parse::<Schedule>().is_err());
+		assert!("0 0 0 * *".parse::<Schedule>().is_err());
+		assert!("10-5 * * * *".parse::<Schedule>().is_err());
+		assert!("*/0 * * * *".parse::<Schedule>().is_err());
+		assert!("a * * * *".parse::<Schedule>().is_err());
+	}
+}
@@ -5,6 +5,7 @@ use tracing::error;
 
 /// Represents configuration structure
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Variables {
 	/// Environment: `developement` or `production`
 	pub environment: String,
@@ -16,12 +17,108 @@ pub struct Variables {
 	pub log_path: String,
 	pub log_file: String,
 
+	/// Log output
+	/// Format: `json`, `pretty` or `compact`
+	/// Target: `stdout`, `file` or `both`
+	/// Rotation: `daily`, `hourly`, `size` or `never`, keeping `log_max_files` files (0: unlimited)
+	/// Empty format and target use the environment defaults
+	pub log_format: String,
+	pub log_target: String,
+	pub log_rotation: String,
+	pub log_max_size_mb: u64,
+	pub log_max_files: usize,
+
+	/// Sampled request/response body capture (0: disabled)
+	/// Redacted JSON fields and headers are comma delimited
+	pub log_body_sample_ratio: f64,
+	pub log_body_max_bytes: usize,
+	pub log_redact_fields: String,
+	pub log_redact_headers: String,
+
 	/// Postgres Config
 	pub db_path: String,
 	pub db_user: String,
 	pub db_pw: String,
 	pub db_port: u16,
 	pub db_timeout: u64,
+	/// External Postgres, e.g. `postgres://user:pw@host:5432/qaswa`, the embedded server is not
+	/// installed nor started when it is set
+	pub database_url: String,
+	/// Connection pool, timeouts in seconds (`0` keeps idle connections)
+	pub db_max_connections: u32,
+	pub db_min_connections: u32,
+	pub db_acquire_timeout: u64,
+	pub db_idle_timeout: u64,
+	/// Prepared statements cached per connection, `0` for PgBouncer in transaction mode
+	pub db_statement_cache: usize,
+	/// `disable`, `allow`, `prefer`, `require`, `verify-ca` or `verify-full`, empty to use the URL
+	pub db_ssl_mode: String,
+	/// Role of the statements run for users, e.g. `/rest`, so that row-level security policies apply.
+	/// The server refuses to start without it, created in the embedded Postgres when missing
+	pub db_user_role: String,
+	/// Directory of the versioned migrations, see `qaswa migrate`
+	pub migrations_dir: String,
+	/// Backups, see `qaswa backup`. The schedule is a cron expression in UTC, empty disables
+	/// scheduled backups. Backups beyond the `BACKUP_KEEP_LAST` most recent ones and older than
+	/// `BACKUP_KEEP_DAYS` days are pruned
+	pub backup_dir: String,
+	pub backup_schedule: String,
+	/// gzip level, from 0 to 9
+	pub backup_compression: u32,
+	pub backup_keep_last: usize,
+	pub backup_keep_days: u64,
+	/// Data directory of flinch (`[dir] data` of `flinch.toml`), empty to back up Postgres only
+	pub flinch_data_dir: String,
+	/// Archive of the WAL of the embedded Postgres for point-in-time recovery, empty disables
+	/// archiving. Backups then include a base backup, see `qaswa restore`
+	pub wal_archive_dir: String,
+	/// Seconds after which the current WAL segment is archived even if not full
+	pub wal_archive_timeout: u64,
+
+	/// Admin SQL console
+	/// Default and maximum `statement_timeout` and row limit, requests can lower them
+	pub sql_statement_timeout_ms: u64,
+	pub sql_max_rows: usize,
+	/// Maximum `statement_timeout` of `/admin/sql/stream`, which includes sending the rows
+	pub sql_stream_timeout_ms: u64,
+	/// `/sql/tx` transactions without statement for this long are rolled back
+	pub sql_tx_idle_timeout_ms: u64,
+	/// Open `/sql/tx` transactions of a user, each one holds a connection of the pool
+	pub sql_tx_max_per_user: usize,
+	/// Statements of the SQL console and of the REST API lasting at least this long are logged,
+	/// see `/admin/sql/slow`. `0` disables the log
+	pub sql_slow_query_ms: u64,
+	/// Slow statements kept in memory, the oldest ones are dropped
+	pub sql_slow_log_size: usize,
+
+	/// REST API
+	/// Default and maximum number of rows returned by `/rest/:table`
+	pub rest_max_rows: usize,
+	/// Tables and columns of the REST API and of GraphQL are read again from `pg_catalog` once this
+	/// old, or at once when a request names one the cached catalog lacks
+	pub rest_catalog_ttl_ms: u64,
+
+	/// GraphQL
+	/// Deepest nesting of the fields of a query, relations included
+	pub graphql_max_depth: usize,
+
+	/// Realtime
+	/// Changes buffered for each realtime subscriber, slower subscribers miss changes and are told
+	/// to resync
+	pub realtime_channel_capacity: usize,
+
+	/// Change data capture
+	/// Deliver the changes of the tables of the `qaswa_cdc` publication, requires `wal_level = logical`
+	pub cdc_enabled: bool,
+	/// Comma delimited URLs receiving each captured transaction as a `POST`
+	pub cdc_webhooks: String,
+	/// Delay between two reads of the replication slot once it is empty
+	pub cdc_poll_interval_ms: u64,
+	/// Changes read from the replication slot at once
+	pub cdc_batch_size: usize,
+	pub cdc_webhook_timeout_ms: u64,
+	/// Attempts to post a transaction to a webhook before it is moved to `qaswa.cdc_dead_letters`
+	pub cdc_webhook_max_attempts: u32,
 
 	/// TLS
 	pub tls_policy: String,
@@ -41,9 +138,27 @@ pub struct Variables {
 	pub limiter_expire_in_seconds: i64,
 	pub limiter_white_list: String,
 
+	/// Client IP
+	/// Comma delimited CIDR lists, e.g. `10.0.0.0/8,::1`
+	pub trusted_proxies: String,
+	/// Header set by the trusted proxies: `X-Forwarded-For` or `Forwarded` (RFC 7239)
+	pub forwarded_header: String,
+	pub proxy_protocol: bool,
+	pub ip_allow_list: String,
+	pub ip_deny_list: String,
+
 	/// Prometheus metrics enabled
 	pub prometheus_metrics_enabled: bool,
 
+	/// OpenTelemetry
+	/// Protocol: `grpc` or `http` (protobuf)
+	pub otel_enabled: bool,
+	pub otel_protocol: String,
+	pub otel_endpoint: String,
+	pub otel_service_name: String,
+	pub otel_sample_ratio: f64,
+	pub otel_filter: String,
+
 	/// Basic Auth
 	pub basic_user: String,
 	pub basic_pw: String,
@@ -61,11 +176,54 @@ impl Default for Variables {
 			rust_log: format!("trace,sqlx=error,config=trace"),
 			log_path: format!("./qaswa-log"),
 			log_file: format!("qaswa"),
+			log_format: "".to_string(),
+			log_target: "".to_string(),
+			log_rotation: "daily".to_string(),
+			log_max_size_mb: 100,
+			log_max_files: 7,
+			log_body_sample_ratio: 0.0,
+			log_body_max_bytes: 4096,
+			log_redact_fields: "password,pw,token,secret,access_token,refresh_token".to_string(),
+			log_redact_headers: "authorization,cookie,set-cookie,x-api-key".to_string(),
 			db_path: format!("./qaswa-data"),
 			db_user: format!("postgres"),
 			db_pw: format!("password"),
 			db_port: 5432,
 			db_timeout: 15,
+			database_url: String::new(),
+			db_max_connections: 50,
+			db_min_connections: 10,
+			db_acquire_timeout: 30,
+			db_idle_timeout: 600,
+			db_statement_cache: 100,
+			db_ssl_mode: String::new(),
+			db_user_role: "qaswa_user".to_string(),
+			migrations_dir: "./migrations".to_string(),
+			backup_dir: "./backups".to_string(),
+			backup_schedule: String::new(),
+			backup_compression: 6,
+			backup_keep_last: 7,
+			backup_keep_days: 30,
+			flinch_data_dir: String::new(),
+			wal_archive_dir: String::new(),
+			wal_archive_timeout: 60,
+			sql_statement_timeout_ms: 5000,
+			sql_max_rows: 1000,
+			sql_stream_timeout_ms: 3600000,
+			sql_tx_idle_timeout_ms: 30000,
+			sql_tx_max_per_user: 4,
+			sql_slow_query_ms: 1000,
+			sql_slow_log_size: 1000,
+			rest_max_rows: 1000,
+			rest_catalog_ttl_ms: 30000,
+			graphql_max_depth: 10,
+			realtime_channel_capacity: 1024,
+			cdc_enabled: false,
+			cdc_webhooks: "".to_string(),
+			cdc_poll_interval_ms: 500,
+			cdc_batch_size: 1000,
+			cdc_webhook_timeout_ms: 10000,
+			cdc_webhook_max_attempts: 10,
 			tls_policy: format!("native"),
 			tls_cert_path: format!("./certs/ssl.cert"),
 			tls_key_path: format!("./certs/ssl.key"),
@@ -76,7 +234,18 @@ impl Default for Variables {
 			limiter_requests_by_second: 100,
 			limiter_expire_in_seconds: 30,
 			limiter_white_list: "".to_string(),
+			trusted_proxies: "".to_string(),
+			forwarded_header: "X-Forwarded-For".to_string(),
+			proxy_protocol: false,
+			ip_allow_list: "".to_string(),
+			ip_deny_list: "".to_string(),
 			prometheus_metrics_enabled: true,
+			otel_enabled: false,
+			otel_protocol: "grpc".to_string(),
+			otel_endpoint: "http://localhost:4317".to_string(),
+			otel_service_name: "qaswa".to_string(),
+			otel_sample_ratio: 1.0,
+			otel_filter: "info,h2=off,hyper=off,tonic=off,tower=off,reqwest=off".to_string(),
 			basic_user: "root".to_string(),
 			basic_pw: "qaswa123@".to_string(),
 			server_url: "0.0.0.0".to_string(),
@@ -1,7 +1,7 @@
 use std::net::AddrParseError;
+use std::sync::atomic::{AtomicBool, Ordering};
 use axum::{
-	http::StatusCode,
-	Json,
+	http::{header, HeaderValue, StatusCode},
 	response::{IntoResponse, Response},
 };
 use color_eyre::eyre::Result as EyreResult;
@@ -10,16 +10,170 @@ use eyre::ErrReport;
 use flinch::errors::DbError;
 use serde::{Deserialize, Serialize};
 use serde::de::StdError;
-use serde_json::json;
 use sqlx::Error;
 use tracing::error;
+use utoipa::ToSchema;
 
 pub type AppResult<T> = EyreResult<T, AppError>;
 
-#[derive(Deserialize, Serialize)]
+/// Base URI of the `type` member of problem details, each code is an anchor of `docs/errors.md`
+pub const ERROR_TYPE_BASE: &str = "https://github.com/mjm918/qaswa.rs/blob/main/docs/errors.md#";
+
+/// Whether details of internal errors are returned to clients (never in production)
+static EXPOSE_INTERNAL_DETAILS: AtomicBool = AtomicBool::new(false);
+
+/// Return details of internal errors to clients, otherwise they are only logged
+pub fn expose_internal_details(expose: bool) {
+	EXPOSE_INTERNAL_DETAILS.store(expose, Ordering::Relaxed);
+}
+
+/// Problem details body (RFC 7807), sent as `application/problem+json`
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[schema(example = json!({
+	"type": "https://github.com/mjm918/qaswa.rs/blob/main/docs/errors.md#h404",
+	"title": "Not found",
+	"status": 404,
+	"detail": "Not found",
+	"instance": "0f8a5b7e-0cf1-4b6e-9a0e-7d1f3bb2a8c1",
+	"code": "H404"
+}))]
 pub struct AppErrorMessage {
-	pub code: u16,
-	pub message: String,
+	/// URI of the error code documentation
+	#[serde(rename = "type")]
+	pub kind: String,
+	pub title: String,
+	/// HTTP status code
+	pub status: u16,
+	pub detail: String,
+	/// Request ID
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub instance: Option<String>,
+	/// Machine-readable code, see `ErrorCode`
+	pub code: ErrorCode,
+}
+
+impl AppErrorMessage {
+	pub fn new(status: StatusCode, code: ErrorCode, detail: &str) -> Self {
+		Self {
+			kind: code.type_uri(),
+			title: code.title().to_string(),
+			status: status.as_u16(),
+			detail: detail.to_string(),
+			instance: None,
+			code,
+		}
+	}
+
+	/// Build the `application/problem+json` response, the message is kept in the response
+	/// extensions so that the `instance` can be filled in by an outer layer
+	pub fn into_problem_response(self) -> Response {
+		let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+		let body = serde_json::to_vec(&self).unwrap_or_default();
+		let mut response = (
+			status,
+			[(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+			body,
+		).into_response();
+		response.extensions_mut().insert(self);
+		response
+	}
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Error code catalog, documented in `docs/errors.md`.
+/// Codes are stable: never reuse or renumber them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ErrorCode {
+	/// Internal server error
+	E100,
+	/// Invalid configuration
+	E101,
+	/// Embedded Postgres server error
+	E301,
+	/// Postgres query error
+	E304,
+	/// SQL statement rejected by Postgres
+	E305,
+	/// Database migration error
+	E306,
+	/// Backup or restore error
+	E307,
+	/// Change data capture error
+	E308,
+	/// JSON serialization error
+	S101,
+	/// Unexpected internal error
+	S109,
+	/// HTTP server error
+	S110,
+	/// In-memory database error
+	M109,
+	/// In-memory query error
+	M110,
+	/// Bad request
+	H400,
+	/// Unauthorized
+	H401,
+	/// Forbidden
+	H403,
+	/// Not found
+	H404,
+	/// Method not allowed
+	H405,
+	/// Request timeout
+	H408,
+	/// Unprocessable entity
+	H422,
+	/// Too many requests
+	H429,
+}
+
+impl ErrorCode {
+	pub fn title(&self) -> &'static str {
+		match self {
+			ErrorCode::E100 => "Internal server error",
+			ErrorCode::E101 => "Invalid configuration",
+			ErrorCode::E301 => "Embedded database error",
+			ErrorCode::E304 => "Database query error",
+			ErrorCode::E305 => "SQL statement error",
+			ErrorCode::E306 => "Migration error",
+			ErrorCode::E307 => "Backup error",
+			ErrorCode::E308 => "Change data capture error",
+			ErrorCode::S101 => "Serialization error",
+			ErrorCode::S109 => "Unexpected internal error",
+			ErrorCode::S110 => "HTTP server error",
+			ErrorCode::M109 => "In-memory database error",
+			ErrorCode::M110 => "In-memory query error",
+			ErrorCode::H400 => "Bad request",
+			ErrorCode::H401 => "Unauthorized",
+			ErrorCode::H403 => "Forbidden",
+			ErrorCode::H404 => "Not found",
+			ErrorCode::H405 => "Method not allowed",
+			ErrorCode::H408 => "Request timeout",
+			ErrorCode::H422 => "Unprocessable entity",
+			ErrorCode::H429 => "Too many requests",
+		}
+	}
+
+	pub fn type_uri(&self) -> String {
+		format!("{}{}", ERROR_TYPE_BASE, format!("{:?}", self).to_lowercase())
+	}
+
+	/// Code of errors built from an HTTP status only
+	pub fn from_status(status: StatusCode) -> Self {
+		match status {
+			StatusCode::BAD_REQUEST => ErrorCode::H400,
+			StatusCode::UNAUTHORIZED => ErrorCode::H401,
+			StatusCode::FORBIDDEN => ErrorCode::H403,
+			StatusCode::NOT_FOUND => ErrorCode::H404,
+			StatusCode::METHOD_NOT_ALLOWED => ErrorCode::H405,
+			StatusCode::REQUEST_TIMEOUT => ErrorCode::H408,
+			StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::H422,
+			StatusCode::TOO_MANY_REQUESTS => ErrorCode::H429,
+			_ => ErrorCode::E100,
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -30,6 +184,7 @@ pub enum AppErrorCode {
 	UnprocessableEntity,
 	Timeout,
 	Unauthorized,
+	Forbidden,
 	TooManyRequests,
 	MethodNotAllowed,
 }
@@ -40,13 +195,29 @@ pub enum AppError {
 	FlinchError { message: String },
 
 	#[display(fmt = "{message}")]
-	LocalDbError { message: String },
+	LocalDbError { code: ErrorCode, message: String },
 
 	#[display(fmt = "{message}")]
 	ConfigError { message: String },
 
+	/// Error of a client provided statement, returned as is
+	#[display(fmt = "{message}")]
+	SqlError { message: String },
+
+	/// Invalid, modified or failed migration
 	#[display(fmt = "{message}")]
-	InternalError { message: String },
+	MigrationError { message: String },
+
+	/// Failed backup, restore or corrupted backup
+	#[display(fmt = "{message}")]
+	BackupError { message: String },
+
+	/// Failed decoding or delivery of the logical replication changes
+	#[display(fmt = "{message}")]
+	CdcError { message: String },
+
+	#[display(fmt = "{message}")]
+	InternalError { code: ErrorCode, message: String },
 
 	#[display(fmt = "{message}")]
 	BadRequest { message: String },
@@ -63,6 +234,9 @@ pub enum AppError {
 	#[display(fmt = "Unauthorized")]
 	Unauthorized,
 
+	#[display(fmt = "Forbidden")]
+	Forbidden,
+
 	#[display(fmt = "Too Many Requests")]
 	TooManyRequests,
 
@@ -70,26 +244,59 @@ pub enum AppError {
 	MethodNotAllowed,
 }
 
-impl IntoResponse for AppError {
-	fn into_response(self) -> Response {
-		let status = match self {
+impl AppError {
+	/// Catalog code of the error
+	pub fn code(&self) -> ErrorCode {
+		match self {
+			AppError::FlinchError { .. } => ErrorCode::M110,
+			AppError::LocalDbError { code, .. } => *code,
+			AppError::ConfigError { .. } => ErrorCode::E101,
+			AppError::SqlError { .. } => ErrorCode::E305,
+			AppError::MigrationError { .. } => ErrorCode::E306,
+			AppError::BackupError { .. } => ErrorCode::E307,
+			AppError::CdcError { .. } => ErrorCode::E308,
+			AppError::InternalError { code, .. } => *code,
+			AppError::BadRequest { .. } => ErrorCode::H400,
+			AppError::NotFound { .. } => ErrorCode::H404,
+			AppError::UnprocessableEntity { .. } => ErrorCode::H422,
+			AppError::Timeout => ErrorCode::H408,
+			AppError::Unauthorized => ErrorCode::H401,
+			AppError::Forbidden => ErrorCode::H403,
+			AppError::TooManyRequests => ErrorCode::H429,
+			AppError::MethodNotAllowed => ErrorCode::H405,
+		}
+	}
+
+	/// HTTP status of the error
+	pub fn status(&self) -> StatusCode {
+		match self {
 			AppError::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
 			AppError::NotFound { .. } => StatusCode::NOT_FOUND,
 			AppError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+			AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
 			AppError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+			AppError::SqlError { .. } => StatusCode::BAD_REQUEST,
 			AppError::Timeout { .. } => StatusCode::REQUEST_TIMEOUT,
 			AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
 			AppError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
 			AppError::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
 			_ => StatusCode::INTERNAL_SERVER_ERROR,
-		};
+		}
+	}
 
-		let body = Json(json!(AppErrorMessage {
-            code: status.as_u16(),
-            message: self.to_string(),
-        }));
+	/// Message returned to the client
+	pub fn detail(&self) -> String {
+		// Internal details (sqlx, pg_embed, ...) are logged where the error is built
+		match self.status().is_server_error() && !EXPOSE_INTERNAL_DETAILS.load(Ordering::Relaxed) {
+			true => self.code().title().to_string(),
+			false => self.to_string(),
+		}
+	}
+}
 
-		(status, body).into_response()
+impl IntoResponse for AppError {
+	fn into_response(self) -> Response {
+		AppErrorMessage::new(self.status(), self.code(), &self.detail()).into_problem_response()
 	}
 }
 
@@ -99,7 +306,7 @@ pub trait IntoInternalError {
 
 impl IntoInternalError for AppError {
 	fn internal(message: AppError) -> AppError {
-		Self::InternalError { message: message.to_string() }
+		Self::InternalError { code: message.code(), message: message.to_string() }
 	}
 }
 
@@ -107,7 +314,8 @@ impl From<ErrReport> for AppError {
 	fn from(value: ErrReport) -> Self {
 		error!("[E100] {:?}",&value);
 		Self::InternalError {
-			message: format!("[E100]{:?}",value)
+			code: ErrorCode::E100,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -116,7 +324,8 @@ impl From<Box<dyn StdError>> for AppError {
 	fn from(value: Box<dyn StdError>) -> Self {
 		error!("[E100] {:?}",&value);
 		Self::InternalError {
-			message: format!("[E100] {:?}",value)
+			code: ErrorCode::E100,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -125,7 +334,8 @@ impl From<std::io::Error> for AppError {
 	fn from(value: std::io::Error) -> Self {
 		error!("[E100] {:?}",&value);
 		Self::InternalError {
-			message: format!("[E100] {:?}",value)
+			code: ErrorCode::E100,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -134,7 +344,8 @@ impl<T> From<std::sync::PoisonError<T>> for AppError {
 	fn from(value: std::sync::PoisonError<T>) -> Self {
 		error!("[E100] {:?}",&value);
 		Self::InternalError {
-			message: format!("[E100] {:?}",value)
+			code: ErrorCode::E100,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -143,7 +354,8 @@ impl From<pg_embed::pg_errors::PgEmbedError> for AppError {
 	fn from(value: pg_embed::pg_errors::PgEmbedError) -> Self {
 		error!("[E301] {:?}",&value);
 		Self::LocalDbError {
-			message: format!("[E301] {:?}",value)
+			code: ErrorCode::E301,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -152,7 +364,8 @@ impl From<serde_json::Error> for AppError {
 	fn from(value: serde_json::Error) -> Self {
 		error!("[S101] {:?}",&value);
 		Self::InternalError {
-			message: format!("[S101] {:?}",&value)
+			code: ErrorCode::S101,
+			message: format!("{:?}",&value)
 		}
 	}
 }
@@ -161,7 +374,8 @@ impl From<anyhow::Error> for AppError {
 	fn from(value: anyhow::Error) -> Self {
 		error!("[S109] {:?}",&value);
 		Self::InternalError {
-			message: format!("[S109] {:?}",&value)
+			code: ErrorCode::S109,
+			message: format!("{:?}",&value)
 		}
 	}
 }
@@ -170,7 +384,8 @@ impl From<DbError> for AppError {
 	fn from(value: DbError) -> Self {
 		error!("[M109] {:?}",&value);
 		Self::InternalError {
-			message: format!("[M109] {:?}",&value)
+			code: ErrorCode::M109,
+			message: format!("{:?}",&value)
 		}
 	}
 }
@@ -179,7 +394,8 @@ impl From<AddrParseError> for AppError {
 	fn from(value: AddrParseError) -> Self {
 		error!("[S109] {:?}",&value);
 		Self::InternalError {
-			message: format!("[S109] {:?}",&value)
+			code: ErrorCode::S109,
+			message: format!("{:?}",&value)
 		}
 	}
 }
@@ -188,7 +404,8 @@ impl From<hyper::Error> for AppError {
 	fn from(value: hyper::Error) -> Self {
 		error!("[S110] {:?}",&value);
 		Self::InternalError {
-			message: format!("[S110] {:?}",&value)
+			code: ErrorCode::S110,
+			message: format!("{:?}",&value)
 		}
 	}
 }
@@ -197,7 +414,8 @@ impl From<sqlx::Error> for AppError {
 	fn from(value: Error) -> Self {
 		error!("[E304] {:?}",&value);
 		Self::LocalDbError {
-			message: format!("[E304] {:?}",value)
+			code: ErrorCode::E304,
+			message: format!("{:?}",value)
 		}
 	}
 }
@@ -225,9 +443,11 @@ macro_rules! app_error {
         match $error {
             AppErrorCode::Timeout => AppError::Timeout,
             AppErrorCode::Unauthorized => AppError::Unauthorized,
+            AppErrorCode::Forbidden => AppError::Forbidden,
             AppErrorCode::TooManyRequests => AppError::TooManyRequests,
             AppErrorCode::MethodNotAllowed => AppError::MethodNotAllowed,
             AppErrorCode::InternalError => AppError::InternalError {
+                code: $crate::errors::ErrorCode::E100,
                 message: String::from("Internal Server Error"),
             },
             AppErrorCode::BadRequest => AppError::BadRequest {
@@ -246,11 +466,13 @@ macro_rules! app_error {
         match $error {
             AppErrorCode::Timeout => AppError::Timeout,
             AppErrorCode::Unauthorized => AppError::Unauthorized,
+            AppErrorCode::Forbidden => AppError::Forbidden,
             AppErrorCode::TooManyRequests => AppError::TooManyRequests,
             AppErrorCode::MethodNotAllowed => AppError::MethodNotAllowed,
             AppErrorCode::InternalError => {
                 error!("{}", $message);
                 AppError::InternalError {
+                    code: $crate::errors::ErrorCode::E100,
                     message: $message.to_string(),
                 }
             }
@@ -270,11 +492,13 @@ macro_rules! app_error {
         match $error {
             AppErrorCode::Timeout => AppError::Timeout,
             AppErrorCode::Unauthorized => AppError::Unauthorized,
+            AppErrorCode::Forbidden => AppError::Forbidden,
             AppErrorCode::TooManyRequests => AppError::TooManyRequests,
             AppErrorCode::MethodNotAllowed => AppError::MethodNotAllowed,
             AppErrorCode::InternalError => {
                 error!("{}", $details);
                 AppError::InternalError {
+                    code: $crate::errors::ErrorCode::E100,
                     message: $message.to_string(),
                 }
             }
@@ -289,4 +513,33 @@ macro_rules! app_error {
             },
         }
     };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_problem_details() {
+		let error = AppError::LocalDbError { code: ErrorCode::E304, message: "relation \"users\" does not exist".to_string() };
+		assert_eq!(error.code(), ErrorCode::E304);
+
+		let response = error.into_response();
+		assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+		assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+
+		// Internal details are hidden by default
+		let msg = response.extensions().get::<AppErrorMessage>().unwrap();
+		assert_eq!(msg.detail, "Database query error");
+		assert_eq!(msg.kind, format!("{}e304", ERROR_TYPE_BASE));
+
+		let value = serde_json::to_value(msg).unwrap();
+		assert_eq!(value["code"], "E304");
+		assert_eq!(value["status"], 500);
+		assert!(value.get("instance").is_none());
+
+		let msg = AppError::BadRequest { message: "missing sql".to_string() }.into_response();
+		let msg = msg.extensions().get::<AppErrorMessage>().unwrap();
+		assert_eq!(msg.code, ErrorCode::H400);
+		assert_eq!(msg.detail, "missing sql");
+	}
+}
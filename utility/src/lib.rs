@@ -2,3 +2,6 @@ pub mod killport;
 pub mod env;
 pub mod errors;
 pub mod pw;
+pub mod cidr;
+pub mod process;
+pub mod cron;
@@ -0,0 +1,31 @@
+/// Resource usage of the current process
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessStats {
+	/// Resident set size in bytes
+	pub rss_bytes: Option<u64>,
+
+	/// Number of open file descriptors
+	pub open_fds: Option<u64>,
+}
+
+/// Read resource usage of the current process.
+///
+/// Only available on Linux, other platforms return empty stats.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn current() -> ProcessStats {
+	match procfs::process::Process::myself() {
+		Ok(process) => ProcessStats {
+			rss_bytes: process.stat().ok().map(|stat| stat.rss_bytes()),
+			open_fds: process.fd_count().ok().map(|count| count as u64),
+		},
+		Err(err) => {
+			tracing::warn!("unable to read process stats - {:?}", err);
+			ProcessStats::default()
+		}
+	}
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn current() -> ProcessStats {
+	ProcessStats::default()
+}